@@ -0,0 +1,65 @@
+/*
+ * C interface to bfint, an over engineered Brainfuck interpreter.
+ *
+ * Build the shared library with `cargo rustc --release --lib --features ffi --crate-type cdylib` and link to
+ * target/release/libbfint.so (libbfint.dylib on macOS, bfint.dll on Windows). Functions returning int return 0 on
+ * success and -1 on failure, with the message of the error available from bfint_last_error. Handles are not thread
+ * safe.
+ */
+#ifndef BFINT_H
+#define BFINT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* States of a program returned by bfint_run and bfint_step */
+#define BFINT_FINISHED 0
+#define BFINT_RUNNABLE 1
+#define BFINT_WAITING 2
+#define BFINT_ERROR (-1)
+
+typedef struct BfintInterpreter BfintInterpreter;
+
+/* New interpreter with a tape of memory_size 8-bit wrapping cells, where leaving the tape is an error. NULL if
+ * memory_size is 0 or the tape cannot be allocated */
+BfintInterpreter *bfint_new(size_t memory_size);
+/* Destroy an interpreter. NULL is ignored */
+void bfint_free(BfintInterpreter *handle);
+
+/* Compile the len bytes of source code and load the program, resetting the machine */
+int bfint_compile(BfintInterpreter *handle, const uint8_t *source, size_t len);
+/* Execute at most budget instructions, and return BFINT_FINISHED, BFINT_RUNNABLE, BFINT_WAITING or BFINT_ERROR */
+int bfint_run(BfintInterpreter *handle, uint64_t budget);
+/* Execute a single instruction, as bfint_run does */
+int bfint_step(BfintInterpreter *handle);
+
+/* Append len bytes to the input of the program */
+void bfint_push_input(BfintInterpreter *handle, const uint8_t *bytes, size_t len);
+/* Signal that no more input will be pushed, so that the program reads EOF instead of waiting */
+void bfint_close_input(BfintInterpreter *handle);
+/* Copy at most capacity bytes of the output not taken yet to buffer, and return how many were copied */
+size_t bfint_take_output(BfintInterpreter *handle, uint8_t *buffer, size_t capacity);
+
+/* Read the cell at addr, relative to the initial position of the memory pointer. Fails outside the tape */
+int bfint_peek(BfintInterpreter *handle, intptr_t addr, uint64_t *value);
+/* Set the cell at addr to value, truncated to the cell width. Fails outside the tape */
+int bfint_poke(BfintInterpreter *handle, intptr_t addr, uint64_t value);
+/* Address of the next instruction */
+size_t bfint_pc(const BfintInterpreter *handle);
+/* Address of the cell under the memory pointer, relative to its initial position */
+intptr_t bfint_address(const BfintInterpreter *handle);
+/* Number of instructions executed since the program started */
+uint64_t bfint_executed(const BfintInterpreter *handle);
+
+/* Message of the last error, empty if nothing failed yet. Valid until the next failing call or bfint_free */
+const char *bfint_last_error(const BfintInterpreter *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BFINT_H */
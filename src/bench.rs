@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use bfint::{EngineKind, Interpreter, Program, Settings, Tape};
+
+/// Run `program` `warmup` times, then `iterations` times measuring the wall time of each run, and print the min, median
+/// and mean timings. Every run starts from a fresh virtual machine configured by `settings`, which should provide the
+/// same input each time, with a fresh engine of kind `engine` and a fresh tape created by `tape`
+pub fn run<T: Fn() -> Result<Box<dyn Tape>, Box<dyn Error>>, F: Fn() -> Settings>(
+    program: &Program, engine: EngineKind, tape: T, settings: F, warmup: usize, iterations: usize,
+) -> Result<(), Box<dyn Error>> {
+    if iterations == 0 {
+        return Err("At least one iteration is required".into());
+    }
+    for _ in 0..warmup {
+        measure(program, engine, tape()?, settings())?;
+    }
+    let mut timings = Vec::with_capacity(iterations);
+    let mut instructions = 0;
+    for _ in 0..iterations {
+        let (elapsed, executed) = measure(program, engine, tape()?, settings())?;
+        timings.push(elapsed);
+        instructions = executed;
+    }
+    timings.sort();
+    let median = match timings.len() {
+        len if len.is_multiple_of(2) => (timings[len / 2 - 1] + timings[len / 2]) / 2,
+        len => timings[len / 2],
+    };
+    let mean = timings.iter().sum::<Duration>() / iterations as u32;
+    println!("{} runs of {} instructions ({} warmup)", iterations, instructions, warmup);
+    println!("min     {:?}", timings[0]);
+    println!("median  {:?}", median);
+    println!("mean    {:?}", mean);
+    if !median.is_zero() {
+        println!("{:.0} instructions per second (median)", instructions as f64 / median.as_secs_f64());
+    }
+    Ok(())
+}
+
+/// Wall time and number of instructions of a single run
+fn measure(
+    program: &Program, engine: EngineKind, tape: Box<dyn Tape>, settings: Settings,
+) -> Result<(Duration, u64), Box<dyn Error>> {
+    let mut interpreter = Interpreter::with_tape(settings, tape).with_engine(engine.create());
+    interpreter.load_program(program.clone());
+    let start = Instant::now();
+    interpreter.run()?;
+    interpreter.flush()?;
+    Ok((start.elapsed(), interpreter.vm().executed()))
+}
@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::parse::program::{Instruction, Program};
+
+/// Width of the column holding labels
+const LABEL_WIDTH: usize = 8;
+/// Width of the column holding instructions, before the source location
+const INSTRUCTION_WIDTH: usize = 24;
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Disassemble the program into a listing with one instruction per line. Jump targets get symbolic labels, such as
+    /// `L1:` and `jz L1`, and instructions are annotated with the source location they come from. If `source` is given,
+    /// each line of source code is quoted as a comment before the first instruction compiled from it
+    pub fn to_asm(&self, source: Option<&str>) -> String {
+        // Labels are numbered in the order of their address
+        let targets: BTreeSet<usize> = self.instructions()
+            .iter()
+            .filter_map(|instruction| match *instruction {
+                Instruction::JZ(addr) | Instruction::JNZ(addr) | Instruction::DefineProc(addr) => Some(addr),
+                _ => None,
+            })
+            .collect();
+        let labels: BTreeMap<usize, usize> = targets.into_iter().enumerate().map(|(i, addr)| (addr, i + 1)).collect();
+        let lines: Vec<&str> = source.map(|source| source.lines().collect()).unwrap_or_default();
+        let mut quoted_row = 0;
+        let mut code = String::new();
+        for (addr, instruction) in self.instructions().iter().enumerate() {
+            let loc = self.location(addr);
+            if let Some(line) = loc.filter(|loc| loc.row != quoted_row).and_then(|loc| lines.get(loc.row - 1)) {
+                quoted_row = loc.expect("Quoted lines have a location").row;
+                writeln!(code, "; {}: {}", quoted_row, line.trim()).unwrap();
+            }
+            let label = labels.get(&addr).map(|label| format!("L{}:", label)).unwrap_or_default();
+            let text = match *instruction {
+                Instruction::JZ(target) => format!("jz L{}", labels[&target]),
+                Instruction::JNZ(target) => format!("jnz L{}", labels[&target]),
+                Instruction::DefineProc(target) => format!("proc L{}", labels[&target]),
+                instruction => instruction.to_string(),
+            };
+            let line = match loc {
+                Some(loc) => format!("{:<3$}{:<4$}; {}", label, text, loc, LABEL_WIDTH, INSTRUCTION_WIDTH),
+                None => format!("{:<2$}{}", label, text, LABEL_WIDTH),
+            };
+            writeln!(code, "{}", line).unwrap();
+        }
+        // Jumps past the last instruction
+        if let Some(label) = labels.get(&self.len()) {
+            writeln!(code, "L{}:", label).unwrap();
+        }
+        code
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disassemble() {
+        let program = Program::compile("+[\n->+<]".as_bytes()).expect("Could not compile");
+        assert_eq!(program.to_asm(None), [
+            "        incd                    ; line 1, column 1",
+            "L1:     jz L2                   ; line 1, column 2",
+            "        decd                    ; line 2, column 1",
+            "        incp                    ; line 2, column 2",
+            "        incd                    ; line 2, column 3",
+            "        decp                    ; line 2, column 4",
+            "        jnz L1                  ; line 2, column 5",
+            "L2:     exit",
+            "",
+        ].join("\n"));
+        let asm = program.to_asm(Some("+[\n->+<]"));
+        assert!(asm.starts_with("; 1: +[\n        incd"));
+        assert!(asm.contains("line 1, column 2\n; 2: ->+<]\n        decd"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::error::Error;
+
+use crate::parse::builder::ProgramBuilder;
+use crate::parse::program::{Instruction, Program};
+
+/// Longest multiplication loop tried when generating a character
+const MAX_FACTOR: i64 = 16;
+/// Commands added by a multiplication loop besides the repeated ones: `>[<>-]<`
+const LOOP_OVERHEAD: i64 = 7;
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Translate the program back to brainfuck source code. Instructions that the optimizer fuses from whole loops,
+    /// such as multiplications, cannot be translated. The final exit is implicit
+    pub fn to_brainfuck(&self) -> Result<String, Box<dyn Error>> {
+        let mut code = String::new();
+        let last = self.len().saturating_sub(1);
+        for (addr, instruction) in self.instructions().iter().enumerate() {
+            match *instruction {
+                Instruction::IncPtr => code.push('>'),
+                Instruction::DecPtr => code.push('<'),
+                Instruction::IncData => code.push('+'),
+                Instruction::DecData => code.push('-'),
+                Instruction::Input => code.push(','),
+                Instruction::Output => code.push('.'),
+                Instruction::JZ(_) => code.push('['),
+                Instruction::JNZ(_) => code.push(']'),
+                Instruction::Add(n) => code.push_str(&repeat(n, '+', '-')),
+                Instruction::Move(n) => code.push_str(&repeat(n as i64, '>', '<')),
+                Instruction::Set(value) => {
+                    code.push_str("[-]");
+                    code.push_str(&"+".repeat(value as usize));
+                }
+                Instruction::AddAt { offset, value } => {
+                    code.push_str(&repeat(offset as i64, '>', '<'));
+                    code.push_str(&repeat(value, '+', '-'));
+                    code.push_str(&repeat(-offset as i64, '>', '<'));
+                }
+                Instruction::SetAt { offset, value } => {
+                    code.push_str(&repeat(offset as i64, '>', '<'));
+                    code.push_str("[-]");
+                    code.push_str(&"+".repeat(value as usize));
+                    code.push_str(&repeat(-offset as i64, '>', '<'));
+                }
+                Instruction::Debug => code.push('#'),
+                Instruction::DefineProc(_) => code.push('('),
+                Instruction::Return => code.push(')'),
+                Instruction::CallProc => code.push(':'),
+                Instruction::Store => code.push('$'),
+                Instruction::Load => code.push('!'),
+                Instruction::ShiftLeft => code.push('{'),
+                Instruction::ShiftRight => code.push('}'),
+                Instruction::Not => code.push('~'),
+                Instruction::Xor => code.push('^'),
+                Instruction::And => code.push('&'),
+                Instruction::Or => code.push('|'),
+                Instruction::Fork => code.push('Y'),
+                Instruction::Exit if addr == last => {}
+                Instruction::Exit => code.push('@'),
+                instruction => {
+                    return Err(format!("'{}' cannot be translated to brainf*ck", instruction).into());
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    /// Generate a short program printing `text`, for 8 bit cells that wrap around. Each byte is reached from the
+    /// previous one either directly or, when it is far away, with a multiplication loop on a counter cell
+    pub fn printing(text: &[u8]) -> Program {
+        let mut builder = ProgramBuilder::new();
+        let mut current = 0u8;
+        for &byte in text {
+            // Shortest way around the wrapping cell
+            let delta = byte.wrapping_sub(current) as i8 as i64;
+            let direct = delta.abs();
+            let best = (2..=MAX_FACTOR)
+                .map(|factor| (factor, (delta as f64 / factor as f64).round() as i64))
+                .map(|(factor, times)| (factor, times, delta - factor * times))
+                .min_by_key(|&(factor, times, rest)| factor + times.abs() + rest.abs() + LOOP_OVERHEAD)
+                .filter(|&(factor, times, rest)| factor + times.abs() + rest.abs() + LOOP_OVERHEAD < direct);
+            builder = match best {
+                Some((factor, times, rest)) => {
+                    let counter = builder.inc_ptr(1).inc_data(factor as u64);
+                    let builder = counter.loop_(|body| add(body.dec_ptr(1), times).inc_ptr(1).dec_data(1));
+                    add(builder.dec_ptr(1), rest)
+                }
+                None => add(builder, delta),
+            }
+            .output();
+            current = byte;
+        }
+        builder.build().expect("Loops are balanced")
+    }
+}
+
+/// Append the instructions adding `n` to the current cell, if any
+fn add(builder: ProgramBuilder, n: i64) -> ProgramBuilder {
+    match n {
+        0 => builder,
+        n if n > 0 => builder.inc_data(n as u64),
+        n => builder.dec_data(n.unsigned_abs()),
+    }
+}
+
+/// `n` times the command `up`, or `-n` times the command `down` if `n` is negative
+fn repeat(n: i64, up: char, down: char) -> String {
+    let command = if n < 0 { down } else { up };
+    command.to_string().repeat(n.unsigned_abs() as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::virtualmachine::Settings;
+    use crate::ui::SharedBuffer;
+
+    #[test]
+    fn translate_to_brainfuck() {
+        let source = "+[->+<]>(.)>:$@";
+        let syntax = crate::parse::token::Syntax { procedures: true, extended: true, ..Default::default() };
+        let program = Program::compile_with(source.as_bytes(), syntax).unwrap();
+        assert_eq!(program.to_brainfuck().unwrap(), source);
+        let program = Program::from_instructions(vec![Instruction::Add(-3), Instruction::Move(2), Instruction::Set(2)]);
+        assert_eq!(program.unwrap().to_brainfuck().unwrap(), "--->>[-]++");
+        let program = Program::from_instructions(vec![Instruction::MulAdd { offset: 1, factor: 2 }]).unwrap();
+        assert_eq!(program.to_brainfuck().unwrap_err().to_string(), "'muladd 1 2' cannot be translated to brainf*ck");
+    }
+
+    #[test]
+    fn print_text() {
+        let text = "Hello, World!\n\x01~";
+        let code = Program::printing(text.as_bytes()).to_brainfuck().unwrap();
+        assert!(code.len() < 250, "{}", code);
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(Settings {
+            memory_size: 2,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..Settings::default()
+        });
+        interpreter.load_source(code.as_bytes()).unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(output.contents(), text.as_bytes());
+    }
+}
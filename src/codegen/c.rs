@@ -0,0 +1,302 @@
+use std::fmt::Write;
+
+use crate::interpreter::virtualmachine::{CellOverflowBehavior, MemoryOverflowBehavior, Settings};
+use crate::parse::program::{Instruction, Program};
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Translate the program into a standalone C source file. Cell width, memory size and overflow behaviors are taken
+    /// from `settings`, except for bit cells which are not supported. Input and output go through stdin and stdout.
+    /// Each pbrain procedure becomes a function
+    pub fn to_c(&self, settings: &Settings) -> String {
+        let mut code = prelude(settings, self);
+        // Each procedure is written to a function of its own, declared in advance so that it can be referenced before
+        // its definition
+        let mut functions = vec![String::from(MAIN)];
+        let mut procedures = Vec::new();
+        let mut depths = Vec::new();
+        let mut depth = 1;
+        for (pc, instruction) in self.instructions().iter().enumerate() {
+            if let Instruction::JNZ(_) = instruction {
+                depth -= 1;
+            }
+            if let Instruction::Return = instruction {
+                let mut function = functions.pop().expect("Procedures are balanced");
+                function.push_str("}\n\n");
+                procedures.push(function);
+                depth = depths.pop().expect("Procedures are balanced");
+                continue;
+            }
+            let indent = "    ".repeat(depth);
+            let statement = match *instruction {
+                Instruction::IncPtr => String::from("move(1);"),
+                Instruction::DecPtr => String::from("move(-1);"),
+                Instruction::IncData => String::from("add(1);"),
+                Instruction::DecData => String::from("sub(1);"),
+                Instruction::Input => String::from("input();"),
+                Instruction::Output => String::from("putchar((unsigned char)tape[mp]);"),
+                Instruction::JZ(_) => String::from("while (tape[mp]) {"),
+                Instruction::JNZ(_) => String::from("}"),
+                Instruction::Add(n) if n >= 0 => format!("add({}u);", n),
+                Instruction::Add(n) => format!("sub({}u);", n.unsigned_abs()),
+                Instruction::Move(n) => format!("move({});", n),
+                Instruction::Set(val) => format!("tape[mp] = {}u;", val),
+                Instruction::MulAdd { offset, factor } => format!(
+                    "if (tape[mp]) {{ cell v = tape[mp]; move({}); tape[mp] += (cell)(v * (uint64_t){}); move({}); }}",
+                    offset, factor, -offset
+                ),
+                Instruction::AddAt { offset, value } if value >= 0 => {
+                    format!("move({}); add({}u); move({});", offset, value, -offset)
+                }
+                Instruction::AddAt { offset, value } => {
+                    format!("move({}); sub({}u); move({});", offset, value.unsigned_abs(), -offset)
+                }
+                Instruction::SetAt { offset, value } => {
+                    format!("move({}); tape[mp] = {}u; move({});", offset, value, -offset)
+                }
+                Instruction::Debug => format!(
+                    "fflush(stdout); \
+                    fprintf(stderr, \"pc 0x{:08x}  mp %zu  | [%llu]\\n\", mp, (unsigned long long)tape[mp]);",
+                    pc
+                ),
+                Instruction::DefineProc(_) => format!("define(proc_{});", pc),
+                Instruction::Return => unreachable!("Procedures are closed above"),
+                Instruction::CallProc => String::from("call();"),
+                Instruction::Store => String::from("storage = tape[mp];"),
+                Instruction::Load => String::from("tape[mp] = storage;"),
+                Instruction::ShiftLeft => String::from("tape[mp] <<= 1;"),
+                Instruction::ShiftRight => String::from("tape[mp] >>= 1;"),
+                Instruction::Not => String::from("tape[mp] = ~tape[mp];"),
+                Instruction::Xor => String::from("tape[mp] ^= storage;"),
+                Instruction::And => String::from("tape[mp] &= storage;"),
+                Instruction::Or => String::from("tape[mp] |= storage;"),
+                Instruction::Fork => String::from("fail(\"Threads are not supported\\n\");"),
+                Instruction::Exit => format!("fflush(stdout);\n{}return 0;", indent),
+            };
+            let function = functions.last_mut().expect("The main function is never closed");
+            writeln!(function, "{}{}", indent, statement).unwrap();
+            match instruction {
+                Instruction::JZ(_) => depth += 1,
+                Instruction::DefineProc(_) => {
+                    writeln!(code, "static void proc_{}(void);", pc).unwrap();
+                    functions.push(format!("static void proc_{}(void) {{\n", pc));
+                    depths.push(depth);
+                    depth = 1;
+                }
+                _ => {}
+            }
+        }
+        if !procedures.is_empty() {
+            code.push('\n');
+        }
+        code.extend(procedures);
+        code.extend(functions);
+        code.push_str("}\n");
+        code
+    }
+}
+
+/// Type definitions, tape and the helper functions implementing the configured overflow behaviors, and the procedure
+/// table and storage register if `program` needs them
+fn prelude(settings: &Settings, program: &Program) -> String {
+    let mut code = String::from(HEADER);
+    writeln!(code, "typedef uint{}_t cell;", settings.cell_width.bits()).unwrap();
+    writeln!(code, "#define MAX ((cell){}u)\n", settings.cell_width.max_value()).unwrap();
+    writeln!(code, "static size_t len = {};", settings.memory_size).unwrap();
+    code.push_str(GLOBALS);
+    code.push_str(match settings.memory_overflow_behavior {
+        MemoryOverflowBehavior::Unchecked => MOVE_UNCHECKED,
+        MemoryOverflowBehavior::Saturate => MOVE_SATURATE,
+        MemoryOverflowBehavior::Wrap => MOVE_WRAP,
+        MemoryOverflowBehavior::Extend => MOVE_EXTEND,
+        MemoryOverflowBehavior::Error => MOVE_ERROR,
+    });
+    code.push_str(match settings.cell_overflow_behavior {
+        CellOverflowBehavior::Wrap => ARITHMETIC_WRAP,
+        CellOverflowBehavior::Saturate => ARITHMETIC_SATURATE,
+        CellOverflowBehavior::Error => ARITHMETIC_ERROR,
+    });
+    if program.defines_procedures() {
+        code.push_str(PROCEDURES);
+    }
+    if program.uses_storage() {
+        code.push_str("static cell storage = 0;\n\n");
+    }
+    code
+}
+
+const HEADER: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+"#;
+
+const GLOBALS: &str = r#"static cell *tape;
+static size_t mp = 0;
+
+static void fail(const char *msg) {
+    fflush(stdout);
+    fputs(msg, stderr);
+    exit(1);
+}
+
+/* Input: newlines are skipped and EOF reads 0 */
+static inline void input(void) {
+    int c;
+    fflush(stdout);
+    do {
+        c = getchar();
+    } while (c == '\n');
+    tape[mp] = c == EOF ? 0 : (cell)c;
+}
+
+"#;
+
+const MOVE_UNCHECKED: &str = r#"static inline void move(long long n) {
+    mp += n;
+}
+
+"#;
+
+const MOVE_SATURATE: &str = r#"static inline void move(long long n) {
+    if (n < 0 && (size_t)-n > mp) mp = 0;
+    else if (n > 0 && mp + n >= len) mp = len - 1;
+    else mp += n;
+}
+
+"#;
+
+const MOVE_WRAP: &str = r#"static inline void move(long long n) {
+    long long m = ((long long)mp + n) % (long long)len;
+    mp = m < 0 ? m + len : m;
+}
+
+"#;
+
+const MOVE_EXTEND: &str = r#"static inline void move(long long n) {
+    if (n < 0 && (size_t)-n > mp) {
+        size_t grow = -n - mp;
+        tape = realloc(tape, (len + grow) * sizeof(cell));
+        if (!tape) fail("Out of memory\n");
+        memmove(tape + grow, tape, len * sizeof(cell));
+        memset(tape, 0, grow * sizeof(cell));
+        len += grow;
+        mp = 0;
+    } else {
+        mp += n;
+        if (mp >= len) {
+            tape = realloc(tape, (mp + 1) * sizeof(cell));
+            if (!tape) fail("Out of memory\n");
+            memset(tape + len, 0, (mp + 1 - len) * sizeof(cell));
+            len = mp + 1;
+        }
+    }
+}
+
+"#;
+
+const MOVE_ERROR: &str = r#"static inline void move(long long n) {
+    if (n < 0 && (size_t)-n > mp) fail("Memory pointer moved left of the tape\n");
+    if (n > 0 && mp + n >= len) fail("Memory pointer moved right of the tape\n");
+    mp += n;
+}
+
+"#;
+
+const ARITHMETIC_WRAP: &str = r#"static inline void add(uint64_t n) {
+    tape[mp] += n;
+}
+
+static inline void sub(uint64_t n) {
+    tape[mp] -= n;
+}
+
+"#;
+
+const ARITHMETIC_SATURATE: &str = r#"static inline void add(uint64_t n) {
+    if (MAX - tape[mp] >= n) tape[mp] += n;
+    else tape[mp] = MAX;
+}
+
+static inline void sub(uint64_t n) {
+    if (tape[mp] >= n) tape[mp] -= n;
+    else tape[mp] = 0;
+}
+
+"#;
+
+const ARITHMETIC_ERROR: &str = r#"static inline void add(uint64_t n) {
+    if (MAX - tape[mp] >= n) tape[mp] += n;
+    else fail("Cell overflow\n");
+}
+
+static inline void sub(uint64_t n) {
+    if (tape[mp] >= n) tape[mp] -= n;
+    else fail("Cell underflow\n");
+}
+
+"#;
+
+const PROCEDURES: &str = r#"typedef void (*procedure)(void);
+static struct { cell id; procedure body; } *procs;
+static size_t nprocs = 0;
+
+static void define(procedure body) {
+    for (size_t i = 0; i < nprocs; i++) {
+        if (procs[i].id == tape[mp]) {
+            procs[i].body = body;
+            return;
+        }
+    }
+    procs = realloc(procs, (nprocs + 1) * sizeof *procs);
+    if (!procs) fail("Out of memory\n");
+    procs[nprocs].id = tape[mp];
+    procs[nprocs++].body = body;
+}
+
+static void call(void) {
+    for (size_t i = 0; i < nprocs; i++) {
+        if (procs[i].id == tape[mp]) {
+            procs[i].body();
+            return;
+        }
+    }
+    fail("Undefined procedure\n");
+}
+
+"#;
+
+const MAIN: &str = r#"int main(void) {
+    tape = calloc(len, sizeof(cell));
+    if (!tape) fail("Out of memory\n");
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::CellWidth;
+    use crate::parse::token::Syntax;
+
+    #[test]
+    fn translate_to_c() {
+        let settings = Settings {
+            memory_size: 16,
+            cell_width: CellWidth::U16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        };
+        let program = Program::compile("+[->+<]".as_bytes()).expect("Could not compile");
+        let code = program.to_c(&settings);
+        assert!(code.contains("typedef uint16_t cell;"));
+        assert!(code.contains("static size_t len = 16;"));
+        assert!(code.contains("    while (tape[mp]) {\n        sub(1);\n        move(1);\n"));
+        let syntax = Syntax { procedures: true, ..Syntax::default() };
+        let program = Program::compile_with("([-]):".as_bytes(), syntax).expect("Could not compile");
+        let code = program.to_c(&settings);
+        assert!(code.contains("static void proc_0(void);\n"));
+        assert!(code.contains("static void proc_0(void) {\n    while (tape[mp]) {\n"));
+        assert!(code.contains("    define(proc_0);\n    call();\n"));
+    }
+}
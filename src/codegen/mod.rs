@@ -0,0 +1,5 @@
+pub mod asm;
+pub mod brainfuck;
+pub mod c;
+pub mod rust;
+pub mod wasm;
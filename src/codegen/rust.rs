@@ -0,0 +1,279 @@
+use std::fmt::Write;
+
+use crate::interpreter::virtualmachine::{CellOverflowBehavior, MemoryOverflowBehavior, Settings};
+use crate::parse::program::{Instruction, Program};
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Translate the program into a self-contained Rust `main.rs`. Cell width, memory size and overflow behaviors are
+    /// taken from `settings`, except for bit cells which are not supported. Input and output go through stdin and
+    /// stdout. Each pbrain procedure becomes a function
+    pub fn to_rust(&self, settings: &Settings) -> String {
+        // Each procedure is written to a function of its own, after `main`
+        let mut functions = vec![prelude(settings)];
+        let mut procedures = String::new();
+        let mut depths = Vec::new();
+        let mut depth = 1;
+        for (pc, instruction) in self.instructions().iter().enumerate() {
+            if let Instruction::JNZ(_) = instruction {
+                depth -= 1;
+            }
+            if let Instruction::Return = instruction {
+                procedures.push('\n');
+                procedures.push_str(&functions.pop().expect("Procedures are balanced"));
+                procedures.push_str("}\n");
+                depth = depths.pop().expect("Procedures are balanced");
+                continue;
+            }
+            let indent = "    ".repeat(depth);
+            let statement = match *instruction {
+                Instruction::IncPtr => String::from("m.mov(1);"),
+                Instruction::DecPtr => String::from("m.mov(-1);"),
+                Instruction::IncData => String::from("m.add(1);"),
+                Instruction::DecData => String::from("m.sub(1);"),
+                Instruction::Input => String::from("m.input();"),
+                Instruction::Output => String::from("m.output();"),
+                Instruction::JZ(_) => String::from("while m.tape[m.mp] != 0 {"),
+                Instruction::JNZ(_) => String::from("}"),
+                Instruction::Add(n) if n >= 0 => format!("m.add({});", n),
+                Instruction::Add(n) => format!("m.sub({});", n.unsigned_abs()),
+                Instruction::Move(n) => format!("m.mov({});", n),
+                Instruction::Set(val) => format!("m.tape[m.mp] = {};", val),
+                Instruction::MulAdd { offset, factor } => format!("m.mul_add({}, {});", offset, factor),
+                Instruction::AddAt { offset, value } if value >= 0 => {
+                    format!("m.mov({}); m.add({}); m.mov({});", offset, value, -offset)
+                }
+                Instruction::AddAt { offset, value } => {
+                    format!("m.mov({}); m.sub({}); m.mov({});", offset, value.unsigned_abs(), -offset)
+                }
+                Instruction::SetAt { offset, value } => {
+                    format!("m.mov({}); m.tape[m.mp] = {}; m.mov({});", offset, value, -offset)
+                }
+                Instruction::Debug => format!(
+                    "m.out.flush().unwrap(); eprintln!(\"pc 0x{:08x}  mp {{}}  | [{{}}]\", m.mp, m.tape[m.mp]);",
+                    pc
+                ),
+                Instruction::DefineProc(_) => format!("m.procs.insert(m.tape[m.mp], proc_{});", pc),
+                Instruction::Return => unreachable!("Procedures are closed above"),
+                Instruction::CallProc => String::from("m.call();"),
+                Instruction::Store => String::from("m.storage = m.tape[m.mp];"),
+                Instruction::Load => String::from("m.tape[m.mp] = m.storage;"),
+                Instruction::ShiftLeft => String::from("m.tape[m.mp] <<= 1;"),
+                Instruction::ShiftRight => String::from("m.tape[m.mp] >>= 1;"),
+                Instruction::Not => String::from("m.tape[m.mp] = !m.tape[m.mp];"),
+                Instruction::Xor => String::from("m.tape[m.mp] ^= m.storage;"),
+                Instruction::And => String::from("m.tape[m.mp] &= m.storage;"),
+                Instruction::Or => String::from("m.tape[m.mp] |= m.storage;"),
+                Instruction::Fork => String::from("fail(\"Threads are not supported\");"),
+                Instruction::Exit => format!("m.out.flush().unwrap();\n{}return;", indent),
+            };
+            let function = functions.last_mut().expect("The main function is never closed");
+            writeln!(function, "{}{}", indent, statement).unwrap();
+            match instruction {
+                Instruction::JZ(_) => depth += 1,
+                Instruction::DefineProc(_) => {
+                    functions.push(format!("fn proc_{}(m: &mut Machine) {{\n", pc));
+                    depths.push(depth);
+                    depth = 1;
+                }
+                _ => {}
+            }
+        }
+        let mut code = functions.pop().expect("The main function is never closed");
+        code.push_str("}\n");
+        code.push_str(&procedures);
+        code
+    }
+}
+
+/// Machine definition with the helper methods implementing the configured overflow behaviors
+fn prelude(settings: &Settings) -> String {
+    let mut code = String::from(HEADER);
+    writeln!(code, "type Cell = u{};\n", settings.cell_width.bits()).unwrap();
+    code.push_str(MACHINE);
+    code.push_str(match settings.memory_overflow_behavior {
+        MemoryOverflowBehavior::Unchecked => MOVE_UNCHECKED,
+        MemoryOverflowBehavior::Saturate => MOVE_SATURATE,
+        MemoryOverflowBehavior::Wrap => MOVE_WRAP,
+        MemoryOverflowBehavior::Extend => MOVE_EXTEND,
+        MemoryOverflowBehavior::Error => MOVE_ERROR,
+    });
+    code.push_str(match settings.cell_overflow_behavior {
+        CellOverflowBehavior::Wrap => ARITHMETIC_WRAP,
+        CellOverflowBehavior::Saturate => ARITHMETIC_SATURATE,
+        CellOverflowBehavior::Error => ARITHMETIC_ERROR,
+    });
+    code.push_str("}\n\n");
+    code.push_str("fn main() {\n    let mut m = Machine {\n");
+    writeln!(code, "        tape: vec![0; {}],", settings.memory_size).unwrap();
+    code.push_str("        mp: 0,\n        out: std::io::BufWriter::new(std::io::stdout()),\n");
+    code.push_str("        procs: HashMap::new(),\n        storage: 0,\n    };\n");
+    code
+}
+
+const HEADER: &str = r#"#![allow(dead_code, unreachable_code, unused_imports)]
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+"#;
+
+const MACHINE: &str = r#"struct Machine {
+    tape: Vec<Cell>,
+    mp: usize,
+    out: std::io::BufWriter<std::io::Stdout>,
+    procs: HashMap<Cell, fn(&mut Machine)>,
+    storage: Cell,
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+impl Machine {
+    fn mul_add(&mut self, offset: isize, factor: i64) {
+        let val = self.tape[self.mp];
+        if val == 0 {
+            return;
+        }
+        self.mov(offset);
+        self.tape[self.mp] = self.tape[self.mp].wrapping_add(val.wrapping_mul(factor as Cell));
+        self.mov(-offset);
+    }
+
+    // Newlines are skipped and EOF reads 0
+    fn input(&mut self) {
+        self.out.flush().unwrap();
+        let mut buffer = [b'\n'];
+        while buffer[0] == b'\n' {
+            if std::io::stdin().read(&mut buffer).unwrap() == 0 {
+                buffer[0] = 0;
+            }
+        }
+        self.tape[self.mp] = buffer[0] as Cell;
+    }
+
+    fn output(&mut self) {
+        self.out.write_all(&[self.tape[self.mp] as u8]).unwrap();
+    }
+
+    fn call(&mut self) {
+        let id = self.tape[self.mp];
+        match self.procs.get(&id).copied() {
+            Some(procedure) => procedure(self),
+            None => {
+                self.out.flush().unwrap();
+                fail("Undefined procedure");
+            }
+        }
+    }
+
+"#;
+
+const MOVE_UNCHECKED: &str = r#"    fn mov(&mut self, n: isize) {
+        self.mp = (self.mp as isize + n) as usize;
+    }
+
+"#;
+
+const MOVE_SATURATE: &str = r#"    fn mov(&mut self, n: isize) {
+        self.mp = (self.mp as isize + n).clamp(0, self.tape.len() as isize - 1) as usize;
+    }
+
+"#;
+
+const MOVE_WRAP: &str = r#"    fn mov(&mut self, n: isize) {
+        self.mp = (self.mp as isize + n).rem_euclid(self.tape.len() as isize) as usize;
+    }
+
+"#;
+
+const MOVE_EXTEND: &str = r#"    fn mov(&mut self, n: isize) {
+        let mp = self.mp as isize + n;
+        if mp < 0 {
+            self.tape.splice(0..0, std::iter::repeat(0).take(mp.unsigned_abs()));
+            self.mp = 0;
+        } else {
+            self.mp = mp as usize;
+            if self.mp >= self.tape.len() {
+                self.tape.resize(self.mp + 1, 0);
+            }
+        }
+    }
+
+"#;
+
+const MOVE_ERROR: &str = r#"    fn mov(&mut self, n: isize) {
+        let mp = self.mp as isize + n;
+        if mp < 0 {
+            fail("Memory pointer moved left of the tape");
+        } else if mp >= self.tape.len() as isize {
+            fail("Memory pointer moved right of the tape");
+        }
+        self.mp = mp as usize;
+    }
+
+"#;
+
+const ARITHMETIC_WRAP: &str = r#"    fn add(&mut self, n: u64) {
+        self.tape[self.mp] = self.tape[self.mp].wrapping_add(n as Cell);
+    }
+
+    fn sub(&mut self, n: u64) {
+        self.tape[self.mp] = self.tape[self.mp].wrapping_sub(n as Cell);
+    }
+"#;
+
+const ARITHMETIC_SATURATE: &str = r#"    fn add(&mut self, n: u64) {
+        let cell = self.tape[self.mp];
+        self.tape[self.mp] = Cell::try_from(n).map_or(Cell::MAX, |n| cell.saturating_add(n));
+    }
+
+    fn sub(&mut self, n: u64) {
+        let cell = self.tape[self.mp];
+        self.tape[self.mp] = Cell::try_from(n).map_or(0, |n| cell.saturating_sub(n));
+    }
+"#;
+
+const ARITHMETIC_ERROR: &str = r#"    fn add(&mut self, n: u64) {
+        let cell = self.tape[self.mp];
+        self.tape[self.mp] = Cell::try_from(n).ok().and_then(|n| cell.checked_add(n))
+            .unwrap_or_else(|| fail("Cell overflow"));
+    }
+
+    fn sub(&mut self, n: u64) {
+        let cell = self.tape[self.mp];
+        self.tape[self.mp] = Cell::try_from(n).ok().and_then(|n| cell.checked_sub(n))
+            .unwrap_or_else(|| fail("Cell underflow"));
+    }
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::CellWidth;
+    use crate::parse::token::Syntax;
+
+    #[test]
+    fn translate_to_rust() {
+        let settings = Settings {
+            memory_size: 16,
+            cell_width: CellWidth::U32,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        };
+        let program = Program::compile("+[->+<]".as_bytes()).expect("Could not compile");
+        let code = program.to_rust(&settings);
+        assert!(code.contains("type Cell = u32;"));
+        assert!(code.contains("tape: vec![0; 16],"));
+        assert!(code.contains("    while m.tape[m.mp] != 0 {\n        m.sub(1);\n        m.mov(1);\n"));
+        let syntax = Syntax { procedures: true, ..Syntax::default() };
+        let program = Program::compile_with("([-]):".as_bytes(), syntax).expect("Could not compile");
+        let code = program.to_rust(&settings);
+        assert!(code.contains("    m.procs.insert(m.tape[m.mp], proc_0);\n    m.call();\n"));
+        assert!(code.contains("fn proc_0(m: &mut Machine) {\n    while m.tape[m.mp] != 0 {\n"));
+    }
+}
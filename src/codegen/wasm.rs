@@ -0,0 +1,337 @@
+use std::error::Error;
+
+use crate::interpreter::virtualmachine::{CellOverflowBehavior, CellWidth, MemoryOverflowBehavior, Settings};
+use crate::parse::program::{Instruction, Program};
+
+const PAGE_SIZE: usize = 65536;
+
+// Opcodes
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const END: u8 = 0x0b;
+const CALL: u8 = 0x10;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_ADD: u8 = 0x6a;
+const I32_REM_U: u8 = 0x70;
+const I32_WRAP_I64: u8 = 0xa7;
+const I64_EXTEND_I32_U: u8 = 0xad;
+const EMPTY_BLOCK_TYPE: u8 = 0x40;
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+
+// Locals of the main function
+const MP: u8 = 0;
+const TMP: u8 = 1;
+const STORAGE: u8 = 2;
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Translate the program into a binary WebAssembly module. The module imports `env.input: () -> i32`, which must
+    /// return the next input byte (0 on EOF), and `env.output: (i32) -> ()`, and exports the tape as `memory` and the
+    /// program as `run`. Only wrapping cells of at least 8 bits and an unchecked or wrapping memory pointer are
+    /// supported
+    pub fn to_wasm(&self, settings: &Settings) -> Result<Vec<u8>, Box<dyn Error>> {
+        if settings.cell_overflow_behavior != CellOverflowBehavior::Wrap {
+            return Err("WebAssembly backend only supports wrapping cells".into());
+        }
+        if settings.cell_width == CellWidth::U1 {
+            return Err("WebAssembly backend does not support bit cells".into());
+        }
+        let wrap_pointer = match settings.memory_overflow_behavior {
+            MemoryOverflowBehavior::Unchecked => false,
+            MemoryOverflowBehavior::Wrap => true,
+            _ => return Err("WebAssembly backend only supports unchecked or wrapping memory pointers".into()),
+        };
+        let cell = CellOps::new(settings.cell_width);
+        let tape_len = settings.memory_size * cell.size;
+        let mut body = Vec::new();
+        // One i32 local for the memory pointer (in bytes), one for temporary addresses and one for the storage register
+        if cell.wide {
+            body.extend([2, 2, I32, 1, I64]);
+        } else {
+            body.extend([1, 3, I32]);
+        }
+        let move_ptr = |code: &mut Vec<u8>, local: u8, n: isize| {
+            code.extend([LOCAL_GET, MP, I32_CONST]);
+            if wrap_pointer {
+                sleb128(code, (n * cell.size as isize).rem_euclid(tape_len as isize) as i64);
+                code.push(I32_ADD);
+                code.push(I32_CONST);
+                sleb128(code, tape_len as i64);
+                code.push(I32_REM_U);
+            } else {
+                sleb128(code, (n * cell.size as isize) as i64);
+                code.push(I32_ADD);
+            }
+            code.extend([LOCAL_SET, local]);
+        };
+        for instruction in self.instructions() {
+            match *instruction {
+                Instruction::IncPtr => move_ptr(&mut body, MP, 1),
+                Instruction::DecPtr => move_ptr(&mut body, MP, -1),
+                Instruction::Move(n) => move_ptr(&mut body, MP, n),
+                Instruction::IncData => cell.add(&mut body, MP, 1),
+                Instruction::DecData => cell.add(&mut body, MP, -1),
+                Instruction::Add(n) => cell.add(&mut body, MP, n),
+                Instruction::Set(val) => {
+                    body.extend([LOCAL_GET, MP]);
+                    cell.constant(&mut body, val as i64);
+                    cell.store(&mut body);
+                }
+                Instruction::AddAt { offset, value } => {
+                    move_ptr(&mut body, TMP, offset);
+                    cell.add(&mut body, TMP, value);
+                }
+                Instruction::SetAt { offset, value } => {
+                    move_ptr(&mut body, TMP, offset);
+                    body.extend([LOCAL_GET, TMP]);
+                    cell.constant(&mut body, value as i64);
+                    cell.store(&mut body);
+                }
+                Instruction::MulAdd { offset, factor } => {
+                    // Skipped if the current cell is 0, like the loop it replaces
+                    body.extend([BLOCK, EMPTY_BLOCK_TYPE, LOCAL_GET, MP]);
+                    cell.load(&mut body);
+                    body.extend([cell.eqz, BR_IF, 0]);
+                    move_ptr(&mut body, TMP, offset);
+                    body.extend([LOCAL_GET, TMP, LOCAL_GET, TMP]);
+                    cell.load(&mut body);
+                    body.extend([LOCAL_GET, MP]);
+                    cell.load(&mut body);
+                    cell.constant(&mut body, factor);
+                    body.extend([cell.mul, cell.add]);
+                    cell.store(&mut body);
+                    body.push(END);
+                }
+                Instruction::Input => {
+                    body.extend([LOCAL_GET, MP, CALL, 0]);
+                    if cell.wide {
+                        body.push(I64_EXTEND_I32_U);
+                    }
+                    cell.store(&mut body);
+                }
+                Instruction::Output => {
+                    body.extend([LOCAL_GET, MP]);
+                    cell.load(&mut body);
+                    if cell.wide {
+                        body.push(I32_WRAP_I64);
+                    }
+                    body.extend([CALL, 1]);
+                }
+                Instruction::JZ(_) => {
+                    body.extend([BLOCK, EMPTY_BLOCK_TYPE, LOOP, EMPTY_BLOCK_TYPE, LOCAL_GET, MP]);
+                    cell.load(&mut body);
+                    body.extend([cell.eqz, BR_IF, 1]);
+                }
+                Instruction::JNZ(_) => body.extend([BR, 0, END, END]),
+                Instruction::Store => {
+                    body.extend([LOCAL_GET, MP]);
+                    cell.load(&mut body);
+                    body.extend([LOCAL_SET, STORAGE]);
+                }
+                Instruction::Load => {
+                    body.extend([LOCAL_GET, MP, LOCAL_GET, STORAGE]);
+                    cell.store(&mut body);
+                }
+                Instruction::ShiftLeft => cell.update(&mut body, &cell.constant_code(1), cell.shl),
+                Instruction::ShiftRight => cell.update(&mut body, &cell.constant_code(1), cell.shr_u),
+                Instruction::Not => cell.update(&mut body, &cell.constant_code(-1), cell.xor),
+                Instruction::Xor => cell.update(&mut body, &[LOCAL_GET, STORAGE], cell.xor),
+                Instruction::And => cell.update(&mut body, &[LOCAL_GET, STORAGE], cell.and),
+                Instruction::Or => cell.update(&mut body, &[LOCAL_GET, STORAGE], cell.or),
+                Instruction::Exit => body.push(RETURN),
+                Instruction::Fork => return Err("Threads cannot be translated to WebAssembly".into()),
+                // There is no standard error to dump the state to
+                Instruction::Debug => {}
+                Instruction::DefineProc(_) | Instruction::Return | Instruction::CallProc => {
+                    return Err("Procedures cannot be translated to WebAssembly".into());
+                }
+            }
+        }
+        body.push(END);
+
+        let mut module = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        // Types: () -> i32, (i32) -> (), () -> ()
+        section(&mut module, 1, &[3, 0x60, 0, 1, I32, 0x60, 1, I32, 0, 0x60, 0, 0]);
+        // Imports
+        let mut imports = vec![2];
+        for (name, type_index) in [("input", 0), ("output", 1)] {
+            string(&mut imports, "env");
+            string(&mut imports, name);
+            imports.extend([0x00, type_index]);
+        }
+        section(&mut module, 2, &imports);
+        // Functions
+        section(&mut module, 3, &[1, 2]);
+        // Memory
+        let mut memory = vec![1, 0x00];
+        uleb128(&mut memory, usize::max(1, tape_len.div_ceil(PAGE_SIZE)) as u64);
+        section(&mut module, 5, &memory);
+        // Exports
+        let mut exports = vec![2];
+        string(&mut exports, "run");
+        exports.extend([0x00, 2]);
+        string(&mut exports, "memory");
+        exports.extend([0x02, 0]);
+        section(&mut module, 7, &exports);
+        // Code
+        let mut code = vec![1];
+        uleb128(&mut code, body.len() as u64);
+        code.extend(body);
+        section(&mut module, 10, &code);
+        Ok(module)
+    }
+}
+
+/// Opcodes to access cells of a given width
+#[derive(Copy, Clone)]
+struct CellOps {
+    size: usize,
+    wide: bool,
+    load: u8,
+    store: u8,
+    align: u8,
+    add: u8,
+    mul: u8,
+    eqz: u8,
+    shl: u8,
+    shr_u: u8,
+    xor: u8,
+    and: u8,
+    or: u8,
+}
+
+impl CellOps {
+    fn new(width: CellWidth) -> CellOps {
+        let (size, load, store, align) = match width {
+            // Bit cells are rejected by `to_wasm`
+            CellWidth::U1 | CellWidth::U8 => (1, 0x2d, 0x3a, 0),
+            CellWidth::U16 => (2, 0x2f, 0x3b, 1),
+            CellWidth::U32 => (4, 0x28, 0x36, 2),
+            CellWidth::U64 => (8, 0x29, 0x37, 3),
+        };
+        let wide = width == CellWidth::U64;
+        let (add, mul, eqz) = if wide { (0x7c, 0x7e, 0x50) } else { (I32_ADD, 0x6c, 0x45) };
+        let (shl, shr_u, xor, and, or) = if wide {
+            (0x86, 0x88, 0x85, 0x83, 0x84)
+        } else {
+            (0x74, 0x76, 0x73, 0x71, 0x72)
+        };
+        CellOps { size, wide, load, store, align, add, mul, eqz, shl, shr_u, xor, and, or }
+    }
+
+    fn load(&self, code: &mut Vec<u8>) {
+        code.extend([self.load, self.align, 0]);
+    }
+
+    fn store(&self, code: &mut Vec<u8>) {
+        code.extend([self.store, self.align, 0]);
+    }
+
+    fn constant(&self, code: &mut Vec<u8>, val: i64) {
+        if self.wide {
+            code.push(I64_CONST);
+            sleb128(code, val);
+        } else {
+            // Narrow cells are truncated when stored
+            code.push(I32_CONST);
+            sleb128(code, val as i32 as i64);
+        }
+    }
+
+    fn constant_code(&self, val: i64) -> Vec<u8> {
+        let mut code = Vec::new();
+        self.constant(&mut code, val);
+        code
+    }
+
+    /// Replace the current cell with the result of `op` applied to it and to the value pushed by `operand`
+    fn update(&self, code: &mut Vec<u8>, operand: &[u8], op: u8) {
+        code.extend([LOCAL_GET, MP, LOCAL_GET, MP]);
+        self.load(code);
+        code.extend(operand);
+        code.push(op);
+        self.store(code);
+    }
+
+    fn add(&self, code: &mut Vec<u8>, local: u8, n: i64) {
+        code.extend([LOCAL_GET, local, LOCAL_GET, local]);
+        self.load(code);
+        self.constant(code, n);
+        code.push(self.add);
+        self.store(code);
+    }
+}
+
+fn section(module: &mut Vec<u8>, id: u8, content: &[u8]) {
+    module.push(id);
+    uleb128(module, content.len() as u64);
+    module.extend(content);
+}
+
+fn string(code: &mut Vec<u8>, s: &str) {
+    uleb128(code, s.len() as u64);
+    code.extend(s.as_bytes());
+}
+
+fn uleb128(code: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            code.push(byte);
+            return;
+        }
+        code.push(byte | 0x80);
+    }
+}
+
+fn sleb128(code: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            code.push(byte);
+            return;
+        }
+        code.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leb128_encoding() {
+        let mut code = Vec::new();
+        uleb128(&mut code, 624485);
+        assert_eq!(code, [0xe5, 0x8e, 0x26]);
+        code.clear();
+        sleb128(&mut code, -123456);
+        assert_eq!(code, [0xc0, 0xbb, 0x78]);
+        code.clear();
+        sleb128(&mut code, 64);
+        assert_eq!(code, [0xc0, 0x00]);
+    }
+
+    #[test]
+    fn module_header() {
+        let settings = Settings {
+            memory_size: 16,
+            memory_overflow_behavior: MemoryOverflowBehavior::Wrap,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        };
+        let program = Program::compile("+[->+<]".as_bytes()).expect("Could not compile");
+        let module = program.to_wasm(&settings).expect("Could not translate");
+        assert_eq!(module[..8], [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use bfint::parse::program::SourceLoc;
+use bfint::{Debugger, StopReason};
+
+const PROMPT: &str = "(bfdb) ";
+const HELP: &str = "Commands:
+  step [n]        execute n instructions (default 1)
+  continue        run until a breakpoint or the end of the program
+  break <pc>      set a breakpoint at instruction address pc
+  break <row:col> set a breakpoint at the first instruction at or after a source location
+  delete <pc>     remove the breakpoint at instruction address pc
+  breakpoints     list breakpoints
+  print mem[<n>]  print the cell at address n
+  back [n]        undo n instructions (default 1), input and output are not undone
+  reverse         undo instructions until a breakpoint or the oldest recorded instruction
+  where           print program counter, next instruction and memory pointer
+  quit            exit the debugger";
+
+/// Run an interactive debugging session on the program loaded in `debugger`, reading commands from stdin
+pub fn run(mut debugger: Debugger) -> Result<(), Box<dyn Error>> {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    loop {
+        debugger.interpreter_mut().flush()?;
+        write!(stdout, "{}", PROMPT)?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            writeln!(stdout)?;
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let result = match (words.next(), words.next()) {
+            (None, _) => Ok(()),
+            (Some("quit" | "q"), _) => break,
+            (Some("help" | "h"), _) => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            (Some("step" | "s"), n) => step(&mut debugger, n),
+            (Some("continue" | "c"), _) => resume(&mut debugger),
+            (Some("back" | "step-back"), n) => step_back(&mut debugger, n),
+            (Some("reverse" | "reverse-continue" | "rc"), _) => {
+                let reason = debugger.reverse_resume();
+                report(&debugger, reason);
+                Ok(())
+            }
+            (Some("break" | "b"), Some(pos)) => add_breakpoint(&mut debugger, pos),
+            (Some("delete" | "d"), Some(pc)) => parse_number(pc).map(|pc| {
+                if !debugger.remove_breakpoint(pc) {
+                    println!("No breakpoint at 0x{:08x}", pc);
+                }
+            }),
+            (Some("breakpoints"), _) => {
+                for (pc, loc) in debugger.interpreter().breakpoints() {
+                    match loc {
+                        Some(loc) => println!("0x{:08x} ({}:{})", pc, loc.row, loc.col),
+                        None => println!("0x{:08x}", pc),
+                    }
+                }
+                Ok(())
+            }
+            (Some("print" | "p"), Some(expr)) => print(&debugger, expr),
+            (Some("where" | "w"), _) => {
+                where_(&debugger);
+                Ok(())
+            }
+            (Some(command), _) => {
+                Err(format!("Invalid command: '{}'. Type 'help' for a list of commands", command).into())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn step(debugger: &mut Debugger, n: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let n = n.map_or(Ok(1), parse_number)?;
+    for _ in 0..n {
+        if debugger.has_exited() {
+            break;
+        }
+        debugger.step()?;
+    }
+    where_(debugger);
+    Ok(())
+}
+
+fn add_breakpoint(debugger: &mut Debugger, pos: &str) -> Result<(), Box<dyn Error>> {
+    let pc = match pos.split_once(':') {
+        Some((row, col)) => {
+            let loc = SourceLoc { row: parse_number(row)?, col: parse_number(col)? };
+            debugger
+                .interpreter_mut()
+                .add_breakpoint_at(loc)
+                .ok_or_else(|| format!("No instruction at or after {}:{}", loc.row, loc.col))?
+        }
+        None => {
+            let pc = parse_number(pos)?;
+            debugger.add_breakpoint(pc);
+            pc
+        }
+    };
+    println!("Breakpoint at 0x{:08x}", pc);
+    Ok(())
+}
+
+fn step_back(debugger: &mut Debugger, n: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let n = n.map_or(Ok(1), parse_number)?;
+    for _ in 0..n {
+        if !debugger.step_back() {
+            println!("Reached the oldest recorded instruction");
+            break;
+        }
+    }
+    where_(debugger);
+    Ok(())
+}
+
+fn resume(debugger: &mut Debugger) -> Result<(), Box<dyn Error>> {
+    let reason = debugger.resume()?;
+    report(debugger, reason);
+    Ok(())
+}
+
+fn report(debugger: &Debugger, reason: StopReason) {
+    match reason {
+        StopReason::Breakpoint(pc) => match debugger.interpreter().program().location(pc) {
+            Some(loc) => println!("Stopped at breakpoint 0x{:08x} ({})", pc, loc),
+            None => println!("Stopped at breakpoint 0x{:08x}", pc),
+        },
+        StopReason::Exited => println!("Program exited"),
+        StopReason::WaitingForInput => println!("Waiting for input"),
+        StopReason::HistoryStart => println!("Reached the oldest recorded instruction"),
+    }
+}
+
+fn print(debugger: &Debugger, expr: &str) -> Result<(), Box<dyn Error>> {
+    let addr = expr
+        .strip_prefix("mem[")
+        .and_then(|expr| expr.strip_suffix(']'))
+        .ok_or_else(|| format!("Invalid expression: '{}'", expr))?;
+    let addr: isize = addr.parse().map_err(|_| format!("Invalid address: '{}'", addr))?;
+    match debugger.interpreter().vm().peek(addr) {
+        Some(val) => println!("mem[{}] = {}", addr, val),
+        None => println!("mem[{}] is outside the tape", addr),
+    }
+    Ok(())
+}
+
+fn where_(debugger: &Debugger) {
+    let vm = debugger.interpreter().vm();
+    match debugger.current_instruction() {
+        Some(instruction) => match debugger.interpreter().program().location(vm.pc()) {
+            Some(loc) => println!("0x{:08x}: {}    mp = {}    ({})", vm.pc(), instruction, vm.address(), loc),
+            None => println!("0x{:08x}: {}    mp = {}", vm.pc(), instruction, vm.address()),
+        },
+        None => println!("Program exited    mp = {}", vm.address()),
+    }
+}
+
+/// Parse a decimal or 0x prefixed hexadecimal number
+fn parse_number(s: &str) -> Result<usize, Box<dyn Error>> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.map_err(|_| format!("Invalid number: '{}'", s).into())
+}
@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::interpreter::device::Device;
+use crate::interpreter::time::Instant;
+use crate::interpreter::virtualmachine::VirtualMachine;
+
+/// Screen of characters drawn in the terminal, stored in a region of the tape row by row: a cell holding a printable
+/// ASCII character shows it, any other value a space. The terminal is cleared and the screen redrawn whenever the
+/// program writes the sync cell, if there is one, and at most `fps` times per second while the program changes the
+/// screen, if a frame rate is set. Changes not drawn yet because of the frame rate are drawn once it allows, and
+/// before the program reads input. Clones share the screen, so that the same framebuffer is mapped at several cells
+#[derive(Clone)]
+pub struct Framebuffer(Rc<RefCell<Screen>>);
+
+struct Screen {
+    /// Address of the top left character, relative to the initial position of the memory pointer
+    start: isize,
+    width: usize,
+    height: usize,
+    sync: Option<isize>,
+    /// Minimum time between two frames drawn because the screen changed
+    interval: Option<Duration>,
+    last_frame: Option<Instant>,
+    /// Whether the screen changed since the last frame
+    dirty: bool,
+    terminal: Box<dyn Write>,
+}
+
+/* Framebuffer ********************************************************************************************************/
+impl Framebuffer {
+    /// Screen of `width` by `height` characters stored from the cell at `start`, drawn to `terminal`. It is only drawn
+    /// once a sync cell or a frame rate is set
+    pub fn new<W: Write + 'static>(start: isize, width: usize, height: usize, terminal: W) -> Framebuffer {
+        Framebuffer(Rc::new(RefCell::new(Screen {
+            start,
+            width,
+            height,
+            sync: None,
+            interval: None,
+            last_frame: None,
+            dirty: false,
+            terminal: Box::new(terminal),
+        })))
+    }
+
+    /// Redraw the screen whenever the program writes the cell at `addr`
+    pub fn with_sync(self, addr: isize) -> Framebuffer {
+        self.0.borrow_mut().sync = Some(addr);
+        self
+    }
+
+    /// Redraw the screen at most `fps` times per second while the program changes it
+    pub fn with_fps(self, fps: u32) -> Framebuffer {
+        self.0.borrow_mut().interval = Some(Duration::from_secs(1) / fps.max(1));
+        self
+    }
+
+    /// Map the framebuffer in `vm` at the sync cell and, if a frame rate is set, at the cells of the screen. Fails if
+    /// the screen does not fit in the `memory_size` cells of the tape of `vm`, or if it holds the sync cell
+    pub fn map(&self, vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        let screen = self.0.borrow();
+        let memory_size = vm.settings().memory_size;
+        let cells = screen.width.checked_mul(screen.height)
+            .and_then(|cells| isize::try_from(cells).ok())
+            .and_then(|cells| screen.start.checked_add(cells))
+            .filter(|&end| screen.start >= 0 && end as usize <= memory_size)
+            .map(|end| screen.start..end)
+            .ok_or_else(|| format!(
+                "A screen of {}x{} cells from cell {} does not fit in the {} cells of the tape",
+                screen.width, screen.height, screen.start, memory_size,
+            ))?;
+        if screen.sync.is_some_and(|sync| cells.contains(&sync)) {
+            return Err("The sync cell of the screen is one of its cells".into());
+        }
+        if let Some(sync) = screen.sync {
+            vm.map_device(sync, Box::new(self.clone()));
+        }
+        if screen.interval.is_some() {
+            vm.map_device_range(cells, Box::new(self.clone()));
+        }
+        Ok(())
+    }
+
+    /// The screen stored in the memory of `vm`, as a line of text per row. Cells past the end of the tape are blank
+    pub fn render(&self, vm: &VirtualMachine) -> String {
+        let screen = self.0.borrow();
+        let mut frame = String::new();
+        for row in 0..screen.height {
+            let cells = vm.memory_slice(screen.start + (row * screen.width) as isize, screen.width);
+            for col in 0..screen.width {
+                frame.push(match cells.get(col) {
+                    Some(&cell @ 0x20..=0x7e) => cell as u8 as char,
+                    _ => ' ',
+                });
+            }
+            frame.push('\n');
+        }
+        frame
+    }
+
+    /// Clear the terminal and draw the screen stored in the memory of `vm`
+    pub fn draw(&self, vm: &VirtualMachine) -> std::io::Result<()> {
+        let frame = self.render(vm);
+        let mut screen = self.0.borrow_mut();
+        // Move the cursor home and clear the screen before drawing
+        write!(screen.terminal, "\x1b[H\x1b[2J{}", frame)?;
+        screen.terminal.flush()?;
+        screen.last_frame = Some(Instant::now());
+        screen.dirty = false;
+        Ok(())
+    }
+
+    /// Draw the changes to the screen that were not drawn yet because of the frame rate, such as after the program
+    /// exits
+    pub fn finish(&self, vm: &VirtualMachine) -> std::io::Result<()> {
+        let dirty = self.0.borrow().dirty;
+        match dirty {
+            true => self.draw(vm),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether the frame rate allows drawing a frame now
+    fn frame_due(&self) -> bool {
+        let screen = self.0.borrow();
+        screen.interval.is_some_and(|interval| screen.last_frame.is_none_or(|last| last.elapsed() >= interval))
+    }
+}
+
+impl Device for Framebuffer {
+    fn write(&mut self, _value: u64, vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        let addr = vm.last_write().map(|(addr, _)| addr);
+        let sync = addr.is_some() && addr == self.0.borrow().sync;
+        match sync || self.frame_due() {
+            true => self.draw(vm)?,
+            false => self.0.borrow_mut().dirty = true,
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        let dirty = self.0.borrow().dirty;
+        if dirty && self.frame_due() {
+            self.draw(vm)?;
+        }
+        Ok(())
+    }
+
+    fn before_input(&mut self, vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        Ok(self.finish(vm)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::virtualmachine::Settings;
+    use crate::ui::SharedBuffer;
+
+    /// Number of frames drawn to `terminal`
+    fn frames(terminal: &SharedBuffer) -> usize {
+        String::from_utf8(terminal.contents()).unwrap().matches("\x1b[2J").count()
+    }
+
+    #[test]
+    fn draw_on_sync() {
+        let terminal = SharedBuffer::new();
+        let framebuffer = Framebuffer::new(1, 3, 2, terminal.clone()).with_sync(0);
+        let settings = Settings::builder().initial_memory(b"\0Hi\0\0\0!".to_vec()).build();
+        let mut interpreter = Interpreter::with_vm_settings(settings);
+        framebuffer.map(interpreter.vm_mut()).unwrap();
+        // Changes to the screen are only drawn on sync
+        interpreter.load_source(b">>>>>>+<<<<<<+").unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(terminal.contents(), b"\x1b[H\x1b[2JHi \n  \"\n");
+    }
+
+    #[test]
+    fn limit_frame_rate() {
+        let terminal = SharedBuffer::new();
+        let framebuffer = Framebuffer::new(0, 2, 1, terminal.clone()).with_fps(1);
+        let mut interpreter = Interpreter::new();
+        framebuffer.map(interpreter.vm_mut()).unwrap();
+        interpreter.load_source(b"+>+>+").unwrap();
+        interpreter.run().unwrap();
+        // Only the first change is drawn within a second, and writes past the screen are not watched
+        assert_eq!(frames(&terminal), 1);
+        framebuffer.finish(interpreter.vm()).unwrap();
+        assert_eq!(frames(&terminal), 2);
+        framebuffer.finish(interpreter.vm()).unwrap();
+        assert_eq!(frames(&terminal), 2);
+    }
+
+    #[test]
+    fn draw_pending_frames() {
+        let terminal = SharedBuffer::new();
+        let framebuffer = Framebuffer::new(0, 1, 1, terminal.clone()).with_fps(1);
+        let mut interpreter = Interpreter::new();
+        framebuffer.map(interpreter.vm_mut()).unwrap();
+        // The second change is drawn before the program reads input, whatever the frame rate
+        interpreter.load_source(b"+>,<+>,").unwrap();
+        interpreter.run_with_io(b"ab").unwrap();
+        assert_eq!(frames(&terminal), 2);
+        // and on a tick once the frame rate allows, while the program keeps running
+        let framebuffer = Framebuffer::new(0, 1, 1, terminal.clone()).with_fps(1000);
+        framebuffer.map(interpreter.vm_mut()).unwrap();
+        interpreter.load_source(b"++").unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(frames(&terminal), 3);
+        std::thread::sleep(Duration::from_millis(2));
+        framebuffer.clone().tick(interpreter.vm_mut()).unwrap();
+        assert_eq!(frames(&terminal), 4);
+        framebuffer.clone().tick(interpreter.vm_mut()).unwrap();
+        assert_eq!(frames(&terminal), 4);
+    }
+
+    #[test]
+    fn fit_in_the_tape() {
+        let mut interpreter = Interpreter::with_vm_settings(Settings::builder().memory_size(100).build());
+        let map = |framebuffer: Framebuffer, interpreter: &mut Interpreter| {
+            framebuffer.map(interpreter.vm_mut()).map_err(|err| err.to_string())
+        };
+        assert!(map(Framebuffer::new(20, 10, 8, std::io::sink()).with_fps(1), &mut interpreter).is_ok());
+        let err = map(Framebuffer::new(30, 10, 8, std::io::sink()).with_sync(0), &mut interpreter);
+        let expected = "A screen of 10x8 cells from cell 30 does not fit in the 100 cells of the tape";
+        assert_eq!(err, Err(String::from(expected)));
+        assert!(map(Framebuffer::new(0, 1 << 32, 1 << 32, std::io::sink()).with_sync(0), &mut interpreter).is_err());
+        let err = map(Framebuffer::new(0, 2, 2, std::io::sink()).with_sync(3), &mut interpreter);
+        assert_eq!(err, Err(String::from("The sync cell of the screen is one of its cells")));
+    }
+}
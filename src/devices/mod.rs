@@ -0,0 +1,3 @@
+//! Devices for programs that interact with the user beyond reading input and writing output, mapped at cells of the
+//! tape like the basic ones in [`interpreter::device`](crate::interpreter::device)
+pub mod framebuffer;
@@ -0,0 +1,288 @@
+//! C interface to the interpreter, built with `--features ffi` and declared in `include/bfint.h`, so that C and C++
+//! applications and other language runtimes can embed it. Interpreters are opaque handles created by [`bfint_new`]
+//! and destroyed by [`bfint_free`]. Functions returning `int` return 0 on success and -1 on failure, with the message
+//! of the error available from [`bfint_last_error`]. Panics abort the process rather than unwinding into C
+use std::ffi::{c_char, c_int, CString};
+use std::io::Write;
+
+use crate::interpreter::interpreter::{Interpreter, StepOutcome};
+use crate::interpreter::virtualmachine::{MemoryOverflowBehavior, Settings};
+use crate::ui::SharedBuffer;
+
+/// The program exited
+pub const BFINT_FINISHED: c_int = 0;
+/// The budget of instructions ran out: the program can be continued
+pub const BFINT_RUNNABLE: c_int = 1;
+/// The program waits for input, to be pushed with [`bfint_push_input`]
+pub const BFINT_WAITING: c_int = 2;
+/// The program stopped with an error
+pub const BFINT_ERROR: c_int = -1;
+
+/// Interpreter behind a handle, with its output and the message of its last error
+pub struct BfintInterpreter {
+    interpreter: Interpreter,
+    output: SharedBuffer,
+    error: CString,
+}
+
+impl BfintInterpreter {
+    /// Record the message of `err` and return -1
+    fn fail(&mut self, err: impl ToString) -> c_int {
+        self.error = CString::new(err.to_string().replace('\0', " ")).expect("NUL bytes were replaced");
+        BFINT_ERROR
+    }
+}
+
+/// New interpreter with a tape of `memory_size` 8-bit wrapping cells, where moving the memory pointer off the tape is
+/// an error. Its program reads the input pushed with [`bfint_push_input`] and its output is taken with
+/// [`bfint_take_output`]. Returns null if `memory_size` is 0 or the tape cannot be allocated
+#[no_mangle]
+pub extern "C" fn bfint_new(memory_size: usize) -> *mut BfintInterpreter {
+    // Allocating the tape in the machine aborts on failure, so check first that it can be allocated
+    if memory_size == 0 || Vec::<u64>::new().try_reserve_exact(memory_size).is_err() {
+        return std::ptr::null_mut();
+    }
+    let output = SharedBuffer::new();
+    let settings = Settings::builder()
+        .memory_size(memory_size)
+        .memory_overflow_behavior(MemoryOverflowBehavior::Error)
+        .output(output.clone())
+        .build();
+    let interpreter = Interpreter::with_vm_settings(settings).with_input_queue();
+    Box::into_raw(Box::new(BfintInterpreter { interpreter, output, error: CString::default() }))
+}
+
+/// Destroy an interpreter created by [`bfint_new`]. Null is ignored
+///
+/// # Safety
+/// `handle` must be null or returned by [`bfint_new`], and not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn bfint_free(handle: *mut BfintInterpreter) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Compile the `len` bytes of source code at `source` and load the program, resetting the machine
+///
+/// # Safety
+/// `handle` must be a live interpreter and `source` point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn bfint_compile(handle: *mut BfintInterpreter, source: *const u8, len: usize) -> c_int {
+    let handle = &mut *handle;
+    match handle.interpreter.load_source(bytes(source, len)) {
+        Ok(()) => 0,
+        Err(err) => handle.fail(err),
+    }
+}
+
+/// Execute at most `budget` instructions, starting the program if it was not started, and return its state:
+/// `BFINT_FINISHED`, `BFINT_RUNNABLE`, `BFINT_WAITING` or `BFINT_ERROR`
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_run(handle: *mut BfintInterpreter, budget: u64) -> c_int {
+    let handle = &mut *handle;
+    match handle.interpreter.run_for(budget.try_into().unwrap_or(usize::MAX)) {
+        StepOutcome::Finished => BFINT_FINISHED,
+        StepOutcome::Runnable | StepOutcome::Paused => BFINT_RUNNABLE,
+        StepOutcome::WaitingForInput => BFINT_WAITING,
+        StepOutcome::Error(err) => handle.fail(err),
+    }
+}
+
+/// Execute a single instruction, as [`bfint_run`] does
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_step(handle: *mut BfintInterpreter) -> c_int {
+    bfint_run(handle, 1)
+}
+
+/// Append the `len` bytes at `bytes` to the input of the program
+///
+/// # Safety
+/// `handle` must be a live interpreter and `bytes` point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn bfint_push_input(handle: *mut BfintInterpreter, bytes: *const u8, len: usize) {
+    (*handle).interpreter.provide_input(self::bytes(bytes, len));
+}
+
+/// Signal that no more input will be pushed, so that the program reads EOF instead of waiting
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_close_input(handle: *mut BfintInterpreter) {
+    (*handle).interpreter.close_input();
+}
+
+/// Copy at most `capacity` bytes of the output not taken yet to `buffer`, and return how many were copied
+///
+/// # Safety
+/// `handle` must be a live interpreter and `buffer` point to `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn bfint_take_output(handle: *mut BfintInterpreter, buffer: *mut u8, capacity: usize) -> usize {
+    let handle = &mut *handle;
+    let mut pending = handle.output.take();
+    let len = pending.len().min(capacity);
+    if len > 0 {
+        std::ptr::copy_nonoverlapping(pending.as_ptr(), buffer, len);
+    }
+    // The buffer was drained, so the bytes that did not fit are put back in front of the next output
+    pending.drain(..len);
+    handle.output.write_all(&pending).expect("Writing to a shared buffer cannot fail");
+    len
+}
+
+/// Store the value of the cell at `addr`, relative to the initial position of the memory pointer, in `value`. Fails
+/// if the cell is outside the tape
+///
+/// # Safety
+/// `handle` must be a live interpreter and `value` point to a writable `uint64_t`
+#[no_mangle]
+pub unsafe extern "C" fn bfint_peek(handle: *mut BfintInterpreter, addr: isize, value: *mut u64) -> c_int {
+    let handle = &mut *handle;
+    match handle.interpreter.vm().peek(addr) {
+        Some(cell) => {
+            *value = cell;
+            0
+        }
+        None => handle.fail(format!("Cell {} is outside the tape", addr)),
+    }
+}
+
+/// Set the cell at `addr` to `value`, truncated to the cell width. Fails if the cell is outside the tape
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_poke(handle: *mut BfintInterpreter, addr: isize, value: u64) -> c_int {
+    let handle = &mut *handle;
+    match handle.interpreter.vm_mut().poke(addr, value) {
+        true => 0,
+        false => handle.fail(format!("Cell {} is outside the tape", addr)),
+    }
+}
+
+/// Address of the next instruction
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_pc(handle: *const BfintInterpreter) -> usize {
+    (*handle).interpreter.vm().pc()
+}
+
+/// Address of the cell under the memory pointer, relative to its initial position
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_address(handle: *const BfintInterpreter) -> isize {
+    (*handle).interpreter.vm().address()
+}
+
+/// Number of instructions executed since the program started
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_executed(handle: *const BfintInterpreter) -> u64 {
+    (*handle).interpreter.vm().executed()
+}
+
+/// Message of the last error, with its source location if known, as a NUL-terminated string owned by the
+/// interpreter. It is empty if nothing failed yet, and valid until the next call failing or freeing the interpreter
+///
+/// # Safety
+/// `handle` must be a live interpreter
+#[no_mangle]
+pub unsafe extern "C" fn bfint_last_error(handle: *const BfintInterpreter) -> *const c_char {
+    (*handle).error.as_ptr()
+}
+
+/// The `len` bytes at `data`, which may be null when there are none
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    match len {
+        0 => &[],
+        len => std::slice::from_raw_parts(data, len),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn run_through_ffi() {
+        unsafe {
+            let handle = bfint_new(8);
+            let source = b",[.,]";
+            assert_eq!(bfint_compile(handle, b"[".as_ptr(), 1), BFINT_ERROR);
+            assert_eq!(CStr::from_ptr(bfint_last_error(handle)).to_str(), Ok("Unmatched '[' at line 1, column 1"));
+            assert_eq!(bfint_compile(handle, source.as_ptr(), source.len()), 0);
+            assert_eq!(bfint_run(handle, 100), BFINT_WAITING);
+            bfint_push_input(handle, b"hi".as_ptr(), 2);
+            assert_eq!(bfint_step(handle), BFINT_RUNNABLE);
+            let mut value = 0;
+            assert_eq!((bfint_peek(handle, 0, &mut value), value), (0, b'h' as u64));
+            assert_eq!(bfint_peek(handle, 8, &mut value), BFINT_ERROR);
+            assert_eq!(bfint_poke(handle, 0, b'H' as u64), 0);
+            assert_eq!(bfint_run(handle, 100), BFINT_WAITING);
+            bfint_close_input(handle);
+            assert_eq!(bfint_run(handle, 100), BFINT_FINISHED);
+            let mut output = [0; 8];
+            assert_eq!(bfint_take_output(handle, output.as_mut_ptr(), 1), 1);
+            assert_eq!(bfint_take_output(handle, output[1..].as_mut_ptr(), 7), 1);
+            assert_eq!(&output[..2], b"Hi");
+            assert_eq!(bfint_take_output(handle, output.as_mut_ptr(), 8), 0);
+            assert_eq!((bfint_pc(handle), bfint_address(handle), bfint_executed(handle)), (6, 0, 10));
+            bfint_free(handle);
+        }
+    }
+
+    #[test]
+    fn drain_output() {
+        unsafe {
+            let handle = bfint_new(8);
+            let source = b",[.,]";
+            assert_eq!(bfint_compile(handle, source.as_ptr(), source.len()), 0);
+            bfint_push_input(handle, b"abc".as_ptr(), 3);
+            assert_eq!(bfint_run(handle, 100), BFINT_WAITING);
+            let mut output = [0; 8];
+            assert_eq!(bfint_take_output(handle, output.as_mut_ptr(), 2), 2);
+            assert_eq!(&output[..2], b"ab");
+            bfint_push_input(handle, b"d".as_ptr(), 1);
+            assert_eq!(bfint_run(handle, 100), BFINT_WAITING);
+            assert_eq!(bfint_take_output(handle, output.as_mut_ptr(), 8), 2);
+            assert_eq!(&output[..2], b"cd");
+            assert!((*handle).output.contents().is_empty());
+            bfint_free(handle);
+        }
+    }
+
+    #[test]
+    fn reject_impossible_tapes() {
+        assert!(bfint_new(0).is_null());
+        assert!(bfint_new(usize::MAX).is_null());
+        assert!(bfint_new(usize::MAX / 16).is_null());
+    }
+
+    /// Every exported function is declared in the header
+    #[test]
+    fn header_is_complete() {
+        let header = include_str!("../../include/bfint.h");
+        let source = include_str!("mod.rs");
+        let exported: Vec<&str> = source.split("extern \"C\" fn ").skip(1)
+            .filter_map(|rest| rest.split('(').next())
+            .collect();
+        assert!(exported.len() > 10);
+        for function in exported {
+            assert!(header.contains(&format!("{}(", function)), "{} is missing from include/bfint.h", function);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::future::{poll_fn, Future};
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::ui::SharedBuffer;
+
+/// Default number of instructions executed between two yields to the executor
+pub const DEFAULT_SLICE: usize = 4096;
+
+/// Source of bytes that can be read without blocking the thread. The method has the signature of `poll_read` in the
+/// `AsyncRead` trait of the futures crate, so that its readers, and those of tokio through its compatibility layer, are
+/// adapted by forwarding to it
+pub trait AsyncRead {
+    /// Read into `buf`, returning the number of bytes read, 0 at the end of the input, or `Poll::Pending` after
+    /// arranging for the task to be woken up when bytes are available
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+/// Sink of bytes that can be written without blocking the thread, with the methods of `AsyncWrite` in the futures
+/// crate, see [`AsyncRead`]
+pub trait AsyncWrite {
+    /// Write some of `buf`, returning the number of bytes written
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>>;
+
+    /// Write out the bytes buffered so far
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// Input and output of a program run with
+/// [`Interpreter::run_async`](super::interpreter::Interpreter::run_async), replacing those of its
+/// [`Settings`](super::virtualmachine::Settings)
+pub struct AsyncSettings {
+    pub input: Pin<Box<dyn AsyncRead>>,
+    pub output: Pin<Box<dyn AsyncWrite>>,
+    /// Number of instructions executed between two yields to the executor, so that a long computation does not starve
+    /// the other tasks
+    pub slice: usize,
+}
+
+/* AsyncSettings ******************************************************************************************************/
+impl AsyncSettings {
+    /// Settings reading from `input` and writing to `output`, yielding every [`DEFAULT_SLICE`] instructions
+    pub fn new(input: impl AsyncRead + 'static, output: impl AsyncWrite + 'static) -> AsyncSettings {
+        AsyncSettings { input: Box::pin(input), output: Box::pin(output), slice: DEFAULT_SLICE }
+    }
+
+    /// Read some bytes of input into `buf`, returning how many were read, 0 at the end of the input
+    pub(super) async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.input.as_mut().poll_read(cx, buf)).await
+    }
+
+    /// Write all of `bytes` to the output and flush it
+    pub(super) async fn write_all(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match poll_fn(|cx| self.output.as_mut().poll_write(cx, bytes)).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                written => bytes = &bytes[written..],
+            }
+        }
+        poll_fn(|cx| self.output.as_mut().poll_flush(cx)).await
+    }
+}
+
+/* SharedBuffer *******************************************************************************************************/
+impl AsyncWrite for SharedBuffer {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Let the executor run other tasks before continuing
+pub(super) fn yield_now() -> impl Future<Output = ()> {
+    let mut yielded = false;
+    poll_fn(move |cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+}
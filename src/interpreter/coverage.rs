@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::parse::program::{Program, SourceLoc};
+use crate::parse::token::Token;
+use super::profile::Profile;
+
+/* Profile ************************************************************************************************************/
+impl Profile {
+    /// Whether the instruction at `pc` was executed at least once
+    pub fn covered(&self, pc: usize) -> bool {
+        self.count(pc) > 0
+    }
+
+    /// Addresses of the instructions of `program` that were never executed
+    pub fn unreached(&self, program: &Program) -> Vec<usize> {
+        (0..program.len()).filter(|&pc| !self.covered(pc)).collect()
+    }
+
+    /// Percentage of the instructions of `program` that were executed at least once
+    pub fn coverage(&self, program: &Program) -> f64 {
+        match program.len() {
+            0 => 100.0,
+            len => 100.0 * (len - self.unreached(program).len()) as f64 / len as f64,
+        }
+    }
+
+    /// Quote `source` with a caret under every command that was never reached. `tokens` are the commands of the
+    /// source, from which `program` was compiled. A command that the optimizer merged into a previous instruction,
+    /// such as the second `+` of `++`, is reached when that instruction is. The same holds for code that the optimizer
+    /// removed, so dead code is only marked in programs compiled without optimizations
+    pub fn annotate(&self, program: &Program, tokens: &[Token], source: &str) -> String {
+        let mut reached: BTreeMap<SourceLoc, bool> = BTreeMap::new();
+        for pc in 0..program.len() {
+            if let Some(loc) = program.location(pc) {
+                *reached.entry(loc).or_insert(false) |= self.covered(pc);
+            }
+        }
+        let mut unreached: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for token in tokens {
+            let loc = SourceLoc { row: token.row(), col: token.col() };
+            if !reached.range(..=loc).next_back().is_some_and(|(_, &reached)| reached) {
+                unreached.entry(loc.row).or_default().push(loc.col);
+            }
+        }
+        let gutter = source.lines().count().to_string().len();
+        let mut listing = String::new();
+        for (row, line) in source.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            writeln!(listing, "{:>2$} | {}", row, line, gutter).unwrap();
+            if let Some(cols) = unreached.get(&row) {
+                // Keep tabs so that the carets line up with the quoted line
+                let carets: String = line.chars()
+                    .take(cols[cols.len() - 1])
+                    .enumerate()
+                    .map(|(i, c)| match c {
+                        _ if cols.contains(&(i + 1)) => '^',
+                        '\t' => '\t',
+                        _ => ' ',
+                    })
+                    .collect();
+                writeln!(listing, "{:>2$} | {}", "", carets, gutter).unwrap();
+            }
+        }
+        listing
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::optimizer::{OptimizationLevel, PassManager};
+    use crate::parse::token::{Token, Tokenizer};
+
+    #[test]
+    fn annotate_unreached() {
+        let source = "++[>+<-]\n[\t.]>[-]\n+>";
+        let mut interpreter = Interpreter::new();
+        interpreter.set_passes(PassManager::with_level(OptimizationLevel::O1, interpreter.vm_settings()));
+        interpreter.set_profiling(true);
+        interpreter.load_source(source.as_bytes()).unwrap();
+        interpreter.run().unwrap();
+        let profile = interpreter.profile().expect("Profiling is enabled");
+        let program = interpreter.program();
+        assert!(profile.coverage(program) < 100.0);
+        let tokens: Vec<Token> = Tokenizer::read(source.as_bytes()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(profile.annotate(program, &tokens, source), "\
+            1 | ++[>+<-]\n\
+            2 | [\t.]>[-]\n\
+            \x20 |  \t^^\n\
+            3 | +>\n\
+        ");
+    }
+}
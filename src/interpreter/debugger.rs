@@ -0,0 +1,232 @@
+use std::error::Error;
+
+use crate::parse::program::Instruction;
+use super::interpreter::Interpreter;
+use super::journal::{Delta, Journal, ProcedureChange};
+use super::virtualmachine::Status;
+
+/// Wraps an [`Interpreter`] to execute its program step by step and stop at breakpoints
+pub struct Debugger {
+    interpreter: Interpreter,
+    journal: Journal,
+}
+
+/// Number of instructions that can be undone by default
+pub const DEFAULT_HISTORY: usize = 1 << 20;
+
+/// Why execution stopped after [`Debugger::resume`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint, the instruction has not been executed yet
+    Breakpoint(usize),
+    /// The program exited
+    Exited,
+    /// No input was available without blocking, the input command will be executed again
+    WaitingForInput,
+    /// Reverse execution reached the oldest recorded instruction
+    HistoryStart,
+}
+
+/* Debugger ***********************************************************************************************************/
+impl Debugger {
+    /// Create a Debugger for the program loaded in `interpreter`
+    pub fn new(interpreter: Interpreter) -> Debugger {
+        Debugger::with_history(interpreter, DEFAULT_HISTORY)
+    }
+
+    /// Create a Debugger able to undo the last `history` instructions
+    pub fn with_history(interpreter: Interpreter, history: usize) -> Debugger {
+        Debugger {
+            interpreter,
+            journal: Journal::with_capacity(history),
+        }
+    }
+
+    /// Get the wrapped interpreter
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Get the wrapped interpreter mutably
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Set a breakpoint at instruction address `pc`. Returns false if it was already set
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.interpreter.add_breakpoint(pc)
+    }
+
+    /// Remove the breakpoint at instruction address `pc`. Returns false if there was none
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.interpreter.remove_breakpoint(pc)
+    }
+
+    /// Whether the program has exited
+    pub fn has_exited(&self) -> bool {
+        self.interpreter.vm().pc() >= self.interpreter.program().len()
+    }
+
+    /// Instruction that will be executed next, if the program has not exited
+    pub fn current_instruction(&self) -> Option<&Instruction> {
+        self.interpreter.program().instructions().get(self.interpreter.vm().pc())
+    }
+
+    /// Execute a single instruction, starting the program if needed. Breakpoints are ignored
+    pub fn step(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.has_exited() {
+            return Err("Program has exited".into());
+        }
+        match self.interpreter.status() {
+            Status::Idle => {
+                self.interpreter.startup()?;
+                if self.interpreter.status() == Status::Paused {
+                    self.interpreter.vm_mut().resume()?;
+                }
+            }
+            Status::Paused | Status::WaitingForInput => self.interpreter.vm_mut().resume()?,
+            Status::Running => {}
+            Status::Finished => return Err("Program has exited".into()),
+            Status::Halted(message) => return Err(message.into()),
+        }
+        self.step_recorded()
+    }
+
+    /// Undo the last executed instruction. Cells, memory pointer, program counter, procedures and storage register are
+    /// restored, input and output are not. Returns false if there is nothing to undo
+    pub fn step_back(&mut self) -> bool {
+        self.journal.undo(self.interpreter.vm_mut()).is_some()
+    }
+
+    /// Undo instructions until a breakpoint is reached or there is nothing left to undo
+    pub fn reverse_resume(&mut self) -> StopReason {
+        while let Some(delta) = self.journal.undo(self.interpreter.vm_mut()) {
+            if self.interpreter.vm().breakpoints().any(|pc| pc == delta.pc) {
+                return StopReason::Breakpoint(delta.pc);
+            }
+        }
+        StopReason::HistoryStart
+    }
+
+    /// Number of instructions that can currently be undone
+    pub fn history_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Execute instructions until a breakpoint is reached or the program exits. The instruction under the current
+    /// program counter is always executed, so resuming from a breakpoint does not stop immediately
+    pub fn resume(&mut self) -> Result<StopReason, Box<dyn Error>> {
+        self.step()?;
+        while self.interpreter.status() == Status::Running {
+            self.step_recorded()?;
+        }
+        match self.interpreter.status() {
+            Status::Paused => Ok(StopReason::Breakpoint(self.interpreter.vm().pc())),
+            Status::WaitingForInput => Ok(StopReason::WaitingForInput),
+            _ => Ok(StopReason::Exited),
+        }
+    }
+
+    fn step_recorded(&mut self) -> Result<(), Box<dyn Error>> {
+        let vm = self.interpreter.vm();
+        let (pc, address) = (vm.pc(), vm.address());
+        let (procedures, call_stack, storage) = vm.procedures();
+        let procedure = match self.current_instruction() {
+            Some(Instruction::CallProc) => ProcedureChange::Call,
+            Some(Instruction::Return) => call_stack.last().map_or(ProcedureChange::None, |&addr| {
+                ProcedureChange::Return(addr)
+            }),
+            Some(Instruction::DefineProc(_)) => {
+                let id = vm.mem_rd();
+                ProcedureChange::Define(id, procedures.get(&id).copied())
+            }
+            _ => ProcedureChange::None,
+        };
+        self.interpreter.step()?;
+        let write = self.interpreter.vm().last_write();
+        self.journal.record(Delta { pc, address, write, storage, procedure });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::token::Syntax;
+
+    #[test]
+    fn stop_at_breakpoints() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet("++[>+<-]").expect("Could not load snippet");
+        let mut debugger = Debugger::new(interpreter);
+        debugger.add_breakpoint(4);
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(4));
+        assert_eq!(debugger.interpreter().vm().peek(1), Some(0));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(4));
+        assert_eq!(debugger.interpreter().vm().peek(1), Some(1));
+        assert!(debugger.remove_breakpoint(4));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Exited);
+        assert!(debugger.step().is_err());
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet("++[>+<-]").expect("Could not load snippet");
+        let mut debugger = Debugger::new(interpreter);
+        debugger.add_breakpoint(4);
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(4));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(4));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Exited);
+        assert!(debugger.step_back());
+        assert_eq!(debugger.interpreter().vm().pc(), 8);
+        assert_eq!(debugger.reverse_resume(), StopReason::Breakpoint(4));
+        assert_eq!(debugger.interpreter().vm().peek(0), Some(1));
+        assert_eq!(debugger.interpreter().vm().peek(1), Some(1));
+        assert_eq!(debugger.reverse_resume(), StopReason::Breakpoint(4));
+        assert_eq!(debugger.interpreter().vm().peek(0), Some(2));
+        assert_eq!(debugger.interpreter().vm().peek(1), Some(0));
+        assert_eq!(debugger.interpreter().vm().address(), 1);
+        assert_eq!(debugger.reverse_resume(), StopReason::HistoryStart);
+        assert_eq!(debugger.interpreter().vm().peek(0), Some(0));
+        assert_eq!(debugger.interpreter().vm().pc(), 0);
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(4));
+    }
+
+    #[test]
+    fn reverse_procedures() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_syntax(Syntax { procedures: true, extended: true, ..Syntax::default() });
+        // Procedure 0 stores 2 and returns, procedure 1 calls it
+        interpreter.load_snippet("(++$--)+(-:+):").expect("Could not load snippet");
+        let mut debugger = Debugger::new(interpreter);
+        let program: Vec<Instruction> = debugger.interpreter().program().instructions().to_vec();
+        let returns: Vec<usize> = (0..program.len()).filter(|&pc| program[pc] == Instruction::Return).collect();
+        debugger.add_breakpoint(returns[0]);
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(returns[0]));
+        let state = |debugger: &Debugger| {
+            let vm = debugger.interpreter().vm();
+            let (procedures, call_stack, storage) = vm.procedures();
+            (procedures.len(), call_stack.to_vec(), storage, vm.pc())
+        };
+        let before = state(&debugger);
+        // Step over the returns of procedure 0 and of procedure 1, back, and again
+        for _ in 0..3 {
+            debugger.step().expect("Error while running");
+        }
+        let after = state(&debugger);
+        assert_eq!((after.1.len(), after.2), (0, 2));
+        assert!(debugger.step_back() && debugger.step_back() && debugger.step_back());
+        assert_eq!(state(&debugger), before);
+        assert_eq!(debugger.reverse_resume(), StopReason::HistoryStart);
+        assert_eq!(state(&debugger), (0, vec![], 0, 0));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Breakpoint(returns[0]));
+        assert_eq!(state(&debugger), before);
+        for _ in 0..3 {
+            debugger.step().expect("Error while running");
+        }
+        assert_eq!(state(&debugger), after);
+        assert!(debugger.remove_breakpoint(returns[0]));
+        assert_eq!(debugger.resume().expect("Error while running"), StopReason::Exited);
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::testing::random::Rng;
+use super::time;
+use super::virtualmachine::{Settings, VirtualMachine};
+
+/// Cell of the tape with a behavior of its own, mapped at an address with
+/// [`VirtualMachine::map_device`]. The device is consulted whenever the program reads the cell with
+/// [`VirtualMachine::mem_rd`], as loops and output do, and after every instruction that writes it. Values read are
+/// truncated to the cell width
+pub trait Device {
+    /// Value the program reads from the cell, which holds `stored`, the value last written to it
+    fn read(&mut self, stored: u64) -> u64 {
+        stored
+    }
+
+    /// React to the program writing `value` to the cell of the machine `vm`, whose address is in
+    /// [`VirtualMachine::last_write`]. The device can write to the output of the machine and read its memory, but must
+    /// read its own cell with [`VirtualMachine::peek`] rather than `mem_rd`. Errors stop the program
+    fn write(&mut self, _value: u64, _vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Do work that is due without waiting for the program to access the cell, such as drawing a frame, every
+    /// [`TICK_INSTRUCTIONS`](super::virtualmachine::TICK_INSTRUCTIONS) instructions. Errors stop the program
+    fn tick(&mut self, _vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Prepare for the program reading input, which may block until its user answers to what the machine shows, as
+    /// [`Device::tick`] does
+    fn before_input(&mut self, _vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Device yielding a new pseudo-random number on every read. The same seed always gives the same numbers. Clones
+/// share the sequence, so that every random device of a machine draws from the one in its
+/// [`Settings::random`](super::virtualmachine::Settings::random), and a seeded run is reproducible as a whole
+#[derive(Clone, Debug)]
+pub struct Random {
+    rng: Rc<RefCell<Rng>>,
+}
+
+/// Device reading as the number of seconds since the Unix epoch, or 0 on targets without a clock
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Clock;
+
+/// Device flushing the output of the machine whenever it is written, for programs that control when their output is
+/// shown, such as with `--flush manual`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Flush;
+
+/// Kind of the devices built into bfint, as named on the command line
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Random,
+    Clock,
+    Flush,
+}
+
+/* Random *************************************************************************************************************/
+impl Random {
+    /// Device reading the numbers determined by `seed`
+    pub fn new(seed: u64) -> Random {
+        Random { rng: Rc::new(RefCell::new(Rng::new(seed))) }
+    }
+
+    /// Device seeded differently on every run
+    pub fn from_entropy() -> Random {
+        Random::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// Next number of the sequence
+    pub fn next_u64(&self) -> u64 {
+        self.rng.borrow_mut().next_u64()
+    }
+}
+
+impl Device for Random {
+    fn read(&mut self, _stored: u64) -> u64 {
+        self.next_u64()
+    }
+}
+
+/* Clock **************************************************************************************************************/
+impl Device for Clock {
+    fn read(&mut self, _stored: u64) -> u64 {
+        time::since_epoch().as_secs()
+    }
+}
+
+/* Flush **************************************************************************************************************/
+impl Device for Flush {
+    fn write(&mut self, _value: u64, vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+        Ok(vm.flush()?)
+    }
+}
+
+/* DeviceKind *********************************************************************************************************/
+impl DeviceKind {
+    /// New device of this kind for a machine with `settings`. Random devices draw from
+    /// [`Settings::random`](super::virtualmachine::Settings::random)
+    pub fn create(self, settings: &Settings) -> Box<dyn Device> {
+        match self {
+            DeviceKind::Random => Box::new(settings.random.clone()),
+            DeviceKind::Clock => Box::new(Clock),
+            DeviceKind::Flush => Box::new(Flush),
+        }
+    }
+}
+
+impl FromStr for DeviceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(DeviceKind::Random),
+            "clock" => Ok(DeviceKind::Clock),
+            "flush" => Ok(DeviceKind::Flush),
+            _ => Err(format!("Invalid device: '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::virtualmachine::TICK_INSTRUCTIONS;
+    use crate::optimizer::{OptimizationLevel, PassManager};
+    use crate::ui::SharedBuffer;
+
+    #[test]
+    fn read_devices() {
+        let mut interpreter = Interpreter::new();
+        interpreter.vm_mut().map_device(1, Box::new(Random::new(7)));
+        interpreter.vm_mut().map_device(2, Box::new(Clock));
+        interpreter.load_source(b">.>.<+++.").unwrap();
+        let before = Clock.read(0) as u8;
+        let output = interpreter.run_with_io(b"").unwrap();
+        let after = Clock.read(0) as u8;
+        let mut random = Rng::new(7);
+        assert_eq!(output[0], random.next_u64() as u8);
+        assert!(output[1] == before || output[1] == after, "Read {} as the time", output[1]);
+        // The value written is kept, but never read
+        assert_eq!(output[2], random.next_u64() as u8);
+        assert_eq!(interpreter.vm().peek(1), Some(3));
+    }
+
+    #[test]
+    fn seeded_runs() {
+        let run = || {
+            let mut interpreter = Interpreter::with_vm_settings(Settings::builder().seed(3).build());
+            for addr in [0, 1] {
+                let device = DeviceKind::Random.create(interpreter.vm().settings());
+                interpreter.vm_mut().map_device(addr, device);
+            }
+            interpreter.load_source(b".>.<.").unwrap();
+            interpreter.run_with_io(b"").unwrap()
+        };
+        let mut rng = Rng::new(3);
+        let expected: Vec<u8> = (0..3).map(|_| rng.next_u64() as u8).collect();
+        // Both devices draw from the same sequence
+        assert_eq!(run(), expected);
+        assert_eq!(run(), expected);
+    }
+
+    #[test]
+    fn not_optimized() {
+        let run = |level| {
+            let mut interpreter = Interpreter::with_vm_settings(Settings::builder().seed(5).build());
+            let device = DeviceKind::Random.create(interpreter.vm().settings());
+            interpreter.vm_mut().map_device(1, device);
+            interpreter.set_passes(PassManager::for_machine(level, interpreter.vm()));
+            interpreter.load_source(b">[[-]<+>]<.").unwrap();
+            interpreter.run_with_io(b"").unwrap()
+        };
+        // The loop runs for as long as the random cell reads non-zero
+        assert_eq!(run(OptimizationLevel::O2), run(OptimizationLevel::O0));
+        assert_eq!(run(OptimizationLevel::O1), run(OptimizationLevel::O0));
+    }
+
+    /// Device counting the ticks and the reads of input in a shared cell
+    struct Ticks(Rc<std::cell::Cell<(u64, u64)>>);
+
+    impl Device for Ticks {
+        fn tick(&mut self, _vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+            let (ticks, inputs) = self.0.get();
+            self.0.set((ticks + 1, inputs));
+            Ok(())
+        }
+
+        fn before_input(&mut self, _vm: &mut VirtualMachine) -> Result<(), Box<dyn Error>> {
+            let (ticks, inputs) = self.0.get();
+            self.0.set((ticks, inputs + 1));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tick_devices() {
+        let counts = Rc::new(std::cell::Cell::new((0, 0)));
+        let mut interpreter = Interpreter::new();
+        interpreter.vm_mut().map_device(9, Box::new(Ticks(counts.clone())));
+        interpreter.load_source(b",,>-[>-[>+<-]<-]").unwrap();
+        interpreter.run_with_io(b"ab").unwrap();
+        let executed = interpreter.vm().executed();
+        assert!(executed > 2 * TICK_INSTRUCTIONS, "Executed {} instructions", executed);
+        assert_eq!(counts.get(), (executed / TICK_INSTRUCTIONS, 2));
+    }
+
+    #[test]
+    fn flush_on_write() {
+        let output = SharedBuffer::new();
+        let writer = std::io::BufWriter::new(output.clone());
+        let mut interpreter = Interpreter::with_vm_settings(Settings::builder().output(writer).build());
+        interpreter.vm_mut().map_device(2, Box::new(Flush));
+        interpreter.load_source(b"+++++++[>++++++++++<-]>.>+").unwrap();
+        interpreter.startup().unwrap();
+        while interpreter.vm().pc() < 25 {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(output.contents(), b"");
+        interpreter.step().unwrap();
+        assert_eq!(output.contents(), b"F");
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+
+use crate::optimizer::PassManager;
+use crate::parse::program::Program;
+use crate::ui::SharedBuffer;
+use super::interpreter::Interpreter;
+use super::virtualmachine::{InstructionLimitExceeded, Settings};
+
+/// Observable result of running a program: what it wrote, the tape it left and how it ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    /// Address and value of every cell that is not 0 at the end, from left to right. Addresses are relative to the
+    /// initial position of the memory pointer
+    pub cells: Vec<(isize, u64)>,
+    /// Final address of the memory pointer
+    pub mp: isize,
+    /// Error that stopped the program, if any
+    pub error: Option<String>,
+    /// Whether the error is the instruction limit, [`InstructionLimitExceeded`], rather than a failure of the program
+    pub exceeded_limit: bool,
+}
+
+/// Different outcomes of the same program run without and with optimizations, as found by [`compare`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub reference: Outcome,
+    pub optimized: Outcome,
+}
+
+/// Run `program` on a virtual machine configured by `settings`, reading `input` and collecting the output
+pub fn execute(program: &Program, mut settings: Settings, input: &[u8]) -> Outcome {
+    let output = SharedBuffer::new();
+    settings.input = Box::new(Cursor::new(input.to_vec()));
+    settings.output = Box::new(output.clone());
+    let mut interpreter = Interpreter::with_vm_settings(settings);
+    interpreter.load_program(program.clone());
+    let error = interpreter.run().and_then(|_| Ok(interpreter.flush()?)).err();
+    let exceeded_limit = error.as_deref().is_some_and(|err| {
+        std::iter::successors(Some(err), |&err| err.source()).any(|err| err.is::<InstructionLimitExceeded>())
+    });
+    let state = interpreter.vm().state();
+    let cells = state.memory
+        .iter()
+        .enumerate()
+        .filter(|&(_, &cell)| cell != 0)
+        .map(|(index, &cell)| (index as isize - state.origin as isize, cell))
+        .collect();
+    let error = error.map(|err| err.to_string());
+    Outcome { output: output.contents(), cells, mp: interpreter.vm().address(), error, exceeded_limit }
+}
+
+/// Run `program` as it is and after the optimization `passes`, each on a fresh virtual machine from `settings` reading
+/// the same `input`, and check that output, final tape and memory pointer agree, and that either both runs fail or
+/// neither does. Error messages are not compared, since they mention instruction addresses
+pub fn compare<F: Fn() -> Settings>(
+    program: &Program, passes: &PassManager, settings: F, input: &[u8],
+) -> Result<Outcome, Box<Divergence>> {
+    let reference = execute(program, settings(), input);
+    let optimized = execute(&passes.run(program), settings(), input);
+    let agree = reference.output == optimized.output
+        && reference.cells == optimized.cells
+        && reference.mp == optimized.mp
+        && reference.error.is_some() == optimized.error.is_some();
+    match agree {
+        true => Ok(reference),
+        false => Err(Box::new(Divergence { reference, optimized })),
+    }
+}
+
+/* Divergence *********************************************************************************************************/
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (reference, optimized) = (&self.reference, &self.optimized);
+        if reference.error.is_some() != optimized.error.is_some() {
+            let describe = |error: &Option<String>| match error {
+                Some(err) => format!("fails with '{}'", err),
+                None => String::from("exits normally"),
+            };
+            return write!(
+                f, "The optimized program {}, while the original one {}", describe(&optimized.error),
+                describe(&reference.error),
+            );
+        }
+        if reference.output != optimized.output {
+            let (expected, actual) = (&reference.output, &optimized.output);
+            let common = expected.iter().zip(actual).take_while(|(a, b)| a == b).count();
+            return match (expected.get(common), actual.get(common)) {
+                (Some(expected), Some(actual)) => write!(
+                    f, "Output byte {} is {} in the optimized program instead of {}", common, actual, expected,
+                ),
+                _ => write!(f, "The optimized program writes {} bytes instead of {}", actual.len(), expected.len()),
+            };
+        }
+        if let Some((addr, expected, actual)) = first_difference(&reference.cells, &optimized.cells) {
+            return write!(f, "Cell {} ends as {} in the optimized program instead of {}", addr, actual, expected);
+        }
+        write!(f, "Memory pointer ends at {} in the optimized program instead of {}", optimized.mp, reference.mp)
+    }
+}
+
+impl Error for Divergence {}
+
+/// Leftmost address whose cell differs between two sparse tapes, with the values of the cell in each of them
+fn first_difference(expected: &[(isize, u64)], actual: &[(isize, u64)]) -> Option<(isize, u64, u64)> {
+    let value = |cells: &[(isize, u64)], addr: isize| cells.iter().find(|&&(a, _)| a == addr).map_or(0, |&(_, v)| v);
+    expected.iter()
+        .chain(actual)
+        .map(|&(addr, _)| addr)
+        .filter(|&addr| value(expected, addr) != value(actual, addr))
+        .min()
+        .map(|addr| (addr, value(expected, addr), value(actual, addr)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::MemoryOverflowBehavior;
+    use crate::optimizer::{OptimizationLevel, Pass};
+    use crate::parse::program::Instruction;
+
+    /// Wrong optimization: doubles every increment
+    struct DoubleIncrements;
+
+    impl Pass for DoubleIncrements {
+        fn name(&self) -> &str {
+            "double-increments"
+        }
+
+        fn run(&self, program: &Program) -> Program {
+            let instructions = program.instructions()
+                .iter()
+                .map(|&instruction| if instruction == Instruction::IncData { Instruction::Add(2) } else { instruction })
+                .collect();
+            Program::from_instructions(instructions).unwrap()
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            memory_size: 64,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn compare_engines() {
+        let program = Program::compile(",[>+++<-]>[>++<-]>>+.<<<<-[-]".as_bytes()).unwrap();
+        let passes = PassManager::with_level(OptimizationLevel::O2, &settings());
+        let outcome = compare(&program, &passes, settings, b"\x05").expect("The optimizer changed the outcome");
+        assert_eq!((outcome.output, outcome.cells, outcome.mp), (vec![1], vec![(2, 30), (3, 1)], -1));
+        let passes = PassManager::new().with_pass(DoubleIncrements);
+        let divergence = compare(&program, &passes, settings, b"\x05").expect_err("The pass is wrong");
+        assert_eq!(divergence.to_string(), "Output byte 0 is 2 in the optimized program instead of 1");
+        let program = Program::compile("+>+".as_bytes()).unwrap();
+        let divergence = compare(&program, &passes, settings, b"").expect_err("The pass is wrong");
+        assert_eq!(divergence.to_string(), "Cell 0 ends as 2 in the optimized program instead of 1");
+    }
+
+    #[test]
+    fn left_edge_of_the_tape() {
+        for memory_overflow_behavior in [MemoryOverflowBehavior::Unchecked, MemoryOverflowBehavior::Extend] {
+            let settings = || Settings { memory_overflow_behavior, ..settings() };
+            let passes = PassManager::with_level(OptimizationLevel::O2, &settings());
+            for source in ["[-<+>]+.", ">[-<<+>>]<[<+>-]+."] {
+                let program = Program::compile(source.as_bytes()).unwrap();
+                let outcome = compare(&program, &passes, settings, b"").expect("The optimizer changed the outcome");
+                assert_eq!(outcome.error, None);
+            }
+        }
+    }
+
+    #[test]
+    fn exceed_instruction_limit() {
+        let limited = || Settings { max_instructions: Some(100), ..settings() };
+        let outcome = execute(&Program::compile("+[]".as_bytes()).unwrap(), limited(), b"");
+        assert!(outcome.exceeded_limit && outcome.error.is_some());
+        let checked = Settings { memory_overflow_behavior: MemoryOverflowBehavior::Error, ..limited() };
+        let outcome = execute(&Program::compile("<".as_bytes()).unwrap(), checked, b"");
+        assert!(!outcome.exceeded_limit && outcome.error.is_some());
+    }
+}
@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::parse::program::Program;
+use super::threaded::Threaded;
+use super::virtualmachine::{Status, VirtualMachine};
+
+/// Strategy used by the [`Interpreter`](super::interpreter::Interpreter) to execute a [`Program`] on a
+/// [`VirtualMachine`], see [`Interpreter::with_engine`](super::interpreter::Interpreter::with_engine). Engines only
+/// execute instructions: the interpreter takes care of hooks, profiling and threads, and only calls
+/// [`Engine::run`] when none of them needs to see each instruction
+pub trait Engine {
+    /// Short name of the engine, used in diagnostics
+    fn name(&self) -> &str;
+
+    /// Execute the instruction of `program` at the program counter of `vm`, which is running
+    fn step(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>>;
+
+    /// Execute instructions of `program` until `vm` stops running, because the program exited or reached a
+    /// breakpoint. On error, the program counter of `vm` must be the address of the failing instruction
+    fn run(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>> {
+        while *vm.status() == Status::Running {
+            self.step(vm, program)?;
+        }
+        Ok(())
+    }
+}
+
+/// Engines built into the crate, which can be selected by name
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EngineKind {
+    /// [`Switch`]
+    Switch,
+    /// [`Threaded`]
+    Threaded,
+}
+
+/// Fetches one instruction at a time and dispatches it with a match on its kind, in
+/// [`VirtualMachine::execute_instruction`]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Switch;
+
+/* EngineKind *********************************************************************************************************/
+impl EngineKind {
+    /// Create an engine of this kind
+    pub fn create(self) -> Box<dyn Engine> {
+        match self {
+            EngineKind::Switch => Box::new(Switch),
+            EngineKind::Threaded => Box::new(Threaded),
+        }
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "switch" => Ok(EngineKind::Switch),
+            "threaded" => Ok(EngineKind::Threaded),
+            _ => Err(format!("Invalid engine: '{}'", s)),
+        }
+    }
+}
+
+/* Switch *************************************************************************************************************/
+impl Engine for Switch {
+    fn name(&self) -> &str {
+        "switch"
+    }
+
+    fn step(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>> {
+        vm.execute_instruction(program.instruction(vm.pc()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+
+    /// Counts the instructions it executes, and stops after a limit
+    struct Counting {
+        steps: Rc<Cell<u64>>,
+        limit: u64,
+    }
+
+    impl Engine for Counting {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn step(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>> {
+            if self.steps.get() == self.limit {
+                return Err("Too many steps".into());
+            }
+            self.steps.set(self.steps.get() + 1);
+            Switch.step(vm, program)
+        }
+    }
+
+    #[test]
+    fn custom_engine() {
+        assert_eq!("switch".parse::<EngineKind>().unwrap().create().name(), "switch");
+        let steps = Rc::new(Cell::new(0));
+        let engine = Counting { steps: steps.clone(), limit: 20 };
+        let mut interpreter = Interpreter::new().with_engine(Box::new(engine));
+        assert_eq!(interpreter.engine().name(), "counting");
+        interpreter.load_source(b"++[>+++<-]").unwrap();
+        interpreter.run().unwrap();
+        assert_eq!((steps.get(), interpreter.vm().peek(1)), (19, Some(6)));
+        steps.set(0);
+        interpreter.load_source(b"+++\n[>+++<-]").unwrap();
+        let err = interpreter.run().expect_err("The engine did not stop");
+        assert_eq!(err.to_string(), "Too many steps (line 2, column 2)");
+    }
+}
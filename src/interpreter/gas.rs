@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::parse::program::Instruction;
+use super::metrics::{opcode, KINDS};
+
+/// Cost of each kind of instruction, whatever its operands. Every instruction costs 1 by default, so that gas counts
+/// instructions like [`Settings::max_instructions`](super::virtualmachine::Settings::max_instructions) does. Costs
+/// apply to the instructions of the optimized program, so the same program costs less at higher optimization levels:
+/// compare costs at a fixed level, and build the passes with
+/// [`PassManager::for_machine`](crate::optimizer::PassManager::for_machine), which does not evaluate loops at compile
+/// time when gas is metered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CostModel {
+    costs: [u64; KINDS.len()],
+}
+
+/// Budget of gas, drawn from by every instruction a
+/// [`VirtualMachine`](super::virtualmachine::VirtualMachine) executes according to its cost, see
+/// [`VirtualMachine::set_gas`](super::virtualmachine::VirtualMachine::set_gas). Clones share the remaining gas, so that
+/// threads forked by a program draw from the same budget
+#[derive(Clone, Debug)]
+pub struct Gas {
+    model: CostModel,
+    budget: u64,
+    remaining: Rc<Cell<u64>>,
+}
+
+/* CostModel **********************************************************************************************************/
+impl CostModel {
+    /// Cost model where every instruction costs `cost`
+    pub fn uniform(cost: u64) -> CostModel {
+        CostModel { costs: [cost; KINDS.len()] }
+    }
+
+    /// Set the cost of the instructions of the same kind as `instruction`
+    pub fn with_cost(mut self, instruction: &Instruction, cost: u64) -> CostModel {
+        self.costs[opcode(instruction)] = cost;
+        self
+    }
+
+    /// Cost of executing `instruction`
+    pub fn cost(&self, instruction: &Instruction) -> u64 {
+        self.costs[opcode(instruction)]
+    }
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel::uniform(1)
+    }
+}
+
+/// Parse comma separated `mnemonic=cost` entries, as in `wr=10,rd=10`, on top of the default costs. The mnemonic `all`
+/// sets the cost of every instruction, and of the following entries only the instructions they name
+impl FromStr for CostModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut model = CostModel::default();
+        for entry in s.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (mnemonic, cost) = entry.split_once('=').ok_or_else(|| format!("Invalid cost: '{}'", entry))?;
+            let cost = cost.trim().parse().map_err(|_| format!("Invalid cost: '{}'", entry))?;
+            match mnemonic.trim() {
+                "all" => model = CostModel::uniform(cost),
+                mnemonic => {
+                    let kind = KINDS.iter()
+                        .find(|kind| kind.mnemonic() == mnemonic)
+                        .ok_or_else(|| format!("Invalid instruction: '{}'", mnemonic))?;
+                    model = model.with_cost(kind, cost);
+                }
+            }
+        }
+        Ok(model)
+    }
+}
+
+/* Gas ****************************************************************************************************************/
+impl Gas {
+    /// Budget of `budget` units of gas, spent according to `model`
+    pub fn new(budget: u64, model: CostModel) -> Gas {
+        Gas { model, budget, remaining: Rc::new(Cell::new(budget)) }
+    }
+
+    /// Gas left
+    pub fn remaining(&self) -> u64 {
+        self.remaining.get()
+    }
+
+    /// Gas spent so far
+    pub fn used(&self) -> u64 {
+        self.budget - self.remaining()
+    }
+
+    /// Initial budget
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Cost model the gas is spent with
+    pub fn model(&self) -> &CostModel {
+        &self.model
+    }
+
+    /// Spend the cost of `instruction`, or return it if there is not enough gas left, which is then left untouched
+    pub(super) fn spend(&self, instruction: &Instruction) -> Result<(), u64> {
+        let cost = self.model.cost(instruction);
+        match self.remaining().checked_sub(cost) {
+            Some(remaining) => {
+                self.remaining.set(remaining);
+                Ok(())
+            }
+            None => Err(cost),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::optimizer::{OptimizationLevel, PassManager};
+
+    #[test]
+    fn parse_costs() {
+        let model: CostModel = "all=2, wr=10,jnz=0".parse().unwrap();
+        assert_eq!(model.cost(&Instruction::Output), 10);
+        assert_eq!(model.cost(&Instruction::JNZ(3)), 0);
+        assert_eq!(model.cost(&Instruction::Add(-4)), 2);
+        assert_eq!("wr".parse::<CostModel>(), Err(String::from("Invalid cost: 'wr'")));
+        assert_eq!("out=1".parse::<CostModel>(), Err(String::from("Invalid instruction: 'out'")));
+    }
+
+    #[test]
+    fn run_out_of_gas() {
+        let mut interpreter = Interpreter::new();
+        let gas = Gas::new(20, CostModel::default().with_cost(&Instruction::Output, 5));
+        interpreter.vm_mut().set_gas(Some(gas.clone()));
+        interpreter.load_source(b"+++..").unwrap();
+        interpreter.run().unwrap();
+        assert_eq!((gas.used(), gas.remaining()), (14, 6));
+        interpreter.load_source(b"+[.]").unwrap();
+        let err = interpreter.run().expect_err("Ran out of gas").to_string();
+        assert_eq!(err, "Out of gas at pc 0x00000002 after 2 instructions: 5 needed, 4 left (line 1, column 3)");
+        assert_eq!(interpreter.vm().gas().map(Gas::remaining), Some(4));
+    }
+
+    #[test]
+    fn charge_optimized_programs() {
+        let used = |level| {
+            let mut interpreter = Interpreter::new();
+            let gas = Gas::new(1000, CostModel::default());
+            interpreter.vm_mut().set_gas(Some(gas.clone()));
+            interpreter.set_passes(PassManager::for_machine(level, interpreter.vm()));
+            interpreter.load_source(b"++++++++[>++++<-]>.").unwrap();
+            assert_eq!(interpreter.run_with_io(b"").unwrap(), b" ");
+            gas.used()
+        };
+        // Loops are not evaluated for free at compile time, but optimized instructions do less for the same cost
+        assert_eq!(used(OptimizationLevel::O0), 83);
+        assert_eq!(used(OptimizationLevel::O1), 52);
+        assert_eq!(used(OptimizationLevel::O2), 6);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use crate::parse::program::Instruction;
+
+/// Width of the longest bar of the text histogram
+const BAR_WIDTH: u64 = 50;
+
+/// Number of times each cell of the tape was read and written, collected by the
+/// [`Interpreter`](super::interpreter::Interpreter) when enabled. Cells are identified by their address relative to
+/// the initial position of the memory pointer, as returned by
+/// [`VirtualMachine::address`](super::virtualmachine::VirtualMachine::address)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Heatmap {
+    /// Reads and writes of consecutive cells, starting from `first`
+    cells: VecDeque<(u64, u64)>,
+    first: isize,
+}
+
+/* Heatmap ************************************************************************************************************/
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap::default()
+    }
+
+    /// Count the accesses of `instruction`, executed with the memory pointer at `mp`. `written` is the cell it wrote,
+    /// if any, as reported by [`VirtualMachine::last_write`](super::virtualmachine::VirtualMachine::last_write)
+    pub fn record(&mut self, instruction: &Instruction, mp: isize, written: Option<isize>) {
+        if reads_cell(instruction) {
+            self.cell(mp).0 += 1;
+        }
+        if let Instruction::MulAdd { offset, .. } | Instruction::AddAt { offset, .. } = *instruction {
+            self.cell(mp + offset).0 += 1;
+        }
+        if let Some(addr) = written {
+            self.cell(addr).1 += 1;
+        }
+    }
+
+    /// Number of reads of the cell at `addr`
+    pub fn reads(&self, addr: isize) -> u64 {
+        self.get(addr).0
+    }
+
+    /// Number of writes of the cell at `addr`
+    pub fn writes(&self, addr: isize) -> u64 {
+        self.get(addr).1
+    }
+
+    /// Address, reads and writes of every cell that was accessed, from left to right
+    pub fn cells(&self) -> impl Iterator<Item = (isize, u64, u64)> + '_ {
+        self.cells.iter()
+            .enumerate()
+            .map(|(i, &(reads, writes))| (self.first + i as isize, reads, writes))
+            .filter(|&(_, reads, writes)| reads + writes > 0)
+    }
+
+    /// Text histogram with a line per accessed cell, whose bar is proportional to its accesses
+    pub fn histogram(&self) -> String {
+        let max = self.cells().map(|(_, reads, writes)| reads + writes).max().unwrap_or(0);
+        let mut histogram = format!("{:>8} {:>12} {:>12}\n", "cell", "reads", "writes");
+        for (addr, reads, writes) in self.cells() {
+            let bar = "#".repeat(((reads + writes) * BAR_WIDTH).div_ceil(max) as usize);
+            writeln!(histogram, "{:>8} {:>12} {:>12} {}", addr, reads, writes, bar).unwrap();
+        }
+        histogram
+    }
+
+    /// Comma separated values with a header and a line per accessed cell
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("cell,reads,writes\n");
+        for (addr, reads, writes) in self.cells() {
+            writeln!(csv, "{},{},{}", addr, reads, writes).unwrap();
+        }
+        csv
+    }
+
+    fn get(&self, addr: isize) -> (u64, u64) {
+        usize::try_from(addr - self.first).ok().and_then(|i| self.cells.get(i)).copied().unwrap_or((0, 0))
+    }
+
+    /// Counters of the cell at `addr`, extending the heatmap as needed
+    fn cell(&mut self, addr: isize) -> &mut (u64, u64) {
+        if self.cells.is_empty() {
+            self.first = addr;
+        }
+        while addr < self.first {
+            self.cells.push_front((0, 0));
+            self.first -= 1;
+        }
+        let index = (addr - self.first) as usize;
+        if index >= self.cells.len() {
+            self.cells.resize(index + 1, (0, 0));
+        }
+        &mut self.cells[index]
+    }
+}
+
+/// Whether the outcome of `instruction` depends on the value of the cell under the memory pointer
+fn reads_cell(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::IncPtr | Instruction::DecPtr | Instruction::Move(_) | Instruction::Set(_) | Instruction::Input
+            | Instruction::AddAt { .. } | Instruction::SetAt { .. } | Instruction::Debug | Instruction::Return
+            | Instruction::Load | Instruction::Fork | Instruction::Exit
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::interpreter::Interpreter;
+
+    #[test]
+    fn count_accesses() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_heatmap(true);
+        interpreter.load_source(b"++[>+++<-]>>+").unwrap();
+        interpreter.run().unwrap();
+        let heatmap = interpreter.heatmap().expect("The heatmap is enabled");
+        assert_eq!(heatmap.cells().collect::<Vec<_>>(), [(0, 8, 4), (1, 6, 6), (2, 1, 1)]);
+        assert_eq!(heatmap.to_csv(), "cell,reads,writes\n0,8,4\n1,6,6\n2,1,1\n");
+        let histogram = heatmap.histogram();
+        let lines: Vec<&str> = histogram.lines().collect();
+        assert_eq!(lines[1], format!("{:>8} {:>12} {:>12} {}", 0, 8, 4, "#".repeat(50)));
+        assert_eq!(lines[3], format!("{:>8} {:>12} {:>12} {}", 2, 1, 1, "#".repeat(9)));
+    }
+}
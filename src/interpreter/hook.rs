@@ -0,0 +1,106 @@
+use std::error::Error;
+
+use crate::parse::program::Instruction;
+
+/// Callbacks invoked by the [`Interpreter`](super::interpreter::Interpreter) while it executes a program, see
+/// [`Interpreter::add_hook`](super::interpreter::Interpreter::add_hook). Every method does nothing by default, so a
+/// hook only implements the events it is interested in. Errors stop the execution, which lets hooks enforce policies
+pub trait Hook {
+    /// Called before the instruction at `pc` is executed. An error prevents its execution
+    fn on_instruction(&mut self, _pc: usize, _instruction: &Instruction) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called after an instruction wrote the cell at `addr`, relative to the initial position of the memory pointer,
+    /// changing it from `old` to `new`. Writes that leave the value unchanged are reported as well
+    fn on_memory_write(&mut self, _addr: isize, _old: u64, _new: u64) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called after `value` was read from the input into the cell at `addr`. A single bit is read with 1 bit cells
+    fn on_input(&mut self, _addr: isize, _value: u64) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Called after the cell holding `value` was written to the output. Only its least significant byte is written,
+    /// or its only bit with 1 bit cells
+    fn on_output(&mut self, _value: u64) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::virtualmachine::Settings;
+    use crate::ui::SharedBuffer;
+
+    /// Records every event as a line of text
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Hook for Recorder {
+        fn on_instruction(&mut self, pc: usize, instruction: &Instruction) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(format!("{} {}", pc, instruction));
+            Ok(())
+        }
+
+        fn on_memory_write(&mut self, addr: isize, old: u64, new: u64) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(format!("[{}] {} -> {}", addr, old, new));
+            Ok(())
+        }
+
+        fn on_input(&mut self, addr: isize, value: u64) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(format!("in [{}] {}", addr, value));
+            Ok(())
+        }
+
+        fn on_output(&mut self, value: u64) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(format!("out {}", value));
+            Ok(())
+        }
+    }
+
+    /// Forbids moving left of the initial cell
+    struct Sandbox;
+
+    impl Hook for Sandbox {
+        fn on_instruction(&mut self, pc: usize, instruction: &Instruction) -> Result<(), Box<dyn Error>> {
+            match instruction {
+                Instruction::DecPtr => Err(format!("Moving left is not allowed (pc {})", pc).into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn interpreter() -> Interpreter {
+        Interpreter::with_vm_settings(Settings {
+            memory_size: 16,
+            input: Box::new(&b"a"[..]),
+            output: Box::new(SharedBuffer::new()),
+            ..Settings::default()
+        })
+    }
+
+    #[test]
+    fn invoke_hooks() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = interpreter();
+        interpreter.add_hook(Box::new(Recorder(events.clone())));
+        interpreter.load_source(b",>+.").unwrap();
+        interpreter.run().unwrap();
+        assert_eq!(*events.borrow(), [
+            "0 rd", "[0] 0 -> 97", "in [0] 97", "1 incp", "2 incd", "[1] 0 -> 1", "3 wr", "out 1", "4 exit",
+        ]);
+        let mut interpreter = self::interpreter();
+        interpreter.add_hook(Box::new(Sandbox));
+        interpreter.load_source(b">+<-").unwrap();
+        let err = interpreter.run().expect_err("The sandbox did not stop the program");
+        assert_eq!(err.to_string(), "Moving left is not allowed (pc 2)");
+        assert_eq!(interpreter.vm().peek(1), Some(1));
+        assert_eq!(interpreter.vm().address(), 1);
+    }
+}
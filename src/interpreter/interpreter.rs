@@ -1,86 +1,1096 @@
+use std::collections::VecDeque;
 use std::error::Error;
-use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use crate::interpreter::virtualmachine;
 
-use crate::parse::program::Program;
-use super::virtualmachine::{VirtualMachine, Settings};
+use crate::optimizer::PassManager;
+use crate::parse::bytecode;
+use crate::parse::diagnostic::RuntimeError;
+use crate::parse::frontend::Frontend;
+use crate::parse::program::{Instruction, Program, SourceLoc};
+use crate::parse::token::Syntax;
+use crate::ui::{InputQueue, SharedBuffer};
+#[cfg(feature = "async")]
+use super::asyncio::{yield_now, AsyncSettings};
+use super::engine::{Engine, Switch};
+use super::heatmap::Heatmap;
+use super::hook::Hook;
+use super::memcheck::MemoryCheck;
+use super::profile::Profile;
+use super::state::VmState;
+use super::tape::{Dense, Tape};
+use super::trace::{TraceEvent, TraceHook};
+use super::virtualmachine::{Settings, Status, VirtualMachine};
 
+/// Runs a compiled [`Program`] on a [`VirtualMachine`]. Programs that fork run one instruction of each thread in turn:
+/// the VirtualMachine of the Interpreter is always the one of the current thread
 pub struct Interpreter {
     program: Program,
     vm: VirtualMachine,
+    engine: Box<dyn Engine>,
+    /// Threads waiting for their turn
+    threads: VecDeque<VirtualMachine>,
+    passes: PassManager,
+    syntax: Syntax,
+    tracer: Option<Box<dyn TraceHook>>,
+    /// Callbacks invoked during execution, in the order they were added
+    hooks: Vec<Box<dyn Hook>>,
+    /// Execution counts of the loaded program, when profiling is enabled
+    profile: Option<Profile>,
+    /// Accesses of each cell of the tape, when enabled
+    heatmap: Option<Heatmap>,
+    /// Reads of cells never written, when enabled
+    memory_check: Option<MemoryCheck>,
+    /// Input provided by the host, once enabled
+    input: Option<InputQueue>,
+    /// Whether loading a program keeps the tape and the memory pointer of the previous one
+    keep_memory: bool,
+}
+
+/// Result of running a program for a limited number of instructions with [`Interpreter::run_for`]
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// The program exited
+    Finished,
+    /// The budget ran out: the program can be continued with another call
+    Runnable,
+    /// A breakpoint was reached: the program can be continued with another call
+    Paused,
+    /// No input was available without blocking: the program can be continued with another call once there is some
+    WaitingForInput,
+    /// Execution failed
+    Error(Box<dyn Error>),
+}
+
+/// Compile `source`, run it on a VirtualMachine with the default settings reading `input`, and return what it wrote
+pub fn run_str(source: &str, input: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut interpreter = Interpreter::new();
+    interpreter.load_str(source)?;
+    interpreter.run_with_io(input)
 }
 
 /* Interpreter *******************************************************************************************************/
 impl Interpreter {
+    /// Create an Interpreter with an empty program and a VirtualMachine with the default settings
     pub fn new() -> Interpreter {
         Interpreter {
             program: Program::new(),
             vm: VirtualMachine::new(),
+            engine: Box::new(Switch),
+            threads: VecDeque::new(),
+            passes: PassManager::new(),
+            syntax: Syntax::default(),
+            tracer: None,
+            hooks: Vec::new(),
+            profile: None,
+            heatmap: None,
+            memory_check: None,
+            input: None,
+            keep_memory: false,
         }
     }
 
+    /// Create an Interpreter with an empty program and a VirtualMachine with the specified settings
     pub fn with_vm_settings(settings: Settings) -> Interpreter {
+        let tape = Box::new(Dense::new(settings.memory_size));
+        Interpreter::with_tape(settings, tape)
+    }
+
+    /// Create an Interpreter with an empty program and a VirtualMachine with the specified settings, which stores its
+    /// cells in `tape`. The tape should hold `settings.memory_size` cells
+    pub fn with_tape(settings: Settings, tape: Box<dyn Tape>) -> Interpreter {
         Interpreter {
             program: Program::new(),
-            vm: VirtualMachine::with_settings(settings),
+            vm: VirtualMachine::with_tape(settings, tape),
+            engine: Box::new(Switch),
+            threads: VecDeque::new(),
+            passes: PassManager::new(),
+            syntax: Syntax::default(),
+            tracer: None,
+            hooks: Vec::new(),
+            profile: None,
+            heatmap: None,
+            memory_check: None,
+            input: None,
+            keep_memory: false,
+        }
+    }
+
+    /// Read program input from the bytes given to [`Interpreter::provide_input`] instead of the input of the settings.
+    /// Input commands that find no input make the program wait, returning control to the caller
+    pub fn with_input_queue(mut self) -> Interpreter {
+        self.input_queue();
+        self
+    }
+
+    /// Append `bytes` to the input of the program. A program waiting for input continues with the next call to
+    /// [`Interpreter::resume`] or [`Interpreter::run_for`]. The first call switches to input provided by the host, as
+    /// [`Interpreter::with_input_queue`] does
+    pub fn provide_input(&mut self, bytes: &[u8]) {
+        self.input_queue().push(bytes);
+    }
+
+    /// Signal that no more input will be provided: once the bytes given so far are read, input commands read EOF
+    /// instead of waiting
+    pub fn close_input(&mut self) {
+        self.input_queue().close();
+    }
+
+    fn input_queue(&mut self) -> &InputQueue {
+        if self.input.is_none() {
+            let queue = InputQueue::new();
+            self.vm.set_input(Box::new(queue.clone()));
+            self.input = Some(queue);
         }
+        self.input.as_ref().expect("Input queue was just created")
+    }
+
+    /// Execute programs with `engine` instead of the default [`Switch`] engine
+    pub fn with_engine(mut self, engine: Box<dyn Engine>) -> Interpreter {
+        self.engine = engine;
+        self
+    }
+
+    /// Replace the engine that executes programs. The state of the virtual machine is kept
+    pub fn set_engine(&mut self, engine: Box<dyn Engine>) {
+        self.engine = engine;
+    }
+
+    /// Get the engine that executes programs
+    pub fn engine(&self) -> &dyn Engine {
+        self.engine.as_ref()
+    }
+
+    /// Set the optimization passes run on the programs loaded from now on
+    pub fn set_passes(&mut self, passes: PassManager) {
+        self.passes = passes;
+    }
+
+    /// Keep the tape and the memory pointer when programs are loaded from now on, so that programs run one after the
+    /// other can communicate through memory. Only the program counter and the status are reset, as with
+    /// [`Interpreter::load_snippet`]
+    pub fn set_keep_memory(&mut self, keep: bool) {
+        self.keep_memory = keep;
+    }
+
+    /// Set the syntax options used to compile the programs loaded from now on
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+    }
+
+    /// Report every executed instruction to `tracer`, or stop tracing if it is `None`
+    pub fn set_trace_hook(&mut self, tracer: Option<Box<dyn TraceHook>>) {
+        self.tracer = tracer;
+    }
+
+    /// Invoke the callbacks of `hook` while executing programs, after those of the hooks added before. Hooks are kept
+    /// when a new program is loaded
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
     }
 
+    /// Remove all the hooks
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    /// Start counting the executions of every instruction, or stop and discard the counts
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(Profile::new()) } else { None };
+    }
+
+    /// Execution counts of the loaded program since it was loaded, if profiling is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Start counting the reads and writes of every cell of the tape, or stop and discard the counts
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.heatmap = if enabled { Some(Heatmap::new()) } else { None };
+    }
+
+    /// Accesses of every cell since the program was loaded, if enabled
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    /// Start looking for reads of cells that were never written, or stop and discard the reads found
+    pub fn set_memory_check(&mut self, enabled: bool) {
+        self.memory_check = if enabled { Some(MemoryCheck::new()) } else { None };
+    }
+
+    /// Reads of cells never written since the program was loaded, if enabled
+    pub fn memory_check(&self) -> Option<&MemoryCheck> {
+        self.memory_check.as_ref()
+    }
+
+    /// Get the settings of the virtual machine
+    pub fn vm_settings(&self) -> &Settings {
+        self.vm.settings()
+    }
+
+    /// Compile the file at `fname` and load it. Bytecode files are recognized and loaded without compiling them. The
+    /// VirtualMachine is completely reset, unless memory is kept with [`Interpreter::set_keep_memory`]
     pub fn load_file(&mut self, fname: &str) -> Result<(), Box<dyn Error>> {
-        let program = Program::compile(File::open(fname)?)?;
-        self.program = program;
-        self.vm.reset();
+        self.load_source(&std::fs::read(fname)?)
+    }
+
+    /// Same as [`Interpreter::load_file`], with the content of the file already in memory
+    pub fn load_source(&mut self, source: &[u8]) -> Result<(), Box<dyn Error>> {
+        let program = if bytecode::is_bytecode(source) {
+            Program::deserialize(source)?
+        } else {
+            self.compile(source)?
+        };
+        self.install(&program);
+        Ok(())
+    }
+
+    /// Compile the tokens produced by `frontend` and load the program. The VirtualMachine is reset as in
+    /// [`Interpreter::load_file`]
+    pub fn load_frontend(&mut self, frontend: &mut dyn Frontend) -> Result<(), Box<dyn Error>> {
+        let program = Program::from_frontend(frontend)?;
+        self.install(&program);
+        Ok(())
+    }
+
+    /// Compile `source` and load it. The VirtualMachine is reset as in [`Interpreter::load_file`]
+    pub fn load_str(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        self.load_source(source.as_bytes())
+    }
+
+    /// Read source code or bytecode from `reader` until EOF and load it like [`Interpreter::load_file`]
+    pub fn load_reader<R: Read>(&mut self, mut reader: R) -> Result<(), Box<dyn Error>> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source)?;
+        self.load_source(&source)
+    }
+
+    /// Compile a program from a string and load it while preserving the state of the virtual machine: memory and
+    /// memory pointer are kept, only the program counter and the status are reset
+    pub fn load_snippet(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        let program = self.compile(source.as_bytes())?;
+        self.program = self.passes.run_snippet(&program);
+        self.vm.rewind();
+        self.threads.clear();
+        self.clear_counts();
         Ok(())
     }
 
+    /// Optimize `program` and make it the current one, resetting the VirtualMachine or only rewinding it
+    fn install(&mut self, program: &Program) {
+        if self.keep_memory {
+            // Optimizations must not assume that the program starts on a fresh tape
+            self.program = self.passes.run_snippet(program);
+            self.vm.rewind();
+        } else {
+            self.program = self.passes.run(program);
+            self.vm.reset();
+        }
+        self.threads.clear();
+        self.clear_counts();
+    }
+
+    /// Load an already compiled program, for instance one decoded with [`Program::deserialize`], leaving the state of
+    /// the virtual machine untouched. Optimization passes are not run
+    pub fn load_program(&mut self, program: Program) {
+        self.program = program;
+        self.clear_counts();
+    }
+
+    /// Flush the output of the virtual machine
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.vm.flush()
+    }
+
+    /// Flush the output and take it back from the virtual machine, as the writer of type `W` it was created with. See
+    /// [`VirtualMachine::into_output`]
+    pub fn into_output<W: virtualmachine::Output>(self) -> Result<W, Box<dyn Error>> {
+        // Waiting threads share the output: they are dropped first
+        let Interpreter { vm, threads, .. } = self;
+        drop(threads);
+        vm.into_output()
+    }
+
+    /// Get the virtual machine
+    pub fn vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    /// Get the virtual machine mutably
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine {
+        &mut self.vm
+    }
+
+    /// Get the loaded program
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Restore a snapshot of the virtual machine running the loaded program, see [`VirtualMachine::restore`]. Fails,
+    /// leaving the machine untouched, if the program counter is past the end of the program or the memory pointer
+    /// outside the tape
+    pub fn restore(&mut self, state: VmState) -> Result<(), Box<dyn Error>> {
+        if state.pc > self.program.len() {
+            return Err(format!("Program counter {} past the program of {} instructions", state.pc, self.program.len())
+                .into());
+        }
+        self.vm.restore(state)
+    }
+
+    /// Write a textual listing of the loaded program to `sink`
     pub fn dump_program<W: Write>(&self, sink: &mut W) -> Result<(), std::io::Error> {
         self.program.dump(sink)
     }
 
+    /// Bring the VirtualMachine to the Running status
     pub fn startup(&mut self) -> Result<(), Box<dyn Error>> {
         self.vm.wakeup()
     }
 
+    /// Execute a single instruction. Returns an error if the interpreter is not running. Runtime errors mention the
+    /// source location of the failing instruction when it is known, and halt the virtual machine
     pub fn step(&mut self) -> Result<(), Box<dyn Error>> {
         // Check if instruction should be running
         if *self.vm.status() != virtualmachine::Status::Running {
             return Err("Interpreter is not running".into());
         }
-        let instruction = self.program.instruction(self.vm.pc());
-        self.vm.execute_instruction(instruction)?;
+        let result = self.step_running();
+        if let Err(err) = &result {
+            self.vm.halt(err.as_ref());
+        }
+        result
+    }
+
+    fn step_running(&mut self) -> Result<(), Box<dyn Error>> {
+        let pc = self.vm.pc();
+        let instruction = self.program.instruction(pc);
+        let (mp, before) = (self.vm.address(), self.vm.mem_rd());
+        for hook in &mut self.hooks {
+            hook.on_instruction(pc, instruction)?;
+        }
+        if let Err(err) = self.engine.step(&mut self.vm, &self.program) {
+            return Err(self.locate(err, pc));
+        }
+        // Input commands waiting for input read nothing yet, and are recorded once executed again
+        if matches!(instruction, Instruction::Input) && self.vm.pc() == pc {
+            self.schedule();
+            return Ok(());
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc);
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(instruction, mp, self.vm.last_write().map(|(addr, _)| addr));
+        }
+        if let Some(check) = &mut self.memory_check {
+            check.record(&self.program, pc, mp, before, self.vm.last_write().map(|(addr, _)| addr));
+        }
+        if let Some(tracer) = &mut self.tracer {
+            let after = self.vm.peek(mp).unwrap_or(before);
+            let loc = self.program.location(pc);
+            tracer.trace(&TraceEvent { pc, instruction: *instruction, mp, before, after, loc })?;
+        }
+        if !self.hooks.is_empty() {
+            self.invoke_hooks(*instruction, mp, before)?;
+        }
+        self.schedule();
         Ok(())
     }
 
+    /// Report to the hooks the effects of `instruction`, just executed with the memory pointer at `mp` on a cell that
+    /// held `before`
+    fn invoke_hooks(&mut self, instruction: Instruction, mp: isize, before: u64) -> Result<(), Box<dyn Error>> {
+        let written = self.vm.last_write().map(|(addr, old)| (addr, old, self.vm.peek(addr).unwrap_or(old)));
+        for hook in &mut self.hooks {
+            if let Some((addr, old, new)) = written {
+                hook.on_memory_write(addr, old, new)?;
+            }
+            match instruction {
+                Instruction::Input => hook.on_input(mp, self.vm.peek(mp).unwrap_or(before))?,
+                Instruction::Output => hook.on_output(before)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the interpreter and execute instructions until the program exits or reaches a breakpoint
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
         self.startup()?;
+        self.run_until_stopped()
+    }
+
+    /// Run the program reading `input`, and return what it wrote. The input and output of the virtual machine are
+    /// replaced for good
+    pub fn run_with_io(&mut self, input: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = SharedBuffer::new();
+        self.vm.set_input(Box::new(std::io::Cursor::new(input.to_vec())));
+        self.vm.set_output(Box::new(output.clone()))?;
+        self.run()?;
+        self.flush()?;
+        Ok(output.contents())
+    }
+
+    /// Continue execution after a breakpoint, or once input is available, until the program exits or stops again
+    pub fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        self.vm.resume()?;
+        // The instruction under the breakpoint must be executed before checking breakpoints again
+        self.step()?;
+        self.run_until_stopped()
+    }
+
+    /// Execute at most `n` instructions, starting the program if it is idle and continuing it if it is paused at a
+    /// breakpoint. This allows interleaving execution with other work without blocking
+    pub fn run_for(&mut self, n: usize) -> StepOutcome {
+        let started = match self.status() {
+            virtualmachine::Status::Idle if self.vm.pc() >= self.program.len() => return StepOutcome::Finished,
+            virtualmachine::Status::Idle => self.startup(),
+            virtualmachine::Status::Paused | virtualmachine::Status::WaitingForInput => self.vm.resume(),
+            virtualmachine::Status::Running => Ok(()),
+            virtualmachine::Status::Finished => return StepOutcome::Finished,
+            virtualmachine::Status::Halted(message) => return StepOutcome::Error(message.into()),
+        };
+        if let Err(err) = started {
+            return StepOutcome::Error(err);
+        }
+        for _ in 0..n {
+            if self.status() != virtualmachine::Status::Running {
+                break;
+            }
+            if let Err(err) = self.step() {
+                return StepOutcome::Error(err);
+            }
+        }
+        match self.status() {
+            virtualmachine::Status::Idle | virtualmachine::Status::Finished => StepOutcome::Finished,
+            virtualmachine::Status::Running => StepOutcome::Runnable,
+            virtualmachine::Status::Paused => StepOutcome::Paused,
+            virtualmachine::Status::WaitingForInput => StepOutcome::WaitingForInput,
+            virtualmachine::Status::Halted(message) => StepOutcome::Error(message.into()),
+        }
+    }
+
+    /// Run the program from async code, reading its input from and writing its output to `io` instead of the input
+    /// and output of the settings, without blocking the thread: input commands await `io.input` and the output is
+    /// written to `io.output` whenever the program waits for input or stops, and every `io.slice` instructions, when
+    /// the task also yields to the executor. Returns when the program exits or pauses. The future is not `Send`, so it
+    /// runs on a single-threaded executor or a local set of tasks
+    #[cfg(feature = "async")]
+    pub async fn run_async(&mut self, mut io: AsyncSettings) -> Result<(), Box<dyn Error>> {
+        let output = SharedBuffer::new();
+        self.vm.set_output(Box::new(output.clone()))?;
+        self.input_queue();
+        let mut buf = [0; 4096];
         loop {
-            match self.vm.status() {
-                virtualmachine::Status::Running => self.step()?,
-                virtualmachine::Status::Idle => break,
+            let outcome = self.run_for(io.slice);
+            self.flush()?;
+            let written = output.take();
+            if !written.is_empty() {
+                io.write_all(&written).await?;
+            }
+            match outcome {
+                StepOutcome::Finished | StepOutcome::Paused => return Ok(()),
+                StepOutcome::Error(err) => return Err(err),
+                StepOutcome::Runnable => yield_now().await,
+                StepOutcome::WaitingForInput => match io.read(&mut buf).await? {
+                    0 => self.close_input(),
+                    len => self.provide_input(&buf[..len]),
+                },
             }
         }
+    }
+
+    /// Get the status of the virtual machine
+    pub fn status(&self) -> virtualmachine::Status {
+        self.vm.status().clone()
+    }
+
+    /// Set a breakpoint at instruction address `pc`. Returns false if it was already set
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.vm.add_breakpoint(pc)
+    }
+
+    /// Set a breakpoint on the first instruction compiled from source code at or after `loc`. Returns the address of
+    /// the breakpoint, or `None` if there is no such instruction
+    pub fn add_breakpoint_at(&mut self, loc: SourceLoc) -> Option<usize> {
+        let pc = self.program.address_of(loc)?;
+        self.vm.add_breakpoint(pc);
+        Some(pc)
+    }
+
+    /// Remove the breakpoint at instruction address `pc`. Returns false if there was none
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.vm.remove_breakpoint(pc)
+    }
+
+    /// Remove the breakpoint set by [`Interpreter::add_breakpoint_at`] for `loc`. Returns false if there was none
+    pub fn remove_breakpoint_at(&mut self, loc: SourceLoc) -> bool {
+        match self.program.address_of(loc) {
+            Some(pc) => self.vm.remove_breakpoint(pc),
+            None => false,
+        }
+    }
+
+    /// List breakpoints by address, together with the source location of their instruction if known
+    pub fn breakpoints(&self) -> Vec<(usize, Option<SourceLoc>)> {
+        self.vm.breakpoints().map(|pc| (pc, self.program.location(pc))).collect()
+    }
+
+    /// Compile `source` with the current syntax. Input embedded in the source replaces the input of the virtual machine
+    fn compile(&mut self, source: &[u8]) -> Result<Program, Box<dyn Error>> {
+        let (code, input) = self.syntax.split_input(source);
+        let program = Program::compile_with(code, self.syntax)?;
+        if let Some(input) = input {
+            self.vm.set_input(Box::new(std::io::Cursor::new(input.to_vec())));
+        }
+        Ok(program)
+    }
+
+    /// Forget the execution counts and the memory accesses of the previous program
+    fn clear_counts(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            *profile = Profile::new();
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            *heatmap = Heatmap::new();
+        }
+        if let Some(check) = &mut self.memory_check {
+            *check = MemoryCheck::new();
+        }
+    }
+
+    /// Switch to the next thread, if any: the current one goes to the back of the queue, or leaves it if it exited. A
+    /// thread paused at a breakpoint or waiting for input stays current, so that it is the one resumed
+    fn schedule(&mut self) {
+        self.threads.extend(self.vm.take_forks());
+        let status = self.vm.status();
+        if self.threads.is_empty() || matches!(status, Status::Paused | Status::WaitingForInput) {
+            return;
+        }
+        let next = self.threads.pop_front().expect("There are other threads");
+        let previous = std::mem::replace(&mut self.vm, next);
+        if *previous.status() == virtualmachine::Status::Running {
+            self.threads.push_back(previous);
+        }
+    }
+
+    /// Turn `err`, raised by the instruction at `pc`, into a [`RuntimeError`] which mentions its source location when
+    /// it is known
+    fn locate(&self, err: Box<dyn Error>, pc: usize) -> Box<dyn Error> {
+        Box::new(RuntimeError::new(err, pc, self.program.location(pc)))
+    }
+
+    /// Whether instructions must be executed one at a time by [`Interpreter::step`], because something observes each
+    /// of them or because threads must take turns
+    fn needs_steps(&self) -> bool {
+        self.tracer.is_some()
+            || !self.hooks.is_empty()
+            || self.profile.is_some()
+            || self.heatmap.is_some()
+            || self.memory_check.is_some()
+            || !self.threads.is_empty()
+            || self.program.instructions().contains(&Instruction::Fork)
+    }
+
+    fn run_until_stopped(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.needs_steps() {
+            return self.engine.run(&mut self.vm, &self.program).map_err(|err| {
+                let err = self.locate(err, self.vm.pc());
+                self.vm.halt(err.as_ref());
+                err
+            });
+        }
+        while let virtualmachine::Status::Running = self.vm.status() {
+            self.step()?;
+        }
         Ok(())
     }
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::optimizer::OptimizationLevel;
 
     /// Execute helloworld.bf as an overall sanity check
     #[test]
     fn run_hello_world() {
-        let sink = Vec::new();
         let settings = virtualmachine::Settings {
             memory_size: 128,
-            memory_overflow_behavior: virtualmachine::MemoryOverflowBehavior::Unchecked,
-            input: Box::new(std::io::stdin()),
-            output: Box::new(sink),
+            output: Box::new(Vec::<u8>::new()),
+            ..virtualmachine::Settings::default()
         };
-        let mut interpreter = Interpreter::new();
-        interpreter.load_file("test/helloworld.bf");
+        let mut interpreter = Interpreter::with_vm_settings(settings);
+        interpreter.load_file("test/helloworld.bf")
+            .expect("Could not load file");
         interpreter.run()
             .expect("Error while running");
+        let output: Vec<u8> = interpreter.into_output().expect("Could not take the output");
+        assert_eq!(output, b"Hello World!\n");
     }
-}
\ No newline at end of file
+
+    /// Snippets loaded one after the other must share the same tape
+    #[test]
+    fn snippets_preserve_tape() {
+        let mut interpreter = Interpreter::new();
+        // Optimizations must not assume that snippets start on a fresh machine
+        interpreter.set_passes(PassManager::with_level(OptimizationLevel::O2, interpreter.vm_settings()));
+        interpreter.load_snippet("+++>++").expect("Could not load snippet");
+        interpreter.run().expect("Error while running");
+        interpreter.load_snippet("+<+").expect("Could not load snippet");
+        interpreter.run().expect("Error while running");
+        assert_eq!(interpreter.vm.mem_rd(), 4);
+        assert_eq!(interpreter.vm.peek(1), Some(3));
+    }
+
+    /// Optimized loops that never run must not move a pedantic pointer off the tape
+    #[test]
+    fn pedantic_optimized() {
+        let mut interpreter = Interpreter::new();
+        interpreter.vm_mut().set_pedantic_pointer(true);
+        interpreter.set_passes(PassManager::for_machine(OptimizationLevel::O2, interpreter.vm()));
+        interpreter.load_source(b"[<+>-]+.").expect("Could not load source");
+        assert_eq!(interpreter.run_with_io(b"").expect("Error while running"), b"\x01");
+    }
+
+    /// Execution must pause at breakpoints and resume from them
+    #[test]
+    fn pause_at_breakpoints() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet("+\n+[-]").expect("Could not load snippet");
+        assert_eq!(interpreter.add_breakpoint_at(SourceLoc { row: 1, col: 2 }), Some(1));
+        interpreter.add_breakpoint(4);
+        interpreter.run().expect("Error while running");
+        assert_eq!(interpreter.status(), virtualmachine::Status::Paused);
+        assert_eq!(interpreter.vm().pc(), 1);
+        interpreter.resume().expect("Error while resuming");
+        assert_eq!(interpreter.vm().pc(), 4);
+        assert_eq!(interpreter.vm().mem_rd(), 1);
+        interpreter.resume().expect("Error while resuming");
+        assert_eq!(interpreter.vm().pc(), 4);
+        assert_eq!(interpreter.vm().mem_rd(), 0);
+        assert!(interpreter.remove_breakpoint(4));
+        interpreter.resume().expect("Error while resuming");
+        assert_eq!(interpreter.status(), virtualmachine::Status::Finished);
+        assert_eq!(interpreter.breakpoints(), vec![(1, Some(SourceLoc { row: 2, col: 1 }))]);
+    }
+
+    /// Setting the interrupt flag from another thread must pause an endless program, with any engine
+    #[test]
+    fn interrupt() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut interpreter = Interpreter::new().with_engine(Box::new(crate::interpreter::threaded::Threaded));
+        interpreter.vm_mut().set_interrupt(Some(flag.clone()));
+        interpreter.load_snippet("+[]").expect("Could not load snippet");
+        let setter = {
+            let flag = flag.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                flag.store(true, Ordering::Relaxed);
+            })
+        };
+        interpreter.run().expect("Error while running");
+        setter.join().expect("Could not set the flag");
+        assert_eq!(interpreter.status(), virtualmachine::Status::Paused);
+        assert!(interpreter.vm().pc() < 3);
+    }
+
+    /// A read interrupted while the interrupt flag is set must pause the machine on the input command
+    #[test]
+    fn interrupt_input() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        /// Reader interrupted on the first attempt by a signal setting the flag, if any
+        struct Interrupted(Option<Arc<AtomicBool>>, bool);
+
+        impl std::io::Read for Interrupted {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if !std::mem::replace(&mut self.1, true) {
+                    if let Some(flag) = &self.0 {
+                        flag.store(true, Ordering::Relaxed);
+                    }
+                    return Err(std::io::ErrorKind::Interrupted.into());
+                }
+                (&b"x"[..]).read(buf)
+            }
+        }
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings::builder()
+            .input(Interrupted(Some(flag.clone()), false))
+            .build());
+        interpreter.vm_mut().set_interrupt(Some(flag.clone()));
+        interpreter.load_snippet("+>,").expect("Could not load snippet");
+        interpreter.run().expect("Error while running");
+        assert_eq!((interpreter.status(), interpreter.vm().pc()), (virtualmachine::Status::Paused, 2));
+        flag.store(false, Ordering::Relaxed);
+        interpreter.resume().expect("Error while running");
+        assert_eq!(interpreter.vm().peek(1), Some(b'x' as u64));
+        // Without the flag, the interrupted read is an error
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings::builder()
+            .input(Interrupted(None, false))
+            .build());
+        interpreter.load_snippet(",").expect("Could not load snippet");
+        assert!(interpreter.run().is_err());
+    }
+
+    /// Runtime errors must report the source location of the failing instruction
+    #[test]
+    fn errors_report_location() {
+        let settings = virtualmachine::Settings {
+            memory_size: 16,
+            cell_overflow_behavior: virtualmachine::CellOverflowBehavior::Error,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..virtualmachine::Settings::default()
+        };
+        let mut interpreter = Interpreter::with_vm_settings(settings);
+        interpreter.load_snippet("+\n>+\n<--").expect("Could not load snippet");
+        let err = interpreter.run().expect_err("Underflow not detected");
+        assert_eq!(err.to_string(), "Cell underflow at pc 0x00000005 (line 3, column 3)");
+        assert_eq!(interpreter.status(), virtualmachine::Status::Halted(err.to_string()));
+        assert!(matches!(interpreter.run_for(1), StepOutcome::Error(halted) if halted.to_string() == err.to_string()));
+        assert!(interpreter.step().is_err());
+        assert_eq!(interpreter.vm().pc(), 5);
+    }
+
+    /// I/O errors must remain available once their location is added, so that callers can tell them apart
+    #[test]
+    fn io_errors_keep_kind() {
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings::builder()
+            .output(std::io::Cursor::new([0u8; 0]))
+            .build());
+        interpreter.load_snippet("+\n.").expect("Could not load snippet");
+        let err = interpreter.run().expect_err("Write to a full buffer succeeded");
+        let err = err.downcast::<RuntimeError>().expect("Not a runtime error");
+        let cause = err.error().downcast_ref::<std::io::Error>().expect("Not an I/O error");
+        assert_eq!(cause.kind(), std::io::ErrorKind::WriteZero);
+        assert_eq!((err.pc(), err.loc()), (1, Some(SourceLoc { row: 2, col: 1 })));
+    }
+
+    /// Input that would block parks the machine on the input command until it is resumed
+    #[test]
+    fn wait_for_input() {
+        /// Reader that has no input available on the first attempt
+        struct Hesitant(bool);
+
+        impl std::io::Read for Hesitant {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if !std::mem::replace(&mut self.0, true) {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                (&b"x"[..]).read(buf)
+            }
+        }
+
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            input: Box::new(Hesitant(false)),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.set_profiling(true);
+        interpreter.load_snippet("+>,.").expect("Could not load snippet");
+        interpreter.run().expect("Error while running");
+        assert_eq!((interpreter.status(), interpreter.vm().pc()), (virtualmachine::Status::WaitingForInput, 2));
+        assert_eq!(interpreter.vm().executed(), 2);
+        assert!(matches!(interpreter.run_for(10), StepOutcome::Finished));
+        assert_eq!(output.contents(), b"x");
+        // Waiting is not executing the input command
+        assert_eq!(interpreter.profile().map(|profile| profile.count(2)), Some(1));
+        assert_eq!(interpreter.vm().metrics().count(&Instruction::Input), 1);
+    }
+
+    #[test]
+    fn run_with_io() {
+        assert_eq!(run_str(",[.,]", b"echo").expect("Error while running"), b"echo");
+        let err = run_str("[", b"").expect_err("Unbalanced brackets were compiled");
+        assert!(err.to_string().contains("Unmatched"), "{}", err);
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet(",+.").expect("Could not load snippet");
+        assert_eq!(interpreter.run_with_io(b"a").expect("Error while running"), b"b");
+    }
+
+    #[test]
+    fn load_from_memory() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_str("+++[>++<-]>.").expect("Could not load source");
+        assert_eq!(interpreter.run_with_io(b"").expect("Error while running"), b"\x06");
+        let bytecode = interpreter.program().serialize();
+        interpreter.load_reader(&bytecode[..]).expect("Could not load bytecode");
+        assert_eq!(interpreter.run_with_io(b"").expect("Error while running"), b"\x06");
+    }
+
+    /// Programs loaded while memory is kept communicate through the tape
+    #[test]
+    fn keep_memory() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_passes(PassManager::with_level(OptimizationLevel::O2, interpreter.vm_settings()));
+        interpreter.set_keep_memory(true);
+        interpreter.load_str("++++++>+++").expect("Could not load source");
+        interpreter.run_with_io(b"").expect("Error while running");
+        interpreter.load_str("[<+>-]<.").expect("Could not load source");
+        assert_eq!(interpreter.run_with_io(b"").expect("Error while running"), b"\x09");
+        interpreter.set_keep_memory(false);
+        interpreter.load_str(".").expect("Could not load source");
+        assert_eq!(interpreter.run_with_io(b"").expect("Error while running"), b"\x00");
+    }
+
+    /// Input provided by the host is read as it comes, until it is closed
+    #[test]
+    fn provide_input() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        }).with_input_queue();
+        interpreter.load_snippet(",[.,]").expect("Could not load snippet");
+        interpreter.run().expect("Error while running");
+        assert_eq!(interpreter.status(), virtualmachine::Status::WaitingForInput);
+        interpreter.provide_input(b"ab");
+        interpreter.resume().expect("Error while resuming");
+        assert_eq!(interpreter.status(), virtualmachine::Status::WaitingForInput);
+        assert_eq!(output.contents(), b"ab");
+        interpreter.provide_input(b"c");
+        interpreter.close_input();
+        assert!(matches!(interpreter.run_for(100), StepOutcome::Finished));
+        assert_eq!(output.contents(), b"abc");
+    }
+
+    /// Input is awaited chunk by chunk, and the program yields between slices of instructions
+    #[cfg(feature = "async")]
+    #[test]
+    fn run_async() {
+        use std::future::Future;
+        use std::io;
+        use std::pin::{pin, Pin};
+        use std::task::{Context, Poll, Waker};
+        use crate::interpreter::asyncio::AsyncRead;
+
+        /// Input making the task wait before handing out each chunk
+        struct Chunks(VecDeque<&'static [u8]>, bool);
+
+        impl AsyncRead for Chunks {
+            fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+                let Chunks(chunks, waited) = self.get_mut();
+                *waited = !*waited;
+                if *waited {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                let chunk = chunks.pop_front().unwrap_or_default();
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Poll::Ready(Ok(chunk.len()))
+            }
+        }
+
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.load_snippet(",[.,]++++[>++++<-]").expect("Could not load snippet");
+        let output = SharedBuffer::new();
+        let mut io = AsyncSettings::new(Chunks(VecDeque::from([&b"ab"[..], b"c"]), false), output.clone());
+        io.slice = 4;
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        let result = {
+            let mut run = pin!(interpreter.run_async(io));
+            loop {
+                match run.as_mut().poll(&mut cx) {
+                    Poll::Ready(result) => break result,
+                    Poll::Pending => pending += 1,
+                }
+            }
+        };
+        result.expect("Error while running");
+        assert_eq!((output.contents(), interpreter.status()), (b"abc".to_vec(), virtualmachine::Status::Finished));
+        // Three reads wait once each, and the loop after the input takes several slices
+        assert!(pending > 3 + 10, "{} polls were pending", pending);
+    }
+
+    /// Infinite loops must be interrupted by the instruction and wall time limits
+    #[test]
+    fn execution_limits() {
+        let limited = |max_instructions, max_wall_time| {
+            let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+                memory_size: 16,
+                max_instructions,
+                max_wall_time,
+                input: Box::new(std::io::empty()),
+                output: Box::new(std::io::sink()),
+                ..virtualmachine::Settings::default()
+            });
+            interpreter.load_snippet("+[]").expect("Could not load snippet");
+            interpreter
+        };
+        let mut interpreter = limited(Some(100), None);
+        let err = interpreter.run().expect_err("Instruction limit not enforced");
+        assert_eq!(
+            err.to_string(),
+            "Instruction limit exceeded at pc 0x00000002 after 100 instructions (line 1, column 3)"
+        );
+        assert_eq!(interpreter.vm().executed(), 100);
+        let mut interpreter = limited(None, Some(std::time::Duration::from_millis(10)));
+        let err = interpreter.run().expect_err("Timeout not enforced");
+        assert!(err.to_string().starts_with("Timeout of 10ms exceeded"), "{}", err);
+        let mut interpreter = limited(Some(3), None);
+        interpreter.load_snippet("+.").expect("Could not load snippet");
+        interpreter.run().expect("Limit exceeded by a program within it");
+    }
+
+    /// A program run in slices must behave as if it was run at once
+    #[test]
+    fn run_for_budget() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet("+++[>++<-]").expect("Could not load snippet");
+        interpreter.add_breakpoint(6);
+        assert!(matches!(interpreter.run_for(4), StepOutcome::Runnable));
+        assert_eq!(interpreter.vm().pc(), 4);
+        assert!(matches!(interpreter.run_for(100), StepOutcome::Paused));
+        assert_eq!(interpreter.vm().pc(), 6);
+        interpreter.remove_breakpoint(6);
+        let mut slices = 0;
+        while let StepOutcome::Runnable = interpreter.run_for(5) {
+            slices += 1;
+        }
+        assert!(matches!(interpreter.run_for(5), StepOutcome::Finished));
+        assert_eq!(slices, 3);
+        assert_eq!(interpreter.vm().peek(1), Some(6));
+        interpreter.load_snippet("-").expect("Could not load snippet");
+        interpreter.vm_mut().set_address(5);
+        assert!(matches!(interpreter.run_for(1), StepOutcome::Runnable));
+        assert_eq!(interpreter.vm().mem_rd(), 255);
+    }
+
+    /// A session suspended in the middle of the program must continue in another interpreter
+    #[test]
+    fn transfer_session() {
+        let mut interpreter = Interpreter::new();
+        interpreter.load_snippet("++++++[>++++++++<-]>[-<+>]").expect("Could not load snippet");
+        assert!(matches!(interpreter.run_for(30), StepOutcome::Runnable));
+        let program = interpreter.program().serialize();
+        let state = interpreter.vm().state().serialize();
+        let mut other = Interpreter::new();
+        other.load_program(Program::deserialize(&program).expect("Could not decode program"));
+        other.restore(VmState::deserialize(&state).expect("Could not decode state")).expect("Invalid state");
+        assert!(matches!(other.run_for(usize::MAX), StepOutcome::Finished));
+        interpreter.run_until_stopped().expect("Error while running");
+        assert_eq!(other.vm().state(), interpreter.vm().state());
+        assert_eq!(other.vm().peek(0), Some(48));
+        // States that do not match the program or their tape are rejected
+        let state = VmState::deserialize(&state).expect("Could not decode state");
+        let mut other = Interpreter::new();
+        other.load_snippet("+").expect("Could not load snippet");
+        let err = other.restore(state.clone()).expect_err("Restored a state past the program");
+        assert_eq!(err.to_string(), "Program counter 17 past the program of 2 instructions");
+        let err = interpreter.restore(VmState { mp: state.memory.len(), ..state }).expect_err("Restored a bad pointer");
+        assert!(err.to_string().starts_with("Memory pointer"), "{}", err);
+        assert_eq!(other.vm().pc(), 0);
+    }
+
+    /// Input following '!' must be fed to the program
+    #[test]
+    fn bang_input() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.set_syntax(Syntax { bang_input: true, ..Syntax::default() });
+        interpreter.load_source(b",[.,]!bang!").expect("Could not load source");
+        interpreter.run().expect("Error while running");
+        assert_eq!(output.contents(), b"bang!");
+    }
+
+    /// pbrain procedures can be defined, called and redefined, and run through the optimizer
+    #[test]
+    fn procedures() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.set_passes(PassManager::with_level(OptimizationLevel::O2, interpreter.vm_settings()));
+        interpreter.set_syntax(Syntax { procedures: true, ..Syntax::default() });
+        // Procedure 65 prints and increments the current cell, then calls procedure 66 which decrements it back
+        interpreter.load_source(b"++++++++[>++++++++<-]>+(.+:)+(>[-]<-)-::").expect("Could not load source");
+        interpreter.run().expect("Error while running");
+        assert_eq!(output.contents(), b"AA");
+        interpreter.load_source(b"+:").expect("Could not load source");
+        let err = interpreter.run().expect_err("Undefined procedure called");
+        assert!(err.to_string().starts_with("Undefined procedure 1 at pc 0x00000001"), "{}", err);
+    }
+
+    /// Extended Brainfuck Type I: storage register, bitwise operations and early exit
+    #[test]
+    fn extended_type_1() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.set_syntax(Syntax { extended: true, ..Syntax::default() });
+        let source = b"++++++++[>++++++++<-]>+$}.{.>+++&.|.~~!.[-]++++[.@-]+.";
+        interpreter.load_source(source).expect("Could not load source");
+        interpreter.run().expect("Error while running");
+        assert_eq!(output.contents(), b" @\x01AA\x04");
+        assert_eq!(interpreter.vm().peek(2), Some(4));
+    }
+
+    /// Forked threads run in turn on copies of the tape, sharing the output
+    #[test]
+    fn fork() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(virtualmachine::Settings {
+            memory_size: 16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        });
+        interpreter.set_syntax(Syntax { fork: true, ..Syntax::default() });
+        // Only the child enters the loop, incrementing its copy of the 'A' before printing it
+        interpreter.load_source(b"++++++++[>++++++++<-]>+>Y[<<+>]<.").expect("Could not load source");
+        interpreter.run().expect("Error while running");
+        assert_eq!(output.contents(), b"AB");
+        assert_eq!(interpreter.vm().peek(1), Some(66));
+        assert_eq!(interpreter.status(), virtualmachine::Status::Finished);
+    }
+
+    /// Sparse tapes only allocate the cells that are written, even when they span a huge wrapping memory
+    #[test]
+    fn sparse_tape() {
+        let output = crate::ui::SharedBuffer::new();
+        let memory_size = 1 << 40;
+        let mut interpreter = Interpreter::with_tape(virtualmachine::Settings {
+            memory_size,
+            memory_overflow_behavior: virtualmachine::MemoryOverflowBehavior::Wrap,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..virtualmachine::Settings::default()
+        }, crate::interpreter::tape::TapeKind::Sparse.create(memory_size).unwrap());
+        interpreter.load_source(b"<++++++++[>++++++++<-]>+.<<.").expect("Could not load source");
+        interpreter.run().expect("Error while running");
+        assert_eq!(output.contents(), b"A\x00");
+        assert_eq!(interpreter.vm().peek(0), Some(65));
+        assert_eq!(interpreter.vm().peek(memory_size as isize - 1), Some(0));
+    }
+}
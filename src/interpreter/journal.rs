@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use super::virtualmachine::VirtualMachine;
+
+/// State of the virtual machine before executing an instruction, limited to what the instruction can change
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+    pub pc: usize,
+    pub address: isize,
+    /// Cell written by the instruction, with its previous value
+    pub write: Option<(isize, u64)>,
+    /// Extended Brainfuck storage register
+    pub storage: u64,
+    pub procedure: ProcedureChange,
+}
+
+/// Change made by an instruction to the procedures of the virtual machine
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProcedureChange {
+    None,
+    /// A procedure was called, pushing its return address
+    Call,
+    /// A procedure returned to the address popped from the call stack
+    Return(usize),
+    /// A procedure was defined with an id, replacing the start address it had if any
+    Define(u64, Option<usize>),
+}
+
+/// Ring buffer of the deltas of the last executed instructions, used to undo them. Input, output and forks are not
+/// undone
+pub struct Journal {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+/* Journal ************************************************************************************************************/
+impl Journal {
+    /// Create a Journal remembering at most `capacity` instructions
+    pub fn with_capacity(capacity: usize) -> Journal {
+        Journal {
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Record an executed instruction, forgetting the oldest one if the journal is full
+    pub fn record(&mut self, delta: Delta) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    /// Undo the last recorded instruction on `vm`. Returns the undone delta, or `None` if the journal is empty
+    pub fn undo(&mut self, vm: &mut VirtualMachine) -> Option<Delta> {
+        let delta = self.deltas.pop_back()?;
+        if let Some((addr, val)) = delta.write {
+            vm.poke(addr, val);
+        }
+        let (procedures, call_stack, storage) = vm.procedures_mut();
+        match delta.procedure {
+            ProcedureChange::None => {}
+            ProcedureChange::Call => {
+                call_stack.pop();
+            }
+            ProcedureChange::Return(addr) => call_stack.push(addr),
+            ProcedureChange::Define(id, Some(start)) => {
+                procedures.insert(id, start);
+            }
+            ProcedureChange::Define(id, None) => {
+                procedures.remove(&id);
+            }
+        }
+        *storage = delta.storage;
+        vm.set_address(delta.address);
+        vm.set_pc(delta.pc);
+        vm.pause();
+        Some(delta)
+    }
+
+    /// Number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Whether no instruction can be undone
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forget all recorded instructions
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+}
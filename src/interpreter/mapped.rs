@@ -0,0 +1,262 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use super::tape::Tape;
+
+/// Size of a cell in a mapped file
+const CELL_BYTES: usize = std::mem::size_of::<u64>();
+
+/// Tape backed by a memory map. Anonymous maps reserve no memory up front, so that multi-gigabyte tapes do not hit the
+/// limits of the allocator; maps of a file also keep the cells in the file after the run, as 8 bytes per cell in the
+/// native byte order, starting from the leftmost cell. Like a `VecDeque`, the map grows geometrically and keeps room
+/// at both ends, so that tapes extended a cell at a time are not copied on every cell
+#[derive(Debug)]
+pub struct Mapped {
+    map: *mut u64,
+    /// Number of cells mapped. Cells that are not in use are always 0
+    capacity: usize,
+    /// Index in the map of the first cell in use
+    front: usize,
+    len: usize,
+    file: Option<File>,
+}
+
+/* Mapped *************************************************************************************************************/
+impl Mapped {
+    /// Create a tape of `len` cells in an anonymous memory map
+    pub fn new(len: usize) -> io::Result<Mapped> {
+        Ok(Mapped { map: map(len, None)?, capacity: len, front: 0, len, file: None })
+    }
+
+    /// Create a tape of `len` cells in a map of the file at `path`, which is created or truncated
+    pub fn with_file<P: AsRef<Path>>(path: P, len: usize) -> io::Result<Mapped> {
+        let size = bytes(len)?;
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(size as u64)?;
+        Ok(Mapped { map: map(len, Some(&file))?, capacity: len, front: 0, len, file: Some(file) })
+    }
+
+    /// Replace the map with one of `capacity` cells, moving the cells in use to index `front`
+    fn remap(&mut self, capacity: usize, front: usize) {
+        let size = bytes(capacity).expect("Could not map the tape");
+        let map = match &self.file {
+            // The file keeps its content while it is unmapped
+            Some(file) => unsafe {
+                if capacity > self.capacity {
+                    file.set_len(size as u64).expect("Could not grow the tape file");
+                }
+                unmap(self.map, self.capacity);
+                let map = self::map(capacity, Some(file)).expect("Could not map the tape file");
+                std::ptr::copy(map.add(self.front), map.add(front), self.len);
+                std::ptr::write_bytes(map, 0, front);
+                std::ptr::write_bytes(map.add(front + self.len), 0, capacity - front - self.len);
+                if capacity < self.capacity {
+                    file.set_len(size as u64).expect("Could not shrink the tape file");
+                }
+                map
+            },
+            None => unsafe {
+                let map = self::map(capacity, None).expect("Could not map the tape");
+                std::ptr::copy_nonoverlapping(self.map.add(self.front), map.add(front), self.len);
+                unmap(self.map, self.capacity);
+                map
+            },
+        };
+        self.map = map;
+        self.capacity = capacity;
+        self.front = front;
+    }
+
+    fn slice(&self) -> &[u64] {
+        unsafe { std::slice::from_raw_parts(self.map.add(self.front), self.len) }
+    }
+
+    fn slice_mut(&mut self) -> &mut [u64] {
+        unsafe { std::slice::from_raw_parts_mut(self.map.add(self.front), self.len) }
+    }
+}
+
+impl Tape for Mapped {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> u64 {
+        self.slice()[index]
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut u64 {
+        &mut self.slice_mut()[index]
+    }
+
+    fn resize(&mut self, len: usize) {
+        let end = self.front.checked_add(len).expect("Could not map the tape");
+        if end > self.capacity {
+            self.remap(end.max(self.capacity.saturating_mul(2)), self.front);
+        }
+        // Cells removed from the end must read 0 if the tape grows again
+        if len < self.len {
+            self.slice_mut()[len..].fill(0);
+        }
+        self.len = len;
+    }
+
+    fn grow_front(&mut self, count: usize) {
+        if count > self.front {
+            // The room at the end is kept, and the rest of the new map goes to the front
+            let back = self.capacity - self.front - self.len;
+            let needed = self.len.checked_add(count).and_then(|len| len.checked_add(back));
+            let capacity = needed.expect("Could not map the tape").max(self.capacity.saturating_mul(2));
+            self.remap(capacity, capacity - back - self.len);
+        }
+        self.front -= count;
+        self.len += count;
+    }
+
+    fn clear(&mut self) {
+        match self.file {
+            Some(_) => self.slice_mut().fill(0),
+            // Anonymous maps get fresh pages, zeroed by the system without touching the old ones
+            None => {
+                let len = std::mem::take(&mut self.len);
+                self.remap(self.capacity, self.front);
+                self.len = len;
+            }
+        }
+    }
+
+    fn load(&mut self, cells: Vec<u64>) {
+        self.len = 0;
+        self.remap(cells.len(), 0);
+        self.len = cells.len();
+        self.slice_mut().copy_from_slice(&cells);
+    }
+
+    fn to_vec(&self) -> Vec<u64> {
+        self.slice().to_vec()
+    }
+
+    /// Copies live in an anonymous map, so that forked threads do not share the file
+    fn clone_box(&self) -> Box<dyn Tape> {
+        let mut copy = Mapped::new(self.len).expect("Could not map the tape");
+        copy.slice_mut().copy_from_slice(self.slice());
+        Box::new(copy)
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        unsafe {
+            // The file only holds the cells in use, from its start
+            if let Some(file) = &self.file {
+                std::ptr::copy(self.map.add(self.front), self.map, self.len);
+                unmap(self.map, self.capacity);
+                let _ = file.set_len((self.len * CELL_BYTES) as u64);
+            } else {
+                unmap(self.map, self.capacity);
+            }
+        }
+    }
+}
+
+/// Map `len` cells, shared with `file` or anonymous and private. Maps are never empty, which the system rejects
+fn map(len: usize, file: Option<&File>) -> io::Result<*mut u64> {
+    use std::os::unix::io::AsRawFd;
+    let bytes = bytes(len.max(1))?;
+    let (flags, fd) = match file {
+        Some(file) => (sys::MAP_SHARED, file.as_raw_fd()),
+        None => (sys::MAP_PRIVATE | sys::MAP_ANONYMOUS | sys::MAP_NORESERVE, -1),
+    };
+    let cells = unsafe { sys::mmap(std::ptr::null_mut(), bytes, sys::PROT_READ | sys::PROT_WRITE, flags, fd, 0) };
+    if cells == sys::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cells as *mut u64)
+}
+
+/// Size in bytes of `len` cells, which fails if it does not fit in the address space
+fn bytes(len: usize) -> io::Result<usize> {
+    len.checked_mul(CELL_BYTES).filter(|&bytes| bytes <= isize::MAX as usize).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::OutOfMemory, format!("A tape of {} cells does not fit in memory", len))
+    })
+}
+
+/// Unmap `len` cells mapped by [`map`] at `cells`
+unsafe fn unmap(cells: *mut u64, len: usize) {
+    sys::munmap(cells as *mut _, len.max(1) * CELL_BYTES);
+}
+
+/// Bindings to the C library, which the standard library already links
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const MAP_SHARED: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    #[cfg(target_os = "linux")]
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+    #[cfg(target_os = "linux")]
+    pub const MAP_NORESERVE: c_int = 0x4000;
+    #[cfg(target_os = "macos")]
+    pub const MAP_ANONYMOUS: c_int = 0x1000;
+    #[cfg(target_os = "macos")]
+    pub const MAP_NORESERVE: c_int = 0x40;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_as_dense() {
+        let mut tape = Mapped::new(3).expect("Could not map the tape");
+        *tape.get_mut(2) = 7;
+        tape.grow_front(2);
+        assert_eq!(tape.to_vec(), [0, 0, 0, 0, 7]);
+        tape.resize(4);
+        tape.resize(6);
+        assert_eq!(tape.to_vec(), [0, 0, 0, 0, 0, 0]);
+        let mut copy = tape.clone_box();
+        tape.load(vec![1, 2]);
+        assert_eq!((tape.to_vec(), copy.len()), (vec![1, 2], 6));
+        tape.clear();
+        copy.resize(0);
+        assert_eq!((tape.to_vec(), copy.to_vec()), (vec![0, 0], vec![]));
+    }
+
+    #[test]
+    fn grow_geometrically() {
+        let mut tape = Mapped::new(1).expect("Could not map the tape");
+        *tape.get_mut(0) = 1;
+        for _ in 1..1000 {
+            tape.resize(tape.len() + 1);
+            tape.grow_front(1);
+        }
+        assert_eq!((tape.len(), tape.get(999), tape.to_vec().iter().sum::<u64>()), (1999, 1, 1));
+        assert!(tape.capacity <= 4 * tape.len(), "{} cells mapped", tape.capacity);
+        assert!(Mapped::new(usize::MAX / 2).is_err());
+    }
+
+    #[test]
+    fn persist_cells() {
+        let path = std::env::temp_dir().join(format!("bfint-tape-{}", std::process::id()));
+        let mut tape = Mapped::with_file(&path, 2).expect("Could not map the tape file");
+        *tape.get_mut(1) = 0x41;
+        tape.grow_front(1);
+        drop(tape);
+        let content = std::fs::read(&path).expect("Could not read the tape file");
+        std::fs::remove_file(&path).expect("Could not remove the tape file");
+        let cells: Vec<u64> = content.chunks(CELL_BYTES)
+            .map(|cell| u64::from_ne_bytes(cell.try_into().expect("Partial cell")))
+            .collect();
+        assert_eq!(cells, [0, 0, 0x41]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parse::program::{Instruction, Program};
+
+/// Reads of cells that were never written, collected by the [`Interpreter`](super::interpreter::Interpreter) when
+/// enabled. Only output and loop guards are checked: they are where a forgotten initialization shows. The guards of
+/// loops clearing the cell, such as `[-]`, are not, since they are a common way to initialize it. A cell that holds
+/// a value other than 0 counts as written, since something, such as the initial memory, must have put it there. Cells
+/// are identified by their address relative to the initial position of the memory pointer
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryCheck {
+    written: HashSet<isize>,
+    /// Reads of cells never written, in the order their instruction first made one
+    reads: Vec<UninitializedRead>,
+    /// Index in `reads` of the read made by each instruction
+    sites: HashMap<usize, usize>,
+}
+
+/// Instruction that read a cell never written
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UninitializedRead {
+    /// Address of the instruction
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Address of the first cell read, relative to the initial position of the memory pointer
+    pub addr: isize,
+    /// Number of reads of cells never written by the instruction
+    pub count: u64,
+}
+
+/* MemoryCheck ********************************************************************************************************/
+impl MemoryCheck {
+    pub fn new() -> MemoryCheck {
+        MemoryCheck::default()
+    }
+
+    /// Check the accesses of the instruction of `program` at `pc`, executed with the memory pointer at `mp` on a cell
+    /// that held `value`. `written` is the cell it wrote, if any, as reported by
+    /// [`VirtualMachine::last_write`](super::virtualmachine::VirtualMachine::last_write)
+    pub fn record(&mut self, program: &Program, pc: usize, mp: isize, value: u64, written: Option<isize>) {
+        let instruction = program.instruction(pc);
+        let checked = match instruction {
+            Instruction::Output | Instruction::JNZ(_) => true,
+            Instruction::JZ(_) => !is_clear_loop(program, pc),
+            _ => false,
+        };
+        if checked && value == 0 && !self.written.contains(&mp) {
+            match self.sites.get(&pc) {
+                Some(&index) => self.reads[index].count += 1,
+                None => {
+                    self.sites.insert(pc, self.reads.len());
+                    self.reads.push(UninitializedRead { pc, instruction: *instruction, addr: mp, count: 1 });
+                }
+            }
+        }
+        if let Some(addr) = written {
+            self.written.insert(addr);
+        }
+    }
+
+    /// Instructions that read cells never written, in the order of their first such read
+    pub fn reads(&self) -> &[UninitializedRead] {
+        &self.reads
+    }
+
+    /// Whether the cell at `addr` was written
+    pub fn is_written(&self, addr: isize) -> bool {
+        self.written.contains(&addr)
+    }
+}
+
+/// Whether the loop starting at `pc` clears the current cell, as `[-]` or `[+]` do
+fn is_clear_loop(program: &Program, pc: usize) -> bool {
+    let body = program.instructions().get(pc + 1..pc + 3);
+    matches!(
+        body,
+        Some([Instruction::IncData | Instruction::DecData | Instruction::Add(1 | -1), Instruction::JNZ(_)]),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+
+    #[test]
+    fn find_uninitialized_reads() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_memory_check(true);
+        interpreter.load_source(b"+[>.<-]>>[-][<<]").unwrap();
+        interpreter.run().unwrap();
+        let check = interpreter.memory_check().expect("The memory check is enabled");
+        assert_eq!(check.reads(), [
+            UninitializedRead { pc: 3, instruction: Instruction::Output, addr: 1, count: 1 },
+            UninitializedRead { pc: 12, instruction: Instruction::JZ(16), addr: 2, count: 1 },
+        ]);
+        assert!(check.is_written(0) && !check.is_written(2));
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::parse::program::Instruction;
+
+/// One instruction of each kind, in the order of [`opcode`]
+pub(super) const KINDS: [Instruction; 28] = [
+    Instruction::IncPtr,
+    Instruction::DecPtr,
+    Instruction::IncData,
+    Instruction::DecData,
+    Instruction::Input,
+    Instruction::Output,
+    Instruction::JZ(0),
+    Instruction::JNZ(0),
+    Instruction::Add(0),
+    Instruction::Move(0),
+    Instruction::Set(0),
+    Instruction::MulAdd { offset: 0, factor: 0 },
+    Instruction::AddAt { offset: 0, value: 0 },
+    Instruction::SetAt { offset: 0, value: 0 },
+    Instruction::Debug,
+    Instruction::DefineProc(0),
+    Instruction::Return,
+    Instruction::CallProc,
+    Instruction::Store,
+    Instruction::Load,
+    Instruction::ShiftLeft,
+    Instruction::ShiftRight,
+    Instruction::Not,
+    Instruction::Xor,
+    Instruction::And,
+    Instruction::Or,
+    Instruction::Fork,
+    Instruction::Exit,
+];
+
+/// Statistics of an execution, collected by the [`VirtualMachine`](super::virtualmachine::VirtualMachine) since its
+/// last wakeup, see [`VirtualMachine::metrics`](super::virtualmachine::VirtualMachine::metrics)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of executed instructions
+    pub instructions: u64,
+    /// Executions of each kind of instruction, indexed by [`opcode`]
+    opcodes: [u64; KINDS.len()],
+    /// Leftmost address reached by the memory pointer, relative to its initial position
+    pub lowest_address: isize,
+    /// Rightmost address reached by the memory pointer, relative to its initial position
+    pub highest_address: isize,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Wall time since the start of the execution, until the program exited if it did
+    pub elapsed: Duration,
+}
+
+/* Metrics ************************************************************************************************************/
+impl Metrics {
+    /// Empty statistics for an execution starting with the memory pointer at `address`
+    pub fn new(address: isize) -> Metrics {
+        Metrics {
+            instructions: 0,
+            opcodes: [0; KINDS.len()],
+            lowest_address: address,
+            highest_address: address,
+            bytes_read: 0,
+            bytes_written: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Count an execution of `instruction`, after which the memory pointer is at `address`
+    pub fn record(&mut self, instruction: &Instruction, address: isize) {
+        self.record_many(instruction, 1);
+        self.record_address(address);
+    }
+
+    /// Count `count` executions of `instruction` at once, without updating the range of the memory pointer
+    pub fn record_many(&mut self, instruction: &Instruction, count: u64) {
+        self.opcodes[opcode(instruction)] += count;
+    }
+
+    /// Extend the range of the memory pointer to include `address`
+    pub fn record_address(&mut self, address: isize) {
+        self.lowest_address = self.lowest_address.min(address);
+        self.highest_address = self.highest_address.max(address);
+    }
+
+    /// Number of executions of instructions of the same kind as `instruction`, whatever their operands
+    pub fn count(&self, instruction: &Instruction) -> u64 {
+        self.opcodes[opcode(instruction)]
+    }
+
+    /// Mnemonic and number of executions of each kind of instruction that was executed, most frequent first
+    pub fn opcodes(&self) -> Vec<(&'static str, u64)> {
+        let mut opcodes: Vec<(&'static str, u64)> = KINDS.iter()
+            .map(|kind| (kind.mnemonic(), self.count(kind)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        opcodes
+    }
+
+    /// Number of cells between the leftmost and the rightmost positions of the memory pointer
+    pub fn cells_touched(&self) -> usize {
+        self.lowest_address.abs_diff(self.highest_address) + 1
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        writeln!(f, "Instructions executed: {}", self.instructions)?;
+        writeln!(f, "Wall time: {:?}", self.elapsed)?;
+        if seconds > 0.0 {
+            writeln!(f, "Instructions per second: {:.0}", self.instructions as f64 / seconds)?;
+        }
+        writeln!(
+            f, "Memory pointer: {} to {} ({} cells)", self.lowest_address, self.highest_address, self.cells_touched(),
+        )?;
+        writeln!(f, "Bytes read: {}", self.bytes_read)?;
+        writeln!(f, "Bytes written: {}", self.bytes_written)?;
+        writeln!(f, "Instructions:")?;
+        let total = self.instructions.max(1);
+        for (mnemonic, count) in self.opcodes() {
+            writeln!(f, "  {:<8}{:>12} ({:.1}%)", mnemonic, count, 100.0 * count as f64 / total as f64)?;
+        }
+        Ok(())
+    }
+}
+
+/// Index of the kind of `instruction` in [`KINDS`]
+pub(super) fn opcode(instruction: &Instruction) -> usize {
+    match instruction {
+        Instruction::IncPtr => 0,
+        Instruction::DecPtr => 1,
+        Instruction::IncData => 2,
+        Instruction::DecData => 3,
+        Instruction::Input => 4,
+        Instruction::Output => 5,
+        Instruction::JZ(_) => 6,
+        Instruction::JNZ(_) => 7,
+        Instruction::Add(_) => 8,
+        Instruction::Move(_) => 9,
+        Instruction::Set(_) => 10,
+        Instruction::MulAdd { .. } => 11,
+        Instruction::AddAt { .. } => 12,
+        Instruction::SetAt { .. } => 13,
+        Instruction::Debug => 14,
+        Instruction::DefineProc(_) => 15,
+        Instruction::Return => 16,
+        Instruction::CallProc => 17,
+        Instruction::Store => 18,
+        Instruction::Load => 19,
+        Instruction::ShiftLeft => 20,
+        Instruction::ShiftRight => 21,
+        Instruction::Not => 22,
+        Instruction::Xor => 23,
+        Instruction::And => 24,
+        Instruction::Or => 25,
+        Instruction::Fork => 26,
+        Instruction::Exit => 27,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::interpreter::virtualmachine::{
+        MemoryOverflowBehavior, Settings,
+    };
+    use crate::ui::SharedBuffer;
+
+    #[test]
+    fn opcodes_in_order() {
+        for (index, kind) in KINDS.iter().enumerate() {
+            assert_eq!(opcode(kind), index, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn collect_metrics() {
+        let mut interpreter = Interpreter::with_vm_settings(Settings {
+            memory_size: 16,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            input: Box::new(&b"ab"[..]),
+            output: Box::new(SharedBuffer::new()),
+            ..Settings::default()
+        });
+        interpreter.load_source(b",[.,]<<+>>>>").unwrap();
+        interpreter.run().unwrap();
+        let metrics = interpreter.vm().metrics();
+        assert_eq!(metrics.instructions, 1 + 2 * 4 + 2 + 1 + 4 + 1);
+        assert_eq!(metrics.opcodes()[..2], [("incp", 4), ("rd", 3)]);
+        assert_eq!(metrics.count(&Instruction::JZ(42)), 2);
+        assert_eq!((metrics.lowest_address, metrics.highest_address, metrics.cells_touched()), (-2, 2, 5));
+        assert_eq!((metrics.bytes_read, metrics.bytes_written), (2, 2));
+        assert_eq!(interpreter.vm().metrics().elapsed, metrics.elapsed);
+    }
+}
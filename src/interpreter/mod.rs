@@ -1,2 +1,25 @@
+#[cfg(feature = "async")]
+pub mod asyncio;
+pub mod coverage;
+pub mod debugger;
+pub mod device;
+pub mod differential;
+pub mod engine;
+pub mod gas;
+pub mod heatmap;
+pub mod hook;
+pub mod journal;
+#[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+pub mod mapped;
+pub mod memcheck;
+pub mod metrics;
+pub mod profile;
+pub mod smbf;
+pub mod state;
+pub mod tape;
+pub mod threaded;
+pub mod time;
+pub mod trace;
+#[allow(clippy::module_inception)]
 pub mod interpreter;
-pub mod virtualmachine;
\ No newline at end of file
+pub mod virtualmachine;
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::parse::program::{Instruction, Program, SourceLoc};
+
+/// Number of times each instruction of a program was executed, collected by the
+/// [`Interpreter`](super::interpreter::Interpreter) when profiling is enabled
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    counts: Vec<u64>,
+}
+
+/// Time spent in a loop
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LoopProfile {
+    /// Address of the `jz` opening the loop
+    pub start: usize,
+    /// Address of the `jnz` closing the loop
+    pub end: usize,
+    /// Location of the loop in the source code, if known
+    pub loc: Option<SourceLoc>,
+    /// Instructions executed inside the loop, including nested loops
+    pub instructions: u64,
+    /// Times the body of the loop was repeated
+    pub iterations: u64,
+}
+
+/* Profile ************************************************************************************************************/
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Count an execution of the instruction at `pc`
+    pub fn record(&mut self, pc: usize) {
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+    }
+
+    /// Number of times the instruction at `pc` was executed
+    pub fn count(&self, pc: usize) -> u64 {
+        self.counts.get(pc).copied().unwrap_or(0)
+    }
+
+    /// Total number of executed instructions
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Executions of the instructions compiled from each character of the source code of `program`
+    pub fn by_location(&self, program: &Program) -> BTreeMap<SourceLoc, u64> {
+        let mut counts = BTreeMap::new();
+        for (pc, &count) in self.counts.iter().enumerate() {
+            if let Some(loc) = program.location(pc) {
+                *counts.entry(loc).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+
+    /// Executions of each kind of instruction of `program`, by mnemonic, most frequent first
+    pub fn opcodes(&self, program: &Program) -> Vec<(&'static str, u64)> {
+        let mut counts: BTreeMap<&'static str, u64> = BTreeMap::new();
+        for (pc, &count) in self.counts.iter().enumerate().filter(|&(pc, _)| pc < program.len()) {
+            *counts.entry(program.instruction(pc).mnemonic()).or_insert(0) += count;
+        }
+        let mut counts: Vec<(&'static str, u64)> = counts.into_iter().filter(|&(_, count)| count > 0).collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts
+    }
+
+    /// Loops of `program` that executed the most instructions, at most `n` of them
+    pub fn hot_loops(&self, program: &Program, n: usize) -> Vec<LoopProfile> {
+        let mut loops: Vec<LoopProfile> = program.instructions()
+            .iter()
+            .enumerate()
+            .filter_map(|(start, instruction)| match *instruction {
+                // The jz jumps right after the matching jnz
+                Instruction::JZ(after) => Some(LoopProfile {
+                    start,
+                    end: after - 1,
+                    loc: program.location(start),
+                    instructions: (start..after).map(|pc| self.count(pc)).sum(),
+                    iterations: self.count(after - 1),
+                }),
+                _ => None,
+            })
+            .filter(|profile| profile.instructions > 0)
+            .collect();
+        loops.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        loops.truncate(n);
+        loops
+    }
+
+    /// Counts in the collapsed stack format read by flamegraph tools such as inferno: one line per nesting of loops,
+    /// such as `main;[line 1, column 4];[line 2, column 1] 42`, where each loop is a frame named after its location
+    /// (or its address) and the count is the number of instructions executed directly in the innermost loop
+    pub fn collapsed(&self, program: &Program) -> String {
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        let mut stack = vec![String::from("main")];
+        for (pc, instruction) in program.instructions().iter().enumerate() {
+            // The jumps delimiting a loop run once per iteration, so they belong to it
+            if let Instruction::JZ(_) = instruction {
+                stack.push(match program.location(pc) {
+                    Some(loc) => format!("[{}]", loc),
+                    None => format!("[0x{:08x}]", pc),
+                });
+            }
+            if self.count(pc) > 0 {
+                *stacks.entry(stack.join(";")).or_insert(0) += self.count(pc);
+            }
+            if let Instruction::JNZ(_) = instruction {
+                stack.pop();
+            }
+        }
+        stacks.into_iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect()
+    }
+
+    /// Human readable summary: the `n` hottest loops and the histogram of the executed instructions
+    pub fn report(&self, program: &Program, n: usize) -> String {
+        let total = self.total().max(1);
+        let mut report = format!("{} instructions executed\n", self.total());
+        let loops = self.hot_loops(program, n);
+        if !loops.is_empty() {
+            report.push_str("Hottest loops:\n");
+        }
+        for profile in loops {
+            let loc = profile.loc.map(|loc| format!(" at {}", loc)).unwrap_or_default();
+            writeln!(
+                report,
+                "  0x{:08x}-0x{:08x}{}: {} instructions ({:.1}%), {} iterations",
+                profile.start, profile.end, loc, profile.instructions,
+                100.0 * profile.instructions as f64 / total as f64, profile.iterations,
+            ).unwrap();
+        }
+        report.push_str("Instructions:\n");
+        for (mnemonic, count) in self.opcodes(program) {
+            writeln!(report, "  {:<8}{:>12} ({:.1}%)", mnemonic, count, 100.0 * count as f64 / total as f64).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+
+    #[test]
+    fn profile_loops() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_profiling(true);
+        interpreter.load_source(b"+++[>++[-]<-]").unwrap();
+        interpreter.run().unwrap();
+        let profile = interpreter.profile().expect("Profiling is enabled");
+        assert_eq!(profile.total(), 43);
+        assert_eq!(profile.by_location(interpreter.program())[&SourceLoc { row: 1, col: 9 }], 6);
+        let loops = profile.hot_loops(interpreter.program(), 5);
+        assert_eq!(loops.len(), 2);
+        assert_eq!((loops[0].start, loops[0].end, loops[0].instructions, loops[0].iterations), (3, 12, 39, 3));
+        assert_eq!((loops[1].start, loops[1].end, loops[1].instructions, loops[1].iterations), (7, 9, 18, 6));
+        assert_eq!(profile.opcodes(interpreter.program())[0], ("decd", 9));
+        assert!(profile.report(interpreter.program(), 1).starts_with("43 instructions executed\nHottest loops:\n  \
+                                                                     0x00000003-0x0000000c at line 1, column 4: 39"));
+        assert_eq!(profile.collapsed(interpreter.program()), "\
+            main 4\n\
+            main;[line 1, column 4] 21\n\
+            main;[line 1, column 4];[line 1, column 8] 18\n\
+        ");
+    }
+}
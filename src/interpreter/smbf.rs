@@ -0,0 +1,125 @@
+use std::error::Error;
+
+use crate::parse::program::Instruction;
+use super::virtualmachine::{Settings, Status, VirtualMachine};
+
+/// Runs self-modifying brainfuck (SMBF). The source code is copied to the beginning of the tape and the data area
+/// starts right after it, so that programs can read and rewrite their own code by moving the memory pointer to the
+/// left. Instructions are decoded from the tape when they are executed: characters that are not commands do nothing,
+/// and execution stops at the first cell holding 0 or at the end of the tape
+pub struct SelfModifying {
+    vm: VirtualMachine,
+}
+
+/* SelfModifying ******************************************************************************************************/
+impl SelfModifying {
+    /// Copy `source` to a new tape holding `settings.memory_size` cells of data after it
+    pub fn new(source: &[u8], mut settings: Settings) -> SelfModifying {
+        settings.memory_size += source.len();
+        let mut vm = VirtualMachine::with_settings(settings);
+        for (addr, &byte) in source.iter().enumerate() {
+            vm.poke(addr as isize, byte as u64);
+        }
+        vm.set_address(source.len() as isize);
+        SelfModifying { vm }
+    }
+
+    /// Get the virtual machine holding code and data
+    pub fn vm(&self) -> &VirtualMachine {
+        &self.vm
+    }
+
+    /// Get the virtual machine holding code and data mutably, to set it up before running the program
+    pub fn vm_mut(&mut self) -> &mut VirtualMachine {
+        &mut self.vm
+    }
+
+    /// Execute the program until it stops
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        self.vm.wakeup()?;
+        while *self.vm.status() == Status::Running {
+            self.step()?;
+        }
+        self.vm.flush()?;
+        Ok(())
+    }
+
+    /// Decode and execute the instruction under the program counter
+    pub fn step(&mut self) -> Result<(), Box<dyn Error>> {
+        let pc = self.vm.pc();
+        let instruction = match self.vm.peek(pc as isize).filter(|&cell| cell != 0) {
+            None => Instruction::Exit,
+            Some(cell) => match cell as u8 {
+                b'>' => Instruction::IncPtr,
+                b'<' => Instruction::DecPtr,
+                b'+' => Instruction::IncData,
+                b'-' => Instruction::DecData,
+                b'.' => Instruction::Output,
+                b',' => Instruction::Input,
+                // Brackets are matched when they are reached, since the code between them may have changed
+                b'[' if self.vm.mem_rd() == 0 => Instruction::JZ(self.matching(pc, b'[', b']', 1)? + 1),
+                b']' if self.vm.mem_rd() != 0 => Instruction::JNZ(self.matching(pc, b']', b'[', -1)?),
+                // Anything else, including a bracket that does not jump, only moves to the next cell
+                _ => Instruction::Move(0),
+            },
+        };
+        self.vm.execute_instruction(&instruction)?;
+        Ok(())
+    }
+
+    /// Address of the bracket `close` matching the bracket `open` at `pc`, searching in direction `step`
+    fn matching(&self, pc: usize, open: u8, close: u8, step: isize) -> Result<usize, Box<dyn Error>> {
+        let mut depth = 0;
+        let mut addr = pc as isize;
+        while let Some(cell) = self.vm.peek(addr) {
+            if cell == open as u64 {
+                depth += 1;
+            } else if cell == close as u64 {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(addr as usize);
+                }
+            }
+            addr += step;
+        }
+        Err(format!("No matching '{}' for the '{}' at address {}", close as char, open as char, pc).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::MemoryOverflowBehavior;
+    use crate::ui::SharedBuffer;
+
+    fn run(source: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = SharedBuffer::new();
+        let mut smbf = SelfModifying::new(source.as_bytes(), Settings {
+            memory_size: 16,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            max_instructions: Some(10_000),
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..Settings::default()
+        });
+        smbf.run()?;
+        Ok(output.contents())
+    }
+
+    #[test]
+    fn read_own_code() {
+        // Print the program backwards, starting from the cell before the data area. The tape is extended with a 0
+        // when the memory pointer moves past its beginning
+        assert_eq!(run("<[.<]").unwrap(), b"]<.[<");
+    }
+
+    #[test]
+    fn rewrite_own_code() {
+        // Turn the trailing '*' into a '.', which prints it once more when it is reached
+        assert_eq!(run("<++++.*").unwrap(), b"..");
+        let err = run("[").expect_err("Unmatched bracket accepted");
+        assert_eq!(err.to_string(), "No matching ']' for the '[' at address 0");
+        let err = run("+[>+]").expect_err("Endless loop not stopped");
+        assert!(err.to_string().starts_with("Instruction limit exceeded"), "{}", err);
+    }
+}
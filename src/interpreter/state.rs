@@ -0,0 +1,104 @@
+use std::error::Error;
+
+use crate::parse::bytecode::Reader;
+use super::virtualmachine::Status;
+
+/// Magic bytes at the beginning of every serialized [`VmState`]
+pub const MAGIC: &[u8; 4] = b"BFS\0";
+/// Version of the format produced by [`VmState::serialize`]. States of version 1 lack the statuses added since
+pub const VERSION: u8 = 2;
+
+/// Snapshot of the execution state of a VirtualMachine, taken with
+/// [`VirtualMachine::state`](super::virtualmachine::VirtualMachine::state). Together with the serialized program it
+/// allows suspending a session and resuming it later, possibly in another process
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmState {
+    /// Content of the tape
+    pub memory: Vec<u64>,
+    /// Index of the cell the memory pointer started from
+    pub origin: usize,
+    /// Index of the cell under the memory pointer
+    pub mp: usize,
+    pub pc: usize,
+    pub status: Status,
+}
+
+/* VmState ************************************************************************************************************/
+impl VmState {
+    /// Encode the state: the magic bytes and the version, followed by status, program counter, origin, memory pointer,
+    /// tape length and cells, all as 64 bit little endian integers except the status byte. The error message of a
+    /// halted machine comes last, as its length and UTF-8 bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(match self.status {
+            Status::Idle => 0,
+            Status::Running => 1,
+            Status::Paused => 2,
+            Status::WaitingForInput => 3,
+            Status::Finished => 4,
+            Status::Halted(_) => 5,
+        });
+        for n in [self.pc, self.origin, self.mp, self.memory.len()] {
+            bytes.extend((n as u64).to_le_bytes());
+        }
+        for cell in &self.memory {
+            bytes.extend(cell.to_le_bytes());
+        }
+        if let Status::Halted(message) = &self.status {
+            bytes.extend((message.len() as u64).to_le_bytes());
+            bytes.extend(message.as_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a state produced by [`VmState::serialize`]
+    pub fn deserialize(bytes: &[u8]) -> Result<VmState, Box<dyn Error>> {
+        if bytes.len() <= MAGIC.len() || !bytes.starts_with(MAGIC) {
+            return Err("Not a virtual machine state".into());
+        }
+        if !(1..=VERSION).contains(&bytes[MAGIC.len()]) {
+            return Err(format!("Unsupported state version: {}", bytes[MAGIC.len()]).into());
+        }
+        let mut reader = Reader::new(&bytes[MAGIC.len() + 1..]);
+        let mut status = match reader.next_byte() {
+            Some(0) => Status::Idle,
+            Some(1) => Status::Running,
+            Some(2) => Status::Paused,
+            Some(3) => Status::WaitingForInput,
+            Some(4) => Status::Finished,
+            Some(5) => Status::Halted(String::new()),
+            Some(status) => return Err(format!("Invalid status: {}", status).into()),
+            None => return Err("Unexpected end of state".into()),
+        };
+        let pc = reader.next_i64()? as usize;
+        let origin = reader.next_i64()? as usize;
+        let mp = reader.next_i64()? as usize;
+        let len = reader.next_i64()? as usize;
+        let memory = (0..len).map(|_| reader.next_i64().map(|cell| cell as u64)).collect::<Result<_, _>>()?;
+        if let Status::Halted(message) = &mut status {
+            let len = reader.next_i64()? as usize;
+            let bytes: Option<Vec<u8>> = (0..len).map(|_| reader.next_byte()).collect();
+            *message = String::from_utf8(bytes.ok_or("Unexpected end of state")?)?;
+        }
+        if reader.next_byte().is_some() {
+            return Err("Trailing bytes after state".into());
+        }
+        Ok(VmState { memory, origin, mp, pc, status })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let state = VmState { memory: vec![0, 1, u64::MAX, 42], origin: 1, mp: 3, pc: 17, status: Status::Paused };
+        assert_eq!(VmState::deserialize(&state.serialize()).expect("Could not deserialize"), state);
+        let state = VmState { status: Status::Halted(String::from("Cell overflow")), ..state };
+        assert_eq!(VmState::deserialize(&state.serialize()).expect("Could not deserialize"), state);
+        assert!(VmState::deserialize(b"BFC\0\x01").is_err());
+        assert!(VmState::deserialize(&state.serialize()[..40]).is_err());
+    }
+}
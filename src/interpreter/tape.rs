@@ -0,0 +1,246 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::str::FromStr;
+
+/// Number of cells in each page of a [`Sparse`] tape
+pub const PAGE_SIZE: usize = 4096;
+
+/// Storage for the cells of a [`VirtualMachine`](super::virtualmachine::VirtualMachine), see
+/// [`Interpreter::with_tape`](super::interpreter::Interpreter::with_tape). Cells are indexed from 0 and start at 0;
+/// tapes can grow at both ends. Accessing a cell past the end is a bug of the caller, and panics
+pub trait Tape {
+    /// Number of cells
+    fn len(&self) -> usize;
+
+    /// Whether the tape has no cells
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Value of the cell at `index`
+    fn get(&self, index: usize) -> u64;
+
+    /// Mutable reference to the cell at `index`
+    fn get_mut(&mut self, index: usize) -> &mut u64;
+
+    /// Add or remove cells at the end, so that the tape holds `len` of them
+    fn resize(&mut self, len: usize);
+
+    /// Insert `count` cells at the beginning, shifting the others right
+    fn grow_front(&mut self, count: usize);
+
+    /// Set every cell to 0
+    fn clear(&mut self);
+
+    /// Replace the content of the tape with `cells`
+    fn load(&mut self, cells: Vec<u64>);
+
+    /// Copy of all the cells
+    fn to_vec(&self) -> Vec<u64>;
+
+    /// Copy of the tape, with the same implementation
+    fn clone_box(&self) -> Box<dyn Tape>;
+}
+
+/// Tapes built into the crate, which can be selected by name
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TapeKind {
+    /// [`Dense`]
+    Dense,
+    /// [`Sparse`]
+    Sparse,
+    /// [`Mapped`](super::mapped::Mapped), in an anonymous memory map
+    #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+    Mapped,
+}
+
+/// Tape holding every cell in a contiguous buffer, which is as fast as it gets for the few thousand cells most
+/// programs use
+#[derive(Clone, Debug, Default)]
+pub struct Dense(VecDeque<u64>);
+
+/// Tape split in pages of [`PAGE_SIZE`] cells, which are only allocated when one of their cells is written. Tapes of
+/// billions of cells cost nothing until they are used, and programs reaching far away cells only pay for the pages
+/// they touch
+#[derive(Clone, Debug, Default)]
+pub struct Sparse {
+    len: usize,
+    /// Number of cells inserted at the beginning, which moved cell 0 of the first page to this index
+    front: usize,
+    /// Allocated pages, indexed by the position of their first cell relative to the first page divided by [`PAGE_SIZE`]
+    pages: HashMap<isize, Box<[u64]>>,
+}
+
+/* TapeKind ***********************************************************************************************************/
+impl TapeKind {
+    /// Create a tape of this kind with `len` cells. Fails if the system cannot map a tape that large
+    pub fn create(self, len: usize) -> io::Result<Box<dyn Tape>> {
+        Ok(match self {
+            TapeKind::Dense => Box::new(Dense::new(len)),
+            TapeKind::Sparse => Box::new(Sparse::new(len)),
+            #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+            TapeKind::Mapped => Box::new(super::mapped::Mapped::new(len)?),
+        })
+    }
+}
+
+impl FromStr for TapeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dense" => Ok(TapeKind::Dense),
+            "sparse" => Ok(TapeKind::Sparse),
+            #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+            "mmap" => Ok(TapeKind::Mapped),
+            _ => Err(format!("Invalid tape: '{}'", s)),
+        }
+    }
+}
+
+/* Dense **************************************************************************************************************/
+impl Dense {
+    /// Create a tape of `len` cells
+    pub fn new(len: usize) -> Dense {
+        Dense(VecDeque::from(vec![0; len]))
+    }
+}
+
+impl Tape for Dense {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get(&self, index: usize) -> u64 {
+        self.0[index]
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut u64 {
+        &mut self.0[index]
+    }
+
+    fn resize(&mut self, len: usize) {
+        self.0.resize(len, 0);
+    }
+
+    fn grow_front(&mut self, count: usize) {
+        for _ in 0..count {
+            self.0.push_front(0);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.iter_mut().for_each(|cell| *cell = 0);
+    }
+
+    fn load(&mut self, cells: Vec<u64>) {
+        self.0 = VecDeque::from(cells);
+    }
+
+    fn to_vec(&self) -> Vec<u64> {
+        self.0.iter().copied().collect()
+    }
+
+    fn clone_box(&self) -> Box<dyn Tape> {
+        Box::new(self.clone())
+    }
+}
+
+/* Sparse *************************************************************************************************************/
+impl Sparse {
+    /// Create a tape of `len` cells, without allocating any of them
+    pub fn new(len: usize) -> Sparse {
+        Sparse { len, front: 0, pages: HashMap::new() }
+    }
+
+    /// Number of pages allocated so far
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Page holding the cell at `index`, and position of the cell in the page
+    fn locate(&self, index: usize) -> (isize, usize) {
+        assert!(index < self.len, "Cell {} is out of a tape of {} cells", index, self.len);
+        let position = index as isize - self.front as isize;
+        (position.div_euclid(PAGE_SIZE as isize), position.rem_euclid(PAGE_SIZE as isize) as usize)
+    }
+}
+
+impl Tape for Sparse {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> u64 {
+        let (page, offset) = self.locate(index);
+        self.pages.get(&page).map_or(0, |page| page[offset])
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut u64 {
+        let (page, offset) = self.locate(index);
+        &mut self.pages.entry(page).or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice())[offset]
+    }
+
+    fn resize(&mut self, len: usize) {
+        // Cells removed from the end must read 0 if the tape grows again
+        let end = len as isize - self.front as isize;
+        let size = PAGE_SIZE as isize;
+        self.pages.retain(|&page, _| page * size < end);
+        if let Some(page) = self.pages.get_mut(&end.div_euclid(size)) {
+            page[end.rem_euclid(size) as usize..].fill(0);
+        }
+        self.len = len;
+    }
+
+    fn grow_front(&mut self, count: usize) {
+        self.front += count;
+        self.len += count;
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    fn load(&mut self, cells: Vec<u64>) {
+        *self = Sparse::new(cells.len());
+        for (index, cell) in cells.into_iter().enumerate().filter(|&(_, cell)| cell != 0) {
+            *self.get_mut(index) = cell;
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u64> {
+        (0..self.len).map(|index| self.get(index)).collect()
+    }
+
+    fn clone_box(&self) -> Box<dyn Tape> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_as_dense() {
+        for kind in [TapeKind::Dense, TapeKind::Sparse] {
+            let mut tape = kind.create(3).unwrap();
+            *tape.get_mut(2) = 7;
+            tape.grow_front(PAGE_SIZE + 1);
+            assert_eq!(tape.len(), PAGE_SIZE + 4);
+            assert_eq!((tape.get(0), tape.get(PAGE_SIZE + 3)), (0, 7));
+            *tape.get_mut(0) = 1;
+            tape.resize(PAGE_SIZE + 3);
+            tape.resize(2 * PAGE_SIZE);
+            assert_eq!((tape.get(0), tape.get(PAGE_SIZE + 3), tape.len()), (1, 0, 2 * PAGE_SIZE));
+            let mut copy = tape.clone_box();
+            copy.clear();
+            assert_eq!((tape.get(0), copy.get(0)), (1, 0));
+            copy.load(vec![0, 5, 0]);
+            assert_eq!(copy.to_vec(), [0, 5, 0]);
+        }
+        let mut tape = Sparse::new(1 << 40);
+        *tape.get_mut((1 << 40) - 1) = 1;
+        assert_eq!((tape.get(0), tape.get((1 << 40) - 1), tape.pages()), (0, 1, 1));
+    }
+}
@@ -0,0 +1,243 @@
+use std::error::Error;
+
+use crate::parse::program::{Instruction, Program};
+use super::engine::{Engine, Switch};
+use super::virtualmachine::{CellOverflowBehavior, MemoryOverflowBehavior, Settings, Status, VirtualMachine};
+
+/// Address returned by handlers when the program stopped running
+const STOP: usize = usize::MAX;
+
+/// Executes one instruction of the translated program, at address `pc`, and returns the address of the next one
+type Handler = fn(&mut VirtualMachine, &Op, usize) -> Result<usize, Box<dyn Error>>;
+
+/// Threaded dispatch engine: before running, every instruction is translated into a handler function specialized for
+/// its kind and for the settings of the virtual machine, with its operands decoded in advance. Instructions then run
+/// by calling their handler, without the bookkeeping that [`Switch`] does for each of them: execution counts are
+/// gathered per address and added to the statistics when the run ends.
+///
+/// Single steps, and runs on virtual machines with breakpoints, execution limits or gas, an interrupt flag or a
+/// pedantic memory pointer, are delegated to [`Switch`]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Threaded;
+
+/// Translated instruction
+struct Op {
+    handler: Handler,
+    instruction: Instruction,
+    /// Decoded operand: jump target, or value to add or store already reduced to the cell width
+    operand: u64,
+    /// Largest value of a cell
+    max: u64,
+}
+
+/* Threaded ***********************************************************************************************************/
+impl Engine for Threaded {
+    fn name(&self) -> &str {
+        "threaded"
+    }
+
+    fn step(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>> {
+        Switch.step(vm, program)
+    }
+
+    fn run(&mut self, vm: &mut VirtualMachine, program: &Program) -> Result<(), Box<dyn Error>> {
+        let limited = vm.settings().max_instructions.is_some() || vm.settings().max_wall_time.is_some()
+            || vm.gas().is_some();
+        let checked = vm.interruptible() || vm.pedantic_pointer() || vm.has_devices();
+        if limited || vm.breakpoints().next().is_some() || checked {
+            return Switch.run(vm, program);
+        }
+        if *vm.status() != Status::Running {
+            return Ok(());
+        }
+        let settings = vm.settings();
+        let ops: Vec<Op> = program.instructions().iter().map(|instruction| translate(instruction, settings)).collect();
+        let mut counts = vec![0u64; ops.len()];
+        let mut pc = vm.pc();
+        let mut result = Ok(());
+        while let Some(op) = ops.get(pc) {
+            counts[pc] += 1;
+            match (op.handler)(vm, op, pc) {
+                Ok(next) => pc = next,
+                Err(err) => {
+                    // The failing instruction had no effect
+                    counts[pc] -= 1;
+                    vm.account_failure();
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        if pc != STOP {
+            vm.set_pc(pc);
+        }
+        for (op, count) in ops.iter().zip(counts).filter(|&(_, count)| count > 0) {
+            vm.account(&op.instruction, count);
+        }
+        result
+    }
+}
+
+/// Choose the handler of `instruction` and decode its operand for a virtual machine configured by `settings`
+fn translate(instruction: &Instruction, settings: &Settings) -> Op {
+    let max = settings.cell_width.max_value();
+    let wrap = settings.cell_overflow_behavior == CellOverflowBehavior::Wrap;
+    // Handlers that move the memory pointer do not set the program counter, which errors refer to
+    let moves = settings.memory_overflow_behavior != MemoryOverflowBehavior::Error;
+    let (handler, operand): (Handler, u64) = match *instruction {
+        Instruction::IncPtr | Instruction::DecPtr | Instruction::Move(_) if moves => (move_mp, 0),
+        Instruction::IncData if wrap => (add, 1),
+        Instruction::DecData if wrap => (add, max),
+        // Two's complement addition modulo the cell size
+        Instruction::Add(n) if wrap => (add, n as u64 & max),
+        Instruction::Set(val) => (set, val & max),
+        Instruction::MulAdd { .. } if moves => (mul_add, 0),
+        Instruction::AddAt { value, .. } if wrap && moves => (add_at, value as u64 & max),
+        Instruction::SetAt { value, .. } if moves => (set_at, value & max),
+        Instruction::JZ(addr) => (jz, addr as u64),
+        Instruction::JNZ(addr) => (jnz, addr as u64),
+        _ => (generic, 0),
+    };
+    Op { handler, instruction: *instruction, operand, max }
+}
+
+fn move_mp(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    match op.instruction {
+        Instruction::IncPtr => vm.move_mp(1)?,
+        Instruction::DecPtr => vm.move_mp(-1)?,
+        Instruction::Move(n) => vm.move_mp(n)?,
+        _ => unreachable!("Not a move"),
+    }
+    vm.track_address();
+    Ok(pc + 1)
+}
+
+fn add(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    let cell = vm.cell_mut();
+    *cell = cell.wrapping_add(op.operand) & op.max;
+    Ok(pc + 1)
+}
+
+fn set(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    *vm.cell_mut() = op.operand;
+    Ok(pc + 1)
+}
+
+fn mul_add(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    if let Instruction::MulAdd { offset, factor } = op.instruction {
+        vm.mul_add(offset, factor)?;
+    }
+    Ok(pc + 1)
+}
+
+fn add_at(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    if let Instruction::AddAt { offset, .. } = op.instruction {
+        vm.move_mp(offset)?;
+        let cell = vm.cell_mut();
+        *cell = cell.wrapping_add(op.operand) & op.max;
+        vm.move_mp(-offset)?;
+    }
+    Ok(pc + 1)
+}
+
+fn set_at(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    if let Instruction::SetAt { offset, .. } = op.instruction {
+        vm.move_mp(offset)?;
+        *vm.cell_mut() = op.operand;
+        vm.move_mp(-offset)?;
+    }
+    Ok(pc + 1)
+}
+
+fn jz(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    Ok(if vm.mem_rd() == 0 { op.operand as usize } else { pc + 1 })
+}
+
+fn jnz(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    Ok(if vm.mem_rd() != 0 { op.operand as usize } else { pc + 1 })
+}
+
+/// Any other instruction, executed by the virtual machine itself
+fn generic(vm: &mut VirtualMachine, op: &Op, pc: usize) -> Result<usize, Box<dyn Error>> {
+    vm.set_pc(pc);
+    let next = vm.dispatch(&op.instruction)?;
+    vm.track_address();
+    vm.set_pc(next);
+    Ok(if *vm.status() == Status::Running { next } else { STOP })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::optimizer::{OptimizationLevel, PassManager};
+    use crate::testing::generator::Generator;
+    use crate::testing::random::Rng;
+    use crate::ui::SharedBuffer;
+
+    /// Run `source` with `engine`, returning the output, the final state and statistics of the machine, and the error
+    fn run(
+        engine: Box<dyn Engine>, source: &[u8], memory_overflow: MemoryOverflowBehavior,
+        cell_overflow: CellOverflowBehavior, level: OptimizationLevel,
+    ) -> impl PartialEq + std::fmt::Debug {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(Settings {
+            memory_size: 16,
+            memory_overflow_behavior: memory_overflow,
+            cell_overflow_behavior: cell_overflow,
+            input: Box::new(&b"\x03\xfe\x80"[..]),
+            output: Box::new(output.clone()),
+            ..Settings::default()
+        }).with_engine(engine);
+        interpreter.set_passes(PassManager::with_level(level, interpreter.vm_settings()));
+        interpreter.load_source(source).unwrap();
+        let error = interpreter.run().err().map(|err| err.to_string());
+        let mut metrics = interpreter.vm().metrics();
+        metrics.elapsed = Default::default();
+        (output.contents(), interpreter.vm().state(), metrics, interpreter.vm().pc(), error)
+    }
+
+    #[test]
+    fn same_as_switch() {
+        let generator = Generator { length: 40, max_depth: 2, cells: 8, io: true };
+        let mut rng = Rng::new(3);
+        for _ in 0..50 {
+            let source = generator.source(&mut rng);
+            for cell_overflow in [CellOverflowBehavior::Wrap, CellOverflowBehavior::Error] {
+                for level in [OptimizationLevel::O0, OptimizationLevel::O2] {
+                    let expected = run(Box::new(Switch), &source, MemoryOverflowBehavior::Extend, cell_overflow, level);
+                    let actual = run(Box::new(Threaded), &source, MemoryOverflowBehavior::Extend, cell_overflow, level);
+                    assert_eq!(actual, expected, "{}", String::from_utf8_lossy(&source));
+                }
+            }
+        }
+        let memory = MemoryOverflowBehavior::Extend;
+        let error = run(Box::new(Threaded), b"+\n<--", memory, CellOverflowBehavior::Error, OptimizationLevel::O0);
+        let expected = run(Box::new(Switch), b"+\n<--", memory, CellOverflowBehavior::Error, OptimizationLevel::O0);
+        assert_eq!(error, expected);
+        assert!(format!("{:?}", error).contains("Cell underflow at pc 0x00000002 (line 2, column 2)"));
+        let memory = MemoryOverflowBehavior::Error;
+        let error = run(Box::new(Threaded), b"+[>+]", memory, CellOverflowBehavior::Wrap, OptimizationLevel::O0);
+        let expected = run(Box::new(Switch), b"+[>+]", memory, CellOverflowBehavior::Wrap, OptimizationLevel::O0);
+        assert_eq!(error, expected);
+        assert!(format!("{:?}", error).contains("Memory pointer moved right of the tape at pc 0x00000002"));
+    }
+
+    #[test]
+    fn unentered_multiplication_loop() {
+        // The copy loop points left of the tape, where an unchecked pointer would underflow if it were entered
+        for engine in [Box::new(Switch) as Box<dyn Engine>, Box::new(Threaded)] {
+            let output = SharedBuffer::new();
+            let mut interpreter = Interpreter::with_vm_settings(Settings {
+                memory_size: 16,
+                memory_overflow_behavior: MemoryOverflowBehavior::Unchecked,
+                output: Box::new(output.clone()),
+                ..Settings::default()
+            }).with_engine(engine);
+            interpreter.set_passes(PassManager::with_level(OptimizationLevel::O2, interpreter.vm_settings()));
+            interpreter.load_source(b"[-<+>]+.").unwrap();
+            interpreter.run().unwrap();
+            assert_eq!(output.contents(), [1]);
+        }
+    }
+}
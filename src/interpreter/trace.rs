@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::parse::program::{Instruction, SourceLoc};
+
+/// An instruction executed by the [`Interpreter`](super::interpreter::Interpreter), together with the state of the
+/// machine around it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Address of the instruction
+    pub pc: usize,
+    pub instruction: Instruction,
+    /// Address of the memory pointer before the instruction, relative to its initial position
+    pub mp: isize,
+    /// Value of the cell at `mp` before the instruction
+    pub before: u64,
+    /// Value of the cell at `mp` after the instruction
+    pub after: u64,
+    /// Where the instruction comes from in the source code, if known
+    pub loc: Option<SourceLoc>,
+}
+
+/// Receives every instruction executed by an interpreter, see
+/// [`Interpreter::set_trace_hook`](super::interpreter::Interpreter::set_trace_hook). Errors stop the execution
+pub trait TraceHook {
+    fn trace(&mut self, event: &TraceEvent) -> Result<(), Box<dyn Error>>;
+}
+
+/// Writes one line per executed instruction to a sink
+pub struct WriteTracer<W: Write> {
+    sink: W,
+}
+
+/* WriteTracer ********************************************************************************************************/
+impl<W: Write> WriteTracer<W> {
+    pub fn new(sink: W) -> WriteTracer<W> {
+        WriteTracer { sink }
+    }
+}
+
+impl<W: Write> TraceHook for WriteTracer<W> {
+    fn trace(&mut self, event: &TraceEvent) -> Result<(), Box<dyn Error>> {
+        let line = format!(
+            "0x{:08x}: {:<24} mp={:<6} {} -> {}",
+            event.pc, event.instruction.to_string(), event.mp, event.before, event.after,
+        );
+        match event.loc {
+            Some(loc) => writeln!(self.sink, "{:<64} ; {}", line, loc)?,
+            None => writeln!(self.sink, "{}", line)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::interpreter::Interpreter;
+    use crate::ui::SharedBuffer;
+
+    #[test]
+    fn trace_to_sink() {
+        let sink = SharedBuffer::new();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_trace_hook(Some(Box::new(WriteTracer::new(sink.clone()))));
+        interpreter.load_source(b"+\n>-").unwrap();
+        interpreter.run().unwrap();
+        let trace = String::from_utf8(sink.contents()).unwrap();
+        let lines: Vec<&str> = trace.lines().map(str::trim_end).collect();
+        assert_eq!(lines, [
+            "0x00000000: incd                     mp=0      0 -> 1            ; line 1, column 1",
+            "0x00000001: incp                     mp=0      1 -> 1            ; line 2, column 1",
+            "0x00000002: decd                     mp=1      0 -> 255          ; line 2, column 2",
+            "0x00000003: exit                     mp=1      255 -> 255",
+        ]);
+    }
+}
@@ -1,56 +1,283 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
-use std::io::{Read, Write};
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use crate::parse::program::Instruction;
+use super::device::{Device, Random};
+use super::gas::Gas;
+use super::metrics::Metrics;
+use super::state::VmState;
+use super::tape::{Dense, Tape};
+use super::time::Instant;
 
+/// Number of instructions between two checks of the wall time limit, since reading the clock is comparatively slow
+const TIME_CHECK_INTERVAL: u64 = 1024;
+/// Number of cells on each side of the memory pointer printed by the debug instruction
+const DUMP_RADIUS: isize = 4;
+/// Number of instructions between two ticks of the devices, see [`Device::tick`]
+pub const TICK_INSTRUCTIONS: u64 = 4096;
 
+/// Method of [`Device`] called on every device mapped
+type DeviceCallback = fn(&mut dyn Device, &mut VirtualMachine) -> Result<(), Box<dyn Error>>;
+/// Device mapped in a virtual machine, shared with its forks
+type SharedDevice = Rc<RefCell<Box<dyn Device>>>;
+
+/// A brainfuck machine: a tape of cells, a memory pointer and a program counter
 pub struct VirtualMachine {
-    memory: Vec<u8>,
+    memory: Box<dyn Tape>,
+    origin: usize,
     mp: usize,
     pc: usize,
     status: Status,
+    breakpoints: BTreeSet<usize>,
+    last_write: Option<(isize, u64)>,
+    /// Start address of each pbrain procedure defined so far
+    procedures: HashMap<u64, usize>,
+    /// Return addresses of the procedures being executed
+    call_stack: Vec<usize>,
+    /// Extended Brainfuck Type I storage register
+    storage: u64,
+    /// Bits of the last input byte that have not been read yet, and how many there are, for bit cells
+    input_bits: (u8, u32),
+    /// Bits written so far to the next output byte, and how many there are, for bit cells
+    output_bits: (u8, u32),
+    /// Bytes of an incomplete UTF-8 sequence written so far, for UTF-8 output
+    output_utf8: Vec<u8>,
+    /// Input and output shared with the threads forked from this machine, once there are any
+    shared_io: Option<(SharedReader, SharedWriter)>,
+    /// Threads forked by the last instruction, waiting to be scheduled
+    forks: Vec<VirtualMachine>,
+    /// Flag that pauses the machine when it is set, from another thread or a signal handler
+    interrupt: Option<Arc<AtomicBool>>,
+    /// Whether leaving the first `memory_size` cells is an error, whatever the memory overflow behavior
+    pedantic_pointer: bool,
+    /// Devices mapped at ranges of cells, by the address of their first cell relative to the initial position of the
+    /// memory pointer, with the address past their last cell. They are shared with the threads forked from this machine
+    devices: BTreeMap<isize, (isize, SharedDevice)>,
+    /// Budget that instructions are paid from, if metered
+    gas: Option<Gas>,
+    executed: u64,
+    started: Instant,
+    /// Statistics of the execution since the last wakeup
+    metrics: Metrics,
     settings: Settings,
 }
 
-#[derive(PartialEq, Eq)]
+/// Execution status of a VirtualMachine
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Status {
+    /// No program has been started since the machine was created or rewound
     Idle,
     Running,
+    /// The program counter reached a breakpoint, or the machine was interrupted: the instruction under the program
+    /// counter has not been executed yet
+    Paused,
+    /// An input command found no input available without blocking: it is executed again when the machine resumes
+    WaitingForInput,
+    /// The program exited
+    Finished,
+    /// An instruction failed, with this error message. The program cannot continue
+    Halted(String),
 }
 
+/// Configuration of a VirtualMachine. The default settings give a tape of 4096 unchecked 8-bit wrapping cells, reading
+/// from stdin and writing to stdout; [`Settings::builder`] changes only the settings it is told to. Settings may be
+/// added in any release: outside this crate they are built with the builder, or by changing the fields of the default
+/// ones
+#[non_exhaustive]
 pub struct Settings {
     pub memory_size: usize,
     pub memory_overflow_behavior: MemoryOverflowBehavior,
+    pub cell_overflow_behavior: CellOverflowBehavior,
+    pub cell_width: CellWidth,
+    /// Abort execution after this many instructions
+    pub max_instructions: Option<u64>,
+    /// Abort execution after running for this long
+    pub max_wall_time: Option<Duration>,
+    /// Abort execution when the program writes more than this many bytes
+    pub max_output_bytes: Option<u64>,
+    /// Abort execution when the program reads more than this many bytes
+    pub max_input_bytes: Option<u64>,
+    /// Instead of aborting execution, silently drop output past `max_output_bytes` and read input past
+    /// `max_input_bytes` as EOF
+    pub truncate_io: bool,
+    /// When output is flushed, besides before the program reads input and when it exits
+    pub flush_policy: FlushPolicy,
+    /// How the bytes output by the program are written
+    pub output_encoding: OutputEncoding,
+    /// Source of the numbers read by random devices, seeded differently on every run by default. Seed it for
+    /// reproducible runs
+    pub random: Random,
+    /// Bytes stored in the cells starting from the initial position of the memory pointer, each truncated to the cell
+    /// width, whenever memory is reset. Bytes past the end of the tape are ignored
+    pub initial_memory: Vec<u8>,
     pub input: Box<dyn Read>,
-    pub output: Box<dyn Write>,
+    pub output: Box<dyn Output>,
+}
+
+/// Builds [`Settings`] starting from the default ones:
+///
+/// ```
+/// use bfint::Settings;
+///
+/// let settings = Settings::builder().memory_size(1 << 16).wrap_pointer().stdin_input().build();
+/// assert_eq!(settings.memory_size, 65536);
+/// ```
+pub struct SettingsBuilder {
+    settings: Settings,
 }
 
+/// Sink for the output of a VirtualMachine. Every writer is one: the concrete writer can be taken back after the run
+/// with [`VirtualMachine::into_output`]
+pub trait Output: Write + Any {}
+
+/// What happens when the memory pointer is moved past either end of the tape. With `Extend` the tape grows on demand
+/// in both directions, so cells to the left of the initial position can be used. With `Error` the program stops with
+/// an error, while `Unchecked` leaves the check to Rust, which panics
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MemoryOverflowBehavior {
     Unchecked,
     Saturate,
     Wrap,
+    Extend,
+    Error,
+}
+
+/// Error of a program moving the memory pointer past either end of the tape, with the [`MemoryOverflowBehavior::Error`]
+/// behavior. The memory pointer is left on the cell at the end it moved past
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PointerOutOfBounds {
+    /// Address of the instruction moving the memory pointer
+    pub pc: usize,
+    /// Index in the tape of the cell the memory pointer moved to, negative left of the tape. It saturates for moves
+    /// too long to be represented
+    pub mp: isize,
+}
+
+/// Error of a program executing more instructions than [`Settings::max_instructions`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstructionLimitExceeded {
+    /// Address of the instruction that was not executed
+    pub pc: usize,
+    /// Number of instructions executed
+    pub executed: u64,
+}
+
+/// What happens when a cell is incremented past its maximum value or decremented below 0
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellOverflowBehavior {
+    Wrap,
+    Saturate,
+    Error,
+}
+
+/// When the output of a VirtualMachine is flushed. Whatever the policy, output is flushed when the program exits and
+/// before it reads input, so that prompts are shown before the program waits for an answer
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every byte
+    Byte,
+    /// After every newline
+    Newline,
+    /// Only before reading input
+    #[default]
+    Input,
+    /// Only when [`VirtualMachine::flush`] is called, besides before reading input and on exit as with every policy
+    Manual,
+}
+
+/// How the bytes output by a VirtualMachine are written
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// As they are
+    #[default]
+    Raw,
+    /// Decoded as UTF-8, so that the output is always valid text: bytes are held back until they complete a character,
+    /// and invalid sequences, including one left incomplete when the program exits, are replaced with U+FFFD
+    Utf8,
+}
+
+/// Width of a single memory cell. Cells are always stored as u64 and kept within the range of the selected width
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CellWidth {
+    /// Single bit cells, as in Boolfuck. Input and output are bitwise: each byte is read and written one bit at a
+    /// time, starting from the least significant bit, and the last output byte is padded with zeros
+    U1,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+/// Input shared by forked machines
+#[derive(Clone)]
+struct SharedReader(Rc<RefCell<Box<dyn Read>>>);
+
+/// Output shared by forked machines. Writes are never interleaved, since machines run one instruction at a time
+#[derive(Clone)]
+struct SharedWriter(Rc<RefCell<Box<dyn Output>>>);
+
+/// Input read by calling a function of the host, see [`SettingsBuilder::input_fn`]
+struct InputFn<F>(F);
+
+/// Output handed to a function of the host whenever it is flushed, see [`SettingsBuilder::output_fn`]
+struct OutputFn<F> {
+    write: F,
+    buffer: Vec<u8>,
 }
 
 /* Environment ********************************************************************************************************/
 impl VirtualMachine {
     /// Create a VirtualMachine with the default settings
     pub fn new() -> VirtualMachine {
-        VirtualMachine::with_settings(Settings {
-            memory_size: 4096,
-            memory_overflow_behavior: MemoryOverflowBehavior::Unchecked,
-            input: Box::new(std::io::stdin()),
-            output: Box::new(std::io::stdout()),
-        })
+        VirtualMachine::with_settings(Settings::default())
     }
 
     /// Create a VirtualMachine with the specified settings
     pub fn with_settings(settings: Settings) -> VirtualMachine {
-        VirtualMachine {
-            memory: vec![0; settings.memory_size],
+        let tape = Box::new(Dense::new(settings.memory_size));
+        VirtualMachine::with_tape(settings, tape)
+    }
+
+    /// Create a VirtualMachine with the specified settings, storing its cells in `tape` instead of a [`Dense`] one.
+    /// The tape should hold `settings.memory_size` cells
+    pub fn with_tape(settings: Settings, tape: Box<dyn Tape>) -> VirtualMachine {
+        let mut vm = VirtualMachine {
+            memory: tape,
+            origin: 0,
             mp: 0,
             pc: 0,
             status: Status::Idle,
+            breakpoints: BTreeSet::new(),
+            last_write: None,
+            procedures: HashMap::new(),
+            call_stack: Vec::new(),
+            storage: 0,
+            input_bits: (0, 0),
+            output_bits: (0, 0),
+            output_utf8: Vec::new(),
+            shared_io: None,
+            forks: Vec::new(),
+            interrupt: None,
+            pedantic_pointer: false,
+            devices: BTreeMap::new(),
+            gas: None,
+            executed: 0,
+            started: Instant::now(),
+            metrics: Metrics::new(0),
             settings,
-        }
+        };
+        vm.preload();
+        vm
     }
 
     /// Completely reset the VirtualMachine, including memory
@@ -59,49 +286,345 @@ impl VirtualMachine {
         self.reset_memory();
     }
 
-    /// Fill memory with 0
+    /// Fill memory with 0, then with the initial memory of the settings
     pub fn reset_memory(&mut self) {
-        self.memory.fill(0);
+        self.memory.clear();
+        self.preload();
+    }
+
+    /// Copy the initial memory of the settings to the tape
+    fn preload(&mut self) {
+        let max = self.settings.cell_width.max_value();
+        let cells = self.memory.len() - self.origin;
+        for (index, &byte) in self.settings.initial_memory.iter().take(cells).enumerate() {
+            *self.memory.get_mut(self.origin + index) = byte as u64 & max;
+        }
     }
 
-    /// Reset the core of the machine. This resets the program counter, memory pointer and status. Note: this method
-    /// does not clear the memory
+    /// Reset the core of the machine. This resets the program counter, memory pointer, status, procedures and storage
+    /// register. Note: this method does not clear the memory
     pub fn reset_core(&mut self) {
+        self.rewind();
+        self.mp = self.origin;
+        self.storage = 0;
+    }
+
+    /// Reset program counter, status and procedures, leaving memory and memory pointer untouched. This allows running
+    /// a new program on the tape left by the previous one
+    pub fn rewind(&mut self) {
         self.pc = 0;
-        self.mp = 0;
         self.status = Status::Idle;
+        self.procedures.clear();
+        self.call_stack.clear();
     }
 
-    /// Bring status from Idle to Running. Returns an error if status is not idle.
+    /// Bring status from Idle to Running. Returns an error if status is not idle. If there is a breakpoint on the
+    /// current program counter, status goes to Paused instead. Instruction count and wall time limits start over
     pub fn wakeup(&mut self) -> Result<(), Box<dyn Error>>{
         match self.status {
             Status::Idle => self.status = Status::Running,
             _ => return Err("Virtual Machine status is not Idle".into()),
         }
+        self.executed = 0;
+        self.started = Instant::now();
+        self.metrics = Metrics::new(self.address());
+        self.check_breakpoint();
+        Ok(())
+    }
+
+    /// Bring status from Paused or WaitingForInput to Running. Returns an error if status is neither
+    pub fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.status {
+            Status::Paused | Status::WaitingForInput => self.status = Status::Running,
+            _ => return Err("Virtual Machine status is not Paused".into()),
+        }
         Ok(())
     }
 
+    /// Set a breakpoint at instruction address `pc`: the machine pauses when the program counter reaches it. Returns
+    /// false if it was already set
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    /// Remove the breakpoint at instruction address `pc`. Returns false if there was none
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Pause the machine before the next instruction whenever `flag` is set, as if there were a breakpoint there. The
+    /// flag can be set from another thread or from a signal handler, and must be cleared before resuming, otherwise
+    /// the machine pauses again after one instruction
+    pub fn set_interrupt(&mut self, flag: Option<Arc<AtomicBool>>) {
+        self.interrupt = flag;
+    }
+
+    /// Whether the interrupt flag set with [`VirtualMachine::set_interrupt`] is set
+    pub fn interrupted(&self) -> bool {
+        self.interrupt.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Whether the machine has an interrupt flag, which must be checked after every instruction
+    pub fn interruptible(&self) -> bool {
+        self.interrupt.is_some()
+    }
+
+    /// Stop with an error as soon as the memory pointer is moved out of cells 0 to `memory_size - 1` relative to its
+    /// initial position, even if the memory overflow behavior would wrap it around, keep it on the tape or extend the
+    /// tape. This verifies that a program stays within the memory it is meant to use
+    pub fn set_pedantic_pointer(&mut self, enabled: bool) {
+        self.pedantic_pointer = enabled;
+    }
+
+    /// Whether the memory pointer is checked as set by [`VirtualMachine::set_pedantic_pointer`]
+    pub fn pedantic_pointer(&self) -> bool {
+        self.pedantic_pointer
+    }
+
+    /// Pay for every instruction from `gas`, according to its cost model, or stop metering if it is `None`. An
+    /// instruction that costs more than the gas left fails without being executed. The budget is not refilled when the
+    /// machine is reset, and is shared with the threads forked afterwards
+    pub fn set_gas(&mut self, gas: Option<Gas>) {
+        self.gas = gas;
+    }
+
+    /// Gas budget set with [`VirtualMachine::set_gas`], with the gas left
+    pub fn gas(&self) -> Option<&Gas> {
+        self.gas.as_ref()
+    }
+
+    /// Map `device` at the cell at `addr`, relative to the initial position of the memory pointer, replacing the device
+    /// mapped there, if any. The cell keeps its value, which the device sees on reads
+    pub fn map_device(&mut self, addr: isize, device: Box<dyn Device>) {
+        self.map_device_range(addr..addr.saturating_add(1), device);
+    }
+
+    /// Map `device` at every cell of `cells`, as [`VirtualMachine::map_device`] does, replacing every device mapped at
+    /// any of them. The device finds which cell is accessed with [`VirtualMachine::address`] or
+    /// [`VirtualMachine::last_write`]
+    pub fn map_device_range(&mut self, cells: Range<isize>, device: Box<dyn Device>) {
+        let overlapping: Vec<isize> = self.devices.range(..cells.end)
+            .filter(|(_, (end, _))| *end > cells.start)
+            .map(|(&start, _)| start)
+            .collect();
+        for start in overlapping {
+            self.devices.remove(&start);
+        }
+        if !cells.is_empty() {
+            self.devices.insert(cells.start, (cells.end, Rc::new(RefCell::new(device))));
+        }
+    }
+
+    /// Turn the cells of the device mapped at `addr` back into plain ones. Returns false if no device was mapped there
+    pub fn unmap_device(&mut self, addr: isize) -> bool {
+        match self.device(addr) {
+            Some(_) => {
+                let start = *self.devices.range(..=addr).next_back().expect("A device is mapped").0;
+                self.devices.remove(&start).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// Whether devices are mapped, which must be consulted on every access to their cells
+    pub fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// Iterate over the breakpoints in increasing address order
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
     /// Get current status
     pub fn status(&self) -> &Status {
         &self.status
     }
 
+    /// Replace the input of the VirtualMachine
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.settings.input = input;
+    }
+
+    /// Replace the output of the VirtualMachine. Pending output is flushed to the old one first
+    pub fn set_output(&mut self, output: Box<dyn Output>) -> Result<(), std::io::Error> {
+        self.flush()?;
+        self.settings.output = output;
+        Ok(())
+    }
+
+    /// Flush the output and take it back from the VirtualMachine, as the writer of type `W` it was created with. This
+    /// fails if the output is of another type, or if it is still shared with forked threads
+    pub fn into_output<W: Output>(mut self) -> Result<W, Box<dyn Error>> {
+        self.flush()?;
+        let mut output = std::mem::replace(&mut self.settings.output, Box::new(std::io::sink()));
+        if let Some((_, SharedWriter(shared))) = self.shared_io.take() {
+            drop(output);
+            output = Rc::try_unwrap(shared).map_err(|_| "The output is still shared with forked threads")?.into_inner();
+        }
+        let output: Box<dyn Any> = output;
+        match output.downcast::<W>() {
+            Ok(output) => Ok(*output),
+            Err(_) => Err(format!("The output is not a {}", std::any::type_name::<W>()).into()),
+        }
+    }
+
+    /// Get the settings the VirtualMachine was created with
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
     /// Return the current value of the program counter
     pub fn pc(&self) -> usize {
         self.pc
     }
 
-    /// Execute requested instruction
+    /// Take a snapshot of memory, memory pointer, program counter and status
+    pub fn state(&self) -> VmState {
+        VmState {
+            memory: self.memory.to_vec(),
+            origin: self.origin,
+            mp: self.mp,
+            pc: self.pc,
+            status: self.status.clone(),
+        }
+    }
+
+    /// Restore a snapshot taken with [`VirtualMachine::state`]. Breakpoints and settings are left untouched, procedures
+    /// are forgotten. Fails, leaving the machine untouched, if the memory pointer is outside the tape. The program
+    /// counter is not checked, as the machine does not know the program: see [`Interpreter::restore`]
+    ///
+    /// [`Interpreter::restore`]: super::interpreter::Interpreter::restore
+    pub fn restore(&mut self, state: VmState) -> Result<(), Box<dyn Error>> {
+        if state.origin > state.memory.len() {
+            return Err("Origin outside the tape".into());
+        }
+        if state.mp >= state.memory.len() {
+            return Err(format!("Memory pointer {} outside the tape of {} cells", state.mp, state.memory.len()).into());
+        }
+        self.memory.load(state.memory);
+        self.origin = state.origin;
+        self.mp = state.mp;
+        self.pc = state.pc;
+        self.status = state.status;
+        self.last_write = None;
+        self.procedures.clear();
+        self.call_stack.clear();
+        Ok(())
+    }
+
+    /// Start address of each procedure, return addresses of the procedures being executed and storage register
+    pub(super) fn procedures(&self) -> (&HashMap<u64, usize>, &[usize], u64) {
+        (&self.procedures, &self.call_stack, self.storage)
+    }
+
+    /// Mutable procedures, call stack and storage register, see [`VirtualMachine::procedures`]
+    pub(super) fn procedures_mut(&mut self) -> (&mut HashMap<u64, usize>, &mut Vec<usize>, &mut u64) {
+        (&mut self.procedures, &mut self.call_stack, &mut self.storage)
+    }
+
+    /// Number of instructions executed since the last wakeup
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Statistics of the execution since the last wakeup. The wall time keeps running until the program exits
+    pub fn metrics(&self) -> Metrics {
+        let elapsed = match self.status {
+            Status::Idle | Status::Finished | Status::Halted(_) => self.metrics.elapsed,
+            _ => self.started.elapsed(),
+        };
+        let mut metrics = self.metrics.clone();
+        metrics.instructions = self.executed;
+        metrics.elapsed = elapsed;
+        metrics
+    }
+
+    /// Execute requested instruction. Fails without executing it if the limits set in [`Settings`] are exceeded, or if
+    /// there is not enough gas left to pay for it. On failure, the machine is halted
     pub fn execute_instruction(&mut self, instruction: &Instruction) -> Result<&Status, Box<dyn Error>> {
+        if let Err(err) = self.check_limits().and_then(|()| self.spend_gas(instruction)) {
+            self.halt(err.as_ref());
+            return Err(err);
+        }
+        self.executed += 1;
+        self.last_write = None;
+        let next_pc = match self.dispatch(instruction).and_then(|next_pc| self.write_device().map(|()| next_pc)) {
+            Ok(next_pc) if self.executed.is_multiple_of(TICK_INSTRUCTIONS) && !self.devices.is_empty() => {
+                match self.notify_devices(|device, vm| device.tick(vm)) {
+                    Ok(()) => next_pc,
+                    Err(err) => {
+                        self.halt(err.as_ref());
+                        return Err(err);
+                    }
+                }
+            }
+            Ok(next_pc) => next_pc,
+            Err(err) => {
+                self.halt(err.as_ref());
+                return Err(err);
+            }
+        };
+        // Input commands waiting for input are executed again once resumed, and only counted then
+        if matches!(instruction, Instruction::Input) && next_pc == self.pc {
+            self.executed -= 1;
+        } else {
+            self.metrics.record(instruction, self.address());
+        }
+        // Update program counter
+        self.pc = next_pc;
+        self.check_breakpoint();
+        Ok(&self.status)
+    }
+
+    /// Perform the effects of `instruction` as the one at the program counter, and return the address of the next
+    /// instruction. Limits, statistics, breakpoints and the program counter itself are left to the caller
+    pub(super) fn dispatch(&mut self, instruction: &Instruction) -> Result<usize, Box<dyn Error>> {
         let mut next_pc = self.pc + 1;
-        // Execute instruction
         match *instruction {
-            Instruction::IncPtr => self.inc_mp(),
-            Instruction::DecPtr => self.dec_mp(),
-            Instruction::IncData => self.mem_inc(),
-            Instruction::DecData => self.mem_dec(),
+            Instruction::IncPtr => self.inc_mp()?,
+            Instruction::DecPtr => self.dec_mp()?,
+            Instruction::IncData => self.mem_inc()?,
+            Instruction::DecData => self.mem_dec()?,
+            Instruction::Add(n) if n >= 0 => self.mem_add(n as u64)?,
+            Instruction::Add(n) => self.mem_sub(n.unsigned_abs())?,
+            Instruction::Move(n) => self.move_mp(n)?,
+            Instruction::Set(val) => self.mem_wr(val),
+            Instruction::MulAdd { offset, factor } => self.mul_add(offset, factor)?,
+            Instruction::AddAt { offset, value } => self.add_at(offset, value)?,
+            Instruction::SetAt { offset, value } => {
+                self.move_mp(offset)?;
+                self.mem_wr(value);
+                self.move_mp(-offset)?;
+            }
+            Instruction::Output if !self.output_allowed()? => {}
+            Instruction::Output if self.settings.cell_width == CellWidth::U1 => self.write_bit()?,
             Instruction::Output => self.write_byte()?,
-            Instruction::Input => self.read_byte(true)?,
+            // Input past the limit reads as EOF
+            Instruction::Input if !self.input_allowed()? => self.mem_wr(0),
+            Instruction::Input => {
+                if !self.devices.is_empty() {
+                    self.notify_devices(|device, vm| device.before_input(vm))?;
+                }
+                self.flush()?;
+                let read = match self.settings.cell_width {
+                    CellWidth::U1 => self.read_bit(),
+                    _ => self.read_byte(true),
+                };
+                match read {
+                    // Input readers that would block are asked again once the caller resumes the machine
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        self.status = Status::WaitingForInput;
+                        next_pc = self.pc;
+                    }
+                    // A signal setting the interrupt flag ended a blocked read: the machine pauses on the input
+                    // command, which reads again once resumed
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted && self.interrupted() => {
+                        next_pc = self.pc;
+                    }
+                    read => read?,
+                }
+            }
             Instruction::JNZ(addr) => {
                 if self.mem_rd() != 0 {
                     next_pc = addr;
@@ -112,83 +635,1306 @@ impl VirtualMachine {
                     next_pc = addr;
                 }
             }
-            Instruction::Exit => self.status = Status::Idle,
+            Instruction::Debug => {
+                self.flush()?;
+                self.dump_state(&mut std::io::stderr())?;
+            }
+            Instruction::DefineProc(end) => {
+                self.procedures.insert(self.mem_rd(), self.pc + 1);
+                next_pc = end;
+            }
+            Instruction::Return => {
+                next_pc = self.call_stack.pop()
+                    .ok_or_else(|| format!("Return outside of a procedure at pc 0x{:08x}", self.pc))?;
+            }
+            Instruction::CallProc => {
+                let id = self.mem_rd();
+                next_pc = *self.procedures.get(&id)
+                    .ok_or_else(|| format!("Undefined procedure {} at pc 0x{:08x}", id, self.pc))?;
+                self.call_stack.push(self.pc + 1);
+            }
+            Instruction::Store => self.storage = self.mem_rd(),
+            Instruction::Load => self.mem_wr(self.storage),
+            Instruction::ShiftLeft => self.mem_wr(self.mem_rd() << 1),
+            Instruction::ShiftRight => self.mem_wr(self.mem_rd() >> 1),
+            Instruction::Not => self.mem_wr(!self.mem_rd()),
+            Instruction::Xor => self.mem_wr(self.mem_rd() ^ self.storage),
+            Instruction::And => self.mem_wr(self.mem_rd() & self.storage),
+            Instruction::Or => self.mem_wr(self.mem_rd() | self.storage),
+            Instruction::Fork => {
+                self.mem_wr(0);
+                let mut child = self.fork();
+                child.pc = next_pc;
+                child.inc_mp()?;
+                child.mem_wr(1);
+                self.forks.push(child);
+            }
+            Instruction::Exit => {
+                self.pad_output()?;
+                self.end_utf8()?;
+                self.flush()?;
+                self.status = Status::Finished;
+                self.metrics.elapsed = self.started.elapsed();
+            }
         }
-        // Update program counter
-        self.pc = next_pc;
-        Ok(&self.status)
+        Ok(next_pc)
+    }
+
+    /// Count `count` executions of `instruction` performed with [`VirtualMachine::dispatch`]
+    pub(super) fn account(&mut self, instruction: &Instruction, count: u64) {
+        self.executed += count;
+        self.metrics.record_many(instruction, count);
+    }
+
+    /// Count an instruction that failed in [`VirtualMachine::dispatch`] as executed, as
+    /// [`VirtualMachine::execute_instruction`] does
+    pub(super) fn account_failure(&mut self) {
+        self.executed += 1;
+    }
+
+    /// Include the current position of the memory pointer in the statistics, after it was moved outside of
+    /// [`VirtualMachine::execute_instruction`]
+    pub(super) fn track_address(&mut self) {
+        self.metrics.record_address(self.address());
+    }
+
+    /// Cell under the memory pointer, which can be changed without the checks and the bookkeeping of
+    /// [`VirtualMachine::mem_wr`]
+    pub(super) fn cell_mut(&mut self) -> &mut u64 {
+        self.memory.get_mut(self.mp)
+    }
+
+    /// Take the threads forked since the last call, which are running and share input and output with this machine
+    pub fn take_forks(&mut self) -> Vec<VirtualMachine> {
+        std::mem::take(&mut self.forks)
+    }
+
+    /// Copy of this machine and its breakpoints, sharing its input and output
+    fn fork(&mut self) -> VirtualMachine {
+        let (input, output) = self.shared_io.get_or_insert_with(|| {
+            let input = std::mem::replace(&mut self.settings.input, Box::new(std::io::empty()));
+            let output = std::mem::replace(&mut self.settings.output, Box::new(std::io::sink()));
+            (SharedReader(Rc::new(RefCell::new(input))), SharedWriter(Rc::new(RefCell::new(output))))
+        }).clone();
+        self.settings.input = Box::new(input.clone());
+        self.settings.output = Box::new(output.clone());
+        let mut child = VirtualMachine::with_tape(Settings {
+            memory_size: self.settings.memory_size,
+            memory_overflow_behavior: self.settings.memory_overflow_behavior,
+            cell_overflow_behavior: self.settings.cell_overflow_behavior,
+            cell_width: self.settings.cell_width,
+            max_instructions: self.settings.max_instructions,
+            max_wall_time: self.settings.max_wall_time,
+            max_output_bytes: self.settings.max_output_bytes,
+            max_input_bytes: self.settings.max_input_bytes,
+            truncate_io: self.settings.truncate_io,
+            flush_policy: self.settings.flush_policy,
+            output_encoding: self.settings.output_encoding,
+            random: self.settings.random.clone(),
+            initial_memory: self.settings.initial_memory.clone(),
+            input: Box::new(input.clone()),
+            output: Box::new(output.clone()),
+        }, Box::new(Dense::new(0)));
+        // The child starts from a copy of the tape rather than from the initial memory
+        child.memory = self.memory.clone_box();
+        child.origin = self.origin;
+        child.mp = self.mp;
+        child.status = self.status.clone();
+        child.storage = self.storage;
+        child.breakpoints = self.breakpoints.clone();
+        child.interrupt = self.interrupt.clone();
+        child.pedantic_pointer = self.pedantic_pointer;
+        child.gas = self.gas.clone();
+        child.devices = self.devices.clone();
+        child.started = self.started;
+        child.metrics = Metrics::new(self.address());
+        child.shared_io = Some((input, output));
+        child
+    }
+
+    /// Return the current address of the memory pointer relative to the initial position. This is negative when the
+    /// tape has been extended to the left
+    pub fn address(&self) -> isize {
+        self.mp as isize - self.origin as isize
+    }
+
+    /// Read the cell at `addr`, relative to the initial position of the memory pointer. Returns `None` if the address
+    /// is outside the tape
+    pub fn peek(&self, addr: isize) -> Option<u64> {
+        let index = usize::try_from(addr + self.origin as isize).ok()?;
+        (index < self.memory.len()).then(|| self.memory.get(index))
+    }
+
+    /// Write `val` to the cell at `addr`, relative to the initial position of the memory pointer. The value is
+    /// truncated to the cell width. Returns false if the address is outside the tape
+    pub fn poke(&mut self, addr: isize, val: u64) -> bool {
+        let max = self.settings.cell_width.max_value();
+        match usize::try_from(addr + self.origin as isize) {
+            Ok(index) if index < self.memory.len() => {
+                *self.memory.get_mut(index) = val & max;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Cells of the machine, starting from the leftmost one
+    pub fn memory(&self) -> &dyn Tape {
+        self.memory.as_ref()
     }
 
-    /// Read memory location under current memory pointer
-    pub fn mem_rd(&self) -> u8 {
-        self.memory[self.mp]
+    /// Cells of the machine, to be changed in place. Callers must keep the length of the tape, which the memory pointer
+    /// and the initial address depend on, and write values within the cell width, which the machine does not truncate
+    /// again before reading them; [`VirtualMachine::set_cell`] and [`VirtualMachine::poke`] do both
+    pub fn memory_mut(&mut self) -> &mut dyn Tape {
+        self.memory.as_mut()
     }
 
-    /// Write to memory location under current memory pointer
-    pub fn mem_wr(&mut self, val: u8) {
-        self.memory[self.mp] = val
+    /// Read the cell at `index` in [`VirtualMachine::memory`]. Returns `None` if the index is outside the tape
+    pub fn cell(&self, index: usize) -> Option<u64> {
+        (index < self.memory.len()).then(|| self.memory.get(index))
     }
 
-    /// Increment data under current memory pointer
-    pub fn mem_inc(&mut self) {
-        self.memory[self.mp] += 1;
+    /// Write `val` to the cell at `index` in [`VirtualMachine::memory`], truncated to the cell width as with
+    /// [`VirtualMachine::poke`]. Returns false if the index is outside the tape
+    pub fn set_cell(&mut self, index: usize, val: u64) -> bool {
+        let inside = index < self.memory.len();
+        if inside {
+            *self.memory.get_mut(index) = val & self.settings.cell_width.max_value();
+        }
+        inside
     }
 
-    /// Decrement data under current memory pointer
-    pub fn mem_dec(&mut self) {
-        self.memory[self.mp] -= 1;
+    /// Index in [`VirtualMachine::memory`] of the cell under the memory pointer. Unlike
+    /// [`VirtualMachine::address`], it changes when the tape is extended to the left
+    pub fn mp(&self) -> usize {
+        self.mp
+    }
+
+    /// Move the memory pointer to the cell at `index` in [`VirtualMachine::memory`]. Returns false if the index is
+    /// outside the tape
+    pub fn set_mp(&mut self, index: usize) -> bool {
+        let inside = index < self.memory.len();
+        if inside {
+            self.mp = index;
+        }
+        inside
+    }
+
+    /// Copy of `len` cells from `start`, relative to the initial position of the memory pointer. The copy stops at the
+    /// end of the tape, and is empty if `start` is outside the tape
+    pub fn memory_slice(&self, start: isize, len: usize) -> Vec<u64> {
+        match usize::try_from(start + self.origin as isize) {
+            Ok(index) if index < self.memory.len() => {
+                (index..usize::min(index.saturating_add(len), self.memory.len())).map(|i| self.memory.get(i)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Move the memory pointer to `addr`, relative to its initial position. Returns false if the address is outside
+    /// the tape
+    pub fn set_address(&mut self, addr: isize) -> bool {
+        match usize::try_from(addr + self.origin as isize) {
+            Ok(index) if index < self.memory.len() => {
+                self.mp = index;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set the program counter
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Bring status to Paused, so that execution can continue from the current program counter with
+    /// [`VirtualMachine::resume`]
+    pub fn pause(&mut self) {
+        self.status = Status::Paused;
+    }
+
+    /// Bring status to Halted, with the message of `err`, which stopped the program
+    pub fn halt(&mut self, err: &dyn Error) {
+        self.status = Status::Halted(err.to_string());
+        self.metrics.elapsed = self.started.elapsed();
+    }
+
+    /// Cell written by the last executed instruction, as its address and the value it had before being written
+    pub fn last_write(&self) -> Option<(isize, u64)> {
+        self.last_write
+    }
+
+    /// Read memory location under current memory pointer, or the device mapped there
+    pub fn mem_rd(&self) -> u64 {
+        let value = self.memory.get(self.mp);
+        match self.devices.is_empty() {
+            true => value,
+            false => self.device(self.address())
+                .map_or(value, |device| device.borrow_mut().read(value) & self.settings.cell_width.max_value()),
+        }
+    }
+
+    /// Write to memory location under current memory pointer. The value is truncated to the cell width
+    pub fn mem_wr(&mut self, val: u64) {
+        self.record_write();
+        *self.memory.get_mut(self.mp) = val & self.settings.cell_width.max_value()
+    }
+
+    /// Increment data under current memory pointer. Returns an error on overflow if the cell overflow behavior is
+    /// `Error`
+    pub fn mem_inc(&mut self) -> Result<(), Box<dyn Error>> {
+        self.mem_add(1)
+    }
+
+    /// Decrement data under current memory pointer. Returns an error on underflow if the cell overflow behavior is
+    /// `Error`
+    pub fn mem_dec(&mut self) -> Result<(), Box<dyn Error>> {
+        self.mem_sub(1)
+    }
+
+    /// Add `n` to data under current memory pointer, with the same semantics as `n` consecutive increments
+    pub fn mem_add(&mut self, n: u64) -> Result<(), Box<dyn Error>> {
+        use CellOverflowBehavior::*;
+        self.record_write();
+        let max = self.settings.cell_width.max_value();
+        let cell = self.memory.get_mut(self.mp);
+        if max - *cell >= n {
+            *cell += n;
+        } else {
+            match self.settings.cell_overflow_behavior {
+                // Cell widths are powers of 2: masking is equivalent to a modulo
+                Wrap => *cell = cell.wrapping_add(n) & max,
+                Saturate => *cell = max,
+                Error => return Err(format!("Cell overflow at pc 0x{:08x}", self.pc).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Subtract `n` from data under current memory pointer, with the same semantics as `n` consecutive decrements
+    pub fn mem_sub(&mut self, n: u64) -> Result<(), Box<dyn Error>> {
+        use CellOverflowBehavior::*;
+        self.record_write();
+        let max = self.settings.cell_width.max_value();
+        let cell = self.memory.get_mut(self.mp);
+        if *cell >= n {
+            *cell -= n;
+        } else {
+            match self.settings.cell_overflow_behavior {
+                Wrap => *cell = cell.wrapping_sub(n) & max,
+                Saturate => *cell = 0,
+                Error => return Err(format!("Cell underflow at pc 0x{:08x}", self.pc).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the current cell multiplied by `factor` to the cell at `offset` from the memory pointer. Arithmetic always
+    /// wraps around, but the memory pointer may leave the tape. Nothing happens if the current cell is 0, like the
+    /// multiplication loop it replaces, which is not entered
+    pub fn mul_add(&mut self, offset: isize, factor: i64) -> Result<(), Box<dyn Error>> {
+        let max = self.settings.cell_width.max_value();
+        let val = self.mem_rd();
+        if val == 0 {
+            return Ok(());
+        }
+        // Two's complement arithmetic modulo 2^64 is also correct modulo any smaller power of 2
+        let product = val.wrapping_mul(factor as u64);
+        self.move_mp(offset)?;
+        self.mem_wr(self.mem_rd().wrapping_add(product) & max);
+        self.move_mp(-offset)
+    }
+
+    /// Add `n` to the cell at `offset` from the memory pointer, with the same semantics as moving there, adding and
+    /// moving back. The memory pointer is back in place even if the cell overflows
+    pub fn add_at(&mut self, offset: isize, n: i64) -> Result<(), Box<dyn Error>> {
+        self.move_mp(offset)?;
+        let result = if n >= 0 { self.mem_add(n as u64) } else { self.mem_sub(n.unsigned_abs()) };
+        self.move_mp(-offset)?;
+        result
     }
 
     /// Read one byte from VirtualMachine's input source and store it under current memory pointer
     pub fn read_byte(&mut self, ignore_newlines: bool) -> Result<(), std::io::Error> {
         let mut buffer = [0u8];
-        self.settings.input.read(&mut buffer)?;
-        while ignore_newlines && buffer[0] == '\n' as u8 {
-            self.settings.input.read(&mut buffer)?;
+        // On EOF the buffer is left untouched, i.e. 0 is read
+        self.metrics.bytes_read += self.settings.input.read(&mut buffer)? as u64;
+        while ignore_newlines && buffer[0] == b'\n' {
+            buffer[0] = 0;
+            self.metrics.bytes_read += self.settings.input.read(&mut buffer)? as u64;
         }
-        self.memory[self.mp] = buffer[0];
+        self.record_write();
+        *self.memory.get_mut(self.mp) = buffer[0] as u64;
         Ok(())
     }
 
-    /// Output one byte under current memory pointer to the VirtualMachine's output
+    /// Output one byte under current memory pointer to the VirtualMachine's output. Wider cells are truncated to their
+    /// least significant byte
     pub fn write_byte(&mut self) -> Result<(), std::io::Error> {
-        write!(self.settings.output, "{}", self.memory[self.mp] as char)
+        self.emit(self.mem_rd() as u8)
+    }
+
+    /// Read the next bit of input and store it under current memory pointer. Bytes are read starting from their least
+    /// significant bit, and EOF reads 0
+    pub fn read_bit(&mut self) -> Result<(), std::io::Error> {
+        if self.input_bits.1 == 0 {
+            let mut buffer = [0u8];
+            self.metrics.bytes_read += self.settings.input.read(&mut buffer)? as u64;
+            self.input_bits = (buffer[0], 8);
+        }
+        let (byte, count) = self.input_bits;
+        self.input_bits = (byte >> 1, count - 1);
+        self.record_write();
+        *self.memory.get_mut(self.mp) = (byte & 1) as u64;
+        Ok(())
+    }
+
+    /// Append the least significant bit under current memory pointer to the output. A byte is written every 8 bits,
+    /// starting from its least significant bit
+    pub fn write_bit(&mut self) -> Result<(), std::io::Error> {
+        let (byte, count) = self.output_bits;
+        let byte = byte | ((self.memory.get(self.mp) & 1) as u8) << count;
+        self.output_bits = (byte, count + 1);
+        if count + 1 == 8 {
+            self.pad_output()?;
+        }
+        Ok(())
+    }
+
+    /// Write the bits output so far, padded with zeros to a whole byte
+    fn pad_output(&mut self) -> Result<(), std::io::Error> {
+        if self.output_bits.1 > 0 {
+            let byte = self.output_bits.0;
+            self.output_bits = (0, 0);
+            self.emit(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Write a byte output by the program in the output encoding, then flush if the flush policy asks for it
+    fn emit(&mut self, byte: u8) -> Result<(), std::io::Error> {
+        self.metrics.bytes_written += 1;
+        match self.settings.output_encoding {
+            OutputEncoding::Raw => self.settings.output.write_all(&[byte])?,
+            OutputEncoding::Utf8 => {
+                self.output_utf8.push(byte);
+                self.decode_utf8()?;
+            }
+        }
+        self.flush_after(byte)
+    }
+
+    /// Write the characters completed by the pending UTF-8 bytes, replacing invalid sequences with U+FFFD. The bytes
+    /// of an incomplete character are kept until the next ones arrive
+    fn decode_utf8(&mut self) -> Result<(), std::io::Error> {
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&self.output_utf8) {
+                Ok(text) => (text.len(), None),
+                Err(err) => (err.valid_up_to(), err.error_len()),
+            };
+            self.settings.output.write_all(&self.output_utf8[..valid])?;
+            match invalid {
+                Some(len) => {
+                    self.settings.output.write_all(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]).as_bytes())?;
+                    self.output_utf8.drain(..valid + len);
+                }
+                None => {
+                    self.output_utf8.drain(..valid);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Replace the incomplete UTF-8 character left at the end of the output, if any, with U+FFFD
+    fn end_utf8(&mut self) -> Result<(), std::io::Error> {
+        if !self.output_utf8.is_empty() {
+            self.output_utf8.clear();
+            write!(self.settings.output, "{}", char::REPLACEMENT_CHARACTER)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the output after writing `byte` if the flush policy asks for it
+    fn flush_after(&mut self, byte: u8) -> Result<(), std::io::Error> {
+        match self.settings.flush_policy {
+            FlushPolicy::Byte => self.flush(),
+            FlushPolicy::Newline if byte == b'\n' => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write program counter, memory pointer and the cells around it on a single line to `sink`. The cell under the
+    /// memory pointer is enclosed in brackets
+    pub fn dump_state<W: Write>(&self, sink: &mut W) -> Result<(), std::io::Error> {
+        let address = self.address();
+        write!(sink, "pc 0x{:08x}  mp {}  |", self.pc, address)?;
+        for addr in address - DUMP_RADIUS..=address + DUMP_RADIUS {
+            match self.peek(addr) {
+                Some(val) if addr == address => write!(sink, " [{}]", val)?,
+                Some(val) => write!(sink, " {}", val)?,
+                None => {}
+            }
+        }
+        writeln!(sink)
+    }
+
+    /// Flush the VirtualMachine's output
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.settings.output.flush()
+    }
+
+    /// Let the device mapped at the cell written by the last instruction, if any, react to the write
+    fn write_device(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((addr, _)) = self.last_write else {
+            return Ok(());
+        };
+        if let Some(device) = self.device(addr).cloned() {
+            let value = self.peek(addr).unwrap_or(0);
+            device.borrow_mut().write(value, self)?;
+        }
+        Ok(())
+    }
+
+    /// Device mapped at the cell at `addr`, if any
+    fn device(&self, addr: isize) -> Option<&SharedDevice> {
+        let (_, (end, device)) = self.devices.range(..=addr).next_back()?;
+        (addr < *end).then_some(device)
+    }
+
+    /// Call `notify` with every device mapped, stopping at the first error
+    fn notify_devices(&mut self, notify: DeviceCallback) -> Result<(), Box<dyn Error>> {
+        let devices: Vec<_> = self.devices.values().map(|(_, device)| device.clone()).collect();
+        for device in devices {
+            notify(device.borrow_mut().as_mut(), self)?;
+        }
+        Ok(())
+    }
+
+    fn record_write(&mut self) {
+        self.last_write = Some((self.address(), self.memory.get(self.mp)));
+    }
+
+    fn check_limits(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(max) = self.settings.max_instructions {
+            if self.executed >= max {
+                return Err(Box::new(InstructionLimitExceeded { pc: self.pc, executed: self.executed }));
+            }
+        }
+        if let Some(max) = self.settings.max_wall_time {
+            if self.executed.is_multiple_of(TIME_CHECK_INTERVAL) && self.started.elapsed() > max {
+                return Err(format!(
+                    "Timeout of {:?} exceeded at pc 0x{:08x} after {} instructions", max, self.pc, self.executed
+                ).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Pay for `instruction` from the gas budget, if any
+    fn spend_gas(&self, instruction: &Instruction) -> Result<(), Box<dyn Error>> {
+        let Some(gas) = &self.gas else {
+            return Ok(());
+        };
+        gas.spend(instruction).map_err(|cost| format!(
+            "Out of gas at pc 0x{:08x} after {} instructions: {} needed, {} left",
+            self.pc, self.executed, cost, gas.remaining(),
+        ).into())
+    }
+
+    /// Whether the next output can be written without exceeding the output limit. Past the limit, output is dropped
+    /// if I/O is truncated, otherwise this fails. Bit cells only count whole bytes
+    fn output_allowed(&self) -> Result<bool, Box<dyn Error>> {
+        let (written, partial) = (self.metrics.bytes_written, self.output_bits.1 > 0);
+        self.io_allowed("Output", written, self.settings.max_output_bytes, partial)
+    }
+
+    /// Whether the next input can be read without exceeding the input limit, as [`VirtualMachine::output_allowed`]
+    fn input_allowed(&self) -> Result<bool, Box<dyn Error>> {
+        let (read, partial) = (self.metrics.bytes_read, self.input_bits.1 > 0);
+        self.io_allowed("Input", read, self.settings.max_input_bytes, partial)
+    }
+
+    /// Whether another byte can be transferred after `done` with a limit of `max`, or the rest of one already started
+    fn io_allowed(&self, what: &str, done: u64, max: Option<u64>, partial: bool) -> Result<bool, Box<dyn Error>> {
+        match max {
+            Some(max) if done >= max && !partial && self.settings.truncate_io => Ok(false),
+            Some(max) if done >= max && !partial => {
+                Err(format!("{} limit of {} bytes exceeded at pc 0x{:08x}", what, max, self.pc).into())
+            }
+            _ => Ok(true),
+        }
     }
 
-    fn inc_mp(&mut self) {
+    fn check_breakpoint(&mut self) {
+        if self.status == Status::Running && (self.breakpoints.contains(&self.pc) || self.interrupted()) {
+            self.status = Status::Paused;
+        }
+    }
+
+    fn inc_mp(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inc_mp_by(1)
+    }
+
+    fn dec_mp(&mut self) -> Result<(), Box<dyn Error>> {
+        self.dec_mp_by(1)
+    }
+
+    /// Move the memory pointer by a signed amount of cells
+    pub(super) fn move_mp(&mut self, n: isize) -> Result<(), Box<dyn Error>> {
+        if n >= 0 {
+            self.inc_mp_by(n as usize)
+        } else {
+            self.dec_mp_by(n.unsigned_abs())
+        }
+    }
+
+    /// Move the memory pointer `n` cells to the right, with the same semantics as `n` consecutive increments
+    fn inc_mp_by(&mut self, n: usize) -> Result<(), Box<dyn Error>> {
         use MemoryOverflowBehavior::*;
+        if self.pedantic_pointer {
+            self.check_pointer_range(self.address().saturating_add_unsigned(n))?;
+        }
+        let len = self.memory.len();
         match self.settings.memory_overflow_behavior {
-            Unchecked => self.mp += 1,
-            Saturate => {
-                if self.mp < self.memory.len() - 1 {
-                    self.mp += 1;
+            Unchecked => self.mp += n,
+            Saturate => self.mp = usize::min(self.mp.saturating_add(n), len - 1),
+            Wrap => self.mp = (self.mp + n % len) % len,
+            Extend => {
+                self.mp += n;
+                if self.mp >= len {
+                    self.memory.resize(self.mp + 1);
                 }
             }
-            Wrap => {
-                self.mp += 1;
-                if self.mp >= self.memory.len() {
-                    self.mp = 0;
+            Error => match self.mp.checked_add(n).filter(|&mp| mp < len) {
+                Some(mp) => self.mp = mp,
+                None => {
+                    let mp = (self.mp as isize).saturating_add_unsigned(n);
+                    self.mp = len - 1;
+                    return Err(Box::new(PointerOutOfBounds { pc: self.pc, mp }));
                 }
             },
         };
+        Ok(())
     }
 
-    fn dec_mp(&mut self) {
+    /// Fail if `address`, where the memory pointer is about to move, is out of the cells allowed by
+    /// [`VirtualMachine::set_pedantic_pointer`]. The memory pointer stays where it is
+    fn check_pointer_range(&self, address: isize) -> Result<(), Box<dyn Error>> {
+        if address < 0 || address >= self.settings.memory_size as isize {
+            return Err(format!(
+                "Memory pointer moved to cell {}, out of cells 0 to {}, at pc 0x{:08x}",
+                address, self.settings.memory_size as isize - 1, self.pc,
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Move the memory pointer `n` cells to the left, with the same semantics as `n` consecutive decrements
+    fn dec_mp_by(&mut self, n: usize) -> Result<(), Box<dyn Error>> {
         use MemoryOverflowBehavior::*;
+        if self.pedantic_pointer {
+            self.check_pointer_range(self.address().saturating_sub_unsigned(n))?;
+        }
         match self.settings.memory_overflow_behavior {
-            Unchecked => self.mp -= 1,
-            Saturate => {
-                if self.mp > 0 {
-                    self.mp -= 1;
-                }
-            }
+            Unchecked => self.mp -= n,
+            Saturate => self.mp = self.mp.saturating_sub(n),
             Wrap => {
-                if self.mp > 0 {
-                    self.mp -= 1;
+                let len = self.memory.len();
+                self.mp = (self.mp + len - n % len) % len;
+            }
+            Extend => {
+                if self.mp >= n {
+                    self.mp -= n;
                 } else {
-                    self.mp = self.memory.len() - 1;
+                    // Allocate new cells to the left: the memory pointer ends up on the first cell
+                    self.memory.grow_front(n - self.mp);
+                    self.origin += n - self.mp;
+                    self.mp = 0;
                 }
             }
+            Error => match self.mp.checked_sub(n) {
+                Some(mp) => self.mp = mp,
+                None => {
+                    let mp = (self.mp as isize).saturating_sub_unsigned(n);
+                    self.mp = 0;
+                    return Err(Box::new(PointerOutOfBounds { pc: self.pc, mp }));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        VirtualMachine::new()
+    }
+}
+
+/* PointerOutOfBounds *************************************************************************************************/
+impl Display for PointerOutOfBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let side = if self.mp < 0 { "left" } else { "right" };
+        write!(f, "Memory pointer moved {} of the tape at pc 0x{:08x}", side, self.pc)
+    }
+}
+
+impl Error for PointerOutOfBounds {}
+
+/* InstructionLimitExceeded *******************************************************************************************/
+impl Display for InstructionLimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Instruction limit exceeded at pc 0x{:08x} after {} instructions", self.pc, self.executed)
+    }
+}
+
+impl Error for InstructionLimitExceeded {}
+
+/* Settings ***********************************************************************************************************/
+impl Settings {
+    /// Start building settings from the default ones
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder { settings: Settings::default() }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            memory_size: 4096,
+            memory_overflow_behavior: MemoryOverflowBehavior::Unchecked,
+            cell_overflow_behavior: CellOverflowBehavior::Wrap,
+            cell_width: CellWidth::U8,
+            max_instructions: None,
+            max_wall_time: None,
+            max_output_bytes: None,
+            max_input_bytes: None,
+            truncate_io: false,
+            flush_policy: FlushPolicy::Input,
+            output_encoding: OutputEncoding::Raw,
+            random: Random::from_entropy(),
+            initial_memory: Vec::new(),
+            input: Box::new(std::io::stdin()),
+            output: Box::new(std::io::stdout()),
         }
     }
 }
+
+/* SettingsBuilder ****************************************************************************************************/
+impl SettingsBuilder {
+    /// Number of cells in the tape
+    pub fn memory_size(mut self, memory_size: usize) -> SettingsBuilder {
+        self.settings.memory_size = memory_size;
+        self
+    }
+
+    pub fn memory_overflow_behavior(mut self, behavior: MemoryOverflowBehavior) -> SettingsBuilder {
+        self.settings.memory_overflow_behavior = behavior;
+        self
+    }
+
+    /// Wrap the memory pointer around the ends of the tape
+    pub fn wrap_pointer(self) -> SettingsBuilder {
+        self.memory_overflow_behavior(MemoryOverflowBehavior::Wrap)
+    }
+
+    /// Grow the tape on demand in both directions
+    pub fn extend_memory(self) -> SettingsBuilder {
+        self.memory_overflow_behavior(MemoryOverflowBehavior::Extend)
+    }
+
+    pub fn cell_overflow_behavior(mut self, behavior: CellOverflowBehavior) -> SettingsBuilder {
+        self.settings.cell_overflow_behavior = behavior;
+        self
+    }
+
+    pub fn cell_width(mut self, width: CellWidth) -> SettingsBuilder {
+        self.settings.cell_width = width;
+        self
+    }
+
+    /// Abort execution after `max` instructions
+    pub fn max_instructions(mut self, max: u64) -> SettingsBuilder {
+        self.settings.max_instructions = Some(max);
+        self
+    }
+
+    /// Abort execution after running for `max`
+    pub fn max_wall_time(mut self, max: Duration) -> SettingsBuilder {
+        self.settings.max_wall_time = Some(max);
+        self
+    }
+
+    /// Abort execution when the program writes more than `max` bytes
+    pub fn max_output_bytes(mut self, max: u64) -> SettingsBuilder {
+        self.settings.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Abort execution when the program reads more than `max` bytes
+    pub fn max_input_bytes(mut self, max: u64) -> SettingsBuilder {
+        self.settings.max_input_bytes = Some(max);
+        self
+    }
+
+    /// Drop output and read EOF past the limits instead of aborting execution
+    pub fn truncate_io(mut self) -> SettingsBuilder {
+        self.settings.truncate_io = true;
+        self
+    }
+
+    /// When the output is flushed
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> SettingsBuilder {
+        self.settings.flush_policy = policy;
+        self
+    }
+
+    /// How the bytes output by the program are written
+    pub fn output_encoding(mut self, encoding: OutputEncoding) -> SettingsBuilder {
+        self.settings.output_encoding = encoding;
+        self
+    }
+
+    /// Seed the numbers read by random devices, so that runs are reproducible
+    pub fn seed(mut self, seed: u64) -> SettingsBuilder {
+        self.settings.random = Random::new(seed);
+        self
+    }
+
+    /// Bytes stored in the cells starting from the initial position of the memory pointer
+    pub fn initial_memory(mut self, bytes: Vec<u8>) -> SettingsBuilder {
+        self.settings.initial_memory = bytes;
+        self
+    }
+
+    pub fn input<R: Read + 'static>(mut self, input: R) -> SettingsBuilder {
+        self.settings.input = Box::new(input);
+        self
+    }
+
+    /// Read input from stdin
+    pub fn stdin_input(self) -> SettingsBuilder {
+        self.input(std::io::stdin())
+    }
+
+    pub fn output<W: Output>(mut self, output: W) -> SettingsBuilder {
+        self.settings.output = Box::new(output);
+        self
+    }
+
+    /// Write output to stdout
+    pub fn stdout_output(self) -> SettingsBuilder {
+        self.output(std::io::stdout())
+    }
+
+    /// Read input by calling `read` with a buffer to fill, which returns the number of bytes it stored there, 0 at the
+    /// end of the input. This lets hosts without a stdin, such as a web page, provide input
+    pub fn input_fn<F: FnMut(&mut [u8]) -> usize + 'static>(self, read: F) -> SettingsBuilder {
+        self.input(InputFn(read))
+    }
+
+    /// Write output by calling `write` with the bytes written since its previous call, whenever output is flushed as
+    /// set by [`SettingsBuilder::flush_policy`]. This lets hosts without a stdout, such as a web page, show output
+    pub fn output_fn<F: FnMut(&[u8]) + 'static>(self, write: F) -> SettingsBuilder {
+        self.output(OutputFn { write, buffer: Vec::new() })
+    }
+
+    /// Read input from and write output to a TCP connection, such as one accepted by a
+    /// [`TcpListener`](std::net::TcpListener), so that the program serves or talks to a peer. The program reads EOF
+    /// once the peer shuts down its side of the connection
+    pub fn tcp_stream(self, stream: TcpStream) -> std::io::Result<SettingsBuilder> {
+        let input = BufReader::new(stream.try_clone()?);
+        Ok(self.input(input).output(BufWriter::new(stream)))
+    }
+
+    pub fn build(self) -> Settings {
+        self.settings
+    }
+}
+
+/* Output *************************************************************************************************************/
+impl<W: Write + Any> Output for W {}
+
+/* MemoryOverflowBehavior *********************************************************************************************/
+impl FromStr for MemoryOverflowBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchecked" => Ok(MemoryOverflowBehavior::Unchecked),
+            "saturate" => Ok(MemoryOverflowBehavior::Saturate),
+            "wrap" => Ok(MemoryOverflowBehavior::Wrap),
+            "extend" => Ok(MemoryOverflowBehavior::Extend),
+            "error" => Ok(MemoryOverflowBehavior::Error),
+            _ => Err(format!("Invalid memory overflow behavior: '{}'", s)),
+        }
+    }
+}
+
+/* SharedReader, SharedWriter *****************************************************************************************/
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/* InputFn, OutputFn **************************************************************************************************/
+impl<F: FnMut(&mut [u8]) -> usize> Read for InputFn<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok((self.0)(buf).min(buf.len()))
+    }
+}
+
+impl<F: FnMut(&[u8])> Write for OutputFn<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            (self.write)(&self.buffer);
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+/* CellWidth **********************************************************************************************************/
+impl CellWidth {
+    /// Number of bits in a cell of this width
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::U1 => 1,
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+            CellWidth::U64 => 64,
+        }
+    }
+
+    /// Largest value a cell of this width can hold
+    pub fn max_value(self) -> u64 {
+        match self {
+            CellWidth::U1 => 1,
+            CellWidth::U8 => u8::MAX as u64,
+            CellWidth::U16 => u16::MAX as u64,
+            CellWidth::U32 => u32::MAX as u64,
+            CellWidth::U64 => u64::MAX,
+        }
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" | "u1" => Ok(CellWidth::U1),
+            "8" | "u8" => Ok(CellWidth::U8),
+            "16" | "u16" => Ok(CellWidth::U16),
+            "32" | "u32" => Ok(CellWidth::U32),
+            "64" | "u64" => Ok(CellWidth::U64),
+            _ => Err(format!("Invalid cell size: '{}'", s)),
+        }
+    }
+}
+
+/* FlushPolicy ********************************************************************************************************/
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(FlushPolicy::Byte),
+            "newline" => Ok(FlushPolicy::Newline),
+            "input" => Ok(FlushPolicy::Input),
+            "manual" => Ok(FlushPolicy::Manual),
+            _ => Err(format!("Invalid flush policy: '{}'", s)),
+        }
+    }
+}
+
+/* OutputEncoding *****************************************************************************************************/
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(OutputEncoding::Raw),
+            "utf8" | "utf-8" => Ok(OutputEncoding::Utf8),
+            _ => Err(format!("Invalid output encoding: '{}'", s)),
+        }
+    }
+}
+
+/* CellOverflowBehavior ***********************************************************************************************/
+impl FromStr for CellOverflowBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(CellOverflowBehavior::Wrap),
+            "saturate" => Ok(CellOverflowBehavior::Saturate),
+            "error" => Ok(CellOverflowBehavior::Error),
+            _ => Err(format!("Invalid cell overflow behavior: '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Settings of a machine without input or output, whose random numbers are the same on every run
+    fn settings() -> Settings {
+        Settings {
+            random: Random::new(0),
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        }
+    }
+
+    fn vm_with_cells(width: CellWidth, behavior: CellOverflowBehavior) -> VirtualMachine {
+        VirtualMachine::with_settings(Settings {
+            memory_size: 1,
+            cell_overflow_behavior: behavior,
+            cell_width: width,
+            ..settings()
+        })
+    }
+
+    #[test]
+    fn cell_overflow_wrap() {
+        let mut vm = vm_with_cells(CellWidth::U8, CellOverflowBehavior::Wrap);
+        vm.mem_dec().expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 255);
+        vm.mem_inc().expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 0);
+    }
+
+    #[test]
+    fn cell_overflow_saturate() {
+        let mut vm = vm_with_cells(CellWidth::U8, CellOverflowBehavior::Saturate);
+        vm.mem_dec().expect("Saturating should never fail");
+        assert_eq!(vm.mem_rd(), 0);
+        vm.mem_wr(255);
+        vm.mem_inc().expect("Saturating should never fail");
+        assert_eq!(vm.mem_rd(), 255);
+    }
+
+    #[test]
+    fn cell_overflow_error() {
+        let mut vm = vm_with_cells(CellWidth::U8, CellOverflowBehavior::Error);
+        assert!(vm.mem_dec().is_err(), "Underflow not detected");
+        vm.mem_wr(255);
+        assert!(vm.mem_inc().is_err(), "Overflow not detected");
+        assert_eq!(vm.mem_rd(), 255);
+    }
+
+    #[test]
+    fn cell_width() {
+        let mut vm = vm_with_cells(CellWidth::U16, CellOverflowBehavior::Wrap);
+        vm.mem_dec().expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), u16::MAX as u64);
+        vm.mem_inc().expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 0);
+        vm.mem_wr(0x12345);
+        assert_eq!(vm.mem_rd(), 0x2345);
+    }
+
+    #[test]
+    fn bit_cells() {
+        let output = crate::ui::SharedBuffer::new();
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 1,
+            cell_width: CellWidth::U1,
+            input: Box::new(&b"\x06"[..]),
+            output: Box::new(output.clone()),
+            ..settings()
+        });
+        vm.mem_add(3).expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 1);
+        // Echo the first three bits of 0b110, then exit with a partial byte
+        for _ in 0..3 {
+            vm.execute_instruction(&Instruction::Input).expect("Could not read");
+            vm.execute_instruction(&Instruction::Output).expect("Could not write");
+        }
+        assert_eq!(output.contents(), b"");
+        vm.execute_instruction(&Instruction::Exit).expect("Could not exit");
+        assert_eq!(output.contents(), b"\x06");
+    }
+
+    #[test]
+    fn into_output() {
+        let vm = vm_with_cells(CellWidth::U8, CellOverflowBehavior::Wrap);
+        assert!(vm.into_output::<Vec<u8>>().is_err(), "Output taken as the wrong type");
+        let mut vm = vm_with_cells(CellWidth::U8, CellOverflowBehavior::Wrap);
+        vm.set_output(Box::new(Vec::<u8>::new())).expect("Could not flush");
+        let child = vm.fork();
+        vm.mem_wr(0x41);
+        vm.execute_instruction(&Instruction::Output).expect("Could not write");
+        // The output is shared with the forked thread until it is dropped
+        drop(child);
+        let output: Vec<u8> = vm.into_output().expect("Could not take the output");
+        assert_eq!(output, b"A");
+    }
+
+    #[test]
+    fn io_limits() {
+        let settings = Settings::builder().max_output_bytes(1).max_input_bytes(1).input(&b"ab"[..]);
+        let mut vm = VirtualMachine::with_settings(settings.output(Vec::<u8>::new()).build());
+        vm.execute_instruction(&Instruction::Input).expect("Could not read");
+        vm.execute_instruction(&Instruction::Output).expect("Could not write");
+        let err = vm.execute_instruction(&Instruction::Output).expect_err("Wrote past the limit");
+        assert_eq!(err.to_string(), "Output limit of 1 bytes exceeded at pc 0x00000002");
+        let err = vm.execute_instruction(&Instruction::Input).expect_err("Read past the limit");
+        assert_eq!(err.to_string(), "Input limit of 1 bytes exceeded at pc 0x00000002");
+
+        let settings = Settings::builder().max_output_bytes(1).max_input_bytes(1).truncate_io().input(&b"ab"[..]);
+        let mut vm = VirtualMachine::with_settings(settings.output(Vec::<u8>::new()).build());
+        for instruction in [Instruction::Input, Instruction::Output, Instruction::Input, Instruction::Output] {
+            vm.execute_instruction(&instruction).expect("Truncating should never fail");
+        }
+        assert_eq!(vm.mem_rd(), 0);
+        let output: Vec<u8> = vm.into_output().expect("Could not take the output");
+        assert_eq!(output, b"a");
+    }
+
+    #[test]
+    fn flush_policy() {
+        let flushed = |policy| {
+            let output = crate::ui::SharedBuffer::new();
+            let settings = Settings::builder().flush_policy(policy).input(&b"x"[..]);
+            let writer = std::io::BufWriter::new(output.clone());
+            let mut vm = VirtualMachine::with_settings(settings.output(writer).build());
+            let mut lengths = Vec::new();
+            let steps = [(b'a', Instruction::Output), (b'\n', Instruction::Output), (0, Instruction::Input)];
+            for (value, instruction) in steps {
+                vm.mem_wr(value as u64);
+                vm.execute_instruction(&instruction).expect("Could not execute");
+                lengths.push(output.contents().len());
+            }
+            vm.execute_instruction(&Instruction::Exit).expect("Could not exit");
+            lengths.push(output.contents().len());
+            lengths
+        };
+        assert_eq!(flushed(FlushPolicy::Byte), [1, 2, 2, 2]);
+        assert_eq!(flushed(FlushPolicy::Newline), [0, 2, 2, 2]);
+        assert_eq!(flushed(FlushPolicy::Input), [0, 0, 2, 2]);
+        assert_eq!(flushed(FlushPolicy::Manual), [0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn output_encoding() {
+        let output = |encoding, bytes: &[u8]| {
+            let settings = Settings::builder().output_encoding(encoding).output(Vec::new());
+            let mut vm = VirtualMachine::with_settings(settings.build());
+            for &byte in bytes {
+                vm.mem_wr(byte as u64);
+                vm.execute_instruction(&Instruction::Output).expect("Could not write");
+            }
+            vm.execute_instruction(&Instruction::Exit).expect("Could not exit");
+            vm.into_output::<Vec<u8>>().expect("Could not take the output")
+        };
+        let bytes = b"\xc3\xa9\xff!\xe2\x82";
+        assert_eq!(output(OutputEncoding::Raw, bytes), bytes);
+        assert_eq!(String::from_utf8(output(OutputEncoding::Utf8, bytes)).unwrap(), "\u{e9}\u{fffd}!\u{fffd}");
+    }
+
+    #[test]
+    fn io_callbacks() {
+        let mut input = b"hi".to_vec();
+        let chunks = Rc::new(RefCell::new(Vec::new()));
+        let written = chunks.clone();
+        let settings = Settings::builder()
+            .input_fn(move |buf| {
+                let len = buf.len().min(input.len());
+                buf[..len].copy_from_slice(&input.drain(..len).collect::<Vec<u8>>());
+                len
+            })
+            .output_fn(move |bytes| written.borrow_mut().push(bytes.to_vec()))
+            .flush_policy(FlushPolicy::Newline);
+        let mut vm = VirtualMachine::with_settings(settings.build());
+        let steps = [Instruction::Input, Instruction::Output, Instruction::Input, Instruction::Output];
+        for instruction in steps {
+            vm.execute_instruction(&instruction).expect("Could not execute");
+        }
+        vm.mem_wr(b'\n' as u64);
+        vm.execute_instruction(&Instruction::Output).expect("Could not write");
+        vm.execute_instruction(&Instruction::Input).expect("Could not read");
+        assert_eq!(*chunks.borrow(), [b"h".to_vec(), b"i\n".to_vec()]);
+    }
+
+    #[test]
+    fn tcp_stream() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Could not listen");
+        let addr = listener.local_addr().expect("Could not get the address");
+        let peer = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("Could not connect");
+            stream.write_all(b"ab").expect("Could not send");
+            stream.shutdown(std::net::Shutdown::Write).expect("Could not shut down");
+            let mut reply = Vec::new();
+            stream.read_to_end(&mut reply).expect("Could not receive");
+            reply
+        });
+        let (stream, _) = listener.accept().expect("Could not accept");
+        let settings = Settings::builder().tcp_stream(stream).expect("Could not clone the stream").build();
+        let mut vm = VirtualMachine::with_settings(settings);
+        // Echo the input, incremented, until EOF
+        for _ in 0..3 {
+            vm.execute_instruction(&Instruction::Input).expect("Could not read");
+            if vm.mem_rd() != 0 {
+                vm.execute_instruction(&Instruction::IncData).expect("Could not increment");
+                vm.execute_instruction(&Instruction::Output).expect("Could not write");
+            }
+        }
+        vm.execute_instruction(&Instruction::Exit).expect("Could not exit");
+        drop(vm);
+        assert_eq!(peer.join().expect("The peer panicked"), b"bc");
+    }
+
+    #[test]
+    fn dump_state() {
+        let mut vm = VirtualMachine::new();
+        vm.mem_wr(7);
+        vm.move_mp(2).expect("Could not move");
+        vm.mem_wr(3);
+        vm.set_pc(5);
+        let mut dump = Vec::new();
+        vm.dump_state(&mut dump).expect("Could not dump state");
+        assert_eq!(String::from_utf8(dump).unwrap(), "pc 0x00000005  mp 2  | 7 0 [3] 0 0 0 0\n");
+    }
+
+    #[test]
+    fn memory_overflow_extend() {
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 1,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            ..settings()
+        });
+        vm.mem_wr(1);
+        vm.dec_mp().expect("Extending should never fail");
+        vm.dec_mp().expect("Extending should never fail");
+        assert_eq!(vm.address(), -2);
+        assert_eq!(vm.mem_rd(), 0);
+        vm.mem_wr(2);
+        vm.inc_mp().expect("Extending should never fail");
+        vm.inc_mp().expect("Extending should never fail");
+        assert_eq!(vm.address(), 0);
+        assert_eq!(vm.mem_rd(), 1);
+        vm.inc_mp().expect("Extending should never fail");
+        assert_eq!(vm.address(), 1);
+        assert_eq!(vm.mem_rd(), 0);
+        vm.reset_core();
+        vm.dec_mp().expect("Extending should never fail");
+        vm.dec_mp().expect("Extending should never fail");
+        assert_eq!(vm.mem_rd(), 2);
+    }
+
+    #[test]
+    fn pedantic_pointer() {
+        let mut vm = VirtualMachine::with_settings(Settings::builder().memory_size(3).wrap_pointer().build());
+        vm.set_pedantic_pointer(true);
+        vm.move_mp(2).expect("Moved within the tape");
+        let err = vm.inc_mp().expect_err("Wrapped around the tape");
+        assert_eq!(err.to_string(), "Memory pointer moved to cell 3, out of cells 0 to 2, at pc 0x00000000");
+        assert_eq!(vm.address(), 2);
+        assert!(vm.move_mp(-3).is_err(), "Wrapped around the tape");
+        vm.set_pedantic_pointer(false);
+        vm.inc_mp().expect("Wrapping should never fail");
+        assert_eq!(vm.address(), 0);
+    }
+
+    #[test]
+    fn checked_memory() {
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 4,
+            memory_overflow_behavior: MemoryOverflowBehavior::Error,
+            ..settings()
+        });
+        let err = vm.execute_instruction(&Instruction::DecPtr).expect_err("Moved left of the tape");
+        assert_eq!(err.to_string(), "Memory pointer moved left of the tape at pc 0x00000000");
+        vm.move_mp(3).expect("Could not move within the tape");
+        let err = vm.move_mp(2).expect_err("Moved right of the tape");
+        assert_eq!(err.to_string(), "Memory pointer moved right of the tape at pc 0x00000000");
+        assert_eq!(err.downcast_ref(), Some(&PointerOutOfBounds { pc: 0, mp: 5 }));
+        assert_eq!(vm.address(), 3);
+        let err = vm.move_mp(isize::MAX).expect_err("Moved right of the tape");
+        assert_eq!(err.downcast_ref(), Some(&PointerOutOfBounds { pc: 0, mp: isize::MAX }));
+        let err = vm.move_mp(isize::MIN).expect_err("Moved left of the tape");
+        assert_eq!(err.downcast_ref(), Some(&PointerOutOfBounds { pc: 0, mp: isize::MIN + 3 }));
+        vm.move_mp(3).expect("Could not move within the tape");
+        vm.mul_add(1, 1).expect("Multiplied a zero cell outside the tape");
+        vm.mem_wr(2);
+        vm.mul_add(-3, 1).expect("Could not multiply within the tape");
+        assert!(vm.mul_add(1, 1).is_err());
+    }
+
+    #[test]
+    fn initial_memory() {
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 3,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            cell_width: CellWidth::U1,
+            initial_memory: vec![1, 2, 3, 4],
+            ..settings()
+        });
+        assert_eq!(vm.state().memory, [1, 0, 1]);
+        vm.move_mp(-1).expect("Could not extend the tape");
+        vm.reset();
+        assert_eq!(vm.state().memory, [0, 1, 0, 1]);
+        assert_eq!(vm.memory_slice(-1, 9), [0, 1, 0, 1]);
+        assert_eq!((vm.memory_slice(1, 2), vm.memory_slice(3, 1)), (vec![0, 1], vec![]));
+    }
+
+    #[test]
+    fn embedder_access() {
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 4,
+            memory_overflow_behavior: MemoryOverflowBehavior::Extend,
+            ..settings()
+        });
+        vm.move_mp(-2).expect("Could not extend the tape");
+        assert_eq!((vm.mp(), vm.address(), vm.memory().len()), (0, -2, 6));
+        assert!(vm.set_cell(2, 0x107) && !vm.set_cell(6, 1));
+        assert_eq!((vm.cell(2), vm.cell(6)), (Some(7), None));
+        *vm.memory_mut().get_mut(3) = 9;
+        assert_eq!(vm.cell(3), Some(9));
+        assert!(vm.poke(1, 0x108));
+        assert_eq!((vm.peek(0), vm.peek(1), vm.peek(4)), (Some(7), Some(8), None));
+        assert!(vm.set_mp(3) && !vm.set_mp(6));
+        assert_eq!((vm.address(), vm.mem_rd()), (1, 8));
+    }
+
+    #[test]
+    fn multi_step_arithmetic() {
+        let mut vm = VirtualMachine::with_settings(Settings {
+            memory_size: 4,
+            memory_overflow_behavior: MemoryOverflowBehavior::Wrap,
+            ..settings()
+        });
+        vm.mem_sub(3).expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 253);
+        vm.mem_add(260).expect("Wrapping should never fail");
+        assert_eq!(vm.mem_rd(), 1);
+        vm.dec_mp_by(5).expect("Wrapping should never fail");
+        assert_eq!(vm.address(), 3);
+        vm.inc_mp_by(2).expect("Wrapping should never fail");
+        assert_eq!(vm.address(), 1);
+    }
+}
@@ -0,0 +1,60 @@
+//! An over engineered Brainfuck interpreter.
+//!
+//! Source code is turned into a [`Program`] by the [`Tokenizer`] and [`Program::compile`], and executed by an
+//! [`Interpreter`] which drives a [`VirtualMachine`] configured through [`Settings`].
+//!
+//! ```
+//! use bfint::{Interpreter, Settings};
+//!
+//! let settings = Settings::builder()
+//!     .memory_size(128)
+//!     .wrap_pointer()
+//!     .input(std::io::empty())
+//!     .output(std::io::sink())
+//!     .build();
+//! let mut interpreter = Interpreter::with_vm_settings(settings);
+//! interpreter.load_snippet("++++[>++++<-]>.").unwrap();
+//! interpreter.run().unwrap();
+//! ```
+//!
+//! For quick experiments, [`run_str`] compiles and runs a program with the default settings on byte input and output:
+//!
+//! ```
+//! assert_eq!(bfint::run_str(",[.,]", b"echo").unwrap(), b"echo");
+//! ```
+pub mod codegen;
+pub mod devices;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod interpreter;
+pub mod optimizer;
+pub mod parse;
+pub mod testing;
+pub mod ui;
+pub mod web;
+
+#[cfg(feature = "async")]
+pub use interpreter::asyncio::{AsyncRead, AsyncSettings, AsyncWrite};
+pub use interpreter::debugger::{Debugger, StopReason};
+pub use interpreter::device::{Clock, Device, DeviceKind, Flush, Random};
+pub use interpreter::engine::{Engine, EngineKind};
+pub use interpreter::gas::{CostModel, Gas};
+pub use interpreter::heatmap::Heatmap;
+pub use interpreter::hook::Hook;
+pub use interpreter::interpreter::{run_str, Interpreter, StepOutcome};
+pub use interpreter::memcheck::{MemoryCheck, UninitializedRead};
+pub use interpreter::metrics::Metrics;
+pub use interpreter::profile::{LoopProfile, Profile};
+pub use interpreter::state::VmState;
+pub use interpreter::tape::{Tape, TapeKind};
+pub use interpreter::trace::{TraceEvent, TraceHook, WriteTracer};
+pub use interpreter::virtualmachine::{
+    CellOverflowBehavior, CellWidth, FlushPolicy, InstructionLimitExceeded, MemoryOverflowBehavior, Output,
+    OutputEncoding, PointerOutOfBounds, Settings, SettingsBuilder, Status, VirtualMachine,
+};
+pub use parse::builder::ProgramBuilder;
+pub use parse::diagnostic::{CompileError, RuntimeError};
+pub use parse::frontend::{Frontend, Language};
+pub use parse::program::{Instruction, Program};
+pub use parse::token::{Syntax, Token, TokenKind, Tokenizer};
+pub use web::playground::Playground;
@@ -1,38 +1,1667 @@
-mod interpreter;
-mod parse;
+mod bench;
+mod debug;
+mod repl;
+mod serve;
+mod signal;
+mod tty;
+mod websocket;
 
 extern crate argparse;
 
 use argparse::ArgumentParser;
+use std::borrow::Cow;
 use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Cursor, IsTerminal, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
-use interpreter::interpreter::Interpreter;
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut fname = String::new();
-    let mut memsize = 4096;
+use bfint::devices::framebuffer::Framebuffer;
+use bfint::interpreter::differential::{compare, Outcome};
+use bfint::interpreter::smbf::SelfModifying;
+use bfint::optimizer::{OptimizationLevel, PassManager};
+use bfint::parse::asm::assemble;
+use bfint::parse::bounds::{memory_bounds, Unbounded};
+use bfint::parse::bytecode;
+use bfint::parse::diagnostic::json_string;
+use bfint::parse::format::{format_with, FormatOptions};
+use bfint::parse::lint::{lint, LintLevel, LintLevels};
+use bfint::parse::ook::Ook;
+use bfint::parse::preprocess::Preprocessor;
+use bfint::parse::substitution::{Mapping, Substitution};
+use bfint::ui::{hexdump, PromptedInput, SharedBuffer, Visualizer};
+use bfint::{
+    CellOverflowBehavior, CellWidth, CompileError, CostModel, Debugger, DeviceKind, EngineKind, FlushPolicy, Frontend,
+    Gas, Instruction, Interpreter, Language, MemoryCheck, MemoryOverflowBehavior, OutputEncoding, Program, Random,
+    RuntimeError, Settings, SettingsBuilder, Status, Syntax, Tape, TapeKind, Token, Tokenizer, VirtualMachine,
+    WriteTracer,
+};
+
+const COMMANDS: [&str; 10] = [
+    "run", "compile", "debug", "check", "fmt", "generate", "bench", "selftest", "reduce", "serve",
+];
+/// Number of loops listed by the profiler report
+const HOT_LOOPS: usize = 10;
+/// Exit code of programs that cannot be compiled, and of invalid arguments
+const COMPILE_ERROR_EXIT_CODE: i32 = 1;
+/// Exit code of programs stopped by an error, such as moving the memory pointer off the tape or the instruction limit
+const RUNTIME_ERROR_EXIT_CODE: i32 = 2;
+/// Exit code of failures to read or write files, and of program input and output
+const IO_ERROR_EXIT_CODE: i32 = 3;
+/// Exit code of runs stopped by SIGINT, as for processes killed by it
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Where the program comes from: a file, stdin if the file name is `-`, or the command line
+#[derive(Default)]
+struct SourceOptions {
+    fname: String,
+    eval: String,
+    lang: Option<Language>,
+    map: String,
+    map_file: String,
+    preprocess: bool,
+    include_paths: Vec<String>,
+    /// Preprocessor that expanded the program, to report errors at their original location
+    preprocessor: Option<Preprocessor>,
+    debug_hash: bool,
+    bang_input: bool,
+    strict: bool,
+    permissive: bool,
+    error_format: ErrorFormat,
+}
+
+/// Configuration of the virtual machine
+struct MachineOptions {
+    memsize: usize,
+    memory_overflow: MemoryOverflowBehavior,
+    cell_overflow: CellOverflowBehavior,
+    cell_width: CellWidth,
+    opt_level: OptimizationLevel,
+    engine: EngineKind,
+    tape: TapeKind,
+    #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+    tape_file: String,
+    pedantic_pointer: bool,
+    devices: Vec<String>,
+}
+
+/// Levels of the lints given on the command line, as lint names or `all`
+#[derive(Default)]
+struct LintOptions {
+    allow: Vec<String>,
+    warn: Vec<String>,
+    deny: Vec<String>,
+}
+
+/// Program input and output, and execution limits
+#[derive(Default)]
+struct IoOptions {
+    input: String,
+    output: String,
+    append: bool,
+    listen: String,
+    connect: String,
+    max_steps: Option<u64>,
+    timeout: Option<f64>,
+    gas: Option<u64>,
+    gas_costs: String,
+    max_output_bytes: Option<u64>,
+    max_input_bytes: Option<u64>,
+    truncate_io: bool,
+    flush: FlushPolicy,
+    output_encoding: OutputEncoding,
+    seed: Option<u64>,
+    preload_memory: String,
+    preload_hex: String,
+}
+
+/// Error raised by a running program, rather than before it started
+#[derive(Debug)]
+struct ProgramError(Box<dyn Error>);
+
+/// How errors and warnings are reported on stderr
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum ErrorFormat {
+    /// Messages quoting the source code
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    // The first argument may select a subcommand, otherwise the arguments are those of `run`
+    let command = match args.get(1) {
+        Some(command) if COMMANDS.contains(&command.as_str()) => args.remove(1),
+        _ => String::from("run"),
+    };
+    let error_format = error_format(&args);
+    // Show the subcommand in usage messages
+    args[0] = format!("{} {}", args[0], command);
+    let result = match command.as_str() {
+        "run" => run(args),
+        "compile" => compile(args),
+        "debug" => debug(args),
+        "check" => check(args),
+        "fmt" => fmt(args),
+        "generate" => generate(args),
+        "bench" => bench(args),
+        "selftest" => selftest(args),
+        "reduce" => reduce(args),
+        "serve" => serve(args),
+        _ => unreachable!(),
+    };
+    if let Err(err) = result {
+        match error_format {
+            ErrorFormat::Human => eprintln!("Error: {}", err),
+            ErrorFormat::Json => eprintln!("{}", error_json(err.as_ref())),
+        }
+        std::process::exit(exit_code(err.as_ref()));
+    }
+}
+
+/* Subcommands ********************************************************************************************************/
+/// Run a program, or start an interactive session if none is given
+fn run(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut lints = LintOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut io = IoOptions::default();
+    let mut visual = false;
+    let mut delay = 50;
+    let mut trace = false;
+    let mut profile = false;
+    let mut flamegraph = String::new();
+    let mut heatmap = false;
+    let mut heatmap_csv = String::new();
+    let mut coverage = false;
+    let mut check_memory = false;
+    let mut stats = false;
+    let mut dump_memory = String::new();
+    let mut dump_file = String::new();
+    let mut handle_sigint = false;
+    let mut pause_signals = false;
+    let mut raw_tty = false;
+    let mut prompt = String::new();
+    let mut echo_input = false;
+    let mut screen = String::new();
+    let mut screen_sync = String::new();
+    let mut screen_fps = 0;
+    let mut exit_cell = false;
+    // The value of --dump-memory is optional, which argparse does not support: a number right after it is its value,
+    // anything else is another argument
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "--dump-memory" {
+            args[i] = match args.get(i + 1).is_some_and(|next| next.parse::<usize>().is_ok()) {
+                true => format!("--dump-memory={}", args.remove(i + 1)),
+                false => String::from("--dump-memory=touched"),
+            };
+        }
+        i += 1;
+    }
     {
-        // Parse args
         let mut parser = ArgumentParser::new();
-        parser.set_description("An over-engineered brainf*ck interpreter.");
+        parser.set_description("An over-engineered brainf*ck interpreter. Run a program, or start an interactive \
+                                session if none is given. Other commands: compile, debug, check, fmt, generate, \
+                                bench, selftest, reduce, serve.");
+        source.register(&mut parser, "brainf*ck file to run, or - to read it from stdin");
+        lints.register(&mut parser);
+        machine.register(&mut parser);
+        io.register(&mut parser);
+
+        parser.refer(&mut visual)
+            .add_option(&["--visual"], argparse::StoreTrue, "show the tape and the current instruction while running");
+
+        parser.refer(&mut delay)
+            .add_option(&["--delay"], argparse::Store, "milliseconds between two instructions in visual mode");
+
+        parser.refer(&mut trace)
+            .add_option(&["--trace"], argparse::StoreTrue,
+                        "log every executed instruction to stderr, with the memory pointer and the cell it changes");
+
+        parser.refer(&mut profile)
+            .add_option(&["--profile"], argparse::StoreTrue,
+                        "count the executions of every instruction and print the hottest loops and the instruction \
+                         histogram to stderr after the run");
+
+        parser.refer(&mut flamegraph)
+            .add_option(&["--flamegraph"], argparse::Store,
+                        "write the execution counts to a file in the collapsed stack format of flamegraph tools, \
+                         with a frame per loop");
 
-        parser.refer(&mut fname)
-            .add_argument("fname", argparse::Store, "brainf*ck file to run");
+        parser.refer(&mut heatmap)
+            .add_option(&["--heatmap"], argparse::StoreTrue,
+                        "count the reads and writes of every cell and print them as a histogram to stderr \
+                         after the run");
+
+        parser.refer(&mut heatmap_csv)
+            .add_option(&["--heatmap-csv"], argparse::Store, "write the reads and writes of every cell to a CSV file");
+
+        parser.refer(&mut coverage)
+            .add_option(&["--coverage"], argparse::StoreTrue,
+                        "print the percentage of instructions executed and the source code with the commands that were \
+                         never reached marked, to stderr after the run");
+
+        parser.refer(&mut check_memory)
+            .add_option(&["--check-memory"], argparse::StoreTrue,
+                        "warn about output and loop conditions reading cells that were never written, with the \
+                         instruction address and source location, to stderr after the run");
+
+        parser.refer(&mut stats)
+            .add_option(&["--stats"], argparse::StoreTrue,
+                        "print statistics such as the number of instructions executed, the cells used and the wall \
+                         time to stderr after the run");
+
+        parser.refer(&mut dump_memory)
+            .add_option(&["--dump-memory"], argparse::Store,
+                        "print a hexdump of the cells to stderr after the run: --dump-memory N dumps the first N \
+                         cells, --dump-memory alone the cells visited by the memory pointer");
+
+        parser.refer(&mut dump_file)
+            .add_option(&["--dump-file"], argparse::Store,
+                        "write the --dump-memory hexdump to a file instead of stderr");
+
+        parser.refer(&mut handle_sigint)
+            .add_option(&["--handle-sigint"], argparse::StoreTrue,
+                        "on Ctrl+C, stop after the current instruction, print where the program stopped and the \
+                         reports asked for, such as --dump-memory, then exit with code 130");
+
+        parser.refer(&mut pause_signals)
+            .add_option(&["--pause-signals"], argparse::StoreTrue,
+                        "pause the program on SIGUSR1, printing where it stopped to stderr, and resume it on SIGUSR2");
+
+        parser.refer(&mut raw_tty)
+            .add_option(&["--raw-tty"], argparse::StoreTrue,
+                        "switch the terminal to raw mode while the program runs, so that it reads every key as soon \
+                         as it is pressed, without echo; implies --handle-sigint, so that Ctrl+C restores the \
+                         terminal");
+
+        parser.refer(&mut prompt)
+            .add_option(&["--prompt"], argparse::Store,
+                        "when program input comes from a terminal, print this prompt, such as 'input> ', to stderr \
+                         whenever the program waits for input");
+
+        parser.refer(&mut echo_input)
+            .add_option(&["--echo-input"], argparse::StoreTrue,
+                        "when program input comes from a terminal, echo the characters the program reads to stderr, \
+                         such as with --raw-tty");
+
+        parser.refer(&mut screen)
+            .add_option(&["--screen"], argparse::Store,
+                        "show the cells from START as a screen of WIDTHxHEIGHT characters in the terminal, given as \
+                         START:WIDTHxHEIGHT, such as 0:80x24; it is redrawn as set by --screen-sync or --screen-fps");
+
+        parser.refer(&mut screen_sync)
+            .add_option(&["--screen-sync"], argparse::Store,
+                        "redraw the --screen whenever the program writes the cell at this address");
+
+        parser.refer(&mut screen_fps)
+            .add_option(&["--screen-fps"], argparse::Store,
+                        "redraw the --screen at most this many times per second while the program changes it");
+
+        parser.refer(&mut exit_cell)
+            .add_option(&["--exitcell"], argparse::StoreTrue,
+                        "exit with the final value of cell 0, modulo 256, when the program exits without errors");
+
+        parse(&parser, args);
+    }
+    let dump_cells = match dump_memory.as_str() {
+        "" | "touched" => None,
+        cells => Some(cells.parse::<usize>().map_err(|_| format!("Invalid number of cells to dump: '{}'", cells))?),
+    };
+    let visual_output = SharedBuffer::new();
+    let mut settings = machine.settings(&source);
+    io.configure(&mut settings, &source)?;
+    // The interactive session reads its own lines from stdin, which the prompted input would buffer ahead of it
+    let interactive = io.reads_stdin() && std::io::stdin().is_terminal() && !source.is_empty();
+    if interactive && (!prompt.is_empty() || echo_input) {
+        settings.input = Box::new(PromptedInput::new(std::io::stdin(), std::io::stderr(), &prompt, echo_input));
+    }
+    if source.language() == Language::Smbf && !source.is_empty() {
+        if visual {
+            return Err("Self-modifying programs cannot be run in visual mode".into());
+        }
+        let mut program = SelfModifying::new(&source.read()?, settings);
+        program.vm_mut().set_gas(io.gas()?);
+        let result = program.run();
+        if !dump_memory.is_empty() {
+            dump(program.vm(), dump_cells, &dump_file)?;
+        }
+        return result.map_err(|err| ProgramError(err).into());
+    }
+    if visual {
+        settings.output = Box::new(visual_output.clone());
+    }
+    let tape = machine.tape()?;
+    let mut interpreter = Interpreter::with_tape(settings, tape).with_engine(machine.engine.create());
+    interpreter.vm_mut().set_pedantic_pointer(machine.pedantic_pointer);
+    interpreter.vm_mut().set_gas(io.gas()?);
+    for (addr, kind) in machine.devices()? {
+        let device = kind.create(interpreter.vm().settings());
+        interpreter.vm_mut().map_device(addr, device);
+    }
+    let framebuffer = framebuffer(&screen, &screen_sync, screen_fps)?;
+    if let Some(framebuffer) = &framebuffer {
+        framebuffer.map(interpreter.vm_mut())?;
+    }
+    interpreter.set_passes(PassManager::for_machine(machine.opt_level, interpreter.vm()));
+    interpreter.set_syntax(source.syntax());
+    if source.is_empty() {
+        return repl::run(interpreter);
+    }
+    let code = source.read()?;
+    source.load_into(&mut interpreter, &code)?;
+    source.lint(&code, &lints.levels()?, !interpreter.vm().settings().initial_memory.is_empty())?;
+    if machine.memory_overflow == MemoryOverflowBehavior::Unchecked {
+        match memory_bounds(interpreter.program()) {
+            Ok(bounds) if !bounds.fits(machine.memsize) => source.warn_program(&format!(
+                "The program may reach cells {} to {}, which do not fit --memsize {}",
+                bounds.min, bounds.max, machine.memsize,
+            )),
+            _ => {}
+        }
+    }
+    if trace {
+        interpreter.set_trace_hook(Some(Box::new(WriteTracer::new(BufWriter::new(std::io::stderr())))));
+    }
+    interpreter.set_profiling(profile || !flamegraph.is_empty() || coverage);
+    interpreter.set_heatmap(heatmap || !heatmap_csv.is_empty());
+    interpreter.set_memory_check(check_memory);
+    // Killed by Ctrl+C, the process would leave the terminal in raw mode
+    let handle_sigint = handle_sigint || raw_tty;
+    if handle_sigint {
+        interpreter.vm_mut().set_interrupt(Some(signal::on_interrupt()?));
+    }
+    if pause_signals {
+        interpreter.vm_mut().set_interrupt(Some(signal::on_pause()?));
+    }
+    let tty = if raw_tty { Some(tty::RawTty::enable()?) } else { None };
+    let mut visualizer = visual.then(|| {
+        Visualizer::new(std::io::stdout(), &String::from_utf8_lossy(&code), visual_output, Duration::from_millis(delay))
+    });
+    let mut result = match &mut visualizer {
+        Some(visualizer) => visualizer.run(&mut interpreter),
+        None => interpreter.run(),
+    };
+    while result.is_ok() && interpreter.status() == Status::Paused && signal::paused() {
+        interpreter.flush()?;
+        eprintln!("Paused {}, memory pointer at {} holding {}; send SIGUSR2 to resume",
+                  position(&interpreter), interpreter.vm().address(), interpreter.vm().mem_rd());
+        signal::wait_for_resume();
+        if signal::interrupted() {
+            break;
+        }
+        result = match &mut visualizer {
+            Some(visualizer) => visualizer.resume(&mut interpreter),
+            None => interpreter.resume(),
+        };
+    }
+    // Failing to write the output must not prevent the reports
+    let flushed = interpreter.flush();
+    // Dropping the tracer flushes the trace before the reports, and before process::exit skips destructors
+    interpreter.set_trace_hook(None);
+    if let Some(framebuffer) = &framebuffer {
+        framebuffer.finish(interpreter.vm())?;
+    }
+    drop(tty);
+    let interrupted = handle_sigint && signal::interrupted();
+    if interrupted {
+        eprintln!("Interrupted {}", position(&interpreter));
+    }
+    // Statistics and profiles of programs stopped by an error, such as the instruction limit, are useful as well
+    if stats {
+        eprint!("{}", interpreter.vm().metrics());
+        if let Some(gas) = interpreter.vm().gas() {
+            eprintln!("Gas used: {} of {} ({} left)", gas.used(), gas.budget(), gas.remaining());
+        }
+    }
+    if machine.pedantic_pointer {
+        let metrics = interpreter.vm().metrics();
+        eprintln!("Memory pointer visited cells {} to {} of 0 to {}",
+                  metrics.lowest_address, metrics.highest_address, machine.memsize as isize - 1);
+    }
+    if !dump_memory.is_empty() {
+        dump(interpreter.vm(), dump_cells, &dump_file)?;
+    }
+    if let Some(counts) = interpreter.profile() {
+        if profile {
+            eprint!("{}", counts.report(interpreter.program(), HOT_LOOPS));
+        }
+        if !flamegraph.is_empty() {
+            create(&flamegraph, false)?.write_all(counts.collapsed(interpreter.program()).as_bytes())?;
+        }
+        if coverage {
+            let program = interpreter.program();
+            eprintln!(
+                "Coverage: {:.1}% ({} of {} instructions executed)",
+                counts.coverage(program), program.len() - counts.unreached(program).len(), program.len(),
+            );
+            if let Some(tokens) = source.tokens(&code)? {
+                eprint!("{}", counts.annotate(program, &tokens, &String::from_utf8_lossy(&code)));
+            }
+        }
+    }
+    if let Some(accesses) = interpreter.heatmap() {
+        if heatmap {
+            eprint!("{}", accesses.histogram());
+        }
+        if !heatmap_csv.is_empty() {
+            create(&heatmap_csv, false)?.write_all(accesses.to_csv().as_bytes())?;
+        }
+    }
+    for read in interpreter.memory_check().map_or(&[][..], MemoryCheck::reads) {
+        let access = match read.instruction {
+            Instruction::Output => "Output of",
+            _ => "Loop condition on",
+        };
+        let times = if read.count > 1 { format!(", {} times", read.count) } else { String::new() };
+        let message = format!("{} cell {}, which was never written (pc 0x{:08x}{})", access, read.addr, read.pc, times);
+        match interpreter.program().location(read.pc) {
+            Some(loc) => source.warn(&CompileError::new(&message, loc), &code),
+            None => source.warn_program(&message),
+        }
+    }
+    if interrupted {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    result.map_err(|err| ProgramError(source.name_runtime_error(err)))?;
+    flushed?;
+    if exit_cell {
+        std::process::exit((interpreter.vm().peek(0).unwrap_or(0) % 256) as i32);
+    }
+    Ok(())
+}
+
+/// Translate a program to another language
+fn compile(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut lints = LintOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut emit = String::from("bytecode");
+    let mut output = String::new();
+    let mut with_source = false;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Translate a brainf*ck program to another language.");
+        source.register(&mut parser, "brainf*ck file to compile, or - to read it from stdin");
+        lints.register(&mut parser);
+        machine.register(&mut parser);
+
+        parser.refer(&mut emit)
+            .add_option(&["--emit"], argparse::Store, "target language: bytecode (default), c, rust, wasm or asm");
+
+        parser.refer(&mut output)
+            .add_option(&["-o", "--output"], argparse::Store, "write the result to a file instead of stdout");
+
+        parser.refer(&mut with_source)
+            .add_option(&["--with-source"], argparse::StoreTrue, "quote the source code in the asm listing");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let settings = machine.settings(&source);
+    let code = source.read()?;
+    let program = source.compile(&code)?;
+    source.lint(&code, &lints.levels()?, !settings.initial_memory.is_empty())?;
+    let program = PassManager::with_level(machine.opt_level, &settings).run(&program);
+    if settings.cell_width == CellWidth::U1 && matches!(emit.as_str(), "c" | "rust") {
+        return Err(format!("Bit cells cannot be translated to {}", emit).into());
+    }
+    if program.instructions().contains(&Instruction::Fork) && matches!(emit.as_str(), "c" | "rust") {
+        return Err(format!("Threads cannot be translated to {}", emit).into());
+    }
+    let translated = match emit.as_str() {
+        "c" => program.to_c(&settings).into_bytes(),
+        "rust" => program.to_rust(&settings).into_bytes(),
+        "wasm" => program.to_wasm(&settings)?,
+        "bytecode" => program.serialize(),
+        "asm" => program.to_asm(with_source.then(|| String::from_utf8_lossy(&code)).as_deref()).into_bytes(),
+        _ => return Err(format!("Unsupported language: '{}'", emit).into()),
+    };
+    if output.is_empty() {
+        std::io::stdout().write_all(&translated)?;
+    } else {
+        create(&output, false)?.write_all(&translated)?;
+    }
+    Ok(())
+}
+
+/// Start the debugger on a program
+fn debug(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut io = IoOptions::default();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Debug a brainf*ck program interactively.");
+        source.register(&mut parser, "brainf*ck file to debug, or - to read it from stdin");
+        machine.register(&mut parser);
+        io.register(&mut parser);
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let mut settings = machine.settings(&source);
+    io.configure(&mut settings, &source)?;
+    let tape = machine.tape()?;
+    let mut interpreter = Interpreter::with_tape(settings, tape).with_engine(machine.engine.create());
+    interpreter.vm_mut().set_pedantic_pointer(machine.pedantic_pointer);
+    interpreter.vm_mut().set_gas(io.gas()?);
+    for (addr, kind) in machine.devices()? {
+        let device = kind.create(interpreter.vm().settings());
+        interpreter.vm_mut().map_device(addr, device);
+    }
+    interpreter.set_passes(PassManager::for_machine(machine.opt_level, interpreter.vm()));
+    interpreter.set_syntax(source.syntax());
+    let code = source.read()?;
+    source.load_into(&mut interpreter, &code)?;
+    debug::run(Debugger::new(interpreter))
+}
+
+/// Validate a program without running it
+fn check(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut lints = LintOptions::default();
+    let mut warn_all = false;
+    let mut analyze = false;
+    let mut memsize = MachineOptions::default().memsize;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Check that a brainf*ck program compiles, without running it.");
+        source.register(&mut parser, "brainf*ck file to check, or - to read it from stdin");
+        lints.register(&mut parser);
+
+        // Lints warn by default now, the option is kept so that existing scripts still work
+        parser.refer(&mut warn_all)
+            .add_option(&["--lint"], argparse::StoreTrue, "same as --warn all");
+
+        parser.refer(&mut analyze)
+            .add_option(&["--analyze"], argparse::StoreTrue,
+                        "report the range of cells the program may reach, and whether it fits --memsize cells");
+
+        parser.refer(&mut memsize)
+            .add_option(&["--memsize"], argparse::Store, "amount of memory checked by --analyze (default: 4096)");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    if warn_all {
+        lints.warn.push(String::from("all"));
+    }
+    let code = source.read()?;
+    let program = source.compile(&code)?;
+    source.lint(&code, &lints.levels()?, false)?;
+    if analyze {
+        match memory_bounds(&program) {
+            Ok(bounds) => {
+                println!("Cells reached: {} to {} from the initial one ({} cells)",
+                         bounds.min, bounds.max, bounds.cells());
+                if bounds.fits(memsize) {
+                    println!("Unchecked memory is safe with --memsize {} or more", bounds.max + 1);
+                } else {
+                    eprintln!("warning: the program does not fit {} cells of unchecked memory", memsize);
+                }
+            }
+            Err(Unbounded { pc, reason }) => {
+                println!("Cells reached: unbounded");
+                match program.location(pc) {
+                    Some(loc) => source.warn(&CompileError::new(&reason, loc), &code),
+                    None => eprintln!("warning: {}", reason),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print a program in a canonical layout
+fn fmt(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut options = FormatOptions::default();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Print a brainf*ck program in a canonical layout.");
+        source.register(&mut parser, "brainf*ck file to format, or - to read it from stdin");
+
+        parser.refer(&mut options.indent)
+            .add_option(&["--indent"], argparse::Store, "spaces per loop nesting level (default: 4)");
+
+        parser.refer(&mut options.width)
+            .add_option(&["--width"], argparse::StoreOption, "split lines of code longer than this");
+
+        parser.refer(&mut options.comments)
+            .add_option(&["--keep-comments"], argparse::StoreTrue, "keep comments on lines of their own");
+
+        parser.refer(&mut options.minify)
+            .add_option(&["--minify"], argparse::StoreTrue,
+                        "remove comments and whitespace, putting the program on one line or as few as --width allows");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let code = source.read()?;
+    if source.frontend(&code)?.is_some() || source.language() == Language::Asm {
+        return Err("Only brainf*ck programs can be formatted".into());
+    }
+    print!("{}", format_with(&code, source.syntax(), &options).map_err(|err| source.report(err, &code))?);
+    Ok(())
+}
+
+/// Write a brainf*ck program printing a text
+fn generate(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut text = String::new();
+    let mut output = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Generate a short brainf*ck program printing a text, for 8 bit cells that wrap around.");
+
+        parser.refer(&mut text)
+            .add_option(&["--text"], argparse::Store, "text printed by the program")
+            .required();
+
+        parser.refer(&mut output)
+            .add_option(&["-o", "--output"], argparse::Store, "write the program to a file instead of stdout");
+
+        parse(&parser, args);
+    }
+    let code = Program::printing(text.as_bytes()).to_brainfuck()? + "\n";
+    if output.is_empty() {
+        print!("{}", code);
+    } else {
+        create(&output, false)?.write_all(code.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Measure how long a program takes to run
+fn bench(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut input = String::new();
+    let mut iterations = 10;
+    let mut warmup = 1;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Run a brainf*ck program repeatedly on the same input, discarding its output, and \
+                                report its timings.");
+        source.register(&mut parser, "brainf*ck file to benchmark, or - to read it from stdin");
+        machine.register(&mut parser);
+
+        parser.refer(&mut input)
+            .add_option(&["--input"], argparse::Store, "read program input from a file, the same for every run");
+
+        parser.refer(&mut iterations)
+            .add_option(&["--iterations"], argparse::Store, "number of measured runs (default: 10)");
+
+        parser.refer(&mut warmup)
+            .add_option(&["--warmup"], argparse::Store, "number of runs before measuring (default: 1)");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let input = read_input(&input)?;
+    let code = source.read()?;
+    let settings = machine.settings(&source);
+    let program = PassManager::with_level(machine.opt_level, &settings).run(&source.compile(&code)?);
+    let settings = || machine.builder(&source).input(Cursor::new(input.clone())).output(std::io::sink()).build();
+    bench::run(&program, machine.engine, || machine.tape(), settings, warmup, iterations)
+}
+
+/// Check that optimizations do not change the behavior of a program
+fn selftest(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut input = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Run a brainf*ck program as it is and optimized, with -O 2 unless another level is \
+                                given, and check that output, final tape and memory pointer agree.");
+        source.register(&mut parser, "brainf*ck file to test, or - to read it from stdin");
+        machine.register(&mut parser);
+
+        parser.refer(&mut input)
+            .add_option(&["--input"], argparse::Store, "read program input from a file, the same for both runs");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let input = read_input(&input)?;
+    let code = source.read()?;
+    let program = source.compile(&code)?;
+    let level = match machine.opt_level {
+        OptimizationLevel::O0 => OptimizationLevel::O2,
+        level => level,
+    };
+    let passes = PassManager::with_level(level, &machine.settings(&source));
+    let outcome = compare(&program, &passes, || machine.settings(&source), &input)?;
+    println!(
+        "Optimized and original programs agree: {} bytes written, {} cells not 0, memory pointer at {}",
+        outcome.output.len(), outcome.cells.len(), outcome.mp,
+    );
+    Ok(())
+}
+
+/// Shrink a program that fails, keeping the failure
+fn reduce(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut source = SourceOptions::default();
+    let mut machine = MachineOptions::default();
+    let mut input = String::new();
+    let mut output = String::new();
+    let mut max_steps = 10_000_000;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Shrink a brainf*ck program that stops with an error, or whose behavior changes when it \
+                                is optimized as in selftest, by removing balanced chunks of code while the failure \
+                                persists.");
+        source.register(&mut parser, "brainf*ck file to reduce, or - to read it from stdin");
+        machine.register(&mut parser);
+
+        parser.refer(&mut input)
+            .add_option(&["--input"], argparse::Store, "read program input from a file, the same for every run");
+
+        parser.refer(&mut output)
+            .add_option(&["-o", "--output"], argparse::Store, "write the reduced program to a file instead of stdout");
+
+        parser.refer(&mut max_steps)
+            .add_option(&["--max-steps"], argparse::Store,
+                        "instructions after which a run is stopped, since removing code can make loops endless \
+                         (default: 10000000)");
+
+        parse(&parser, args);
+    }
+    if source.is_empty() {
+        return Err("No program given".into());
+    }
+    let input = read_input(&input)?;
+    let code = source.read()?;
+    if bytecode::is_bytecode(&code) || source.language() == Language::Asm || source.frontend(&code)?.is_some() {
+        return Err("Only brainf*ck source code can be reduced".into());
+    }
+    let level = match machine.opt_level {
+        OptimizationLevel::O0 => OptimizationLevel::O2,
+        level => level,
+    };
+    let passes = PassManager::with_level(level, &machine.settings(&source));
+    // Removing code can move the memory pointer left of the tape, which unchecked memory does not survive
+    if machine.memory_overflow == MemoryOverflowBehavior::Unchecked {
+        machine.memory_overflow = MemoryOverflowBehavior::Extend;
+    }
+    let settings = || machine.builder(&source).max_instructions(max_steps).build();
+    let stopped = |outcome: &Outcome| outcome.exceeded_limit;
+    let failure = |code: &[u8]| -> Option<String> {
+        let program = Program::compile_with(code, source.syntax()).ok()?;
+        match compare(&program, &passes, settings, &input) {
+            // Runs stopped by the limit after a different number of instructions do not diverge
+            Err(divergence) if stopped(&divergence.reference) || stopped(&divergence.optimized) => None,
+            Err(_) => Some(String::from("Optimizations change the behavior of the program")),
+            Ok(outcome) if stopped(&outcome) => None,
+            // The kind of error, without the address and location where it happened
+            Ok(outcome) => outcome.error.map(|error| String::from(error.split(" at ").next().unwrap_or_default())),
+        }
+    };
+    let original = failure(&code).ok_or("The program runs correctly: there is no failure to reproduce")?;
+    eprintln!("Reducing: {}", original);
+    let reduced = bfint::parse::reduce::reduce(&code, |candidate| failure(candidate).as_ref() == Some(&original));
+    eprintln!("Reduced from {} to {} bytes", code.len(), reduced.len());
+    if output.is_empty() {
+        println!("{}", String::from_utf8_lossy(&reduced));
+    } else {
+        create(&output, false)?.write_all(&reduced)?;
+    }
+    Ok(())
+}
+
+/// Run programs sent over HTTP, as the backend of an online judge or playground
+fn serve(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut host = String::from("127.0.0.1");
+    let mut port: u16 = 8080;
+    let mut memsize = 30000;
+    let mut max_steps = 10_000_000;
+    let mut timeout = 5.0;
+    let mut max_output_bytes = 1 << 16;
+    let mut gas = None;
+    let mut gas_costs = String::new();
+    let mut max_request_bytes = 1 << 20;
+    let mut session_timeout = 300.0;
+    let mut max_connections = 64;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("Serve runs of brainf*ck programs over HTTP. POST a program and its input to /run, as \
+                                a JSON object with the 'program' and 'input' strings or as multipart form data, to \
+                                receive its output and statistics as JSON, or open a WebSocket at /ws to run \
+                                interactive programs. Every run is held to the limits below.");
+        parser.refer(&mut host)
+            .add_option(&["--host"], argparse::Store, "address to listen on (default: 127.0.0.1)");
+
+        parser.refer(&mut port)
+            .add_option(&["--port"], argparse::Store, "port to listen on (default: 8080)");
 
         parser.refer(&mut memsize)
+            .add_option(&["--memsize"], argparse::Store,
+                        "cells of the tape of every run; moving past either end is an error (default: 30000)");
+
+        parser.refer(&mut max_steps)
+            .add_option(&["--max-steps"], argparse::Store,
+                        "abort runs after executing this many instructions (default: 10000000)");
+
+        parser.refer(&mut timeout)
+            .add_option(&["--timeout"], argparse::Store, "abort runs after this many seconds (default: 5)");
+
+        parser.refer(&mut max_output_bytes)
+            .add_option(&["--max-output-bytes"], argparse::Store,
+                        "abort runs writing more than this many bytes (default: 65536)");
+
+        parser.refer(&mut gas)
+            .add_option(&["--gas"], argparse::StoreOption,
+                        "abort runs once the instructions executed cost more than this much gas, see --gas-costs");
+
+        parser.refer(&mut gas_costs)
+            .add_option(&["--gas-costs"], argparse::Store,
+                        "cost of the instructions for --gas, by mnemonic, such as 'wr=10,rd=10' (default: 1 each)");
+
+        parser.refer(&mut max_request_bytes)
+            .add_option(&["--max-request-bytes"], argparse::Store,
+                        "reject requests larger than this many bytes, program and input included, and WebSocket \
+                         messages as well (default: 1048576)");
+
+        parser.refer(&mut session_timeout)
+            .add_option(&["--session-timeout"], argparse::Store,
+                        "abort WebSocket sessions after this many seconds, time waiting for input included, instead \
+                         of --timeout (default: 300)");
+
+        parser.refer(&mut max_connections)
+            .add_option(&["--max-connections"], argparse::Store,
+                        "answer at most this many connections at a time, and reject further ones with 503 Service \
+                         Unavailable (default: 64)");
+
+        parse(&parser, args);
+    }
+    if gas.is_none() && !gas_costs.is_empty() {
+        return Err("--gas-costs requires --gas".into());
+    }
+    let model: CostModel = gas_costs.parse()?;
+    let limits = serve::Limits {
+        memsize,
+        max_steps,
+        timeout: Duration::try_from_secs_f64(timeout).map_err(|_| format!("Invalid timeout: {}", timeout))?,
+        max_output_bytes,
+        gas: gas.map(|budget| (budget, model)),
+        max_request_bytes,
+        session_timeout: Duration::try_from_secs_f64(session_timeout)
+            .map_err(|_| format!("Invalid session timeout: {}", session_timeout))?,
+        max_connections,
+    };
+    // IPv6 addresses are bracketed before the port
+    let addr = match host.contains(':') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    };
+    serve::serve(&addr, limits)
+}
+
+/* Options ************************************************************************************************************/
+impl SourceOptions {
+    fn register<'parser>(&'parser mut self, parser: &mut ArgumentParser<'parser>, help: &'parser str) {
+        parser.refer(&mut self.fname)
+            .add_argument("fname", argparse::Store, help);
+
+        parser.refer(&mut self.eval)
+            .add_option(&["-e", "--eval"], argparse::Store, "use the program given as argument instead of a file");
+
+        parser.refer(&mut self.lang)
+            .add_option(&["--lang"], argparse::StoreOption,
+                        "language of the program: bf, ook, pbrain, ebf1, smbf, boolfuck, brainfork or asm (default: \
+                        from the file extension)");
+
+        parser.refer(&mut self.map)
+            .add_option(&["--map"], argparse::Store,
+                        "words standing for the commands, as in '+:a,-:b', to run trivial brainf*ck substitutions");
+
+        parser.refer(&mut self.map_file)
+            .add_option(&["--map-file"], argparse::Store, "read the --map entries from a file, one per line");
+
+        parser.refer(&mut self.preprocess)
+            .add_option(&["--preprocess"], argparse::StoreTrue,
+                        "expand @define macros, @include directives and repetitions such as +*10 before compiling");
+
+        parser.refer(&mut self.include_paths)
+            .add_option(&["-I", "--include-path"], argparse::Collect,
+                        "directory where --preprocess looks for included files, after the one of the including file");
+
+        parser.refer(&mut self.debug_hash)
+            .add_option(&["--debug-hash"], argparse::StoreTrue,
+                        "treat # as a command printing the state of the machine to stderr, instead of a comment");
+
+        parser.refer(&mut self.bang_input)
+            .add_option(&["--bang-input"], argparse::StoreTrue,
+                        "treat everything after the first ! in the source as the input of the program");
+
+        parser.refer(&mut self.permissive)
+            .add_option(&["--permissive"], argparse::StoreTrue,
+                        "ignore characters that are not commands (default for .bf files)");
+
+        parser.refer(&mut self.strict)
+            .add_option(&["--strict"], argparse::StoreTrue, "reject characters that are not commands");
+
+        parser.refer(&mut self.error_format)
+            .add_option(&["--error-format"], argparse::Store,
+                        "human (default) or json, to report errors and warnings as one JSON object per line with \
+                         their kind, message, file, line, column and instruction address");
+    }
+
+    /// Syntax options
+    fn syntax(&self) -> Syntax {
+        Syntax {
+            debug_hash: self.debug_hash,
+            bang_input: self.bang_input,
+            permissive: self.permissive || (!self.strict && self.fname.ends_with(".bf")),
+            procedures: self.language() == Language::Pbrain,
+            extended: self.language() == Language::Ebf1,
+            fork: self.language() == Language::Brainfork,
+        }
+    }
+
+    /// Language of the program, guessed from the file extension unless given explicitly
+    fn language(&self) -> Language {
+        self.lang.unwrap_or_else(|| Language::from_path(&self.fname))
+    }
+
+    /// Command mapping given with --map or --map-file, if any
+    fn mapping(&self) -> Result<Option<Mapping>, Box<dyn Error>> {
+        let spec = match (self.map.is_empty(), self.map_file.is_empty()) {
+            (true, true) => return Ok(None),
+            (false, true) => self.map.clone(),
+            (true, false) => {
+                let mut spec = String::new();
+                open(&self.map_file)?.read_to_string(&mut spec)?;
+                spec
+            }
+            (false, false) => return Err("--map cannot be used together with --map-file".into()),
+        };
+        let mapping = spec.parse::<Mapping>().map_err(|err| format!("Invalid mapping: {}", err))?;
+        Ok(Some(mapping))
+    }
+
+    /// Frontend reading the program, or `None` for brainf*ck source code and bytecode
+    fn frontend(&self, code: &[u8]) -> Result<Option<Box<dyn Frontend>>, Box<dyn Error>> {
+        if bytecode::is_bytecode(code) {
+            return Ok(None);
+        }
+        let source = String::from_utf8_lossy(code);
+        if let Some(mapping) = self.mapping()? {
+            return Ok(Some(Box::new(Substitution::new(&source, &mapping))));
+        }
+        Ok(match self.language() {
+            Language::Brainfuck | Language::Pbrain | Language::Ebf1 | Language::Brainfork | Language::Asm => None,
+            Language::Ook => Some(Box::new(Ook::new(&source))),
+            Language::Smbf => return Err("Self-modifying programs can only be run".into()),
+            Language::Boolfuck => Some(Box::new(Substitution::new(&source, &Mapping::boolfuck()))),
+        })
+    }
+
+    /// Commands of the source code, or `None` for bytecode and instruction listings, which have none
+    fn tokens(&self, code: &[u8]) -> Result<Option<Vec<Token>>, Box<dyn Error>> {
+        if bytecode::is_bytecode(code) || self.language() == Language::Asm {
+            return Ok(None);
+        }
+        let tokens = match self.frontend(code)? {
+            Some(mut frontend) => frontend.tokens().collect::<Result<_, _>>()?,
+            None => Tokenizer::with_syntax(code, self.syntax()).collect::<Result<_, _>>()?,
+        };
+        Ok(Some(tokens))
+    }
+
+    /// Decode a bytecode file or compile source code
+    fn compile(&self, code: &[u8]) -> Result<Program, Box<dyn Error>> {
+        let program = match self.frontend(code)? {
+            Some(mut frontend) => Program::from_frontend(frontend.as_mut()),
+            None if bytecode::is_bytecode(code) => Program::deserialize(code),
+            None if self.language() == Language::Asm => assemble(&String::from_utf8_lossy(code)),
+            None => Program::compile_with(code, self.syntax()),
+        };
+        program.map_err(|err| self.report(err, code))
+    }
+
+    /// Load a bytecode file or source code into `interpreter`. Instruction listings are loaded as they are, without
+    /// optimizations
+    fn load_into(&self, interpreter: &mut Interpreter, code: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.language() == Language::Asm && !bytecode::is_bytecode(code) {
+            interpreter.load_program(self.compile(code)?);
+            return Ok(());
+        }
+        let loaded = match self.frontend(code)? {
+            Some(mut frontend) => interpreter.load_frontend(frontend.as_mut()),
+            None => interpreter.load_source(code),
+        };
+        loaded.map_err(|err| self.report(err, code))
+    }
+
+    /// Whether no program was given
+    fn is_empty(&self) -> bool {
+        self.fname.is_empty() && self.eval.is_empty()
+    }
+
+    /// Whether the program is read from stdin
+    fn is_stdin(&self) -> bool {
+        self.fname == "-"
+    }
+
+    /// Read the program, expanding its macros if requested
+    fn read(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut code = Vec::new();
+        if !self.fname.is_empty() && !self.eval.is_empty() {
+            return Err("A program file cannot be used together with --eval".into());
+        } else if !self.eval.is_empty() {
+            code.extend_from_slice(self.eval.as_bytes());
+        } else if self.is_stdin() {
+            std::io::stdin().read_to_end(&mut code)?;
+        } else {
+            open(&self.fname)?.read_to_end(&mut code)?;
+        }
+        if self.preprocess && !bytecode::is_bytecode(&code) {
+            let mut preprocessor = Preprocessor::new();
+            preprocessor.add_include_path(Path::new(&self.fname).parent().unwrap_or(Path::new("")));
+            for path in &self.include_paths {
+                preprocessor.add_include_path(Path::new(path));
+            }
+            code = match preprocessor.expand(&String::from_utf8_lossy(&code)) {
+                Ok(expanded) => expanded.into_bytes(),
+                // Errors of the preprocessor itself are already located in the original files
+                Err(err) => match err.downcast_ref::<CompileError>() {
+                    Some(err) => self.fail(err, preprocessor.source(err.file())),
+                    None => return Err(err),
+                },
+            };
+            self.preprocessor = Some(preprocessor);
+        }
+        Ok(code)
+    }
+
+    /// Report what the lints find in the compiled `code` at their `levels`, run on a tape that may be `preloaded`.
+    /// Denied lints are reported as errors, and terminate the process once they are all reported
+    fn lint(&self, code: &[u8], levels: &LintLevels, preloaded: bool) -> Result<(), Box<dyn Error>> {
+        let Some(tokens) = self.tokens(code)?.filter(|_| levels.any_enabled()) else {
+            return Ok(());
+        };
+        let mut denied = false;
+        for (lint, warning) in lint(&tokens, preloaded) {
+            match levels.level(lint) {
+                LintLevel::Allow => {}
+                LintLevel::Warn => self.warn(&warning.with_help(&format!("silence it with --allow {}", lint)), code),
+                LintLevel::Deny => {
+                    let err = self.locate(&warning.with_help(&format!("{} is denied by --deny", lint)));
+                    self.print_error(&err, &self.source_of(&err, code));
+                    denied = true;
+                }
+            }
+        }
+        if denied {
+            std::process::exit(COMPILE_ERROR_EXIT_CODE);
+        }
+        Ok(())
+    }
+
+    /// Print a warning together with the source code it refers to
+    fn warn(&self, warning: &CompileError, code: &[u8]) {
+        let warning = self.locate(warning);
+        match self.error_format {
+            ErrorFormat::Human => eprint!("{}", render_warning(&warning, &self.source_of(&warning, code))),
+            ErrorFormat::Json => eprintln!("{}", warning.warning_to_json(self.main_file())),
+        }
+    }
+
+    /// Print a warning about the program as a whole, or about code without a source location, like
+    /// [`SourceOptions::warn`] but with a null line and column
+    fn warn_program(&self, message: &str) {
+        match self.error_format {
+            ErrorFormat::Human => eprintln!("warning: {}", message),
+            ErrorFormat::Json => eprintln!(
+                r#"{{"kind":"warning","message":{},"file":{},"line":null,"col":null,"pc":null}}"#,
+                json_string(message), self.main_file().map_or(String::from("null"), json_string),
+            ),
+        }
+    }
+
+    /// Compile errors are printed together with the source code they refer to, and terminate the process. Errors in
+    /// preprocessed code are reported in the files it comes from. Other errors are returned as they are
+    fn report(&self, err: Box<dyn Error>, code: &[u8]) -> Box<dyn Error> {
+        match err.downcast_ref::<CompileError>() {
+            Some(err) => {
+                let err = self.locate(err);
+                self.fail(&err, &self.source_of(&err, code))
+            }
+            None => err,
+        }
+    }
+
+    /// Print a compile error in `source`, the code it refers to, and terminate the process
+    fn fail(&self, err: &CompileError, source: &str) -> ! {
+        self.print_error(err, source);
+        std::process::exit(COMPILE_ERROR_EXIT_CODE);
+    }
+
+    /// Print a compile error in `source`, the code it refers to
+    fn print_error(&self, err: &CompileError, source: &str) {
+        match self.error_format {
+            ErrorFormat::Human if colored() => eprint!("{}", err.render_colored(source)),
+            ErrorFormat::Human => eprint!("{}", err.render(source)),
+            ErrorFormat::Json => eprintln!("{}", err.to_json(self.main_file())),
+        }
+    }
+
+    /// Map an error in the compiled code back to the file it comes from, if the code was preprocessed
+    fn locate(&self, err: &CompileError) -> CompileError {
+        match &self.preprocessor {
+            Some(preprocessor) => preprocessor.locate(err),
+            None => err.clone(),
+        }
+    }
+
+    /// Source code an error returned by [`SourceOptions::locate`] refers to, given the compiled `code`
+    fn source_of<'a>(&'a self, err: &CompileError, code: &'a [u8]) -> Cow<'a, str> {
+        match &self.preprocessor {
+            Some(preprocessor) => Cow::Borrowed(preprocessor.source(err.file())),
+            None => String::from_utf8_lossy(code),
+        }
+    }
+
+    /// Mention the program file in the [`RuntimeError`] `err`, if it is one
+    fn name_runtime_error(&self, err: Box<dyn Error>) -> Box<dyn Error> {
+        match (err.downcast::<RuntimeError>(), self.main_file()) {
+            (Ok(err), Some(file)) => Box::new(err.in_file(file)),
+            (Ok(err), None) => err,
+            (Err(err), _) => err,
+        }
+    }
+
+    /// Name of the program file, unless the program comes from stdin or the command line
+    fn main_file(&self) -> Option<&str> {
+        (!self.fname.is_empty() && !self.is_stdin()).then_some(self.fname.as_str())
+    }
+}
+
+impl LintOptions {
+    fn register<'parser>(&'parser mut self, parser: &mut ArgumentParser<'parser>) {
+        parser.refer(&mut self.allow)
+            .add_option(&["-A", "--allow"], argparse::Collect,
+                        "do not report a lint, such as endless-loop, or all of them with all");
+
+        parser.refer(&mut self.warn)
+            .add_option(&["-W", "--warn"], argparse::Collect,
+                        "report a lint as a warning, which is the default: dead-loop, cancelling-commands, \
+                        unwritten-output, endless-loop, overwritten-input or all");
+
+        parser.refer(&mut self.deny)
+            .add_option(&["-D", "--deny"], argparse::Collect,
+                        "report a lint as an error, which stops the compilation, or all of them with all");
+    }
+
+    /// Levels of the lints. Lints named on the command line take the level given for them over the one given for
+    /// `all`, whatever the order of the options. Otherwise allowed lints are set first and denied ones last
+    fn levels(&self) -> Result<LintLevels, Box<dyn Error>> {
+        let mut levels = LintLevels::default();
+        let names = [(&self.allow, LintLevel::Allow), (&self.warn, LintLevel::Warn), (&self.deny, LintLevel::Deny)];
+        for specific in [false, true] {
+            for (names, level) in names {
+                for name in names.iter().filter(|&name| (name != "all") == specific) {
+                    levels.set_by_name(name, level)?;
+                }
+            }
+        }
+        Ok(levels)
+    }
+}
+
+impl Default for MachineOptions {
+    fn default() -> Self {
+        MachineOptions {
+            memsize: 4096,
+            memory_overflow: MemoryOverflowBehavior::Unchecked,
+            cell_overflow: CellOverflowBehavior::Wrap,
+            cell_width: CellWidth::U8,
+            opt_level: OptimizationLevel::O0,
+            engine: EngineKind::Switch,
+            tape: TapeKind::Dense,
+            #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+            tape_file: String::new(),
+            pedantic_pointer: false,
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl MachineOptions {
+    fn register<'parser>(&'parser mut self, parser: &mut ArgumentParser<'parser>) {
+        parser.refer(&mut self.memsize)
             .add_option(&["--memsize"], argparse::Store, "amount of memory to allocate in bytes");
 
-        if let Err(code) = parser.parse_args() {
-            return Err(format!("Error while parsing arguments: code {}", code).into());
+        parser.refer(&mut self.opt_level)
+            .add_option(&["-O", "--opt-level"], argparse::Store, "optimization level: 0 (default), 1 or 2");
+
+        parser.refer(&mut self.memory_overflow)
+            .add_option(&["--memory-overflow"], argparse::Store,
+                        "behavior when the memory pointer leaves the tape: unchecked (default), saturate, wrap, \
+                        extend or error");
+
+        parser.refer(&mut self.cell_overflow)
+            .add_option(&["--cell-overflow"], argparse::Store,
+                        "behavior when a cell overflows: wrap (default), saturate or error");
+
+        parser.refer(&mut self.cell_width)
+            .add_option(&["--cell-size"], argparse::Store,
+                        "size of a memory cell in bits: 1, 8 (default), 16, 32 or 64");
+
+        parser.refer(&mut self.engine)
+            .add_option(&["--engine"], argparse::Store,
+                        "engine executing the instructions: switch (default) or threaded, which is faster");
+
+        parser.refer(&mut self.tape)
+            .add_option(&["--tape"], argparse::Store,
+                        "storage of the cells: dense (default), sparse, which only allocates the pages that are \
+                        used, or mmap with the mmap feature");
+
+        #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+        parser.refer(&mut self.tape_file)
+            .add_option(&["--tape-file"], argparse::Store,
+                        "map the cells to a file, which holds the final tape after the run as 8 bytes per cell");
+
+        parser.refer(&mut self.pedantic_pointer)
+            .add_option(&["--pedantic-pointer"], argparse::StoreTrue,
+                        "stop with an error as soon as the memory pointer leaves the first --memsize cells, whatever \
+                         --memory-overflow says, and report the range of cells it visited after the run");
+
+        parser.refer(&mut self.devices)
+            .add_option(&["--device"], argparse::Collect,
+                        "map a device at a cell, as ADDRESS=KIND: random cells read as random numbers, clock cells \
+                         as the seconds since the Unix epoch, and writing a flush cell flushes the output; can be \
+                         repeated");
+    }
+
+    /// Devices given with --device, by address
+    fn devices(&self) -> Result<Vec<(isize, DeviceKind)>, Box<dyn Error>> {
+        self.devices.iter().map(|device| {
+            let (addr, kind) = device.split_once('=')
+                .ok_or_else(|| format!("Invalid device: '{}', expected ADDRESS=KIND", device))?;
+            let addr = addr.parse().map_err(|_| format!("Invalid device address: '{}'", addr))?;
+            Ok((addr, kind.parse()?))
+        }).collect()
+    }
+
+    /// Tape of `--memsize` cells, mapped to the `--tape-file` if one is given
+    fn tape(&self) -> Result<Box<dyn Tape>, Box<dyn Error>> {
+        #[cfg(all(feature = "mmap", any(target_os = "linux", target_os = "macos")))]
+        if !self.tape_file.is_empty() {
+            let tape = bfint::interpreter::mapped::Mapped::with_file(&self.tape_file, self.memsize)
+                .map_err(|err| {
+                    std::io::Error::new(err.kind(), format!("Could not map '{}': {}", self.tape_file, err))
+                })?;
+            return Ok(Box::new(tape));
+        }
+        Ok(self.tape.create(self.memsize).map_err(|err| format!("Could not create the tape: {}", err))?)
+    }
+
+    /// Settings with standard input and output and no limits. Boolfuck programs always use bit cells
+    fn settings(&self, source: &SourceOptions) -> Settings {
+        self.builder(source).build()
+    }
+
+    /// Builder starting from [`MachineOptions::settings`]
+    fn builder(&self, source: &SourceOptions) -> SettingsBuilder {
+        let cell_width = match source.language() {
+            Language::Boolfuck => CellWidth::U1,
+            _ => self.cell_width,
+        };
+        Settings::builder()
+            .memory_size(self.memsize)
+            .memory_overflow_behavior(self.memory_overflow)
+            .cell_overflow_behavior(self.cell_overflow)
+            .cell_width(cell_width)
+    }
+}
+
+impl IoOptions {
+    fn register<'parser>(&'parser mut self, parser: &mut ArgumentParser<'parser>) {
+        parser.refer(&mut self.input)
+            .add_option(&["--input"], argparse::Store, "read program input from a file instead of stdin");
+
+        parser.refer(&mut self.output)
+            .add_option(&["--output"], argparse::Store, "write program output to a file instead of stdout");
+
+        parser.refer(&mut self.append)
+            .add_option(&["--append"], argparse::StoreTrue, "append to the --output file instead of truncating it");
+
+        parser.refer(&mut self.listen)
+            .add_option(&["--listen"], argparse::Store,
+                        "wait for a TCP connection on this address, such as 127.0.0.1:7000, and use it for program \
+                         input and output instead of stdin and stdout");
+
+        parser.refer(&mut self.connect)
+            .add_option(&["--connect"], argparse::Store,
+                        "connect to this TCP address, such as example.com:7000, and use the connection for program \
+                         input and output instead of stdin and stdout");
+
+        parser.refer(&mut self.max_steps)
+            .add_option(&["--max-steps"], argparse::StoreOption, "abort after executing this many instructions");
+
+        parser.refer(&mut self.timeout)
+            .add_option(&["--timeout"], argparse::StoreOption, "abort after running for this many seconds");
+
+        parser.refer(&mut self.gas)
+            .add_option(&["--gas"], argparse::StoreOption,
+                        "abort once the instructions executed cost more than this much gas, see --gas-costs");
+
+        parser.refer(&mut self.gas_costs)
+            .add_option(&["--gas-costs"], argparse::Store,
+                        "cost of the instructions for --gas, by mnemonic as listed by compile --emit asm, such as \
+                         'wr=10,rd=10' (default: 1 each, all=N sets them all)");
+
+        parser.refer(&mut self.max_output_bytes)
+            .add_option(&["--max-output-bytes"], argparse::StoreOption,
+                        "abort when the program writes more than this many bytes");
+
+        parser.refer(&mut self.max_input_bytes)
+            .add_option(&["--max-input-bytes"], argparse::StoreOption,
+                        "abort when the program reads more than this many bytes");
+
+        parser.refer(&mut self.truncate_io)
+            .add_option(&["--truncate-io"], argparse::StoreTrue,
+                        "instead of aborting, drop output past --max-output-bytes and read input past \
+                         --max-input-bytes as EOF");
+
+        parser.refer(&mut self.flush)
+            .add_option(&["--flush"], argparse::Store,
+                        "when program output is flushed: byte, newline, input (default) or manual; output is \
+                         always flushed before reads and at exit");
+
+        parser.refer(&mut self.output_encoding)
+            .add_option(&["--output-encoding"], argparse::Store,
+                        "how program output is written: raw bytes (default) or utf8, which replaces invalid UTF-8 \
+                         sequences with U+FFFD");
+
+        parser.refer(&mut self.seed)
+            .add_option(&["--seed"], argparse::StoreOption,
+                        "seed the numbers read by random devices, so that runs are reproducible (default: a \
+                         different seed on every run)");
+
+        parser.refer(&mut self.preload_memory)
+            .add_option(&["--preload-memory"], argparse::Store,
+                        "fill the first cells of the tape with the bytes of a file before running");
+
+        parser.refer(&mut self.preload_hex)
+            .add_option(&["--preload-hex"], argparse::Store,
+                        "fill the first cells of the tape with bytes given in hexadecimal, such as '48 69 0a'");
+    }
+
+    /// Gas budget given with --gas, if any
+    fn gas(&self) -> Result<Option<Gas>, Box<dyn Error>> {
+        if self.gas.is_none() && !self.gas_costs.is_empty() {
+            return Err("--gas-costs requires --gas".into());
+        }
+        let model: CostModel = self.gas_costs.parse()?;
+        Ok(self.gas.map(|budget| Gas::new(budget, model)))
+    }
+
+    /// Whether program input comes from stdin
+    fn reads_stdin(&self) -> bool {
+        self.input.is_empty() && self.listen.is_empty() && self.connect.is_empty()
+    }
+
+    /// TCP connection given with --listen or --connect, if any, once it is established
+    fn stream(&self) -> Result<Option<TcpStream>, Box<dyn Error>> {
+        match (self.listen.as_str(), self.connect.as_str()) {
+            ("", "") => Ok(None),
+            (addr, "") => {
+                let listener = TcpListener::bind(addr)
+                    .map_err(|err| format!("Could not listen on '{}': {}", addr, err))?;
+                eprintln!("Listening on {}", listener.local_addr()?);
+                let (stream, peer) = listener.accept()?;
+                eprintln!("Accepted a connection from {}", peer);
+                Ok(Some(stream))
+            }
+            ("", addr) => {
+                let stream = TcpStream::connect(addr)
+                    .map_err(|err| format!("Could not connect to '{}': {}", addr, err))?;
+                Ok(Some(stream))
+            }
+            _ => Err("--listen and --connect cannot be used together".into()),
+        }
+    }
+
+    /// Apply input, output and limits to `settings`
+    fn configure(&self, settings: &mut Settings, source: &SourceOptions) -> Result<(), Box<dyn Error>> {
+        if source.is_stdin() && self.reads_stdin() && !source.bang_input {
+            return Err("Reading the program from stdin requires --input or --bang-input".into());
+        }
+        let tcp = !self.listen.is_empty() || !self.connect.is_empty();
+        if tcp && (!self.input.is_empty() || !self.output.is_empty()) {
+            return Err("--listen and --connect cannot be used with --input or --output".into());
+        }
+        if !self.input.is_empty() {
+            settings.input = Box::new(BufReader::new(open(&self.input)?));
+        }
+        if !self.output.is_empty() {
+            settings.output = Box::new(BufWriter::new(create(&self.output, self.append)?));
+        }
+        settings.max_instructions = self.max_steps;
+        settings.max_wall_time = self.timeout.map(Duration::from_secs_f64);
+        settings.max_output_bytes = self.max_output_bytes;
+        settings.max_input_bytes = self.max_input_bytes;
+        settings.truncate_io = self.truncate_io;
+        settings.flush_policy = self.flush;
+        settings.output_encoding = self.output_encoding;
+        if let Some(seed) = self.seed {
+            settings.random = Random::new(seed);
+        }
+        settings.initial_memory = match (self.preload_memory.is_empty(), self.preload_hex.is_empty()) {
+            (false, false) => return Err("--preload-memory and --preload-hex cannot be used together".into()),
+            (false, true) => read_input(&self.preload_memory)?,
+            (true, false) => parse_hex(&self.preload_hex)?,
+            (true, true) => Vec::new(),
+        };
+        if settings.initial_memory.len() > settings.memory_size {
+            let (bytes, cells) = (settings.initial_memory.len(), settings.memory_size);
+            return Err(format!("Cannot preload {} bytes into a tape of {} cells", bytes, cells).into());
+        }
+        // Connect last, so that invalid options are reported without waiting for a peer
+        if let Some(stream) = self.stream()? {
+            let tcp = Settings::builder().tcp_stream(stream)?.build();
+            settings.input = tcp.input;
+            settings.output = tcp.output;
+        }
+        Ok(())
+    }
+}
+
+/* ProgramError *******************************************************************************************************/
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for ProgramError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+/* ErrorFormat ********************************************************************************************************/
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Invalid error format: '{}'", s)),
         }
     }
-    // Run interpreter
-    if fname.is_empty() {
-        // CL mode
-        todo!("Command line mode is not supported yet");
+}
+
+/* Helpers ************************************************************************************************************/
+/// Exit code of a subcommand that failed with `err`
+fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    if causes(err).any(|cause| cause.is::<std::io::Error>()) {
+        IO_ERROR_EXIT_CODE
+    } else if err.is::<ProgramError>() {
+        RUNTIME_ERROR_EXIT_CODE
     } else {
-        let mut interpreter = Interpreter::new();
-        interpreter.load_file(&fname)?;
-        interpreter.run()?;
+        COMPILE_ERROR_EXIT_CODE
+    }
+}
+
+/// `err` followed by the errors that caused it
+fn causes<'a>(err: &'a (dyn Error + 'static)) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    std::iter::successors(Some(err), |&err| err.source())
+}
+
+/// Describe `err` as a single line JSON object, like [`RuntimeError::to_json`]. Errors without a location have the
+/// kind "io", "runtime" or "error", and null file, line, column and address
+fn error_json(err: &(dyn Error + 'static)) -> String {
+    for cause in causes(err) {
+        if let Some(cause) = cause.downcast_ref::<RuntimeError>() {
+            return cause.to_json();
+        }
+    }
+    let kind = match exit_code(err) {
+        IO_ERROR_EXIT_CODE => "io",
+        RUNTIME_ERROR_EXIT_CODE => "runtime",
+        _ => "error",
+    };
+    format!(
+        r#"{{"kind":"{}","message":{},"file":null,"line":null,"col":null,"pc":null}}"#,
+        kind, json_string(&err.to_string()),
+    )
+}
+
+/// Error format selected by `--error-format` in `args`. It is looked up before the arguments are parsed, since it
+/// also applies to the errors returned by the subcommand
+fn error_format(args: &[String]) -> ErrorFormat {
+    let value = args.iter().enumerate().find_map(|(index, arg)| match arg.strip_prefix("--error-format") {
+        Some("") => args.get(index + 1).map(String::as_str),
+        Some(value) => value.strip_prefix('='),
+        None => None,
+    });
+    value.and_then(|value| value.parse().ok()).unwrap_or_default()
+}
+
+/// Whether diagnostics are colored: stderr must be a terminal and `NO_COLOR` unset
+fn colored() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Render `warning`, colored if [`colored`]
+fn render_warning(warning: &CompileError, source: &str) -> String {
+    if colored() { warning.render_warning_colored(source) } else { warning.render_warning(source) }
+}
+
+/// Parse the arguments of a subcommand. On errors, or after printing the help, the process exits
+fn parse(parser: &ArgumentParser, args: Vec<String>) {
+    match parser.parse(args, &mut std::io::stdout(), &mut std::io::stderr()) {
+        Ok(()) => {}
+        Err(0) => std::process::exit(0),
+        // Invalid arguments must not be mistaken for runtime errors
+        Err(_) => std::process::exit(COMPILE_ERROR_EXIT_CODE),
+    }
+}
+
+/// Program counter of the current thread of `interpreter`, with its source location, and instructions executed
+fn position(interpreter: &Interpreter) -> String {
+    let vm = interpreter.vm();
+    let location = match interpreter.program().location(vm.pc()) {
+        Some(loc) => format!(" ({})", loc),
+        None => String::new(),
+    };
+    format!("at pc 0x{:08x}{} after {} instructions", vm.pc(), location, vm.executed())
+}
+
+/// Print a hexdump of the tape of `vm` to stderr, or write it to `file` if given, for `--dump-memory`: the first
+/// `cells` cells, or the cells visited by the memory pointer if it is not given
+fn dump(vm: &VirtualMachine, cells: Option<usize>, file: &str) -> Result<(), Box<dyn Error>> {
+    let metrics = vm.metrics();
+    let (start, len) = cells.map_or((metrics.lowest_address, metrics.cells_touched()), |cells| (0, cells));
+    let dump = hexdump(&vm.memory_slice(start, len), start, vm.settings().cell_width);
+    match file {
+        "" => eprint!("{}", dump),
+        fname => create(fname, false)?.write_all(dump.as_bytes())?,
     }
     Ok(())
 }
+
+/// Framebuffer drawn to stdout for `--screen`, given as START:WIDTHxHEIGHT, or none if no screen is given
+fn framebuffer(screen: &str, sync: &str, fps: u32) -> Result<Option<Framebuffer>, Box<dyn Error>> {
+    if screen.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || format!("Invalid screen: '{}', expected START:WIDTHxHEIGHT", screen);
+    let (start, size) = screen.split_once(':').ok_or_else(invalid)?;
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let start = start.parse().map_err(|_| invalid())?;
+    let (width, height) = match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+        _ => return Err(invalid().into()),
+    };
+    let mut framebuffer = Framebuffer::new(start, width, height, std::io::stdout());
+    if !sync.is_empty() {
+        let sync = sync.parse().map_err(|_| format!("Invalid screen sync address: '{}'", sync))?;
+        framebuffer = framebuffer.with_sync(sync);
+    }
+    match fps {
+        0 if sync.is_empty() => Err("--screen requires --screen-sync or --screen-fps".into()),
+        0 => Ok(Some(framebuffer)),
+        fps => Ok(Some(framebuffer.with_fps(fps))),
+    }
+}
+
+/// Open a file, with an error message mentioning its name
+fn open(fname: &str) -> Result<File, Box<dyn Error>> {
+    File::open(fname)
+        .map_err(|err| std::io::Error::new(err.kind(), format!("Could not open '{}': {}", fname, err)).into())
+}
+
+/// Bytes written as pairs of hexadecimal digits, which may be separated by whitespace
+fn parse_hex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    digits.chunks(2).map(|pair| {
+        let pair: String = pair.iter().collect();
+        if pair.len() != 2 || !pair.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid hexadecimal byte: '{}'", pair).into());
+        }
+        Ok(u8::from_str_radix(&pair, 16)?)
+    }).collect()
+}
+
+/// Content of the file `fname`, or nothing if no file is given
+fn read_input(fname: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut input = Vec::new();
+    if !fname.is_empty() {
+        open(fname)?.read_to_end(&mut input)?;
+    }
+    Ok(input)
+}
+
+/// Create a file for writing, or open it for appending if `append` is set
+fn create(fname: &str, append: bool) -> Result<File, Box<dyn Error>> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(fname)
+        .map_err(|err| std::io::Error::new(err.kind(), format!("Could not create '{}': {}", fname, err)).into())
+}
@@ -0,0 +1,60 @@
+use super::Pass;
+use crate::parse::program::{Instruction, Program};
+
+/// Replace the clear loops `[-]` and `[+]` with a single `Set(0)`. `[+]` only terminates on 0 when cells wrap around,
+/// so it is only replaced if `cells_wrap` is set
+pub fn clear_loops(program: &Program, cells_wrap: bool) -> Program {
+    let source = program.instructions();
+    let mut instructions = Vec::with_capacity(source.len());
+    let mut locations = Vec::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        locations.push(program.location(i));
+        match source[i..] {
+            [Instruction::JZ(_), Instruction::DecData, Instruction::JNZ(_), ..] => {
+                instructions.push(Instruction::Set(0));
+                i += 3;
+            }
+            [Instruction::JZ(_), Instruction::IncData, Instruction::JNZ(_), ..] if cells_wrap => {
+                instructions.push(Instruction::Set(0));
+                i += 3;
+            }
+            _ => {
+                instructions.push(source[i]);
+                i += 1;
+            }
+        }
+    }
+    Program::with_locations(instructions, locations).expect("Clear loop elimination preserves bracket structure")
+}
+
+/// Pass wrapper around [`clear_loops`]
+pub struct ClearLoops {
+    pub cells_wrap: bool,
+}
+
+impl Pass for ClearLoops {
+    fn name(&self) -> &str {
+        "clear-loops"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        clear_loops(program, self.cells_wrap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn replace_clear_loops() {
+        let program = Program::compile(">[-]<[+]".as_bytes()).expect("Could not compile");
+        assert_eq!(clear_loops(&program, true).instructions(), &[IncPtr, Set(0), DecPtr, Set(0), Exit]);
+        assert_eq!(
+            clear_loops(&program, false).instructions(),
+            &[IncPtr, Set(0), DecPtr, JZ(6), IncData, JNZ(3), Exit]
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use super::Pass;
+use crate::parse::program::{Instruction, Program};
+
+/// Remove loops that can never be entered because the current cell is known to be 0: loops immediately following
+/// another loop or a `Set(0)`
+pub fn remove_dead_loops(program: &Program) -> Program {
+    let source = program.instructions();
+    let mut instructions: Vec<Instruction> = Vec::with_capacity(source.len());
+    let mut locations = Vec::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        match (instructions.last(), source[i]) {
+            (Some(Instruction::JNZ(_) | Instruction::Set(0)), Instruction::JZ(end)) => i = end,
+            (_, instruction) => {
+                instructions.push(instruction);
+                locations.push(program.location(i));
+                i += 1;
+            }
+        }
+    }
+    Program::with_locations(instructions, locations).expect("Dead loop elimination preserves bracket structure")
+}
+
+/// Pass wrapper around [`remove_dead_loops`]
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &str {
+        "dead-code"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        remove_dead_loops(program)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn remove_loops_after_loops() {
+        let program = Program::compile("+[>][-[+]]>[<]".as_bytes()).expect("Could not compile");
+        assert_eq!(
+            remove_dead_loops(&program).instructions(),
+            &[IncData, JZ(4), IncPtr, JNZ(1), IncPtr, JZ(8), DecPtr, JNZ(5), Exit]
+        );
+    }
+}
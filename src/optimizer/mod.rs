@@ -0,0 +1,167 @@
+use std::str::FromStr;
+
+use crate::interpreter::virtualmachine::{CellOverflowBehavior, MemoryOverflowBehavior, Settings, VirtualMachine};
+use crate::parse::program::Program;
+
+pub mod clear;
+pub mod dead;
+pub mod multiply;
+pub mod offset;
+pub mod prefix;
+pub mod rle;
+
+/// A transformation of a program that preserves its observable behavior
+pub trait Pass {
+    /// Short name of the pass, used in diagnostics
+    fn name(&self) -> &str;
+
+    /// Transform `program` into an equivalent program
+    fn run(&self, program: &Program) -> Program;
+
+    /// Whether the pass assumes that the program starts on a fresh machine, with every cell 0 and the memory pointer on
+    /// the first one. Such passes are skipped by [`PassManager::run_snippet`]
+    fn needs_fresh_machine(&self) -> bool {
+        false
+    }
+}
+
+/// Predefined optimization pipelines
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptimizationLevel {
+    /// No optimization
+    O0,
+    /// Run length encoding and clear loops
+    O1,
+    /// All optimizations
+    O2,
+}
+
+/// Runs a sequence of passes over a program, in the order they were added
+///
+/// ```
+/// use bfint::optimizer::{clear::ClearLoops, rle::RunLengthEncoding, PassManager};
+/// use bfint::Program;
+///
+/// let passes = PassManager::new()
+///     .with_pass(ClearLoops { cells_wrap: true })
+///     .with_pass(RunLengthEncoding);
+/// let program = passes.run(&Program::compile("+++[-]".as_bytes()).unwrap());
+/// assert_eq!(program.len(), 3);
+/// ```
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+/* PassManager ********************************************************************************************************/
+impl PassManager {
+    /// Create a PassManager with no passes
+    pub fn new() -> PassManager {
+        PassManager { passes: Vec::new() }
+    }
+
+    /// Create the pipeline for an optimization level. Passes that rely on wrapping arithmetic are only included when
+    /// `settings` guarantee it
+    pub fn with_level(level: OptimizationLevel, settings: &Settings) -> PassManager {
+        PassManager::build(level, settings, None)
+    }
+
+    /// Create the pipeline for an optimization level that preserves the behavior of programs run by `vm`, as
+    /// [`PassManager::with_level`] does for its settings. Optimizations treat every cell as plain memory, so none are
+    /// run when devices are mapped. Prefix evaluation is left out when gas is metered, since the instructions it runs
+    /// at compile time would cost nothing, and multiply loops and offset addressing when the memory pointer is
+    /// [pedantic](VirtualMachine::set_pedantic_pointer), since they move it where loops that never run would not
+    pub fn for_machine(level: OptimizationLevel, vm: &VirtualMachine) -> PassManager {
+        match vm.has_devices() {
+            true => PassManager::new(),
+            false => PassManager::build(level, vm.settings(), Some(vm)),
+        }
+    }
+
+    /// Pipeline for an optimization level, given the settings of the machine and the machine itself if known
+    fn build(level: OptimizationLevel, settings: &Settings, vm: Option<&VirtualMachine>) -> PassManager {
+        let cells_wrap = settings.cell_overflow_behavior == CellOverflowBehavior::Wrap;
+        // Moves that saturate or fail at the ends of the tape cannot be undone. Other pointers only visit the cells
+        // that the original loops reach, since multiplications are skipped when the loop would not be entered
+        let pointer_saturates = matches!(
+            settings.memory_overflow_behavior,
+            MemoryOverflowBehavior::Saturate | MemoryOverflowBehavior::Error
+        );
+        let metered = vm.is_some_and(|vm| vm.gas().is_some());
+        let pedantic = vm.is_some_and(VirtualMachine::pedantic_pointer);
+        let mut passes = PassManager::new();
+        if level >= OptimizationLevel::O1 {
+            passes.add_pass(clear::ClearLoops { cells_wrap });
+        }
+        if level >= OptimizationLevel::O2 && !pedantic {
+            passes.add_pass(multiply::MultiplyLoops { wrapping: cells_wrap && !pointer_saturates });
+        }
+        if level >= OptimizationLevel::O2 {
+            passes.add_pass(dead::DeadCode);
+        }
+        if level >= OptimizationLevel::O1 {
+            passes.add_pass(rle::RunLengthEncoding);
+        }
+        // Prefix evaluation starts from a blank tape
+        if level >= OptimizationLevel::O2 && settings.initial_memory.is_empty() && !metered {
+            passes.add_pass(prefix::PrefixEvaluation {
+                cell_width: settings.cell_width,
+                cells_wrap,
+                memory_size: settings.memory_size,
+            });
+        }
+        if level >= OptimizationLevel::O2 && !pedantic {
+            passes.add_pass(offset::OffsetAddressing { wrapping: cells_wrap && !pointer_saturates });
+        }
+        passes
+    }
+
+    /// Append a pass to the pipeline
+    pub fn add_pass<P: Pass + 'static>(&mut self, pass: P) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Append a pass to the pipeline, builder style
+    pub fn with_pass<P: Pass + 'static>(mut self, pass: P) -> PassManager {
+        self.add_pass(pass);
+        self
+    }
+
+    /// Names of the passes in the pipeline, in order
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// Run all passes over `program`
+    pub fn run(&self, program: &Program) -> Program {
+        let mut program = program.clone();
+        for pass in self.passes.iter() {
+            program = pass.run(&program);
+        }
+        program
+    }
+
+    /// Run the passes over `program`, which continues from the state left by a previous one, skipping those that
+    /// [need a fresh machine](Pass::needs_fresh_machine)
+    pub fn run_snippet(&self, program: &Program) -> Program {
+        let mut program = program.clone();
+        for pass in self.passes.iter().filter(|pass| !pass.needs_fresh_machine()) {
+            program = pass.run(&program);
+        }
+        program
+    }
+}
+
+/* OptimizationLevel **************************************************************************************************/
+impl FromStr for OptimizationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptimizationLevel::O0),
+            "1" => Ok(OptimizationLevel::O1),
+            "2" => Ok(OptimizationLevel::O2),
+            _ => Err(format!("Invalid optimization level: '{}'", s)),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use super::Pass;
+use crate::parse::program::{Instruction, Program};
+
+/// Replace copy and multiplication loops such as `[->+>++<<]` with a sequence of `MulAdd` instructions followed by
+/// `Set(0)`. A loop qualifies if its body only changes cells and moves the pointer, the pointer ends where it started
+/// and the current cell changes by exactly 1 each iteration. The rewrite assumes cells wrap around and pointer moves
+/// can be undone, so nothing is replaced unless `wrapping` is set
+pub fn multiply_loops(program: &Program, wrapping: bool) -> Program {
+    if !wrapping {
+        return program.clone();
+    }
+    let source = program.instructions();
+    let mut instructions = Vec::with_capacity(source.len());
+    let mut locations = Vec::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        if let Instruction::JZ(end) = source[i] {
+            // JZ jumps right after its JNZ
+            if let Some(deltas) = analyze_loop(&source[i + 1..end - 1]) {
+                // The loop runs `v` times if the current cell is decremented, `-v` times if it is incremented
+                let sign = -deltas[&0];
+                for (&offset, &delta) in deltas.iter().filter(|(&offset, _)| offset != 0) {
+                    if delta != 0 {
+                        instructions.push(Instruction::MulAdd { offset, factor: delta.wrapping_mul(sign) });
+                        locations.push(program.location(i));
+                    }
+                }
+                instructions.push(Instruction::Set(0));
+                locations.push(program.location(i));
+                i = end;
+                continue;
+            }
+        }
+        instructions.push(source[i]);
+        locations.push(program.location(i));
+        i += 1;
+    }
+    Program::with_locations(instructions, locations).expect("Multiplication loop elimination preserves bracket structure")
+}
+
+/// Compute the net change of every cell touched by a loop body, indexed by offset from the memory pointer. Returns
+/// `None` if the body is not a simple multiplication loop
+fn analyze_loop(body: &[Instruction]) -> Option<BTreeMap<isize, i64>> {
+    let mut deltas = BTreeMap::new();
+    let mut offset = 0isize;
+    for instruction in body {
+        match *instruction {
+            Instruction::IncData => *deltas.entry(offset).or_insert(0) += 1,
+            Instruction::DecData => *deltas.entry(offset).or_insert(0) -= 1,
+            Instruction::Add(n) => *deltas.entry(offset).or_insert(0) += n,
+            Instruction::IncPtr => offset += 1,
+            Instruction::DecPtr => offset -= 1,
+            Instruction::Move(n) => offset += n,
+            _ => return None,
+        }
+    }
+    match (offset, deltas.get(&0)) {
+        (0, Some(1 | -1)) => Some(deltas),
+        _ => None,
+    }
+}
+
+/// Pass wrapper around [`multiply_loops`]
+pub struct MultiplyLoops {
+    pub wrapping: bool,
+}
+
+impl Pass for MultiplyLoops {
+    fn name(&self) -> &str {
+        "multiply-loops"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        multiply_loops(program, self.wrapping)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn replace_multiply_loops() {
+        let program = Program::compile("[->+>++<<]+[>>-<<+]".as_bytes()).expect("Could not compile");
+        assert_eq!(
+            multiply_loops(&program, true).instructions(),
+            &[
+                MulAdd { offset: 1, factor: 1 },
+                MulAdd { offset: 2, factor: 2 },
+                Set(0),
+                IncData,
+                MulAdd { offset: 2, factor: 1 },
+                Set(0),
+                Exit
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_other_loops() {
+        let source = "[->+<<]+[-->+<][->.<]";
+        let program = Program::compile(source.as_bytes()).expect("Could not compile");
+        assert_eq!(multiply_loops(&program, true).instructions(), program.instructions());
+        let program = Program::compile("[->+<]".as_bytes()).expect("Could not compile");
+        assert_eq!(multiply_loops(&program, false).instructions(), program.instructions());
+    }
+}
@@ -0,0 +1,134 @@
+use super::Pass;
+use crate::parse::program::{Instruction, Program};
+
+/// Coalesce pointer moves within straight-line code. Moves are deferred while only cells are changed, and the changes
+/// are made with `AddAt` and `SetAt` instructions at the offset of the deferred moves from the memory pointer. A
+/// single `Move` is emitted before any other instruction, such as a jump or I/O, so that the pointer is in place
+/// whenever it is observable. The rewrite assumes cells wrap around and pointer moves can be undone, so nothing is
+/// changed unless `wrapping` is set
+pub fn address_by_offset(program: &Program, wrapping: bool) -> Program {
+    if !wrapping {
+        return program.clone();
+    }
+    let source = program.instructions();
+    let mut instructions = Vec::with_capacity(source.len());
+    let mut locations = Vec::with_capacity(source.len());
+    // Distance of the deferred moves, and location of the last one
+    let mut offset = 0isize;
+    let mut moved_at = None;
+    for (pc, &instruction) in source.iter().enumerate() {
+        let change = match instruction {
+            Instruction::IncPtr | Instruction::DecPtr | Instruction::Move(_) => {
+                offset += match instruction {
+                    Instruction::IncPtr => 1,
+                    Instruction::DecPtr => -1,
+                    Instruction::Move(n) => n,
+                    _ => unreachable!("Not a move"),
+                };
+                moved_at = program.location(pc);
+                continue;
+            }
+            // Changes of the current cell keep their original form
+            Instruction::IncData | Instruction::DecData | Instruction::Add(_) | Instruction::Set(_) if offset == 0 => {
+                Some(instruction)
+            }
+            Instruction::IncData => Some(at(offset, Instruction::Add(1))),
+            Instruction::DecData => Some(at(offset, Instruction::Add(-1))),
+            Instruction::Add(_) | Instruction::Set(_) => Some(at(offset, instruction)),
+            Instruction::AddAt { offset: other, value } => Some(at(offset + other, Instruction::Add(value))),
+            Instruction::SetAt { offset: other, value } => Some(at(offset + other, Instruction::Set(value))),
+            _ => None,
+        };
+        match change {
+            Some(change) => instructions.push(change),
+            None => {
+                if offset != 0 {
+                    instructions.push(Instruction::Move(offset));
+                    locations.push(moved_at);
+                    offset = 0;
+                }
+                instructions.push(instruction);
+            }
+        }
+        locations.push(program.location(pc));
+    }
+    if offset != 0 {
+        instructions.push(Instruction::Move(offset));
+        locations.push(moved_at);
+    }
+    Program::with_locations(instructions, locations).expect("Offset addressing preserves bracket structure")
+}
+
+/// Apply `change`, an `Add` or a `Set`, to the cell at `offset` from the memory pointer
+fn at(offset: isize, change: Instruction) -> Instruction {
+    match (offset, change) {
+        (0, _) => change,
+        (offset, Instruction::Add(value)) => Instruction::AddAt { offset, value },
+        (offset, Instruction::Set(value)) => Instruction::SetAt { offset, value },
+        _ => unreachable!("Only additions and assignments are addressed by offset"),
+    }
+}
+
+/// Pass wrapper around [`address_by_offset`]
+pub struct OffsetAddressing {
+    pub wrapping: bool,
+}
+
+impl Pass for OffsetAddressing {
+    fn name(&self) -> &str {
+        "offset-addressing"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        address_by_offset(program, self.wrapping)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::SourceLoc;
+    use Instruction::*;
+
+    #[test]
+    fn coalesce_moves() {
+        let program = Program::compile(">+>++<<-[>>.<]>".as_bytes()).expect("Could not compile");
+        assert_eq!(
+            address_by_offset(&program, true).instructions(),
+            &[
+                AddAt { offset: 1, value: 1 },
+                AddAt { offset: 2, value: 1 },
+                AddAt { offset: 2, value: 1 },
+                DecData,
+                JZ(9),
+                Move(2),
+                Output,
+                Move(-1),
+                JNZ(4),
+                Move(1),
+                Exit
+            ]
+        );
+        let program = Program::compile(">+<".as_bytes()).expect("Could not compile");
+        assert_eq!(address_by_offset(&program, false).instructions(), program.instructions());
+    }
+
+    #[test]
+    fn combine_offsets() {
+        let instructions = vec![Move(2), AddAt { offset: -2, value: 3 }, SetAt { offset: 1, value: 0 }, Move(-1), Exit];
+        let program = Program::from_instructions(instructions).unwrap();
+        assert_eq!(
+            address_by_offset(&program, true).instructions(),
+            &[Add(3), SetAt { offset: 3, value: 0 }, Move(1), Exit]
+        );
+    }
+
+    #[test]
+    fn keep_locations() {
+        let program = Program::compile(">>\n+<\n.".as_bytes()).expect("Could not compile");
+        let program = address_by_offset(&program, true);
+        let loc = |row, col| Some(SourceLoc { row, col });
+        assert_eq!(program.instructions(), &[AddAt { offset: 2, value: 1 }, Move(1), Output, Exit]);
+        assert_eq!(program.locations(), &[loc(2, 1), loc(2, 2), loc(3, 1), None]);
+    }
+}
@@ -0,0 +1,248 @@
+use super::Pass;
+use crate::interpreter::virtualmachine::CellWidth;
+use crate::parse::program::{Instruction, Program, SourceLoc};
+
+/// Number of instructions executed by [`evaluate_prefix`] before it gives up
+pub const MAX_STEPS: u64 = 1_000_000;
+
+/// Execute the beginning of `program` at compile time, starting from a fresh machine, and replace it with `Set`
+/// instructions producing the same memory, followed by the rest of the program. Output commands are kept, each one
+/// after the instructions setting the cells it depends on, so that text-printing programs shrink to a list of values
+/// and outputs.
+///
+/// Evaluation stops at the first input command, at any command other than a data, pointer, jump or output one, and
+/// after [`MAX_STEPS`] instructions, which catches loops that never end. It also stops before a cell overflows, unless
+/// `cells_wrap`, and before the memory pointer leaves the first `memory_size` cells, so that the result does not
+/// depend on the other settings of the machine. The rest of the program starts at the beginning of the outermost loop
+/// being executed when evaluation stopped
+pub fn evaluate_prefix(program: &Program, cell_width: CellWidth, cells_wrap: bool, memory_size: usize) -> Program {
+    let source = program.instructions();
+    let depths = loop_depths(source);
+    let mut evaluation = Evaluation { max: cell_width.max_value(), cells_wrap, memory_size, ..Evaluation::default() };
+    // Instructions bringing a fresh machine to the same state as the evaluation, as far as it was observed
+    let mut code = Vec::new();
+    // Evaluation state and length of `code` when the outermost loop being executed was entered
+    let mut checkpoint = (evaluation.clone(), 0);
+    let mut previous = None;
+    for _ in 0..MAX_STEPS {
+        let Some(&instruction) = source.get(evaluation.pc) else { break };
+        // JNZ jumps back to the JZ of its loop
+        let entering = previous != Some(Instruction::JNZ(evaluation.pc));
+        if depths[evaluation.pc] == 0 && matches!(instruction, Instruction::JZ(_)) && entering {
+            checkpoint = (evaluation.clone(), code.len());
+        }
+        if !evaluation.execute(instruction, program.location(evaluation.pc), &mut code) {
+            break;
+        }
+        previous = Some(instruction);
+    }
+    // Outside loops, instructions that cannot be evaluated have no effect
+    if depths.get(evaluation.pc).is_some_and(|&depth| depth > 0) {
+        evaluation = checkpoint.0;
+        code.truncate(checkpoint.1);
+    }
+    if evaluation.pc == 0 {
+        return program.clone();
+    }
+    evaluation.materialize(program.location(evaluation.pc), &mut code);
+    let (mut instructions, mut locations): (Vec<_>, Vec<_>) = code.into_iter().unzip();
+    instructions.extend_from_slice(&source[evaluation.pc..]);
+    locations.extend_from_slice(&program.locations()[evaluation.pc..]);
+    Program::with_locations(instructions, locations).expect("Prefix evaluation preserves bracket structure")
+}
+
+/// Number of loops around each instruction. Jumps belong to the loop they delimit, except that the opening one is
+/// executed before entering it
+fn loop_depths(instructions: &[Instruction]) -> Vec<usize> {
+    let mut depth = 0;
+    instructions.iter().map(|instruction| match instruction {
+        Instruction::JZ(_) => {
+            depth += 1;
+            depth - 1
+        }
+        Instruction::JNZ(_) => {
+            depth -= 1;
+            depth + 1
+        }
+        _ => depth,
+    }).collect()
+}
+
+/// Instructions and their source locations
+type Code = Vec<(Instruction, Option<SourceLoc>)>;
+
+/// State of a program evaluated at compile time
+#[derive(Clone, Default)]
+struct Evaluation {
+    pc: usize,
+    mp: usize,
+    /// Cells reached so far
+    tape: Vec<u64>,
+    /// Cells and memory pointer of a fresh machine after running the instructions emitted so far
+    emitted_tape: Vec<u64>,
+    emitted_mp: usize,
+    max: u64,
+    cells_wrap: bool,
+    memory_size: usize,
+}
+
+impl Evaluation {
+    /// Execute `instruction`, located at `location`, if its effects are known at compile time. Instructions that must
+    /// be run by the machine, such as outputs, are appended to `code`
+    fn execute(&mut self, instruction: Instruction, location: Option<SourceLoc>, code: &mut Code) -> bool {
+        let mut next = self.pc + 1;
+        let done = match instruction {
+            Instruction::IncPtr => self.move_mp(1),
+            Instruction::DecPtr => self.move_mp(-1),
+            Instruction::Move(n) => self.move_mp(n),
+            Instruction::IncData => self.add(0, 1),
+            Instruction::DecData => self.add(0, -1),
+            Instruction::Add(n) => self.add(0, n),
+            Instruction::Set(value) => self.set(0, value),
+            Instruction::AddAt { offset, value } => self.add(offset, value),
+            Instruction::SetAt { offset, value } => self.set(offset, value),
+            // Multiplications wrap around whatever the settings
+            Instruction::MulAdd { offset, factor } => match self.cell(offset) {
+                Some(index) => {
+                    let product = self.current().wrapping_mul(factor as u64);
+                    self.tape[index] = self.tape[index].wrapping_add(product) & self.max;
+                    true
+                }
+                None => false,
+            },
+            Instruction::JZ(addr) => {
+                if self.current() == 0 {
+                    next = addr;
+                }
+                true
+            }
+            Instruction::JNZ(addr) => {
+                if self.current() != 0 {
+                    next = addr;
+                }
+                true
+            }
+            Instruction::Output => {
+                self.materialize(location, code);
+                code.push((instruction, location));
+                true
+            }
+            _ => false,
+        };
+        if done {
+            self.pc = next;
+        }
+        done
+    }
+
+    /// Value of the current cell
+    fn current(&self) -> u64 {
+        self.tape.get(self.mp).copied().unwrap_or(0)
+    }
+
+    /// Index in `tape` of the cell at `offset` from the memory pointer, if it is within the first `memory_size` cells
+    fn cell(&mut self, offset: isize) -> Option<usize> {
+        let index = self.mp.checked_add_signed(offset).filter(|&index| index < self.memory_size)?;
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        Some(index)
+    }
+
+    fn move_mp(&mut self, n: isize) -> bool {
+        self.cell(n).map(|index| self.mp = index).is_some()
+    }
+
+    fn add(&mut self, offset: isize, n: i64) -> bool {
+        let Some(index) = self.cell(offset) else { return false };
+        let value = self.tape[index] as i128 + n as i128;
+        if !self.cells_wrap && !(0..=self.max as i128).contains(&value) {
+            return false;
+        }
+        // Two's complement arithmetic modulo 2^64 is also correct modulo any smaller power of 2
+        self.tape[index] = self.tape[index].wrapping_add(n as u64) & self.max;
+        true
+    }
+
+    fn set(&mut self, offset: isize, value: u64) -> bool {
+        self.cell(offset).map(|index| self.tape[index] = value & self.max).is_some()
+    }
+
+    /// Append to `code` the instructions bringing the cells and memory pointer of the machine to the current state
+    fn materialize(&mut self, location: Option<SourceLoc>, code: &mut Code) {
+        self.emitted_tape.resize(self.tape.len(), 0);
+        for index in 0..self.tape.len() {
+            if self.tape[index] != self.emitted_tape[index] {
+                self.emit_move(index, location, code);
+                code.push((Instruction::Set(self.tape[index]), location));
+                self.emitted_tape[index] = self.tape[index];
+            }
+        }
+        self.emit_move(self.mp, location, code);
+    }
+
+    fn emit_move(&mut self, index: usize, location: Option<SourceLoc>, code: &mut Code) {
+        if index != self.emitted_mp {
+            code.push((Instruction::Move(index as isize - self.emitted_mp as isize), location));
+            self.emitted_mp = index;
+        }
+    }
+}
+
+/// Pass wrapper around [`evaluate_prefix`]
+pub struct PrefixEvaluation {
+    pub cell_width: CellWidth,
+    pub cells_wrap: bool,
+    pub memory_size: usize,
+}
+
+impl Pass for PrefixEvaluation {
+    fn name(&self) -> &str {
+        "prefix-evaluation"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        evaluate_prefix(program, self.cell_width, self.cells_wrap, self.memory_size)
+    }
+
+    fn needs_fresh_machine(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Instruction::*;
+
+    fn evaluate(source: &str, cells_wrap: bool) -> Vec<Instruction> {
+        let program = Program::compile(source.as_bytes()).expect("Could not compile");
+        evaluate_prefix(&program, CellWidth::U8, cells_wrap, 16).instructions().to_vec()
+    }
+
+    #[test]
+    fn evaluate_until_input() {
+        assert_eq!(evaluate("++[>+++<-]>.>+<,[.,]", true), [
+            Move(1), Set(6), Output, Move(1), Set(1), Move(-1), Input, JZ(11), Output, Input, JNZ(7), Exit,
+        ]);
+        assert_eq!(evaluate("+[>+<,-]>", true), [
+            Set(1), JZ(8), IncPtr, IncData, DecPtr, Input, DecData, JNZ(1), IncPtr, Exit,
+        ]);
+        assert_eq!(evaluate(",+", true), [Input, IncData, Exit]);
+    }
+
+    #[test]
+    fn stop_at_endless_loops() {
+        assert_eq!(evaluate(">+<+[>]", true), [Set(1), Move(1), Set(1), Move(1), Exit]);
+        assert_eq!(evaluate("+[]", true), [Set(1), JZ(3), JNZ(1), Exit]);
+        // The memory pointer would leave the first 16 cells
+        assert_eq!(evaluate("++[>+]", true), [Set(2), JZ(5), IncPtr, IncData, JNZ(1), Exit]);
+    }
+
+    #[test]
+    fn stop_before_overflows() {
+        assert_eq!(evaluate("++-->-", false), [Move(1), DecData, Exit]);
+        assert_eq!(evaluate("+--", true), [Set(255), Exit]);
+        assert_eq!(evaluate("+<", true), [Set(1), DecPtr, Exit]);
+    }
+}
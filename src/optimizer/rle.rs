@@ -0,0 +1,77 @@
+use super::Pass;
+use crate::parse::program::{Instruction, Program};
+
+/// Collapse runs of identical data and pointer instructions into single `Add` and `Move` instructions. Only runs of
+/// the same instruction are merged, so that `+-` keeps the semantics of each step under saturating or checked
+/// cell arithmetic
+pub fn run_length_encode(program: &Program) -> Program {
+    let source = program.instructions();
+    let mut instructions = Vec::with_capacity(source.len());
+    let mut locations = Vec::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        let instruction = source[i];
+        let run = match instruction {
+            Instruction::IncData | Instruction::DecData | Instruction::IncPtr | Instruction::DecPtr => {
+                source[i..].iter().take_while(|&&other| other == instruction).count()
+            }
+            _ => 1,
+        };
+        instructions.push(match (instruction, run) {
+            (_, 1) => instruction,
+            (Instruction::IncData, n) => Instruction::Add(n as i64),
+            (Instruction::DecData, n) => Instruction::Add(-(n as i64)),
+            (Instruction::IncPtr, n) => Instruction::Move(n as isize),
+            (Instruction::DecPtr, n) => Instruction::Move(-(n as isize)),
+            _ => unreachable!("Only data and pointer instructions are merged"),
+        });
+        locations.push(program.location(i));
+        i += run;
+    }
+    Program::with_locations(instructions, locations).expect("Run length encoding preserves bracket structure")
+}
+
+/// Pass wrapper around [`run_length_encode`]
+pub struct RunLengthEncoding;
+
+impl Pass for RunLengthEncoding {
+    fn name(&self) -> &str {
+        "rle"
+    }
+
+    fn run(&self, program: &Program) -> Program {
+        run_length_encode(program)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::SourceLoc;
+    use Instruction::*;
+
+    #[test]
+    fn collapse_runs() {
+        let program = Program::compile("+++>>-<[--]".as_bytes()).expect("Could not compile");
+        let program = run_length_encode(&program);
+        assert_eq!(
+            program.instructions(),
+            &[Add(3), Move(2), DecData, DecPtr, JZ(7), Add(-2), JNZ(4), Exit]
+        );
+    }
+
+    #[test]
+    fn keep_mixed_runs() {
+        let program = Program::compile("+-+".as_bytes()).expect("Could not compile");
+        let program = run_length_encode(&program);
+        assert_eq!(program.instructions(), &[IncData, DecData, IncData, Exit]);
+    }
+
+    #[test]
+    fn keep_locations() {
+        let program = Program::compile("++\n>>".as_bytes()).expect("Could not compile");
+        let program = run_length_encode(&program);
+        let loc = |row, col| Some(SourceLoc { row, col });
+        assert_eq!(program.locations(), &[loc(1, 1), loc(2, 1), None]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+
+use super::diagnostic::CompileError;
+use super::program::{Instruction, Program, SourceLoc};
+
+/// Assemble a textual listing of instructions, as written by [`Program::to_asm`] or [`Program::dump`], back into a
+/// program. Every line holds at most one instruction, optionally preceded by a label such as `L1:` or by an address,
+/// and followed by a comment starting with `;`. Jump targets are labels or absolute addresses, and must agree with the
+/// nesting of loops and procedures. A comment holding a source location, such as `; line 1, column 2`, becomes the
+/// location of the instruction
+pub fn assemble(text: &str) -> Result<Program, Box<dyn Error>> {
+    let mut instructions = Vec::new();
+    let mut locations = Vec::new();
+    // Address, target and location in the listing of every jump, to resolve once all labels are known
+    let mut jumps = Vec::new();
+    let mut labels = HashMap::new();
+    for (row, line) in text.lines().enumerate() {
+        let (code, comment) = line.split_once(';').unwrap_or((line, ""));
+        let mut words = Words::new(code, row + 1);
+        let mut word = words.next();
+        // Labels and addresses of the instruction
+        while let Some((text, loc)) = word.filter(|(text, _)| text.ends_with(':')) {
+            let label = &text[..text.len() - 1];
+            if labels.insert(String::from(label), instructions.len()).is_some() {
+                return Err(CompileError::new(&format!("Label '{}' is defined more than once", label), loc).into());
+            }
+            word = words.next();
+        }
+        let (mnemonic, loc) = match word {
+            Some(word) => word,
+            None => continue,
+        };
+        let instruction = match mnemonic {
+            "incp" => Instruction::IncPtr,
+            "decp" => Instruction::DecPtr,
+            "incd" => Instruction::IncData,
+            "decd" => Instruction::DecData,
+            "rd" => Instruction::Input,
+            "wr" => Instruction::Output,
+            "jz" | "jnz" | "proc" => {
+                let (target, target_loc) = words.operand(loc)?;
+                jumps.push((instructions.len(), String::from(target), target_loc));
+                match mnemonic {
+                    "jz" => Instruction::JZ(0),
+                    "jnz" => Instruction::JNZ(0),
+                    _ => Instruction::DefineProc(0),
+                }
+            }
+            "add" => Instruction::Add(words.number(loc)?),
+            "mov" => Instruction::Move(words.number(loc)?),
+            "set" => Instruction::Set(words.number(loc)?),
+            "muladd" => Instruction::MulAdd { offset: words.number(loc)?, factor: words.number(loc)? },
+            "addat" => Instruction::AddAt { offset: words.number(loc)?, value: words.number(loc)? },
+            "setat" => Instruction::SetAt { offset: words.number(loc)?, value: words.number(loc)? },
+            "dbg" => Instruction::Debug,
+            "ret" => Instruction::Return,
+            "call" => Instruction::CallProc,
+            "st" => Instruction::Store,
+            "ld" => Instruction::Load,
+            "shl" => Instruction::ShiftLeft,
+            "shr" => Instruction::ShiftRight,
+            "not" => Instruction::Not,
+            "xor" => Instruction::Xor,
+            "and" => Instruction::And,
+            "or" => Instruction::Or,
+            "fork" => Instruction::Fork,
+            "exit" => Instruction::Exit,
+            _ => return Err(CompileError::new(&format!("Unknown instruction: '{}'", mnemonic), loc).into()),
+        };
+        if let Some((extra, loc)) = words.next() {
+            return Err(CompileError::new(&format!("Unexpected operand: '{}'", extra), loc).into());
+        }
+        instructions.push(instruction);
+        locations.push(source_loc(comment));
+    }
+    // Resolve the targets, which must be where the nesting of loops and procedures puts them
+    let mut resolved_jumps = Vec::new();
+    for (addr, target, loc) in jumps {
+        let resolved = match labels.get(&target) {
+            Some(&resolved) => resolved,
+            None => parse_address(&target)
+                .ok_or_else(|| CompileError::new(&format!("Undefined label: '{}'", target), loc))?,
+        };
+        instructions[addr] = match instructions[addr] {
+            Instruction::JZ(_) => Instruction::JZ(resolved),
+            Instruction::JNZ(_) => Instruction::JNZ(resolved),
+            _ => Instruction::DefineProc(resolved),
+        };
+        resolved_jumps.push((addr, loc));
+    }
+    let program = Program::with_locations(instructions.clone(), locations)?;
+    for (addr, loc) in resolved_jumps {
+        if program.instruction(addr) != &instructions[addr] {
+            return Err(CompileError::new("Jump target does not match the nesting of loops and procedures", loc).into());
+        }
+    }
+    Ok(program)
+}
+
+/// Absolute address written in hexadecimal, as in `0x0000002a`, or in decimal
+fn parse_address(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Source location written in a comment as `line R, column C`, if any
+fn source_loc(comment: &str) -> Option<SourceLoc> {
+    let (row, col) = comment.trim().strip_prefix("line ")?.split_once(", column ")?;
+    Some(SourceLoc { row: row.parse().ok()?, col: col.parse().ok()? })
+}
+
+/// Words of a line of the listing, with their location
+struct Words<'a> {
+    words: Vec<(&'a str, SourceLoc)>,
+    next: usize,
+}
+
+/* Words **************************************************************************************************************/
+impl<'a> Words<'a> {
+    fn new(line: &'a str, row: usize) -> Words<'a> {
+        let words = line.split_whitespace()
+            .map(|word| {
+                let start = word.as_ptr() as usize - line.as_ptr() as usize;
+                (word, SourceLoc { row, col: line[..start].chars().count() + 1 })
+            })
+            .collect();
+        Words { words, next: 0 }
+    }
+
+    /// Operand of the instruction at `loc`
+    fn operand(&mut self, loc: SourceLoc) -> Result<(&'a str, SourceLoc), CompileError> {
+        self.next().ok_or_else(|| CompileError::new("Missing operand", loc))
+    }
+
+    /// Numeric operand of the instruction at `loc`
+    fn number<T: FromStr>(&mut self, loc: SourceLoc) -> Result<T, CompileError> {
+        let (text, loc) = self.operand(loc)?;
+        text.parse().map_err(|_| CompileError::new(&format!("Invalid number: '{}'", text), loc))
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = (&'a str, SourceLoc);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let word = self.words.get(self.next).copied();
+        self.next += 1;
+        word
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::optimizer::offset::address_by_offset;
+    use crate::parse::token::Syntax;
+
+    #[test]
+    fn round_trip() {
+        let syntax = Syntax { procedures: true, ..Syntax::default() };
+        let program = Program::compile_with("+[->+<]>(.):\n[-]".as_bytes(), syntax).unwrap();
+        let assembled = assemble(&program.to_asm(Some("ignored"))).expect("Could not assemble");
+        assert_eq!(assembled.instructions(), program.instructions());
+        assert_eq!(assembled.locations(), program.locations());
+        let mut dump = Vec::new();
+        program.dump(&mut dump).unwrap();
+        let assembled = assemble(&String::from_utf8(dump).unwrap()).expect("Could not assemble the dump");
+        assert_eq!(assembled.instructions(), program.instructions());
+        let program = address_by_offset(&Program::compile(">+>[-]<<--.".as_bytes()).unwrap(), true);
+        let assembled = assemble(&program.to_asm(None)).expect("Could not assemble offsets");
+        assert_eq!(assembled.instructions(), program.instructions());
+    }
+
+    #[test]
+    fn errors() {
+        let program = assemble("add -3\nmuladd 2 -4 ; line 7, column 1\nset 9\n").unwrap();
+        assert_eq!(program.instructions(), [
+            Instruction::Add(-3),
+            Instruction::MulAdd { offset: 2, factor: -4 },
+            Instruction::Set(9),
+            Instruction::Exit,
+        ]);
+        assert_eq!(program.location(1), Some(SourceLoc { row: 7, col: 1 }));
+        let errors: Vec<String> = ["L1: jz L2\n  jnz L1", "jz L1\nL1: jnz L1\n", "add", "mov x", "nop", "wr 1"]
+            .iter()
+            .map(|text| assemble(text).err().unwrap().to_string())
+            .collect();
+        assert_eq!(errors, [
+            "Undefined label: 'L2' at line 1, column 8",
+            "Jump target does not match the nesting of loops and procedures at line 1, column 4",
+            "Missing operand at line 1, column 1",
+            "Invalid number: 'x' at line 1, column 5",
+            "Unknown instruction: 'nop' at line 1, column 1",
+            "Unexpected operand: '1' at line 1, column 4",
+        ]);
+    }
+}
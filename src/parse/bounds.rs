@@ -0,0 +1,122 @@
+use super::program::{Instruction, Program};
+
+/// Range of cells a program may reach, as offsets from the cell the memory pointer starts on. Both ends are included
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: isize,
+    pub max: isize,
+}
+
+/// Reason why [`memory_bounds`] could not bound the cells reached by a program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unbounded {
+    /// Address of the instruction that prevented the analysis
+    pub pc: usize,
+    pub reason: String,
+}
+
+/// Compute the range of cells that `program` may reach, assuming that the memory pointer never wraps around. Loops
+/// must leave the memory pointer where they found it, so that their body reaches the same cells on each iteration;
+/// loops such as `[>]`, which search the tape, make the range unbounded. Procedure calls and threads are not
+/// analyzed. The range is an upper bound: code that never runs is taken into account as well
+pub fn memory_bounds(program: &Program) -> Result<Bounds, Unbounded> {
+    let mut bounds = Bounds { min: 0, max: 0 };
+    block(program.instructions(), 0, program.len(), 0, &mut bounds)?;
+    Ok(bounds)
+}
+
+/// Extend `bounds` with the cells reached by the instructions from `start` to `end`, the memory pointer being at
+/// `offset` at the beginning. Returns the offset of the memory pointer at the end, or `None` if the program always
+/// exits before
+fn block(
+    instructions: &[Instruction], start: usize, end: usize, mut offset: isize, bounds: &mut Bounds,
+) -> Result<Option<isize>, Unbounded> {
+    let mut pc = start;
+    while pc < end {
+        match instructions[pc] {
+            Instruction::IncPtr => offset += 1,
+            Instruction::DecPtr => offset -= 1,
+            Instruction::Move(n) => offset += n,
+            Instruction::MulAdd { offset: other, .. }
+            | Instruction::AddAt { offset: other, .. }
+            | Instruction::SetAt { offset: other, .. } => bounds.include(offset + other),
+            // JZ jumps right after its JNZ
+            Instruction::JZ(after) => {
+                match block(instructions, pc + 1, after - 1, offset, bounds)? {
+                    Some(moved) if moved != offset => {
+                        let reason = format!(
+                            "Each iteration of the loop moves the memory pointer by {}",
+                            moved - offset,
+                        );
+                        return Err(Unbounded { pc, reason });
+                    }
+                    // Whether it exits or not, the loop may also not run at all
+                    _ => pc = after,
+                }
+                continue;
+            }
+            // Procedures are analyzed where they are called
+            Instruction::DefineProc(after) => {
+                pc = after;
+                continue;
+            }
+            Instruction::CallProc => {
+                return Err(Unbounded { pc, reason: String::from("Procedure calls are not analyzed") });
+            }
+            Instruction::Fork => return Err(Unbounded { pc, reason: String::from("Threads are not analyzed") }),
+            Instruction::Exit => return Ok(None),
+            _ => {}
+        }
+        bounds.include(offset);
+        pc += 1;
+    }
+    Ok(Some(offset))
+}
+
+/* Bounds *************************************************************************************************************/
+impl Bounds {
+    /// Number of cells in the range
+    pub fn cells(&self) -> usize {
+        (self.max - self.min) as usize + 1
+    }
+
+    /// Whether the range fits a tape of `memory_size` cells, with the memory pointer starting on the first one
+    pub fn fits(&self, memory_size: usize) -> bool {
+        self.min >= 0 && (self.max as usize) < memory_size
+    }
+
+    fn include(&mut self, offset: isize) {
+        self.min = self.min.min(offset);
+        self.max = self.max.max(offset);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::token::Syntax;
+
+    fn bounds(source: &str) -> Result<Bounds, Unbounded> {
+        let syntax = Syntax { procedures: true, fork: true, ..Syntax::default() };
+        memory_bounds(&Program::compile_with(source.as_bytes(), syntax).expect("Could not compile"))
+    }
+
+    #[test]
+    fn bounded_programs() {
+        assert_eq!(bounds("++[>+++[>++<-]<-]>>."), Ok(Bounds { min: 0, max: 2 }));
+        assert_eq!(bounds("<<+>>>"), Ok(Bounds { min: -2, max: 1 }));
+        assert_eq!(bounds(""), Ok(Bounds { min: 0, max: 0 }));
+        assert!(bounds(">>>").unwrap().fits(4));
+        assert!(!bounds(">>>>").unwrap().fits(4));
+        assert!(!bounds("<").unwrap().fits(4));
+    }
+
+    #[test]
+    fn unbounded_programs() {
+        let err = bounds("+>+[[-]>]").expect_err("Tape search was bounded");
+        assert_eq!(err.pc, 3);
+        assert_eq!(err.reason, "Each iteration of the loop moves the memory pointer by 1");
+        assert_eq!(bounds("(>):").expect_err("Procedure call was bounded").pc, 3);
+        assert_eq!(bounds("+Y>").expect_err("Fork was bounded").pc, 1);
+    }
+}
@@ -0,0 +1,149 @@
+use std::error::Error;
+
+use super::program::{Instruction, Program};
+
+/// Builds a [`Program`] one instruction at a time, without going through brainfuck source code:
+///
+/// ```
+/// use bfint::ProgramBuilder;
+///
+/// // ++++[>++<-]>.
+/// let program = ProgramBuilder::new()
+///     .inc_data(4)
+///     .loop_(|body| body.inc_ptr(1).inc_data(2).dec_ptr(1).dec_data(1))
+///     .inc_ptr(1)
+///     .output()
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Loops are either given as a closure building their body, or opened and closed explicitly. Jump targets are
+/// computed when the program is built, which fails if loops are not balanced
+#[derive(Clone, Debug, Default)]
+pub struct ProgramBuilder {
+    instructions: Vec<Instruction>,
+}
+
+/* ProgramBuilder *****************************************************************************************************/
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+
+    /// Append any instruction. The targets of jumps and procedure definitions are ignored
+    pub fn instruction(mut self, instruction: Instruction) -> ProgramBuilder {
+        self.instructions.push(instruction);
+        self
+    }
+
+    /// Move the memory pointer `n` cells to the right
+    pub fn inc_ptr(self, n: usize) -> ProgramBuilder {
+        match n {
+            1 => self.instruction(Instruction::IncPtr),
+            n => self.instruction(Instruction::Move(n as isize)),
+        }
+    }
+
+    /// Move the memory pointer `n` cells to the left
+    pub fn dec_ptr(self, n: usize) -> ProgramBuilder {
+        match n {
+            1 => self.instruction(Instruction::DecPtr),
+            n => self.instruction(Instruction::Move(-(n as isize))),
+        }
+    }
+
+    /// Add `n` to the current cell
+    pub fn inc_data(self, n: u64) -> ProgramBuilder {
+        match n {
+            1 => self.instruction(Instruction::IncData),
+            n => self.instruction(Instruction::Add(n as i64)),
+        }
+    }
+
+    /// Subtract `n` from the current cell
+    pub fn dec_data(self, n: u64) -> ProgramBuilder {
+        match n {
+            1 => self.instruction(Instruction::DecData),
+            n => self.instruction(Instruction::Add(-(n as i64))),
+        }
+    }
+
+    /// Set the current cell to `value`
+    pub fn set(self, value: u64) -> ProgramBuilder {
+        self.instruction(Instruction::Set(value))
+    }
+
+    /// Read a byte into the current cell
+    pub fn input(self) -> ProgramBuilder {
+        self.instruction(Instruction::Input)
+    }
+
+    /// Write the current cell as a byte
+    pub fn output(self) -> ProgramBuilder {
+        self.instruction(Instruction::Output)
+    }
+
+    /// Repeat the instructions appended by `body` while the current cell is not 0
+    pub fn loop_<F: FnOnce(ProgramBuilder) -> ProgramBuilder>(self, body: F) -> ProgramBuilder {
+        body(self.open_loop()).close_loop()
+    }
+
+    /// Start a loop, which must be closed by [`close_loop`](ProgramBuilder::close_loop)
+    pub fn open_loop(self) -> ProgramBuilder {
+        self.instruction(Instruction::JZ(0))
+    }
+
+    /// End the innermost open loop
+    pub fn close_loop(self) -> ProgramBuilder {
+        self.instruction(Instruction::JNZ(0))
+    }
+
+    /// Number of instructions appended so far
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Whether no instruction was appended yet
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Check the nesting of loops and build the program, computing the jump targets
+    pub fn build(self) -> Result<Program, Box<dyn Error>> {
+        Program::from_instructions(self.instructions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let program = ProgramBuilder::new()
+            .inc_data(3)
+            .loop_(|body| body.dec_data(1).inc_ptr(2).loop_(|body| body.set(0)).dec_ptr(2))
+            .inc_ptr(1)
+            .output()
+            .build()
+            .expect("Could not build the program");
+        assert_eq!(program.instructions(), [
+            Instruction::Add(3),
+            Instruction::JZ(9),
+            Instruction::DecData,
+            Instruction::Move(2),
+            Instruction::JZ(7),
+            Instruction::Set(0),
+            Instruction::JNZ(4),
+            Instruction::Move(-2),
+            Instruction::JNZ(1),
+            Instruction::IncPtr,
+            Instruction::Output,
+            Instruction::Exit,
+        ]);
+        let err = ProgramBuilder::new().open_loop().inc_data(1).build().err().unwrap();
+        assert_eq!(err.to_string(), "Unmatched '['");
+        let err = ProgramBuilder::new().close_loop().build().err().unwrap();
+        assert_eq!(err.to_string(), "No matching '['");
+    }
+}
@@ -0,0 +1,213 @@
+use std::error::Error;
+
+use super::program::{Instruction, Program};
+
+/// Magic bytes at the beginning of every bytecode file
+pub const MAGIC: &[u8; 4] = b"BFC\0";
+/// Version of the bytecode format produced by [`Program::serialize`]
+pub const VERSION: u8 = 1;
+
+// Opcodes
+const INC_PTR: u8 = 0x01;
+const DEC_PTR: u8 = 0x02;
+const INC_DATA: u8 = 0x03;
+const DEC_DATA: u8 = 0x04;
+const INPUT: u8 = 0x05;
+const OUTPUT: u8 = 0x06;
+const JZ: u8 = 0x07;
+const JNZ: u8 = 0x08;
+const ADD: u8 = 0x09;
+const MOVE: u8 = 0x0a;
+const SET: u8 = 0x0b;
+const MUL_ADD: u8 = 0x0c;
+const EXIT: u8 = 0x0d;
+const DEBUG: u8 = 0x0e;
+const DEFINE_PROC: u8 = 0x0f;
+const RETURN: u8 = 0x10;
+const CALL_PROC: u8 = 0x11;
+const STORE: u8 = 0x12;
+const LOAD: u8 = 0x13;
+const SHIFT_LEFT: u8 = 0x14;
+const SHIFT_RIGHT: u8 = 0x15;
+const NOT: u8 = 0x16;
+const XOR: u8 = 0x17;
+const AND: u8 = 0x18;
+const OR: u8 = 0x19;
+const FORK: u8 = 0x1a;
+const ADD_AT: u8 = 0x1b;
+const SET_AT: u8 = 0x1c;
+
+/* Program ************************************************************************************************************/
+impl Program {
+    /// Encode the program in the bytecode format: the magic bytes and the version, followed by one opcode per
+    /// instruction with its operands as 64 bit little endian integers. Jump and procedure targets are not stored, since
+    /// they are recomputed when loading
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for instruction in self.instructions() {
+            match *instruction {
+                Instruction::IncPtr => bytes.push(INC_PTR),
+                Instruction::DecPtr => bytes.push(DEC_PTR),
+                Instruction::IncData => bytes.push(INC_DATA),
+                Instruction::DecData => bytes.push(DEC_DATA),
+                Instruction::Input => bytes.push(INPUT),
+                Instruction::Output => bytes.push(OUTPUT),
+                Instruction::JZ(_) => bytes.push(JZ),
+                Instruction::JNZ(_) => bytes.push(JNZ),
+                Instruction::Add(n) => {
+                    bytes.push(ADD);
+                    bytes.extend(n.to_le_bytes());
+                }
+                Instruction::Move(n) => {
+                    bytes.push(MOVE);
+                    bytes.extend((n as i64).to_le_bytes());
+                }
+                Instruction::Set(val) => {
+                    bytes.push(SET);
+                    bytes.extend(val.to_le_bytes());
+                }
+                Instruction::MulAdd { offset, factor } => {
+                    bytes.push(MUL_ADD);
+                    bytes.extend((offset as i64).to_le_bytes());
+                    bytes.extend(factor.to_le_bytes());
+                }
+                Instruction::AddAt { offset, value } => {
+                    bytes.push(ADD_AT);
+                    bytes.extend((offset as i64).to_le_bytes());
+                    bytes.extend(value.to_le_bytes());
+                }
+                Instruction::SetAt { offset, value } => {
+                    bytes.push(SET_AT);
+                    bytes.extend((offset as i64).to_le_bytes());
+                    bytes.extend(value.to_le_bytes());
+                }
+                Instruction::Debug => bytes.push(DEBUG),
+                Instruction::DefineProc(_) => bytes.push(DEFINE_PROC),
+                Instruction::Return => bytes.push(RETURN),
+                Instruction::CallProc => bytes.push(CALL_PROC),
+                Instruction::Store => bytes.push(STORE),
+                Instruction::Load => bytes.push(LOAD),
+                Instruction::ShiftLeft => bytes.push(SHIFT_LEFT),
+                Instruction::ShiftRight => bytes.push(SHIFT_RIGHT),
+                Instruction::Not => bytes.push(NOT),
+                Instruction::Xor => bytes.push(XOR),
+                Instruction::And => bytes.push(AND),
+                Instruction::Or => bytes.push(OR),
+                Instruction::Fork => bytes.push(FORK),
+                Instruction::Exit => bytes.push(EXIT),
+            }
+        }
+        bytes
+    }
+
+    /// Decode a program produced by [`Program::serialize`]
+    pub fn deserialize(bytes: &[u8]) -> Result<Program, Box<dyn Error>> {
+        if !is_bytecode(bytes) {
+            return Err("Not a bytecode file".into());
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(format!("Unsupported bytecode version: {}", bytes[MAGIC.len()]).into());
+        }
+        let mut reader = Reader::new(&bytes[MAGIC.len() + 1..]);
+        let mut instructions = Vec::new();
+        while let Some(opcode) = reader.next_byte() {
+            instructions.push(match opcode {
+                INC_PTR => Instruction::IncPtr,
+                DEC_PTR => Instruction::DecPtr,
+                INC_DATA => Instruction::IncData,
+                DEC_DATA => Instruction::DecData,
+                INPUT => Instruction::Input,
+                OUTPUT => Instruction::Output,
+                JZ => Instruction::JZ(0),
+                JNZ => Instruction::JNZ(0),
+                ADD => Instruction::Add(reader.next_i64()?),
+                MOVE => Instruction::Move(reader.next_i64()? as isize),
+                SET => Instruction::Set(reader.next_i64()? as u64),
+                MUL_ADD => Instruction::MulAdd { offset: reader.next_i64()? as isize, factor: reader.next_i64()? },
+                ADD_AT => Instruction::AddAt { offset: reader.next_i64()? as isize, value: reader.next_i64()? },
+                SET_AT => Instruction::SetAt { offset: reader.next_i64()? as isize, value: reader.next_i64()? as u64 },
+                DEBUG => Instruction::Debug,
+                DEFINE_PROC => Instruction::DefineProc(0),
+                RETURN => Instruction::Return,
+                CALL_PROC => Instruction::CallProc,
+                STORE => Instruction::Store,
+                LOAD => Instruction::Load,
+                SHIFT_LEFT => Instruction::ShiftLeft,
+                SHIFT_RIGHT => Instruction::ShiftRight,
+                NOT => Instruction::Not,
+                XOR => Instruction::Xor,
+                AND => Instruction::And,
+                OR => Instruction::Or,
+                FORK => Instruction::Fork,
+                EXIT => Instruction::Exit,
+                _ => return Err(format!("Invalid opcode: 0x{:02x}", opcode).into()),
+            });
+        }
+        Program::from_instructions(instructions)
+    }
+}
+
+/// Whether `bytes` start with the bytecode magic bytes and a version
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.len() > MAGIC.len() && bytes.starts_with(MAGIC)
+}
+
+/// Cursor over little endian encoded data
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    pub(crate) fn next_byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    pub(crate) fn next_i64(&mut self) -> Result<i64, Box<dyn Error>> {
+        if self.bytes.len() < 8 {
+            return Err("Unexpected end of data".into());
+        }
+        let (operand, rest) = self.bytes.split_at(8);
+        self.bytes = rest;
+        Ok(i64::from_le_bytes(operand.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::VirtualMachine;
+    use crate::optimizer::offset::address_by_offset;
+    use crate::optimizer::{OptimizationLevel, PassManager};
+    use crate::parse::token::Syntax;
+
+    #[test]
+    fn round_trip() {
+        let syntax = Syntax { debug_hash: true, procedures: true, extended: true, fork: true, ..Syntax::default() };
+        let source = "+++[->++>-<<]>[-]<<,.#(+[-]):$!{}~^&|Y@";
+        let program = Program::compile_with(source.as_bytes(), syntax).expect("Could not compile");
+        let program = PassManager::with_level(OptimizationLevel::O2, VirtualMachine::new().settings()).run(&program);
+        let decoded = Program::deserialize(&program.serialize()).expect("Could not deserialize");
+        assert_eq!(decoded.instructions(), program.instructions());
+        let program = Program::compile(">+>[-]<<-".as_bytes()).unwrap();
+        let program = address_by_offset(&program, true);
+        let decoded = Program::deserialize(&program.serialize()).expect("Could not deserialize offsets");
+        assert_eq!(decoded.instructions(), program.instructions());
+    }
+
+    #[test]
+    fn reject_invalid_bytecode() {
+        assert!(Program::deserialize(b"+++").is_err());
+        assert!(Program::deserialize(b"BFC\0\x02").is_err());
+        assert!(Program::deserialize(b"BFC\0\x01\x09\x01").is_err());
+        assert!(Program::deserialize(b"BFC\0\x01\x07").is_err());
+        let err = Program::deserialize(b"BFC\0\x01\x0c\0\0\0\0\0\0\0\x80\x01\0\0\0\0\0\0\0").err();
+        assert_eq!(err.map(|err| err.to_string()).as_deref(), Some("Offset out of range"));
+    }
+}
@@ -0,0 +1,362 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use super::program::SourceLoc;
+
+/// Escape sequences coloring rendered diagnostics: bold red, yellow, cyan, green and blue, and the reset
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// An error found while compiling source code, pointing at the offending location and the `span` characters starting
+/// there. It may carry a note pointing at a second, related location, a suggestion to fix it, and the name of the file
+/// it was found in when it is not the main source file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    message: String,
+    loc: SourceLoc,
+    span: usize,
+    // Boxed to keep results small, since most errors have no note
+    note: Option<Box<(String, SourceLoc)>>,
+    help: Option<String>,
+    file: Option<String>,
+}
+
+/// An error raised by a running program, at the instruction at address `pc`. The source location of the instruction
+/// is known when the program was compiled from source code
+#[derive(Debug)]
+pub struct RuntimeError {
+    cause: Box<dyn Error>,
+    pc: usize,
+    loc: Option<SourceLoc>,
+    file: Option<String>,
+}
+
+/* CompileError *******************************************************************************************************/
+impl CompileError {
+    /// Create an error with `message` located at `loc`
+    pub fn new(message: &str, loc: SourceLoc) -> CompileError {
+        CompileError { message: String::from(message), loc, span: 1, note: None, help: None, file: None }
+    }
+
+    /// Extend the offending code to `span` characters, all of them on the line of the error
+    pub fn with_span(mut self, span: usize) -> CompileError {
+        self.span = span.max(1);
+        self
+    }
+
+    /// Attach a note about a related location
+    pub fn with_note(mut self, note: &str, loc: SourceLoc) -> CompileError {
+        self.note = Some(Box::new((String::from(note), loc)));
+        self
+    }
+
+    /// Attach a suggestion to fix the error
+    pub fn with_help(mut self, help: &str) -> CompileError {
+        self.help = Some(String::from(help));
+        self
+    }
+
+    /// Set the name of the file the error was found in
+    pub fn in_file(mut self, file: &str) -> CompileError {
+        self.file = Some(String::from(file));
+        self
+    }
+
+    /// Get the error message, without location
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Get the location of the error
+    pub fn loc(&self) -> SourceLoc {
+        self.loc
+    }
+
+    /// Get the number of characters of the offending code
+    pub fn span(&self) -> usize {
+        self.span
+    }
+
+    /// Get the note and its location, if any
+    pub fn note(&self) -> Option<(&str, SourceLoc)> {
+        self.note.as_deref().map(|(note, loc)| (note.as_str(), *loc))
+    }
+
+    /// Get the suggestion to fix the error, if any
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    /// Get the name of the file the error was found in, if it is not the main source file
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Render the error for the user, quoting the lines of `source` it refers to with carets under the offending code
+    pub fn render(&self, source: &str) -> String {
+        self.render_as("error", source, None)
+    }
+
+    /// Render the error as a warning, which does not stop the compilation
+    pub fn render_warning(&self, source: &str) -> String {
+        self.render_as("warning", source, None)
+    }
+
+    /// Same as [`CompileError::render`], colored with ANSI escape sequences for terminals
+    pub fn render_colored(&self, source: &str) -> String {
+        self.render_as("error", source, Some(RED))
+    }
+
+    /// Same as [`CompileError::render_warning`], colored with ANSI escape sequences for terminals
+    pub fn render_warning_colored(&self, source: &str) -> String {
+        self.render_as("warning", source, Some(YELLOW))
+    }
+
+    /// Describe the error as a single line JSON object, with the `kind` "compile", the message, the file, which is
+    /// `main_file` for errors in the main source file, the line and the column. A note is added as an object with its
+    /// own message, line and column
+    pub fn to_json(&self, main_file: Option<&str>) -> String {
+        self.json_as("compile", main_file)
+    }
+
+    /// Same as [`CompileError::to_json`], with the `kind` "warning"
+    pub fn warning_to_json(&self, main_file: Option<&str>) -> String {
+        self.json_as("warning", main_file)
+    }
+
+    fn json_as(&self, kind: &str, main_file: Option<&str>) -> String {
+        let note = match self.note() {
+            Some((note, loc)) => format!(
+                r#","note":{{"message":{},"line":{},"col":{}}}"#, json_string(note), loc.row, loc.col,
+            ),
+            None => String::new(),
+        };
+        let help = match &self.help {
+            Some(help) => format!(r#","help":{}"#, json_string(help)),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"kind":{},"message":{},"file":{},"line":{},"col":{},"pc":null{}{}}}"#,
+            json_string(kind), json_string(&self.message), json_option(self.file.as_deref().or(main_file)),
+            self.loc.row, self.loc.col, note, help,
+        )
+    }
+
+    /// Render the error with `label`, in `color` and with the other parts in their own colors if one is given
+    fn render_as(&self, label: &str, source: &str, color: Option<&str>) -> String {
+        let paint = |text: &str, code: &str| match color {
+            Some(_) => format!("{}{}{}", code, text, RESET),
+            None => String::from(text),
+        };
+        let label_color = color.unwrap_or_default();
+        let mut text = format!("{}: {}\n", paint(label, label_color), self);
+        text.push_str(&snippet(source, self.loc, &paint(&"^".repeat(self.span), label_color), &paint));
+        if let Some((note, loc)) = self.note() {
+            text.push_str(&format!("{}: {} at {}\n", paint("note", CYAN), note, loc));
+            text.push_str(&snippet(source, loc, &paint("^", CYAN), &paint));
+        }
+        if let Some(help) = &self.help {
+            text.push_str(&format!("{}: {}\n", paint("help", GREEN), help));
+        }
+        text
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.loc)?;
+        match &self.file {
+            Some(file) => write!(f, " of {}", file),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/* RuntimeError *******************************************************************************************************/
+impl RuntimeError {
+    /// Create an error caused by `cause` at address `pc`, compiled from the source code at `loc` if known
+    pub fn new(cause: Box<dyn Error>, pc: usize, loc: Option<SourceLoc>) -> RuntimeError {
+        RuntimeError { cause, pc, loc, file: None }
+    }
+
+    /// Set the name of the file the program was compiled from
+    pub fn in_file(mut self, file: &str) -> RuntimeError {
+        self.file = Some(String::from(file));
+        self
+    }
+
+    /// Get the error that stopped the program, without location
+    pub fn error(&self) -> &(dyn Error + 'static) {
+        self.cause.as_ref()
+    }
+
+    /// Get the address of the failing instruction
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Get the source location of the failing instruction, if known
+    pub fn loc(&self) -> Option<SourceLoc> {
+        self.loc
+    }
+
+    /// Get the name of the file the program was compiled from, if set
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Describe the error as a single line JSON object, like [`CompileError::to_json`], with the `kind` "runtime" and
+    /// the address of the failing instruction. Line and column are null when the location is unknown
+    pub fn to_json(&self) -> String {
+        let (line, col) = match self.loc {
+            Some(loc) => (loc.row.to_string(), loc.col.to_string()),
+            None => (String::from("null"), String::from("null")),
+        };
+        format!(
+            r#"{{"kind":"runtime","message":{},"file":{},"line":{},"col":{},"pc":{}}}"#,
+            json_string(&self.cause.to_string()), json_option(self.file.as_deref()), line, col, self.pc,
+        )
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.loc {
+            Some(loc) => write!(f, "{} ({})", self.cause, loc),
+            None => write!(f, "{}", self.cause),
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.cause.as_ref())
+    }
+}
+
+/// Quote `text` as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote `text` as a JSON string, or null
+fn json_option(text: Option<&str>) -> String {
+    text.map_or(String::from("null"), json_string)
+}
+
+/// Quote the line of `source` at `loc` with `carets`, already colored, under its column. `paint` colors the gutter
+fn snippet(source: &str, loc: SourceLoc, carets: &str, paint: &dyn Fn(&str, &str) -> String) -> String {
+    let line = source.lines().nth(loc.row - 1).unwrap_or("");
+    let gutter = loc.row.to_string().len();
+    let (empty, numbered) = (paint(&format!("{:>1$} |", "", gutter), BLUE), paint(&format!("{} |", loc.row), BLUE));
+    // Keep tabs so that the carets line up with the quoted line
+    let padding: String = line.chars().take(loc.col - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    format!("{0}\n{1} {2}\n{0} {3}{4}\n", empty, numbered, line, padding, carets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::Program;
+
+    fn compile_error(source: &str) -> CompileError {
+        let err = Program::compile(source.as_bytes()).err().expect("Compilation did not fail");
+        err.downcast_ref::<CompileError>().expect("Not a CompileError").clone()
+    }
+
+    #[test]
+    fn unmatched_open_bracket() {
+        let source = "+[\n  >[-]";
+        let err = compile_error(source);
+        assert_eq!(err.loc(), SourceLoc { row: 1, col: 2 });
+        assert_eq!(err.render(source), "error: Unmatched '[' at line 1, column 2\n  |\n1 | +[\n  |  ^\n\
+             help: close the loop with a ']'\n");
+    }
+
+    #[test]
+    fn unmatched_close_bracket() {
+        let source = "+[-]\n\t>]";
+        let err = compile_error(source);
+        assert_eq!(err.to_string(), "No matching '[' at line 2, column 3");
+        assert_eq!(err.note(), Some(("last '[' is already closed", SourceLoc { row: 1, col: 2 })));
+        assert_eq!(
+            err.render(source),
+            "error: No matching '[' at line 2, column 3\n  |\n2 | \t>]\n  | \t ^\n\
+             note: last '[' is already closed at line 1, column 2\n  |\n1 | +[-]\n  |  ^\n\
+             help: remove this ']' or open the loop with a '[' before it\n",
+        );
+    }
+
+    #[test]
+    fn colored_span() {
+        let err = CompileError::new("'+-' cancel each other out", SourceLoc { row: 1, col: 2 })
+            .with_span(2)
+            .with_help("remove both");
+        assert_eq!(err.render_warning(">+-"), "warning: '+-' cancel each other out at line 1, column 2\n\
+                                               \x20 |\n1 | >+-\n  |  ^^\nhelp: remove both\n");
+        assert_eq!(
+            err.render_colored(">+-"),
+            "\x1b[1;31merror\x1b[0m: '+-' cancel each other out at line 1, column 2\n\
+             \x1b[1;34m  |\x1b[0m\n\x1b[1;34m1 |\x1b[0m >+-\n\x1b[1;34m  |\x1b[0m  \x1b[1;31m^^\x1b[0m\n\
+             \x1b[1;32mhelp\x1b[0m: remove both\n",
+        );
+    }
+
+    #[test]
+    fn json() {
+        let source = "+[-]\n\t>]";
+        assert_eq!(
+            compile_error(source).to_json(Some("a \"b\".bf")),
+            r#"{"kind":"compile","message":"No matching '['","file":"a \"b\".bf","line":2,"col":3,"pc":null,"#
+                .to_owned() + r#""note":{"message":"last '[' is already closed","line":1,"col":2},"#
+                + r#""help":"remove this ']' or open the loop with a '[' before it"}"#,
+        );
+        let err = RuntimeError::new("Cell underflow".into(), 5, Some(SourceLoc { row: 3, col: 3 }));
+        assert_eq!(err.to_string(), "Cell underflow (line 3, column 3)");
+        assert_eq!(
+            err.to_json(),
+            r#"{"kind":"runtime","message":"Cell underflow","file":null,"line":3,"col":3,"pc":5}"#,
+        );
+        assert_eq!(json_string("\u{1}\t"), r#""\u0001\t""#);
+    }
+
+    #[test]
+    fn invalid_character() {
+        let err = compile_error("++\n+a");
+        assert_eq!(err.to_string(), "Invalid character: 'a' at line 2, column 2");
+    }
+
+    #[test]
+    fn interleaved_procedures() {
+        let syntax = crate::parse::token::Syntax { procedures: true, ..Default::default() };
+        let errors: Vec<String> = ["([)]", "[(])", "+)", "(+"]
+            .iter()
+            .map(|source| Program::compile_with(source.as_bytes(), syntax).err().unwrap().to_string())
+            .collect();
+        assert_eq!(errors, [
+            "Unmatched '[' at line 1, column 2",
+            "Unmatched '(' at line 1, column 2",
+            "No matching '(' at line 1, column 2",
+            "Unmatched '(' at line 1, column 1",
+        ]);
+    }
+}
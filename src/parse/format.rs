@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use super::program::Program;
+use super::token::{Syntax, Token, TokenKind, Tokenizer};
+
+/// Layout options of the formatter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Number of spaces per loop nesting level
+    pub indent: usize,
+    /// Maximum length of a line of code, including its indentation. Longer lines are split, while comments are kept
+    /// as they are
+    pub width: Option<usize>,
+    /// Keep comments, each on a line of its own before the code that follows it
+    pub comments: bool,
+    /// Put all the code on as few lines as the width allows, without indentation or comments
+    pub minify: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { indent: 4, width: None, comments: false, minify: false }
+    }
+}
+
+/// Rewrite brainfuck source in a canonical layout with the default options. See [`format_with`]
+pub fn format(source: &[u8], syntax: Syntax) -> Result<String, Box<dyn Error>> {
+    format_with(source, syntax, &FormatOptions::default())
+}
+
+/// Rewrite brainfuck source in a canonical layout: loops containing other loops open a new indentation level, with
+/// their closing bracket on a line of its own, while innermost loops such as `[-]` are kept inline. Fails with the same
+/// errors as [`Program::compile_with`]
+pub fn format_with(source: &[u8], syntax: Syntax, options: &FormatOptions) -> Result<String, Box<dyn Error>> {
+    // Compile first, so that unbalanced brackets are reported with their location
+    Program::compile_with(source, syntax)?;
+    let tokens: Vec<Token> = Tokenizer::with_syntax(source, syntax).collect::<Result<_, _>>()?;
+    let comments = match options.comments && !options.minify {
+        true => comments(&String::from_utf8_lossy(source), &tokens),
+        false => vec![Vec::new(); tokens.len() + 1],
+    };
+    Ok(layout(&tokens, &comments, options))
+}
+
+/// Lay out a stream of tokens as [`format_with`] does, without comments. Unbalanced brackets are kept where they are
+pub fn format_tokens(tokens: &[Token], options: &FormatOptions) -> String {
+    layout(tokens, &vec![Vec::new(); tokens.len() + 1], options)
+}
+
+/// Lay out `tokens`, with the lines of `comments[i]` before the `i`-th token and the lines of the last item at the end
+fn layout(tokens: &[Token], comments: &[Vec<String>], options: &FormatOptions) -> String {
+    if options.minify {
+        let mut layout = Layout { formatted: String::new(), line: String::new(), depth: 0, options };
+        layout.line.extend(tokens.iter().map(|token| token.kind().to_char()));
+        layout.push_line();
+        return layout.formatted;
+    }
+    // For every '[', the position of the matching ']' and whether the loop contains other loops or comments
+    let mut loops = vec![(None, false); tokens.len()];
+    let mut open_brackets: Vec<usize> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if !comments[i].is_empty() {
+            for &open in &open_brackets {
+                loops[open].1 = true;
+            }
+        }
+        match token.kind() {
+            TokenKind::LeftBracket => {
+                if let Some(&outer) = open_brackets.last() {
+                    loops[outer].1 = true;
+                }
+                open_brackets.push(i);
+            }
+            TokenKind::RightBracket => {
+                if let Some(open) = open_brackets.pop() {
+                    loops[open].0 = Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut layout = Layout { formatted: String::new(), line: String::new(), depth: 0, options };
+    let mut i = 0;
+    while i < tokens.len() {
+        layout.push_comments(&comments[i]);
+        match (tokens[i].kind(), loops[i]) {
+            (TokenKind::LeftBracket, (Some(end), false)) => {
+                layout.line.extend(tokens[i..=end].iter().map(|token| token.kind().to_char()));
+                i = end;
+            }
+            (TokenKind::LeftBracket, _) => {
+                layout.line.push('[');
+                layout.push_line();
+                layout.depth += 1;
+            }
+            (TokenKind::RightBracket, _) => {
+                layout.push_line();
+                layout.depth = layout.depth.saturating_sub(1);
+                layout.line.push(']');
+                layout.push_line();
+            }
+            (kind, _) => layout.line.push(kind.to_char()),
+        }
+        i += 1;
+    }
+    layout.push_comments(&comments[tokens.len()]);
+    layout.push_line();
+    layout.formatted
+}
+
+/// Lines of comments between `tokens` in `source`: the `i`-th item holds the comments before the `i`-th token, and
+/// the last one those after the last token. Every line of source code contributes at most one line of comment
+fn comments(source: &str, tokens: &[Token]) -> Vec<Vec<String>> {
+    let positions: HashSet<(usize, usize)> = tokens.iter().map(|token| (token.row(), token.col())).collect();
+    let mut comments = vec![Vec::new(); tokens.len() + 1];
+    let mut next = 0;
+    for (row, line) in source.split('\n').enumerate() {
+        let mut comment = String::new();
+        for (col, c) in line.chars().enumerate() {
+            if positions.contains(&(row + 1, col + 1)) {
+                push_comment(&mut comments[next], &mut comment);
+                next += 1;
+            } else {
+                comment.push(c);
+            }
+        }
+        push_comment(&mut comments[next], &mut comment);
+    }
+    comments
+}
+
+/// Move `comment` to `lines`, without surrounding whitespace. Empty comments are skipped
+fn push_comment(lines: &mut Vec<String>, comment: &mut String) {
+    if !comment.trim().is_empty() {
+        lines.push(String::from(comment.trim()));
+    }
+    comment.clear();
+}
+
+/// Formatted code and the line being built
+struct Layout<'a> {
+    formatted: String,
+    line: String,
+    depth: usize,
+    options: &'a FormatOptions,
+}
+
+/* Layout *************************************************************************************************************/
+impl Layout<'_> {
+    /// Append the line being built, indented and split to fit the width, and clear it. Empty lines are skipped
+    fn push_line(&mut self) {
+        let indent = self.depth * self.options.indent;
+        let width = self.options.width.map_or(usize::MAX, |width| width.saturating_sub(indent).max(1));
+        let chars: Vec<char> = self.line.chars().collect();
+        for chunk in chars.chunks(width) {
+            self.formatted.push_str(&" ".repeat(indent));
+            self.formatted.extend(chunk);
+            self.formatted.push('\n');
+        }
+        self.line.clear();
+    }
+
+    /// Append lines of comments after the line being built
+    fn push_comments(&mut self, comments: &[String]) {
+        if comments.is_empty() {
+            return;
+        }
+        self.push_line();
+        for comment in comments {
+            self.formatted.push_str(&" ".repeat(self.depth * self.options.indent));
+            self.formatted.push_str(comment);
+            self.formatted.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn indent_loops() {
+        let source = "++++ # comment\n[>++[>+<-]>[<+>-]<<-]>.";
+        assert_eq!(format(source.as_bytes(), Syntax::default()).unwrap(), "++++[\n    >++[>+<-]>[<+>-]<<-\n]\n>.\n");
+        let source = "+[[-]>[[-]>]]";
+        assert_eq!(format(source.as_bytes(), Syntax::default()).unwrap(), "+[\n    [-]>[\n        [-]>\n    ]\n]\n");
+        assert!(format(b"+[", Syntax::default()).is_err());
+        let syntax = Syntax { debug_hash: true, ..Syntax::default() };
+        assert_eq!(format(b"+# #\n[-#]", syntax).unwrap(), "+##[-#]\n");
+    }
+
+    #[test]
+    fn options() {
+        let syntax = Syntax { permissive: true, ..Syntax::default() };
+        let options = FormatOptions { indent: 2, width: Some(6), comments: true, minify: false };
+        let source = "increment ++++++++\n[>++[>+ add <-] # move + twice\n <<-] done";
+        assert_eq!(
+            format_with(source.as_bytes(), syntax, &options).unwrap(),
+            "increment\n++++++\n++[\n  >++[\n    >+\n    add\n    <-\n  ]\n  # move + twice\n  <<-\n]\ndone\n",
+        );
+        let options = FormatOptions { minify: true, ..options };
+        assert_eq!(format_with(source.as_bytes(), syntax, &options).unwrap(), "++++++\n++[>++\n[>+<-]\n<<-]\n");
+        let err = format_with(b"+\n +a", Syntax::default(), &options).err().unwrap();
+        assert_eq!(err.to_string(), "Invalid character: 'a' at line 2, column 3");
+        let tokens: Vec<Token> = Tokenizer::read("+[[-]".as_bytes()).collect::<Result<_, _>>().unwrap();
+        assert_eq!(format_tokens(&tokens, &FormatOptions::default()), "+[\n    [-]\n");
+    }
+}
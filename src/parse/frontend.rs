@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::token::{Token, Tokenizer};
+
+/// Source of tokens for [`Program::from_frontend`](super::program::Program::from_frontend). A frontend turns the
+/// source code of a brainfuck dialect into standard tokens, so that new syntaxes can be compiled and executed without
+/// changes to the rest of the interpreter
+pub trait Frontend {
+    /// Iterate over the tokens of the source code. Tokens must carry their location in the original source, so that
+    /// errors and the debugger can refer to it
+    fn tokens(&mut self) -> Box<dyn Iterator<Item = Result<Token, Box<dyn Error>>> + '_>;
+}
+
+/// Languages understood by the interpreter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Language {
+    Brainfuck,
+    /// See [`Ook`](super::ook::Ook)
+    Ook,
+    /// Brainfuck with procedures, see [`Syntax::procedures`](super::token::Syntax::procedures)
+    Pbrain,
+    /// Brainfuck with bitwise operations, see [`Syntax::extended`](super::token::Syntax::extended)
+    Ebf1,
+    /// Self-modifying brainfuck, which is executed from the tape and cannot be compiled, see
+    /// [`SelfModifying`](crate::interpreter::smbf::SelfModifying)
+    Smbf,
+    /// Brainfuck on a tape of bits, see [`Mapping::boolfuck`](super::substitution::Mapping::boolfuck)
+    Boolfuck,
+    /// Brainfuck with threads, see [`Syntax::fork`](super::token::Syntax::fork)
+    Brainfork,
+    /// Listing of instructions, see [`assemble`](super::asm::assemble)
+    Asm,
+}
+
+/* Language ***********************************************************************************************************/
+impl Language {
+    /// Guess the language of a file from its extension. Unknown extensions are brainfuck
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Language {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("ook") => Language::Ook,
+            Some("smbf") => Language::Smbf,
+            Some("asm") => Language::Asm,
+            _ => Language::Brainfuck,
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bf" | "brainfuck" => Ok(Language::Brainfuck),
+            "ook" => Ok(Language::Ook),
+            "pbrain" => Ok(Language::Pbrain),
+            "ebf1" => Ok(Language::Ebf1),
+            "smbf" => Ok(Language::Smbf),
+            "boolfuck" => Ok(Language::Boolfuck),
+            "brainfork" => Ok(Language::Brainfork),
+            "asm" => Ok(Language::Asm),
+            _ => Err(format!("Invalid language: '{}'", s)),
+        }
+    }
+}
+
+/* Tokenizer **********************************************************************************************************/
+impl<R: Read> Frontend for Tokenizer<R> {
+    fn tokens(&mut self) -> Box<dyn Iterator<Item = Result<Token, Box<dyn Error>>> + '_> {
+        Box::new(self.by_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::{Instruction, Program};
+    use crate::parse::token::TokenKind;
+
+    /// Dialect where every command is spelled as a word
+    struct Words(&'static str);
+
+    impl Frontend for Words {
+        fn tokens(&mut self) -> Box<dyn Iterator<Item = Result<Token, Box<dyn Error>>> + '_> {
+            Box::new(self.0.split(' ').enumerate().map(|(i, word)| {
+                let kind = match word {
+                    "inc" => TokenKind::Plus,
+                    "loop" => TokenKind::LeftBracket,
+                    "end" => TokenKind::RightBracket,
+                    _ => return Err(format!("Invalid word: '{}'", word).into()),
+                };
+                Ok(Token::new(kind, 1, i + 1))
+            }))
+        }
+    }
+
+    #[test]
+    fn custom_frontend() {
+        let program = Program::from_frontend(&mut Words("inc loop inc end")).expect("Could not compile");
+        assert_eq!(program.instructions(), [
+            Instruction::IncData,
+            Instruction::JZ(4),
+            Instruction::IncData,
+            Instruction::JNZ(1),
+            Instruction::Exit,
+        ]);
+        let err = Program::from_frontend(&mut Words("inc loop")).err().expect("Unmatched loop accepted");
+        assert_eq!(err.to_string(), "Unmatched '[' at line 1, column 2");
+        assert!(Program::from_frontend(&mut Words("inc dec")).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::diagnostic::CompileError;
+use super::program::SourceLoc;
+use super::token::{Token, TokenKind};
+
+/// Construct that compiles but is most likely a mistake, which can be allowed, warned about or denied by name
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// A loop right after another loop, which never runs since the cell is 0 when the first loop ends
+    DeadLoop,
+    /// Adjacent commands that cancel each other out, such as `+-` or `<>`
+    CancellingCommands,
+    /// Output before any cell is written, which prints a 0
+    UnwrittenOutput,
+    /// A loop that never ends once entered, because its body cannot change the current cell or move away from it, as
+    /// `[]`
+    EndlessLoop,
+    /// Input cleared right away by a loop such as `[-]`, so that it is lost
+    OverwrittenInput,
+}
+
+/// What to do when a lint finds something
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Ignore it
+    Allow,
+    /// Report a warning, which does not stop the compilation
+    Warn,
+    /// Report an error
+    Deny,
+}
+
+/// Level of every lint. All of them warn by default
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintLevels {
+    levels: [LintLevel; Lint::ALL.len()],
+}
+
+/* Lint ***************************************************************************************************************/
+impl Lint {
+    /// Every lint, in the order of their declaration
+    pub const ALL: [Lint; 5] = [
+        Lint::DeadLoop, Lint::CancellingCommands, Lint::UnwrittenOutput, Lint::EndlessLoop, Lint::OverwrittenInput,
+    ];
+
+    /// Name of the lint on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::DeadLoop => "dead-loop",
+            Lint::CancellingCommands => "cancelling-commands",
+            Lint::UnwrittenOutput => "unwritten-output",
+            Lint::EndlessLoop => "endless-loop",
+            Lint::OverwrittenInput => "overwritten-input",
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == s).ok_or_else(|| format!("Invalid lint: '{}'", s))
+    }
+}
+
+/* LintLevels *********************************************************************************************************/
+impl LintLevels {
+    /// Level of `lint`
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels[lint as usize]
+    }
+
+    /// Set the level of `lint`
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels[lint as usize] = level;
+    }
+
+    /// Set the level of the lint called `name`, or of all of them if it is `all`
+    pub fn set_by_name(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        match name {
+            "all" => self.levels = [level; Lint::ALL.len()],
+            name => self.set(name.parse()?, level),
+        }
+        Ok(())
+    }
+
+    /// Whether any lint is not allowed, so that the code must be linted at all
+    pub fn any_enabled(&self) -> bool {
+        self.levels.iter().any(|&level| level != LintLevel::Allow)
+    }
+}
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        LintLevels { levels: [LintLevel::Warn; Lint::ALL.len()] }
+    }
+}
+
+/// Look for constructs that compile but are most likely mistakes, returning a warning for each of them together with
+/// the lint that found it. The tokens must form a program that compiles. If the tape is `preloaded`, its cells count as
+/// written from the start
+pub fn lint(tokens: &[Token], preloaded: bool) -> Vec<(Lint, CompileError)> {
+    let mut warnings = Vec::new();
+    let mut written = preloaded;
+    for (i, token) in tokens.iter().enumerate() {
+        let loc = SourceLoc { row: token.row(), col: token.col() };
+        let previous = i.checked_sub(1).map(|previous| tokens[previous].kind());
+        match (previous, token.kind()) {
+            (Some(TokenKind::RightBracket), TokenKind::LeftBracket) => {
+                let message = "Loop never runs, since the cell is 0 after the previous loop";
+                warnings.push((Lint::DeadLoop, CompileError::new(message, loc)));
+            }
+            (Some(previous @ (TokenKind::Plus | TokenKind::Minus | TokenKind::LeftBrace | TokenKind::RightBrace)), kind)
+                if kind == opposite(previous) =>
+            {
+                let message = format!("'{}{}' cancel each other out", previous.to_char(), kind.to_char());
+                let pair = SourceLoc { row: tokens[i - 1].row(), col: tokens[i - 1].col() };
+                // Underline both commands when nothing stands between them
+                let span = if token.row() == pair.row { token.col() - pair.col + 1 } else { 1 };
+                warnings.push((Lint::CancellingCommands, CompileError::new(&message, pair).with_span(span)));
+            }
+            _ => {}
+        }
+        match token.kind() {
+            TokenKind::Dot if !written => {
+                let message = "Output of a cell that was never written";
+                warnings.push((Lint::UnwrittenOutput, CompileError::new(message, loc)));
+                written = true;
+            }
+            TokenKind::LeftBracket if is_endless(&tokens[i + 1..]) => {
+                warnings.push((Lint::EndlessLoop, CompileError::new("Loop never ends once entered", loc)));
+            }
+            TokenKind::Comma if is_clear_loop(&tokens[i + 1..]) => {
+                let message = "Input is lost, since the loop right after it clears the cell";
+                let end = &tokens[i + 3];
+                // Underline the input and the loop when nothing stands between them
+                let span = if end.row() == loc.row { end.col() - loc.col + 1 } else { 1 };
+                warnings.push((Lint::OverwrittenInput, CompileError::new(message, loc).with_span(span)));
+                written = true;
+            }
+            TokenKind::LeftBrace | TokenKind::RightBrace | TokenKind::LeftBracket | TokenKind::RightBracket => {}
+            TokenKind::Hash => {}
+            _ => written = true,
+        }
+    }
+    warnings
+}
+
+/// The command undoing `kind`, for the commands that have one
+fn opposite(kind: TokenKind) -> TokenKind {
+    match kind {
+        TokenKind::Plus => TokenKind::Minus,
+        TokenKind::Minus => TokenKind::Plus,
+        TokenKind::LeftBrace => TokenKind::RightBrace,
+        TokenKind::RightBrace => TokenKind::LeftBrace,
+        kind => kind,
+    }
+}
+
+/// Whether `code` starts with a loop clearing the current cell, `[-]` or `[+]`
+fn is_clear_loop(code: &[Token]) -> bool {
+    matches!(
+        code.iter().take(3).map(Token::kind).collect::<Vec<_>>()[..],
+        [TokenKind::LeftBracket, TokenKind::Minus | TokenKind::Plus, TokenKind::RightBracket],
+    )
+}
+
+/// Whether the body of a loop, given together with the code after it, only holds commands that leave the current cell
+/// alone: output and debug commands
+fn is_endless(body: &[Token]) -> bool {
+    body.iter()
+        .map(Token::kind)
+        .take_while(|&kind| kind != TokenKind::RightBracket)
+        .all(|kind| matches!(kind, TokenKind::Dot | TokenKind::Hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::token::{Syntax, Tokenizer};
+
+    fn lint_source(source: &str) -> Vec<String> {
+        lint_tape(source, false)
+    }
+
+    fn lint_tape(source: &str, preloaded: bool) -> Vec<String> {
+        let syntax = Syntax { permissive: true, ..Syntax::default() };
+        let tokens: Vec<Token> = Tokenizer::with_syntax(source.as_bytes(), syntax).collect::<Result<_, _>>().unwrap();
+        lint(&tokens, preloaded).iter().map(|(lint, warning)| format!("{}: {}", lint, warning)).collect()
+    }
+
+    #[test]
+    fn warnings() {
+        assert!(lint_source("++++[>++<-]>.[-]<,[.,]").is_empty());
+        assert_eq!(lint_source(".+[-][+]\n+-<>+[.]"), [
+            "unwritten-output: Output of a cell that was never written at line 1, column 1",
+            "dead-loop: Loop never runs, since the cell is 0 after the previous loop at line 1, column 6",
+            "cancelling-commands: '+-' cancel each other out at line 2, column 1",
+            "cancelling-commands: '<>' cancel each other out at line 2, column 3",
+            "endless-loop: Loop never ends once entered at line 2, column 6",
+        ]);
+        assert_eq!(lint_source("+[]"), ["endless-loop: Loop never ends once entered at line 1, column 2"]);
+        assert_eq!(lint_source(">, [-]\n.,[+-]"), [
+            "overwritten-input: Input is lost, since the loop right after it clears the cell at line 1, column 2",
+            "cancelling-commands: '+-' cancel each other out at line 2, column 4",
+        ]);
+        let unwritten = "unwritten-output: Output of a cell that was never written at line 1, column 2";
+        assert_eq!(lint_source("[.[-]]"), [unwritten]);
+        assert!(lint_tape("[.[-]]", true).is_empty());
+    }
+
+    #[test]
+    fn levels() {
+        let mut levels = LintLevels::default();
+        assert_eq!(levels.level(Lint::EndlessLoop), LintLevel::Warn);
+        levels.set_by_name("all", LintLevel::Allow).unwrap();
+        assert!(!levels.any_enabled());
+        levels.set_by_name("overwritten-input", LintLevel::Deny).unwrap();
+        assert_eq!(levels.level(Lint::OverwrittenInput), LintLevel::Deny);
+        assert_eq!(levels.level(Lint::DeadLoop), LintLevel::Allow);
+        assert_eq!(levels.set_by_name("typo", LintLevel::Warn), Err(String::from("Invalid lint: 'typo'")));
+    }
+}
@@ -1,2 +1,14 @@
+pub mod asm;
+pub mod bounds;
+pub mod builder;
+pub mod bytecode;
+pub mod diagnostic;
+pub mod format;
+pub mod frontend;
+pub mod lint;
+pub mod ook;
+pub mod preprocess;
 pub mod program;
-mod token;
+pub mod reduce;
+pub mod substitution;
+pub mod token;
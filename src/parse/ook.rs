@@ -0,0 +1,81 @@
+use std::error::Error;
+
+use super::diagnostic::CompileError;
+use super::frontend::Frontend;
+use super::program::SourceLoc;
+use super::token::{Token, TokenKind};
+
+/// Frontend for Ook!, where each brainfuck command is a pair of the words `Ook.`, `Ook?` and `Ook!`. Any other text
+/// is ignored
+pub struct Ook {
+    /// Punctuation of every word, with its location
+    words: Vec<(char, SourceLoc)>,
+}
+
+/* Ook ****************************************************************************************************************/
+impl Ook {
+    /// Split Ook! source code into words
+    pub fn new(source: &str) -> Ook {
+        let mut words = Vec::new();
+        for (row, line) in source.lines().enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut col = 0;
+            while col < chars.len() {
+                match chars[col..] {
+                    ['O', 'o', 'k', punctuation @ ('.' | '?' | '!'), ..] => {
+                        words.push((punctuation, SourceLoc { row: row + 1, col: col + 1 }));
+                        col += 4;
+                    }
+                    _ => col += 1,
+                }
+            }
+        }
+        Ook { words }
+    }
+}
+
+impl Frontend for Ook {
+    fn tokens(&mut self) -> Box<dyn Iterator<Item = Result<Token, Box<dyn Error>>> + '_> {
+        Box::new(self.words.chunks(2).map(|pair| {
+            let (first, loc) = pair[0];
+            let second = match pair.get(1) {
+                Some(&(second, _)) => second,
+                None => return Err(CompileError::new("Incomplete Ook! command", loc).into()),
+            };
+            let kind = match (first, second) {
+                ('.', '?') => TokenKind::RightBrace,
+                ('?', '.') => TokenKind::LeftBrace,
+                ('.', '.') => TokenKind::Plus,
+                ('!', '!') => TokenKind::Minus,
+                ('!', '.') => TokenKind::Dot,
+                ('.', '!') => TokenKind::Comma,
+                ('!', '?') => TokenKind::LeftBracket,
+                ('?', '!') => TokenKind::RightBracket,
+                _ => {
+                    let message = format!("Invalid Ook! command: 'Ook{} Ook{}'", first, second);
+                    return Err(CompileError::new(&message, loc).into());
+                }
+            };
+            Ok(Token::new(kind, loc.row, loc.col))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::Program;
+
+    #[test]
+    fn translate() {
+        let source = "Ook. Ook. Ook! Ook?\nOok. Ook? Ook? Ook. Ook! Ook! Ook? Ook! Ook! Ook.";
+        let expected = Program::compile("+[><-].".as_bytes()).unwrap();
+        let program = Program::from_frontend(&mut Ook::new(source)).expect("Could not compile");
+        assert_eq!(program.instructions(), expected.instructions());
+        assert_eq!(program.location(2), Some(SourceLoc { row: 2, col: 1 }));
+        let err = Program::from_frontend(&mut Ook::new("Ook. Ook? Ook?")).err().unwrap();
+        assert_eq!(err.to_string(), "Incomplete Ook! command at line 1, column 11");
+        let err = Program::from_frontend(&mut Ook::new("Ook? Ook?")).err().unwrap();
+        assert_eq!(err.to_string(), "Invalid Ook! command: 'Ook? Ook?' at line 1, column 1");
+    }
+}
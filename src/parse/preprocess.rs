@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::diagnostic::CompileError;
+use super::program::SourceLoc;
+
+/// Largest number of characters a line expands to, so that repetitions cannot exhaust the memory
+pub const MAX_LINE_LEN: usize = 1 << 22;
+
+/// Expands macros, repetitions and inclusions in source code before it is tokenized:
+/// - a line starting with `@define name body` defines the macro `name` as the rest of the line
+/// - `@name` is replaced by the body of the macro, which must be defined on a previous line
+/// - `*N` right after a command, a macro or a repetition repeats it N times, as in `+*10` or `@zero>*3`
+/// - a line starting with `@include "file"` is replaced by the expanded contents of the file, which is looked for in
+///   the directory of the including file first, and then in the include paths
+///
+/// Definitions become empty lines. The preprocessor remembers where each line of the expanded code comes from, so that
+/// errors found in it can be reported at their location in the original files
+#[derive(Default)]
+pub struct Preprocessor {
+    macros: HashMap<String, String>,
+    include_paths: Vec<PathBuf>,
+    /// Name and contents of the expanded files. The main source code comes first, without a name
+    files: Vec<(String, String)>,
+    /// Origin of every expanded line
+    lines: Vec<Origin>,
+    /// Files being expanded, innermost last, to detect circular inclusions
+    stack: Vec<PathBuf>,
+}
+
+/// Where a line of expanded code comes from: a line of a file, and the column of each of its characters
+struct Origin {
+    file: usize,
+    row: usize,
+    cols: Vec<usize>,
+}
+
+/* Preprocessor *******************************************************************************************************/
+impl Preprocessor {
+    pub fn new() -> Preprocessor {
+        Preprocessor::default()
+    }
+
+    /// Look for included files in `path` too, after the directories already added
+    pub fn add_include_path(&mut self, path: &Path) {
+        self.include_paths.push(path.to_path_buf());
+    }
+
+    /// Expand the macros, repetitions and inclusions in the main source code
+    pub fn expand(&mut self, source: &str) -> Result<String, Box<dyn Error>> {
+        self.files = vec![(String::new(), String::from(source))];
+        self.lines.clear();
+        Ok(self.expand_source(0)?)
+    }
+
+    /// Map an error found in the expanded code back to the file and the location it comes from. The note is dropped if
+    /// it refers to another file
+    pub fn locate(&self, err: &CompileError) -> CompileError {
+        let (file, loc) = self.origin(err.loc());
+        let mut located = CompileError::new(err.message(), loc).with_span(err.span());
+        if let Some(help) = err.help() {
+            located = located.with_help(help);
+        }
+        if let Some((note, loc)) = err.note().map(|(note, loc)| (note, self.origin(loc))) {
+            if loc.0 == file {
+                located = located.with_note(note, loc.1);
+            }
+        }
+        match file {
+            0 => located,
+            _ => located.in_file(&self.files[file].0),
+        }
+    }
+
+    /// Contents of the original file an error was found in, to render it. `None` is the main source code
+    pub fn source(&self, file: Option<&str>) -> &str {
+        let file = file.and_then(|name| self.files.iter().find(|(file, _)| file == name));
+        file.or(self.files.first()).map_or("", |(_, source)| source.as_str())
+    }
+
+    /// File and location in it of the expanded code at `loc`
+    fn origin(&self, loc: SourceLoc) -> (usize, SourceLoc) {
+        match self.lines.get(loc.row - 1) {
+            Some(origin) => {
+                // Past the end of the line, keep the distance from its last character
+                let col = match origin.cols.get(loc.col - 1) {
+                    Some(&col) => col,
+                    None => origin.cols.last().map_or(loc.col, |&last| last + loc.col - origin.cols.len()),
+                };
+                (origin.file, SourceLoc { row: origin.row, col })
+            }
+            None => (0, loc),
+        }
+    }
+
+    /// Expand the file at index `file`
+    fn expand_source(&mut self, file: usize) -> Result<String, CompileError> {
+        let (name, source) = self.files[file].clone();
+        // Errors in included files already carry their name
+        let located = |err: CompileError| match file == 0 || err.file().is_some() {
+            true => err,
+            false => err.in_file(&name),
+        };
+        let mut expanded = String::new();
+        for (row, line) in source.lines().enumerate() {
+            let row = row + 1;
+            let indent = line.len() - line.trim_start().len();
+            let directive = |name: &str| line.trim_start().strip_prefix(name).filter(|rest| name_len(rest) == 0);
+            let (text, cols) = if let Some(definition) = directive("@define") {
+                let col = indent + "@define".len() + 1;
+                self.parse_definition(definition, SourceLoc { row, col }).map_err(located)?;
+                (String::new(), Vec::new())
+            } else if let Some(include) = directive("@include") {
+                let loc = SourceLoc { row, col: indent + 1 };
+                expanded.push_str(&self.include(include.trim(), loc, file).map_err(located)?);
+                continue;
+            } else {
+                self.expand_line(line, SourceLoc { row, col: 1 }).map_err(located)?
+            };
+            expanded.push_str(&text);
+            expanded.push('\n');
+            self.lines.push(Origin { file, row, cols });
+        }
+        Ok(expanded)
+    }
+
+    /// Expand the file named by the quoted `name` of an `@include` at `loc`, in the file at index `from`
+    fn include(&mut self, name: &str, loc: SourceLoc, from: usize) -> Result<String, CompileError> {
+        let name = name.strip_prefix('"').and_then(|name| name.strip_suffix('"'))
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| CompileError::new("Expected a quoted file name after '@include'", loc))?;
+        let path = self.find(name, from).ok_or_else(|| CompileError::new(&format!("File not found: '{}'", name), loc))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.stack.contains(&canonical) {
+            return Err(CompileError::new(&format!("Circular inclusion of '{}'", name), loc));
+        }
+        let source = std::fs::read_to_string(&path)
+            .map_err(|err| CompileError::new(&format!("Could not read '{}': {}", name, err), loc))?;
+        self.files.push((path.to_string_lossy().into_owned(), source));
+        self.stack.push(canonical);
+        let expanded = self.expand_source(self.files.len() - 1);
+        self.stack.pop();
+        expanded
+    }
+
+    /// Path of the file `name` included by the file at index `from`
+    fn find(&self, name: &str, from: usize) -> Option<PathBuf> {
+        let including = Path::new(&self.files[from].0).parent().map(Path::to_path_buf).unwrap_or_default();
+        let directories = std::iter::once(&including).chain(&self.include_paths);
+        directories.map(|directory| directory.join(name)).find(|path| path.is_file())
+    }
+
+    /// Parse the name and the body following `@define`, starting at `loc`
+    fn parse_definition(&mut self, definition: &str, loc: SourceLoc) -> Result<(), CompileError> {
+        let name_start = definition.len() - definition.trim_start().len();
+        let name_end = name_start + name_len(&definition[name_start..]);
+        let name = &definition[name_start..name_end];
+        let name_loc = SourceLoc { row: loc.row, col: loc.col + name_start };
+        if name.is_empty() {
+            return Err(CompileError::new("Expected a macro name", name_loc));
+        }
+        if self.macros.contains_key(name) {
+            return Err(CompileError::new(&format!("Macro '{}' is already defined", name), name_loc));
+        }
+        let body = definition[name_end..].trim_start();
+        let body_loc = SourceLoc { row: loc.row, col: loc.col + definition.len() - body.len() };
+        let (body, _) = self.expand_line(body, body_loc)?;
+        self.macros.insert(String::from(name), body);
+        Ok(())
+    }
+
+    /// Expand a line of code starting at `loc`, together with the column each expanded character comes from. Fails if
+    /// the expanded line is longer than [`MAX_LINE_LEN`] characters
+    fn expand_line(&self, line: &str, loc: SourceLoc) -> Result<(String, Vec<usize>), CompileError> {
+        let mut expanded = Vec::new();
+        let mut cols = Vec::new();
+        // Where the last unit that can be repeated starts in the expanded line
+        let mut unit = None;
+        let mut chars = line.char_indices().enumerate();
+        while let Some((i, (pos, c))) = chars.next() {
+            let here = SourceLoc { row: loc.row, col: loc.col + i };
+            let rest = &line[pos + c.len_utf8()..];
+            let skip = match c {
+                '@' if name_len(rest) > 0 => {
+                    let name = &rest[..name_len(rest)];
+                    let body = self.macros.get(name)
+                        .ok_or_else(|| CompileError::new(&format!("Undefined macro '{}'", name), here))?;
+                    check_len(expanded.len().checked_add(body.chars().count()), here)?;
+                    unit = Some(expanded.len());
+                    expanded.extend(body.chars());
+                    cols.resize(expanded.len(), here.col);
+                    name.len()
+                }
+                '*' if unit.is_some() && rest.starts_with(|c: char| c.is_ascii_digit()) => {
+                    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+                    let count: usize = digits.parse()
+                        .map_err(|_| CompileError::new(&format!("Invalid repetition count: {}", digits), here))?;
+                    let start = unit.expect("There is a unit to repeat");
+                    let unit_len = expanded.len() - start;
+                    check_len(unit_len.checked_mul(count).and_then(|len| len.checked_add(start)), here)?;
+                    expanded.truncate(start + unit_len * count.min(1));
+                    cols.truncate(expanded.len());
+                    for _ in 1..count {
+                        expanded.extend_from_within(start..start + unit_len);
+                        cols.extend_from_within(start..start + unit_len);
+                    }
+                    digits.len()
+                }
+                c => {
+                    check_len(Some(expanded.len() + 1), here)?;
+                    unit = if c.is_whitespace() { None } else { Some(expanded.len()) };
+                    expanded.push(c);
+                    cols.push(here.col);
+                    0
+                }
+            };
+            // Names and digits are ASCII
+            for _ in 0..skip {
+                chars.next();
+            }
+        }
+        Ok((expanded.into_iter().collect(), cols))
+    }
+}
+
+/// Fail at `loc` unless `len`, the length of a line being expanded, is known and at most [`MAX_LINE_LEN`] characters
+fn check_len(len: Option<usize>, loc: SourceLoc) -> Result<(), CompileError> {
+    match len {
+        Some(len) if len <= MAX_LINE_LEN => Ok(()),
+        _ => Err(CompileError::new(&format!("Expanded line is longer than {} characters", MAX_LINE_LEN), loc)),
+    }
+}
+
+/// Length in bytes of the macro name at the beginning of `text`: a letter or underscore, followed by letters, digits
+/// and underscores
+fn name_len(text: &str) -> usize {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::Program;
+
+    fn expand(source: &str) -> Result<String, Box<dyn Error>> {
+        Preprocessor::new().expand(source)
+    }
+
+    #[test]
+    fn macros() {
+        let source = "@define zero [-]\n  @define move2 [->>+<<]\n@zero>+*3@move2 @zero*2\nOk.";
+        assert_eq!(expand(source).unwrap(), "\n\n[-]>+++[->>+<<] [-][-]\nOk.\n");
+        assert_eq!(expand("@define x +*2\n@define y @x>\n@y*2<*0").unwrap(), "\n\n++>++>\n");
+        let err = expand("+\n @define bad @undefined").err().unwrap();
+        assert_eq!(err.to_string(), "Undefined macro 'undefined' at line 2, column 14");
+        let err = expand("@define\n").err().unwrap();
+        assert_eq!(err.to_string(), "Expected a macro name at line 1, column 8");
+        let err = expand("@define x +\n@define x -").err().unwrap();
+        assert_eq!(err.to_string(), "Macro 'x' is already defined at line 2, column 9");
+    }
+
+    #[test]
+    fn repetitions() {
+        assert_eq!(expand("+*3 2 * 3 *4 @*2").unwrap(), "+++ 2 * 3 *4 @@\n");
+        assert_eq!(expand("[-]*2+*2*3").unwrap(), "[-]]++++++\n");
+        let err = expand("+*99999999999999999999999").err().unwrap();
+        assert_eq!(err.to_string(), "Invalid repetition count: 99999999999999999999999 at line 1, column 2");
+        let err = expand("@define x >+*4096\n>@x*4096 +*18446744073709551615").err().unwrap();
+        assert_eq!(err.to_string(), "Expanded line is longer than 4194304 characters at line 2, column 4");
+        let err = expand("+ +*18446744073709551615").err().unwrap();
+        assert_eq!(err.to_string(), "Expanded line is longer than 4194304 characters at line 1, column 4");
+        assert_eq!(expand(&format!("{}*{}", "<", MAX_LINE_LEN)).unwrap().len(), MAX_LINE_LEN + 1);
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("bfint-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/zero.bf"), "@define zero [-]\n@include \"open.bf\"").unwrap();
+        std::fs::write(dir.join("lib/open.bf"), "\n  +[").unwrap();
+        std::fs::write(dir.join("lib/cycle.bf"), "@include \"cycle.bf\"").unwrap();
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_include_path(&dir.join("lib"));
+        let expanded = preprocessor.expand("@include \"zero.bf\"\n>@zero").unwrap();
+        assert_eq!(expanded, "\n\n  +[\n>[-]\n");
+
+        // Errors in the expanded code point at the included file
+        let err = Program::compile(expanded.as_bytes()).err().unwrap();
+        let err = preprocessor.locate(err.downcast_ref::<CompileError>().unwrap());
+        let open = dir.join("lib/open.bf").to_string_lossy().into_owned();
+        assert_eq!(err.to_string(), format!("Unmatched '[' at line 2, column 4 of {}", open));
+        let rendered = err.render(preprocessor.source(err.file()));
+        assert!(rendered.ends_with("2 |   +[\n  |    ^\nhelp: close the loop with a ']'\n"));
+
+        let err = preprocessor.expand("\n@include \"cycle.bf\"").err().unwrap();
+        let cycle = dir.join("lib/cycle.bf").to_string_lossy().into_owned();
+        assert_eq!(err.to_string(), format!("Circular inclusion of 'cycle.bf' at line 1, column 1 of {}", cycle));
+        let err = preprocessor.expand("@include \"missing.bf\"").err().unwrap();
+        assert_eq!(err.to_string(), "File not found: 'missing.bf' at line 1, column 1");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
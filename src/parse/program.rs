@@ -2,13 +2,28 @@ use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::io::{Read, Write};
 
-use super::token::{TokenKind, Tokenizer};
+use super::diagnostic::CompileError;
+use super::frontend::Frontend;
+use super::token::{Syntax, TokenKind, Tokenizer};
 
+/// A compiled brainfuck program: a sequence of instructions always terminated by [`Instruction::Exit`]. Each
+/// instruction may carry the location of the source code it was compiled from
+#[derive(Clone, Default)]
 pub struct Program {
     instructions: Vec<Instruction>,
+    locations: Vec<Option<SourceLoc>>,
 }
 
-#[derive(Debug)]
+/// Position in the source code, with 1-based row and column
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourceLoc {
+    pub row: usize,
+    pub col: usize,
+}
+
+/// A single instruction of the virtual machine. Jump instructions carry their absolute target address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Instruction {
     IncPtr,
     DecPtr,
@@ -18,22 +33,81 @@ pub enum Instruction {
     Output,
     JZ(usize),
     JNZ(usize),
+    /// Add a signed amount to the current cell
+    Add(i64),
+    /// Move the memory pointer by a signed amount of cells
+    Move(isize),
+    /// Set the current cell to a value
+    Set(u64),
+    /// Add the current cell multiplied by `factor` to the cell at `offset` from the memory pointer
+    MulAdd { offset: isize, factor: i64 },
+    /// Add a signed amount to the cell at `offset` from the memory pointer, without moving it
+    AddAt { offset: isize, value: i64 },
+    /// Set the cell at `offset` from the memory pointer to a value, without moving it
+    SetAt { offset: isize, value: u64 },
+    /// Print the state of the machine to stderr
+    Debug,
+    /// Define the procedure numbered by the current cell, starting at the next instruction, and jump to the address
+    /// following its end
+    DefineProc(usize),
+    /// Return from the current procedure
+    Return,
+    /// Call the procedure numbered by the current cell
+    CallProc,
+    /// Copy the current cell to the storage register
+    Store,
+    /// Copy the storage register to the current cell
+    Load,
+    /// Shift the current cell left by one bit
+    ShiftLeft,
+    /// Shift the current cell right by one bit
+    ShiftRight,
+    /// Invert the bits of the current cell
+    Not,
+    /// Bitwise exclusive or of the current cell with the storage register
+    Xor,
+    /// Bitwise and of the current cell with the storage register
+    And,
+    /// Bitwise or of the current cell with the storage register
+    Or,
+    /// Start a new thread with a copy of the tape, where the memory pointer is moved right and the cell under it is
+    /// set to 1. The current cell is set to 0 in the original thread
+    Fork,
     Exit,
 }
 
 /* Program ************************************************************************************************************/
 impl Program {
+    /// Create an empty program
     pub fn new() -> Program {
         Program {
             instructions: Vec::new(),
+            locations: Vec::new(),
         }
     }
 
+    /// Compile brainfuck source code read from `source`. Fails on invalid characters or unbalanced brackets, with a
+    /// [`CompileError`] pointing at the offending token
     pub fn compile<R: Read>(source: R) -> Result<Program, Box<dyn Error>> {
+        Program::compile_with(source, Syntax::default())
+    }
+
+    /// Same as [`Program::compile`], with the given syntax options
+    pub fn compile_with<R: Read>(source: R, syntax: Syntax) -> Result<Program, Box<dyn Error>> {
+        Program::from_frontend(&mut Tokenizer::with_syntax(source, syntax))
+    }
+
+    /// Compile the tokens produced by `frontend`
+    pub fn from_frontend<F: Frontend + ?Sized>(frontend: &mut F) -> Result<Program, Box<dyn Error>> {
         let mut instructions = Vec::new();
+        let mut locations = Vec::new();
+        // Open brackets and parentheses, which must be nested properly
         let mut open_bracket_stack = Vec::new();
-        for (i, token) in Tokenizer::read(source).enumerate() {
+        // Location of the last '[' that was closed, to explain unmatched ']'
+        let mut last_closed = None;
+        for (i, token) in frontend.tokens().enumerate() {
             let token = token?;
+            let loc = SourceLoc { row: token.row(), col: token.col() };
             let instruction = match token.kind() {
                 TokenKind::RightBrace => Instruction::IncPtr,
                 TokenKind::LeftBrace => Instruction::DecPtr,
@@ -41,59 +115,255 @@ impl Program {
                 TokenKind::Minus => Instruction::DecData,
                 TokenKind::Dot => Instruction::Output,
                 TokenKind::Comma => Instruction::Input,
+                TokenKind::Hash => Instruction::Debug,
+                TokenKind::Colon => Instruction::CallProc,
+                TokenKind::At => Instruction::Exit,
+                TokenKind::Dollar => Instruction::Store,
+                TokenKind::Bang => Instruction::Load,
+                TokenKind::LeftCurly => Instruction::ShiftLeft,
+                TokenKind::RightCurly => Instruction::ShiftRight,
+                TokenKind::Tilde => Instruction::Not,
+                TokenKind::Caret => Instruction::Xor,
+                TokenKind::Ampersand => Instruction::And,
+                TokenKind::Pipe => Instruction::Or,
+                TokenKind::Y => Instruction::Fork,
                 TokenKind::LeftBracket => {
                     open_bracket_stack.push(i);
                     Instruction::JZ(0)
                 }
-                TokenKind::RightBracket => {
-                    if let Some(open_bracket_pos) = open_bracket_stack.pop() {
+                TokenKind::LeftParen => {
+                    open_bracket_stack.push(i);
+                    Instruction::DefineProc(0)
+                }
+                TokenKind::RightBracket => match open_bracket_stack.pop() {
+                    Some(open_bracket_pos) if instructions[open_bracket_pos] == Instruction::JZ(0) => {
                         instructions[open_bracket_pos] = Instruction::JZ(i + 1);
+                        last_closed = locations[open_bracket_pos];
                         Instruction::JNZ(open_bracket_pos)
-                    } else {
-                        return Err("No matching '['".into());
                     }
-                }
+                    Some(open_paren_pos) => return Err(unclosed(&instructions, &locations, open_paren_pos).into()),
+                    None => {
+                        let err = CompileError::new("No matching '['", loc)
+                            .with_help("remove this ']' or open the loop with a '[' before it");
+                        return Err(match last_closed {
+                            Some(last_closed) => err.with_note("last '[' is already closed", last_closed),
+                            None => err,
+                        }.into());
+                    }
+                },
+                TokenKind::RightParen => match open_bracket_stack.pop() {
+                    Some(open_paren_pos) if instructions[open_paren_pos] == Instruction::DefineProc(0) => {
+                        instructions[open_paren_pos] = Instruction::DefineProc(i + 1);
+                        Instruction::Return
+                    }
+                    Some(open_bracket_pos) => {
+                        return Err(unclosed(&instructions, &locations, open_bracket_pos).into());
+                    }
+                    None => {
+                        let help = "remove this ')' or define the procedure with a '(' before it";
+                        return Err(CompileError::new("No matching '('", loc).with_help(help).into());
+                    }
+                },
             };
             instructions.push(instruction);
+            locations.push(Some(loc));
         }
-        if !open_bracket_stack.is_empty() {
-            return Err("Unmatched '['".into());
+        if let Some(&open_bracket_pos) = open_bracket_stack.last() {
+            return Err(unclosed(&instructions, &locations, open_bracket_pos).into());
         }
         // Always push exit instruction at the end
         instructions.push(Instruction::Exit);
-        Ok(Program { instructions })
+        locations.push(None);
+        Ok(Program { instructions, locations })
     }
 
+    /// Build a program from a sequence of instructions. Jump targets are recomputed by matching each `JZ` with its
+    /// `JNZ` and each `DefineProc` with its `Return`, so the addresses they carry are irrelevant. An exit instruction
+    /// is appended if missing. Offsets of `isize::MIN` are rejected
+    pub fn from_instructions(instructions: Vec<Instruction>) -> Result<Program, Box<dyn Error>> {
+        let locations = vec![None; instructions.len()];
+        Program::with_locations(instructions, locations)
+    }
+
+    /// Same as [`Program::from_instructions`], with the source location of each instruction
+    pub fn with_locations(
+        mut instructions: Vec<Instruction>,
+        mut locations: Vec<Option<SourceLoc>>,
+    ) -> Result<Program, Box<dyn Error>> {
+        if instructions.len() != locations.len() {
+            return Err("Every instruction must have a location".into());
+        }
+        let mut open_bracket_stack = Vec::new();
+        for i in 0..instructions.len() {
+            match instructions[i] {
+                Instruction::JZ(_) | Instruction::DefineProc(_) => open_bracket_stack.push(i),
+                Instruction::JNZ(_) => match open_bracket_stack.pop().map(|pos| (pos, instructions[pos])) {
+                    Some((open_bracket_pos, Instruction::JZ(_))) => {
+                        instructions[open_bracket_pos] = Instruction::JZ(i + 1);
+                        instructions[i] = Instruction::JNZ(open_bracket_pos);
+                    }
+                    _ => return Err("No matching '['".into()),
+                },
+                Instruction::Return => match open_bracket_stack.pop().map(|pos| (pos, instructions[pos])) {
+                    Some((open_paren_pos, Instruction::DefineProc(_))) => {
+                        instructions[open_paren_pos] = Instruction::DefineProc(i + 1);
+                    }
+                    _ => return Err("No matching '('".into()),
+                },
+                // Moving back from the cell would need an offset of isize::MAX + 1
+                Instruction::MulAdd { offset: isize::MIN, .. }
+                | Instruction::AddAt { offset: isize::MIN, .. }
+                | Instruction::SetAt { offset: isize::MIN, .. } => return Err("Offset out of range".into()),
+                _ => {}
+            }
+        }
+        match open_bracket_stack.last().map(|&pos| instructions[pos]) {
+            Some(Instruction::DefineProc(_)) => return Err("Unmatched '('".into()),
+            Some(_) => return Err("Unmatched '['".into()),
+            None => {}
+        }
+        if instructions.last() != Some(&Instruction::Exit) {
+            instructions.push(Instruction::Exit);
+            locations.push(None);
+        }
+        Ok(Program { instructions, locations })
+    }
+
+    /// Get all instructions
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Get the source locations of all instructions
+    pub fn locations(&self) -> &[Option<SourceLoc>] {
+        &self.locations
+    }
+
+    /// Get the source location of the instruction at address `addr`, if known
+    pub fn location(&self, addr: usize) -> Option<SourceLoc> {
+        self.locations.get(addr).copied().flatten()
+    }
+
+    /// Address of the first instruction compiled from source code at or after `loc`
+    pub fn address_of(&self, loc: SourceLoc) -> Option<usize> {
+        self.locations
+            .iter()
+            .enumerate()
+            .filter_map(|(addr, other)| other.filter(|other| *other >= loc).map(|other| (other, addr)))
+            .min()
+            .map(|(_, addr)| addr)
+    }
+
+    /// Get the instruction at address `addr`
     pub fn instruction(&self, addr: usize) -> &Instruction {
         &self.instructions[addr]
     }
 
+    /// Number of instructions, including the final exit
     pub fn len(&self) -> usize {
         self.instructions.len()
     }
 
+    /// Whether the program contains no instructions at all
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Whether the program uses pbrain procedures
+    pub fn defines_procedures(&self) -> bool {
+        self.instructions.iter().any(|instruction| matches!(instruction, Instruction::DefineProc(_)))
+    }
+
+    /// Whether the program uses the storage register of Extended Brainfuck Type I
+    pub fn uses_storage(&self) -> bool {
+        self.instructions.iter().any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::Store | Instruction::Load | Instruction::Xor | Instruction::And | Instruction::Or
+            )
+        })
+    }
+
+    /// Write a textual listing of the program, one instruction per line, to `sink`. Instructions with a known source
+    /// location are annotated with it
     pub fn dump<W: Write>(&self, sink: &mut W) -> Result<(), std::io::Error> {
         for (i, instruction) in self.instructions.iter().enumerate() {
-            writeln!(sink, "0x{:08x}: {}", i, instruction)?;
+            match self.location(i) {
+                Some(loc) => writeln!(sink, "0x{:08x}: {:<24}; {}", i, instruction.to_string(), loc)?,
+                None => writeln!(sink, "0x{:08x}: {}", i, instruction)?,
+            }
         }
         Ok(())
     }
 }
 
+/// Error for the bracket or parenthesis at `pos` that is still open when it should be closed
+fn unclosed(instructions: &[Instruction], locations: &[Option<SourceLoc>], pos: usize) -> CompileError {
+    let loc = locations[pos].expect("Compiled instructions always have a location");
+    match instructions[pos] {
+        Instruction::DefineProc(_) => CompileError::new("Unmatched '('", loc).with_help("end the procedure with a ')'"),
+        _ => CompileError::new("Unmatched '['", loc).with_help("close the loop with a ']'"),
+    }
+}
+
+/* SourceLoc **********************************************************************************************************/
+impl Display for SourceLoc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.row, self.col)
+    }
+}
+
 /* Instruction ********************************************************************************************************/
+impl Instruction {
+    /// Name of the instruction in listings, without its operands
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::IncPtr => "incp",
+            Instruction::DecPtr => "decp",
+            Instruction::IncData => "incd",
+            Instruction::DecData => "decd",
+            Instruction::Input => "rd",
+            Instruction::Output => "wr",
+            Instruction::JZ(_) => "jz",
+            Instruction::JNZ(_) => "jnz",
+            Instruction::Add(_) => "add",
+            Instruction::Move(_) => "mov",
+            Instruction::Set(_) => "set",
+            Instruction::MulAdd { .. } => "muladd",
+            Instruction::AddAt { .. } => "addat",
+            Instruction::SetAt { .. } => "setat",
+            Instruction::Debug => "dbg",
+            Instruction::DefineProc(_) => "proc",
+            Instruction::Return => "ret",
+            Instruction::CallProc => "call",
+            Instruction::Store => "st",
+            Instruction::Load => "ld",
+            Instruction::ShiftLeft => "shl",
+            Instruction::ShiftRight => "shr",
+            Instruction::Not => "not",
+            Instruction::Xor => "xor",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            Instruction::Fork => "fork",
+            Instruction::Exit => "exit",
+        }
+    }
+}
+
 impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match *self {
-                Instruction::IncPtr => String::from("incp"),
-                Instruction::DecPtr => String::from("decp"),
-                Instruction::IncData => String::from("incd"),
-                Instruction::DecData => String::from("decd"),
-                Instruction::Input => String::from("rd"),
-                Instruction::Output => String::from("wr"),
-                Instruction::JZ(addr) => format!("jz 0x{:08x}", addr),
-                Instruction::JNZ(addr) => format!("jnz 0x{:08x}", addr),
-                Instruction::Exit => String::from("exit"),
+        let mnemonic = self.mnemonic();
+        match *self {
+            Instruction::JZ(addr) | Instruction::JNZ(addr) | Instruction::DefineProc(addr) => {
+                write!(f, "{} 0x{:08x}", mnemonic, addr)
             }
-        )
+            Instruction::Add(n) => write!(f, "{} {}", mnemonic, n),
+            Instruction::Move(n) => write!(f, "{} {}", mnemonic, n),
+            Instruction::Set(val) => write!(f, "{} {}", mnemonic, val),
+            Instruction::MulAdd { offset, factor } => write!(f, "{} {} {}", mnemonic, offset, factor),
+            Instruction::AddAt { offset, value } => write!(f, "{} {} {}", mnemonic, offset, value),
+            Instruction::SetAt { offset, value } => write!(f, "{} {} {}", mnemonic, offset, value),
+            _ => write!(f, "{}", mnemonic),
+        }
     }
 }
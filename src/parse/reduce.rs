@@ -0,0 +1,120 @@
+use std::ops::Range;
+
+/// Whether `chunk` of source code can be removed from a program without unbalancing it: every bracket and parenthesis
+/// it holds is matched within it
+pub fn is_balanced(chunk: &[u8]) -> bool {
+    let mut open = Vec::new();
+    for &byte in chunk {
+        match byte {
+            b'[' | b'(' => open.push(byte),
+            b']' if open.pop() != Some(b'[') => return false,
+            b')' if open.pop() != Some(b'(') => return false,
+            _ => {}
+        }
+    }
+    open.is_empty()
+}
+
+/// Ranges of `size` bytes of `code` that are balanced, as in [`is_balanced`], from left to right. They may overlap
+pub fn balanced_chunks(code: &[u8], size: usize) -> Vec<Range<usize>> {
+    (0..(code.len() + 1).saturating_sub(size))
+        .map(|start| start..start + size)
+        .filter(|range| !range.is_empty() && is_balanced(&code[range.clone()]))
+        .collect()
+}
+
+/// Positions of the brackets of every loop of `code`, ordered by the position of the opening bracket. Unmatched
+/// brackets are ignored
+pub fn loops(code: &[u8]) -> Vec<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut loops = Vec::new();
+    for (i, &byte) in code.iter().enumerate() {
+        match byte {
+            b'[' => open.push(i),
+            b']' => loops.extend(open.pop().map(|start| (start, i))),
+            _ => {}
+        }
+    }
+    loops.sort();
+    loops
+}
+
+/// Shrink source code while `fails` holds for it, and return the smallest version found. Balanced chunks are removed,
+/// from halves of the program down to single bytes, then loops are replaced by their body, until neither helps.
+/// `fails` must hold for `code` itself, and is only called with balanced programs
+pub fn reduce<F: FnMut(&[u8]) -> bool>(code: &[u8], mut fails: F) -> Vec<u8> {
+    let mut code = code.to_vec();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut size = code.len().div_ceil(2);
+        while size > 0 {
+            let mut start = 0;
+            while start + size <= code.len() {
+                let candidate = [&code[..start], &code[start + size..]].concat();
+                if is_balanced(&code[start..start + size]) && fails(&candidate) {
+                    // Try the chunk that took the place of the removed one
+                    code = candidate;
+                    changed = true;
+                } else {
+                    start += 1;
+                }
+            }
+            size /= 2;
+        }
+        let mut i = 0;
+        while let Some(&(start, end)) = loops(&code).get(i) {
+            let candidate = [&code[..start], &code[start + 1..end], &code[end + 1..]].concat();
+            if fails(&candidate) {
+                code = candidate;
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::differential::execute;
+    use crate::interpreter::virtualmachine::{
+        MemoryOverflowBehavior, Settings,
+    };
+    use crate::parse::program::Program;
+
+    #[test]
+    fn mutations() {
+        assert!(is_balanced(b"+[-[>]<](.)"));
+        assert!(!is_balanced(b"[-"));
+        assert!(!is_balanced(b"]["));
+        assert!(!is_balanced(b"[)"));
+        assert_eq!(balanced_chunks(b"+[-]>[<]", 3), [1..4, 5..8]);
+        assert_eq!(balanced_chunks(b"+[-]>", 4), [0..4, 1..5]);
+        assert_eq!(loops(b"[[-]>[<]]]"), [(0, 8), (1, 3), (5, 7)]);
+    }
+
+    #[test]
+    fn reduce_programs() {
+        // Any program with a loop
+        let reduced = reduce(b"++[>+++<-]>[-]<.>", |code| code.contains(&b']'));
+        assert_eq!(reduced, b"[]");
+        // Any program whose output contains a 3
+        let settings = || Settings {
+            memory_size: 16,
+            memory_overflow_behavior: MemoryOverflowBehavior::Wrap,
+            max_instructions: Some(1000),
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+            ..Settings::default()
+        };
+        let prints_3 = |code: &[u8]| {
+            let program = Program::compile(code).unwrap();
+            execute(&program, settings(), b"").output.contains(&3)
+        };
+        let reduced = reduce(b"+++++\n[>+++<-]>.<+++.", prints_3);
+        assert_eq!(String::from_utf8(reduced).unwrap(), "+++.");
+    }
+}
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::str::FromStr;
+
+use super::frontend::Frontend;
+use super::token::{Token, TokenKind};
+
+/// The words standing for each command in a trivial brainfuck substitution, such as Alphuck or ReverseFuck
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    /// Words sorted from the longest to the shortest, so that the longest match wins
+    words: Vec<(String, TokenKind)>,
+}
+
+/// Frontend for a trivial brainfuck substitution. Any text that is not one of the words of the mapping is ignored
+pub struct Substitution {
+    tokens: Vec<Token>,
+}
+
+/* Mapping ************************************************************************************************************/
+impl Mapping {
+    /// Create a mapping from pairs of a command and the word standing for it
+    pub fn new<S: Into<String>>(pairs: impl IntoIterator<Item = (TokenKind, S)>) -> Result<Mapping, Box<dyn Error>> {
+        let mut words: Vec<(String, TokenKind)> = Vec::new();
+        for (kind, word) in pairs {
+            let word = word.into();
+            if word.is_empty() {
+                return Err(format!("Empty word for '{}'", kind.to_char()).into());
+            }
+            if words.iter().any(|(other, _)| *other == word) {
+                return Err(format!("Word '{}' is used more than once", word).into());
+            }
+            words.push((word, kind));
+        }
+        words.sort_by_key(|(word, _)| std::cmp::Reverse(word.chars().count()));
+        Ok(Mapping { words })
+    }
+
+    /// Boolfuck, where `;` outputs a bit and `-` and `.` are not commands. It is meant to be run with
+    /// [`CellWidth::U1`](crate::interpreter::virtualmachine::CellWidth::U1) cells
+    pub fn boolfuck() -> Mapping {
+        let pairs = "+,<>[]".chars().map(|c| (TokenKind::from_char(c).expect("Valid command"), c.to_string()));
+        Mapping::new(pairs.chain([(TokenKind::Dot, String::from(";"))])).expect("Valid mapping")
+    }
+
+    /// The longest word of the mapping `text` starts with, and its command
+    fn longest_match(&self, text: &[char]) -> Option<(usize, TokenKind)> {
+        self.words.iter().find_map(|(word, kind)| {
+            let len = word.chars().count();
+            (len <= text.len() && word.chars().eq(text[..len].iter().copied())).then_some((len, *kind))
+        })
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = Box<dyn Error>;
+
+    /// Parse `command:word` entries such as `+:a,-:b`. Entries are separated by newlines if there are any, so that
+    /// words in a mapping file can contain commas, and by commas otherwise
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let separator = if s.contains('\n') { '\n' } else { ',' };
+        let mut pairs = Vec::new();
+        let mut chars = s.chars().filter(|&c| c != '\r').peekable();
+        // The command always comes first, so that it can be the separator itself
+        while let Some(command) = chars.next() {
+            if command == '\n' {
+                continue;
+            }
+            let kind = TokenKind::from_char(command)?;
+            if chars.next() != Some(':') {
+                return Err(format!("Expected ':' after '{}' in mapping", command).into());
+            }
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|&c| c != separator) {
+                word.push(c);
+            }
+            chars.next();
+            pairs.push((kind, word));
+        }
+        Mapping::new(pairs)
+    }
+}
+
+/* Substitution *******************************************************************************************************/
+impl Substitution {
+    /// Translate `source` into tokens according to `mapping`
+    pub fn new(source: &str, mapping: &Mapping) -> Substitution {
+        let mut tokens = Vec::new();
+        for (row, line) in source.lines().enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut col = 0;
+            while col < chars.len() {
+                match mapping.longest_match(&chars[col..]) {
+                    Some((len, kind)) => {
+                        tokens.push(Token::new(kind, row + 1, col + 1));
+                        col += len;
+                    }
+                    None => col += 1,
+                }
+            }
+        }
+        Substitution { tokens }
+    }
+}
+
+impl Frontend for Substitution {
+    fn tokens(&mut self) -> Box<dyn Iterator<Item = Result<Token, Box<dyn Error>>> + '_> {
+        Box::new(self.tokens.iter().cloned().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::program::{Program, SourceLoc};
+
+    #[test]
+    fn parse_mapping() {
+        let mapping: Mapping = ",:x\n.:y,y\n+:a".parse().expect("Could not parse mapping");
+        assert_eq!(mapping.longest_match(&['y', ',', 'y', 'y']), Some((3, TokenKind::Dot)));
+        assert_eq!(mapping.longest_match(&['x']), Some((1, TokenKind::Comma)));
+        assert!("+:a,-:a".parse::<Mapping>().is_err());
+        assert!("+:a,-:".parse::<Mapping>().is_err());
+        assert!("+a".parse::<Mapping>().is_err());
+        assert!("x:a".parse::<Mapping>().is_err());
+    }
+
+    #[test]
+    fn translate() {
+        // Alphuck, with an extra multi-character word that shares its first letter with another one
+        let mapping: Mapping = ">:a,<:c,+:e,-:i,.:j,,:o,[:p,]:s,-:ee".parse().unwrap();
+        let expected = Program::compile("+[->+<]-.".as_bytes()).unwrap();
+        let program = Program::from_frontend(&mut Substitution::new("epi\nae c s ee j", &mapping)).unwrap();
+        assert_eq!(program.instructions(), expected.instructions());
+        assert_eq!(program.location(3), Some(SourceLoc { row: 2, col: 1 }));
+        let expected = Program::compile("+[>,.<]".as_bytes()).unwrap();
+        let program = Program::from_frontend(&mut Substitution::new("+-[>,;.<]", &Mapping::boolfuck())).unwrap();
+        assert_eq!(program.instructions(), expected.instructions());
+    }
+}
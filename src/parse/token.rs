@@ -2,6 +2,10 @@ use std::error::Error;
 use std::fmt::Formatter;
 use std::io::{BufRead, BufReader, Read};
 
+use super::diagnostic::CompileError;
+use super::program::SourceLoc;
+
+/// A brainfuck command together with its position (1-based row and column) in the source
 #[derive(Clone, PartialEq, Eq)]
 pub struct Token {
     kind: TokenKind,
@@ -9,6 +13,8 @@ pub struct Token {
     col: usize,
 }
 
+/// The eight brainfuck commands, the `#` debug command, and the commands of the pbrain, Extended Brainfuck Type I and
+/// Brainfork dialects
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum TokenKind {
     Plus,
@@ -19,19 +25,76 @@ pub enum TokenKind {
     Comma,
     LeftBracket,
     RightBracket,
+    /// Dump the state of the machine. Only produced when [`Syntax::debug_hash`] is set
+    Hash,
+    /// Start the definition of a procedure. Only produced when [`Syntax::procedures`] is set
+    LeftParen,
+    /// End the definition of a procedure. Only produced when [`Syntax::procedures`] is set
+    RightParen,
+    /// Call a procedure. Only produced when [`Syntax::procedures`] is set
+    Colon,
+    /// End the program. Only produced when [`Syntax::extended`] is set, like the following kinds
+    At,
+    /// Copy the current cell to the storage register
+    Dollar,
+    /// Copy the storage register to the current cell
+    Bang,
+    /// Shift the current cell left by one bit
+    LeftCurly,
+    /// Shift the current cell right by one bit
+    RightCurly,
+    /// Invert the bits of the current cell
+    Tilde,
+    /// Bitwise exclusive or of the current cell with the storage register
+    Caret,
+    /// Bitwise and of the current cell with the storage register
+    Ampersand,
+    /// Bitwise or of the current cell with the storage register
+    Pipe,
+    /// Fork the current thread. Only produced when [`Syntax::fork`] is set
+    Y,
+}
+
+/// Options controlling how source code is split into tokens. The default is standard brainfuck with `#` comments
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Syntax {
+    /// Treat `#` as the debug command instead of the start of a comment
+    pub debug_hash: bool,
+    /// Treat everything after the first `!` as the input of the program instead of code
+    pub bang_input: bool,
+    /// Silently skip characters that are not commands instead of rejecting them
+    pub permissive: bool,
+    /// Accept the pbrain procedure commands `(`, `)` and `:`
+    pub procedures: bool,
+    /// Accept the Extended Brainfuck Type I commands `@`, `$`, `!`, `{`, `}`, `~`, `^`, `&` and `|`. When
+    /// [`Syntax::bang_input`] is also set, `!` still starts the input
+    pub extended: bool,
+    /// Accept the Brainfork command `Y`
+    pub fork: bool,
 }
 
+/// Iterator over the tokens of a brainfuck source. Whitespace is skipped and `#` starts a comment that runs until the
+/// end of the line. The source is decoded as UTF-8 while it is read, one character at a time: invalid sequences are
+/// replaced by U+FFFD
 pub struct Tokenizer<R: Read> {
     reader: BufReader<R>,
-    chars: Vec<char>,
-    current_line_n: usize,
-    current_char_n: usize,
+    syntax: Syntax,
+    finished: bool,
+    in_comment: bool,
+    row: usize,
+    col: usize,
 }
 
 /* Token **************************************************************************************************************/
 impl Token {
+    /// Create a token of the given kind found at `row` and `col`
+    pub fn new(kind: TokenKind, row: usize, col: usize) -> Token {
+        Token { kind, row, col }
+    }
+
     pub fn from_char(c: char, row: usize, col: usize) -> Result<Token, Box<dyn Error>> {
-        Ok(Token { kind: TokenKind::from_char(c)?, row, col })
+        let kind = TokenKind::from_char(c).map_err(|err| CompileError::new(&err.to_string(), SourceLoc { row, col }))?;
+        Ok(Token { kind, row, col })
     }
 
     pub fn kind(&self) -> TokenKind {
@@ -53,34 +116,94 @@ impl std::fmt::Debug for Token {
     }
 }
 
+/* Syntax *************************************************************************************************************/
+impl Syntax {
+    /// Split `source` into code and input according to [`Syntax::bang_input`]. The input is `None` when the option is
+    /// not set or there is no `!` in the source
+    pub fn split_input<'a>(&self, source: &'a [u8]) -> (&'a [u8], Option<&'a [u8]>) {
+        match source.iter().position(|&c| c == b'!') {
+            Some(pos) if self.bang_input => (&source[..pos], Some(&source[pos + 1..])),
+            _ => (source, None),
+        }
+    }
+
+    /// Whether tokens of the given kind are commands with these options
+    pub fn accepts(&self, kind: TokenKind) -> bool {
+        match kind {
+            TokenKind::Hash => self.debug_hash,
+            TokenKind::LeftParen | TokenKind::RightParen | TokenKind::Colon => self.procedures,
+            TokenKind::At
+            | TokenKind::Dollar
+            | TokenKind::Bang
+            | TokenKind::LeftCurly
+            | TokenKind::RightCurly
+            | TokenKind::Tilde
+            | TokenKind::Caret
+            | TokenKind::Ampersand
+            | TokenKind::Pipe => self.extended,
+            TokenKind::Y => self.fork,
+            _ => true,
+        }
+    }
+}
+
 /* Tokenizer **********************************************************************************************************/
 impl<R: Read> Tokenizer<R> {
+    /// Create a Tokenizer reading from `source` with the default syntax
     pub fn read(source: R) -> Tokenizer<R> {
-        let reader = BufReader::new(source);
+        Tokenizer::with_syntax(source, Syntax::default())
+    }
+
+    /// Create a Tokenizer reading from `source` with the given syntax options
+    pub fn with_syntax(source: R, syntax: Syntax) -> Tokenizer<R> {
         Tokenizer {
-            reader,
-            chars: Vec::new(),
-            current_line_n: 0,
-            current_char_n: 0,
+            reader: BufReader::new(source),
+            syntax,
+            finished: false,
+            in_comment: false,
+            row: 1,
+            col: 0,
         }
     }
 
-    fn read_next_line(&mut self) -> Result<bool, Box<dyn Error>> {
-        let mut line = String::new();
-        match self.reader.read_line(&mut line) {
-            Ok(0) => {
-                // No more lines: iteration ends
-                Ok(false)
-            }
-            Ok(..) => {
-                // Read n characters: update chars iterator and read next character
-                self.chars = line.chars().collect();
-                self.current_char_n = 0;
-                self.current_line_n += 1;
-                Ok(true)
+    /// Read the next character, or `None` at the end of the source
+    fn next_char(&mut self) -> Result<Option<char>, std::io::Error> {
+        let first = match self.next_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let len = match first {
+            0x00..=0x7f => return Ok(Some(first as char)),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Ok(Some(char::REPLACEMENT_CHARACTER)),
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for byte in bytes.iter_mut().take(len).skip(1) {
+            // Continuation bytes only: anything else starts the next character
+            match self.peek_byte()? {
+                Some(next @ 0x80..=0xbf) => {
+                    *byte = next;
+                    self.reader.consume(1);
+                }
+                _ => return Ok(Some(char::REPLACEMENT_CHARACTER)),
             }
-            Err(e) => Err(e.into()),
         }
+        let c = std::str::from_utf8(&bytes[..len]).ok().and_then(|s| s.chars().next());
+        Ok(Some(c.unwrap_or(char::REPLACEMENT_CHARACTER)))
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, std::io::Error> {
+        let byte = self.peek_byte()?;
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>, std::io::Error> {
+        Ok(self.reader.fill_buf()?.first().copied())
     }
 }
 
@@ -88,32 +211,47 @@ impl<R: Read> Iterator for Tokenizer<R> {
     type Item = Result<Token, Box<dyn Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let char_n = self.current_char_n;
-        self.current_char_n += 1;
-        if char_n < self.chars.len() {
-            let c = self.chars[char_n];
-            // Ignore whitespace
-            if c.is_whitespace() {
-                return self.next();
+        while !self.finished {
+            let c = match self.next_char() {
+                Ok(Some(c)) => c,
+                Ok(None) => break,
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            // Keep track of the position
+            if c == '\n' {
+                self.row += 1;
+                self.col = 0;
+                self.in_comment = false;
+                continue;
+            }
+            self.col += 1;
+            // Ignore whitespace and comments
+            if self.in_comment || c.is_whitespace() {
+                continue;
             }
-            // Ignore comments
-            if c == '#' {
-                return match self.read_next_line() {
-                    Ok(true) => self.next(),
-                    Ok(false) => None,
-                    Err(e) => Some(Err(e.into())),
-                };
+            // The rest of the source is input
+            if c == '!' && self.syntax.bang_input {
+                break;
             }
-            // Generate token
-            Some(Token::from_char(c, self.current_line_n, self.current_char_n))
-        } else {
-            // End of line, try to read next
-            match self.read_next_line() {
-                Ok(true) => self.next(),
-                Ok(false) => None,
-                Err(e) => Some(Err(e.into())),
+            if c == '#' && !self.syntax.debug_hash {
+                self.in_comment = true;
+                continue;
+            }
+            match TokenKind::from_char(c) {
+                Ok(kind) if self.syntax.accepts(kind) => return Some(Ok(Token::new(kind, self.row, self.col))),
+                // Ignore anything else that is not a command in permissive mode
+                _ if self.syntax.permissive => continue,
+                _ => {
+                    let loc = SourceLoc { row: self.row, col: self.col };
+                    return Some(Err(CompileError::new(&format!("Invalid character: '{}'", c), loc).into()));
+                }
             }
         }
+        self.finished = true;
+        None
     }
 }
 
@@ -129,12 +267,26 @@ impl TokenKind {
             ',' => Ok(TokenKind::Comma),
             '[' => Ok(TokenKind::LeftBracket),
             ']' => Ok(TokenKind::RightBracket),
+            '#' => Ok(TokenKind::Hash),
+            '(' => Ok(TokenKind::LeftParen),
+            ')' => Ok(TokenKind::RightParen),
+            ':' => Ok(TokenKind::Colon),
+            '@' => Ok(TokenKind::At),
+            '$' => Ok(TokenKind::Dollar),
+            '!' => Ok(TokenKind::Bang),
+            '{' => Ok(TokenKind::LeftCurly),
+            '}' => Ok(TokenKind::RightCurly),
+            '~' => Ok(TokenKind::Tilde),
+            '^' => Ok(TokenKind::Caret),
+            '&' => Ok(TokenKind::Ampersand),
+            '|' => Ok(TokenKind::Pipe),
+            'Y' => Ok(TokenKind::Y),
             _ => Err(format!("Invalid character: '{}'", c).into()),
         }
     }
 
-    pub fn to_char(&self) -> char {
-        match *self {
+    pub fn to_char(self) -> char {
+        match self {
             TokenKind::Plus => '+',
             TokenKind::Minus => '-',
             TokenKind::LeftBrace => '<',
@@ -143,6 +295,20 @@ impl TokenKind {
             TokenKind::Comma => ',',
             TokenKind::LeftBracket => '[',
             TokenKind::RightBracket => ']',
+            TokenKind::Hash => '#',
+            TokenKind::LeftParen => '(',
+            TokenKind::RightParen => ')',
+            TokenKind::Colon => ':',
+            TokenKind::At => '@',
+            TokenKind::Dollar => '$',
+            TokenKind::Bang => '!',
+            TokenKind::LeftCurly => '{',
+            TokenKind::RightCurly => '}',
+            TokenKind::Tilde => '~',
+            TokenKind::Caret => '^',
+            TokenKind::Ampersand => '&',
+            TokenKind::Pipe => '|',
+            TokenKind::Y => 'Y',
         }
     }
 }
@@ -196,5 +362,72 @@ mod test {
                 assert_eq!(token, exp_tokens[i]);
             }
         }
+
+        #[test]
+        fn debug_hash() {
+            let kinds = |source: &str, syntax| -> Vec<TokenKind> {
+                Tokenizer::with_syntax(source.as_bytes(), syntax).map(|token| token.unwrap().kind()).collect()
+            };
+            let syntax = Syntax { debug_hash: true, ..Syntax::default() };
+            assert_eq!(kinds("+# comment\n-", Syntax::default()), [TokenKind::Plus, TokenKind::Minus]);
+            assert_eq!(kinds("+#\n-", syntax), [TokenKind::Plus, TokenKind::Hash, TokenKind::Minus]);
+            let err = Tokenizer::with_syntax("+# comment".as_bytes(), syntax)
+                .find_map(Result::err)
+                .expect("Comment not rejected");
+            assert_eq!(err.to_string(), "Invalid character: 'c' at line 1, column 4");
+        }
+
+        #[test]
+        fn long_runs() {
+            // A single line with a long run of whitespace and comments must neither be buffered nor recurse
+            let mut source = " ".repeat(1 << 20);
+            source.push_str("+#");
+            source.push_str(&"x".repeat(1 << 20));
+            source.push_str("\n\t-");
+            let tokens: Vec<Token> = Tokenizer::read(source.as_bytes()).map(Result::unwrap).collect();
+            assert_eq!(tokens, [
+                Token { row: 1, col: (1 << 20) + 1, kind: TokenKind::Plus },
+                Token { row: 2, col: 2, kind: TokenKind::Minus },
+            ]);
+        }
+
+        #[test]
+        fn utf8() {
+            // Columns count characters, not bytes
+            let err = Tokenizer::read("+é".as_bytes()).find_map(Result::err).expect("Invalid character accepted");
+            assert_eq!(err.to_string(), "Invalid character: 'é' at line 1, column 2");
+            let err = Tokenizer::read(&b"\xff+"[..]).find_map(Result::err).expect("Invalid UTF-8 accepted");
+            assert_eq!(err.to_string(), "Invalid character: '\u{fffd}' at line 1, column 1");
+            let syntax = Syntax { permissive: true, ..Syntax::default() };
+            let source = b"\xe2\x82+\xc3\xa9-";
+            let tokens: Vec<Token> = Tokenizer::with_syntax(&source[..], syntax).map(Result::unwrap).collect();
+            assert_eq!(tokens, [
+                Token { row: 1, col: 2, kind: TokenKind::Plus },
+                Token { row: 1, col: 4, kind: TokenKind::Minus },
+            ]);
+        }
+
+        #[test]
+        fn permissive() {
+            let source = "Print 1+1: ++\n[comments, too.]";
+            let syntax = Syntax { permissive: true, ..Syntax::default() };
+            let tokens: String = Tokenizer::with_syntax(source.as_bytes(), syntax)
+                .map(|token| token.unwrap().kind().to_char())
+                .collect();
+            assert_eq!(tokens, "+++[,.]");
+            assert!(Tokenizer::read(source.as_bytes()).any(|token| token.is_err()));
+        }
+
+        #[test]
+        fn procedures() {
+            let source = "(+):";
+            let syntax = Syntax { procedures: true, ..Syntax::default() };
+            let tokens: String = Tokenizer::with_syntax(source.as_bytes(), syntax)
+                .map(|token| token.unwrap().kind().to_char())
+                .collect();
+            assert_eq!(tokens, source);
+            let err = Tokenizer::read(source.as_bytes()).find_map(Result::err).expect("Procedure accepted");
+            assert_eq!(err.to_string(), "Invalid character: '(' at line 1, column 1");
+        }
     }
 }
@@ -0,0 +1,38 @@
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use bfint::{CompileError, Interpreter};
+
+const PROMPT: &str = "bf> ";
+
+/// Run an interactive session: every line read from stdin is compiled and executed on the same tape, so memory and
+/// memory pointer are preserved between entries. The session ends on EOF.
+pub fn run(mut interpreter: Interpreter) -> Result<(), Box<dyn Error>> {
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    loop {
+        write!(stdout, "{}", PROMPT)?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            // EOF: terminate the session
+            writeln!(stdout)?;
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Errors are reported but never end the session
+        if let Err(e) = interpreter.load_snippet(&line) {
+            match e.downcast_ref::<CompileError>() {
+                Some(e) => eprint!("{}", e.render(&line)),
+                None => eprintln!("Error: {}", e),
+            }
+        } else if let Err(e) = interpreter.run() {
+            eprintln!("Error: {}", e);
+        }
+        interpreter.flush()?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
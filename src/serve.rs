@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::iter::Peekable;
+use std::net::{TcpListener, TcpStream};
+use std::str::Chars;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bfint::parse::diagnostic::json_string;
+use bfint::ui::SharedBuffer;
+use bfint::{
+    CompileError, CostModel, FlushPolicy, Gas, Interpreter, MemoryOverflowBehavior, RuntimeError, Settings,
+    SettingsBuilder,
+};
+
+use crate::websocket;
+
+/// Time a client has to send its request, and then to receive the response
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest request line and headers
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+/// Limits every program run by the server is held to, whatever the client asks for
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pub memsize: usize,
+    pub max_steps: u64,
+    pub timeout: Duration,
+    pub max_output_bytes: u64,
+    /// Gas budget of each run and the cost model it is spent with, if gas is metered
+    pub gas: Option<(u64, CostModel)>,
+    /// Largest request body, with the program and its input, and largest WebSocket message
+    pub max_request_bytes: usize,
+    /// Time a WebSocket session can last, waiting for input included
+    pub session_timeout: Duration,
+    /// Connections answered at a time, each on a thread of its own. Further connections are rejected
+    pub max_connections: usize,
+}
+
+/// HTTP request
+struct Request {
+    method: String,
+    path: String,
+    /// Headers by lowercase name
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// HTTP response with a JSON body
+struct Response {
+    status: u16,
+    body: String,
+}
+
+/// Connection being answered, counted in the number of connections until dropped
+struct Slot(Arc<AtomicUsize>);
+
+/// Serve runs of programs over HTTP on `addr`, each connection on a thread of its own, until the process is killed.
+/// Connections beyond the `max_connections` of `limits` are answered at once with 503 Service Unavailable.
+/// Clients POST a program and its input to `/run`, as a JSON object with the `program` and `input` strings or as
+/// multipart form data with fields of the same names, and receive its output, the error that stopped it if any, and
+/// statistics of the run. Interactive programs run in WebSocket sessions opened at `/ws`, see [`session`]
+pub fn serve(addr: &str, limits: Limits) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).map_err(|err| format!("Could not listen on '{}': {}", addr, err))?;
+    eprintln!("Serving on http://{}, POST programs to /run", listener.local_addr()?);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("warning: could not accept a connection: {}", err);
+                continue;
+            }
+        };
+        let Some(slot) = Slot::take(&connections, limits.max_connections) else {
+            let busy = Response::error(503, "Too many connections, try again later");
+            if let Err(err) = stream.set_write_timeout(Some(IO_TIMEOUT)).and_then(|()| respond(&stream, &busy)) {
+                eprintln!("warning: could not reject a connection: {}", err);
+            }
+            continue;
+        };
+        let limits = limits.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            if let Err(err) = handle(stream, &limits) {
+                eprintln!("warning: could not answer a request: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer the request sent on `stream`, then close the connection
+fn handle(stream: TcpStream, limits: &Limits) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader, limits.max_request_bytes) {
+        Ok(request) => {
+            let key = request.headers.get("sec-websocket-key");
+            match (request.method.as_str(), request.path.as_str(), key) {
+                ("GET", "/ws", Some(key)) => return session(stream, reader, key, limits),
+                _ => route(&request, limits),
+            }
+        }
+        Err(response) => response,
+    };
+    respond(&stream, &response)
+}
+
+/// Send `response` on `stream`
+fn respond(mut stream: &TcpStream, response: &Response) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, reason(response.status), response.body.len(), response.body,
+    )?;
+    stream.flush()
+}
+
+/// Request read by `reader`, or the response rejecting it
+fn read_request<R: BufRead>(reader: &mut R, max_body_bytes: usize) -> Result<Request, Response> {
+    let mut head = Vec::new();
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        match reader.by_ref().take((MAX_HEAD_BYTES - head.len()) as u64).read_until(b'\n', &mut line) {
+            Ok(0) => return Err(Response::error(400, "Incomplete request")),
+            Ok(_) if !line.ends_with(b"\n") => return Err(Response::error(431, "Request headers are too large")),
+            Ok(_) => {}
+            Err(err) => return Err(Response::error(400, &format!("Could not read the request: {}", err))),
+        }
+        head.extend_from_slice(&line);
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let mut request_line = lines.first().map_or("", String::as_str).split_whitespace();
+    let (method, path) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(Response::error(400, "Invalid request line")),
+    };
+    let headers: HashMap<String, String> = lines.iter().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    if headers.contains_key("transfer-encoding") {
+        return Err(Response::error(411, "Chunked requests are not supported, send a Content-Length"));
+    }
+    let length = match headers.get("content-length").map(|length| length.parse::<usize>()) {
+        None => 0,
+        Some(Ok(length)) if length <= max_body_bytes => length,
+        Some(Ok(_)) => return Err(Response::error(413, &format!("Requests are limited to {} bytes", max_body_bytes))),
+        Some(Err(_)) => return Err(Response::error(400, "Invalid Content-Length")),
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|err| Response::error(400, &format!("Could not read the request: {}", err)))?;
+    Ok(Request { method, path, headers, body })
+}
+
+/// Response to `request`
+fn route(request: &Request, limits: &Limits) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/run") => match job(request) {
+            Ok((program, input)) => run(&program, input, limits),
+            Err(message) => Response::error(400, &message),
+        },
+        (_, "/run") => Response::error(405, "Programs are run with POST"),
+        ("GET", "/ws") => Response::error(400, "Expected a WebSocket handshake"),
+        (_, "/ws") => Response::error(405, "WebSocket sessions are opened with GET"),
+        (_, path) => Response::error(404, &format!("Nothing at '{}'", path)),
+    }
+}
+
+/// Program and input sent with `request`
+fn job(request: &Request) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let content_type = request.headers.get("content-type").map_or("", String::as_str);
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let mut fields = match media_type.as_str() {
+        "application/json" => {
+            let body = std::str::from_utf8(&request.body).map_err(|_| "The request is not valid UTF-8")?;
+            json_fields(body)?.into_iter().map(|(name, value)| (name, value.into_bytes())).collect()
+        }
+        "multipart/form-data" => multipart_fields(content_type, &request.body)?,
+        _ => return Err(String::from("Send the program as application/json or multipart/form-data")),
+    };
+    let program = fields.remove("program").ok_or("Missing the program")?;
+    Ok((program, fields.remove("input").unwrap_or_default()))
+}
+
+/// Run `program` on `input` within `limits`
+fn run(program: &[u8], input: Vec<u8>, limits: &Limits) -> Response {
+    let output = SharedBuffer::new();
+    let settings = limited(limits, limits.timeout).input(Cursor::new(input)).output(output.clone()).build();
+    let outcome = execute(program, settings, limits);
+    let output = json_string(&String::from_utf8_lossy(&output.contents()));
+    Response::json(200, format!(r#"{{"output":{},{}}}"#, output, outcome))
+}
+
+/// Run the program sent as the first message of a WebSocket session on `stream`, opened with `key`, within `limits`.
+/// Messages are read by `reader`, which may have buffered some of them along with the request. The output of the
+/// program is sent as binary messages as soon as it is written, and the following messages are its input, until the
+/// client sends an empty message or closes the connection. The outcome of the run is sent last, as a text message
+/// with the JSON object returned by `/run` but the output. Sessions lasting longer than the session timeout, such as
+/// while the program waits for input, stop with an error
+fn session(mut stream: TcpStream, reader: BufReader<TcpStream>, key: &str, limits: &Limits) -> std::io::Result<()> {
+    websocket::accept(&mut stream, key)?;
+    // Interactive programs wait for their user
+    stream.set_read_timeout(Some(limits.session_timeout))?;
+    let mut receiver = websocket::Receiver::new(reader, limits.max_request_bytes)?;
+    let outcome = match receiver.next_message() {
+        Ok(Some(program)) => {
+            let settings = limited(limits, limits.session_timeout)
+                .flush_policy(FlushPolicy::Byte)
+                .input(receiver)
+                .output(websocket::Sender::new(stream.try_clone()?))
+                .build();
+            format!("{{{}}}", execute(&program, settings, limits))
+        }
+        Ok(None) => String::from(r#"{"error":"Missing the program"}"#),
+        Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+            format!(r#"{{"error":{}}}"#, json_string(&err.to_string()))
+        }
+        Err(err) => return Err(err),
+    };
+    websocket::send_text(&mut stream, &outcome)?;
+    websocket::close(&mut stream)
+}
+
+/// Settings holding runs to `limits`, aborting them after `timeout`
+fn limited(limits: &Limits, timeout: Duration) -> SettingsBuilder {
+    Settings::builder()
+        .memory_size(limits.memsize)
+        .memory_overflow_behavior(MemoryOverflowBehavior::Error)
+        .max_instructions(limits.max_steps)
+        .max_wall_time(timeout)
+        .max_output_bytes(limits.max_output_bytes)
+}
+
+/// Run `program` with `settings` and the gas of `limits`, and return its outcome as the `ok`, `error` and `metrics`
+/// fields of a JSON object
+fn execute(program: &[u8], settings: Settings, limits: &Limits) -> String {
+    let mut interpreter = Interpreter::with_vm_settings(settings);
+    let gas = limits.gas.as_ref().map(|(budget, model)| Gas::new(*budget, model.clone()));
+    interpreter.vm_mut().set_gas(gas.clone());
+    if let Err(err) = interpreter.load_source(program) {
+        return format!(r#""ok":false,"error":{},"metrics":null"#, error_json(err.as_ref()));
+    }
+    let result = interpreter.run().and_then(|()| Ok(interpreter.flush()?));
+    let metrics = interpreter.vm().metrics();
+    let error = match &result {
+        Ok(()) => String::from("null"),
+        Err(err) => error_json(err.as_ref()),
+    };
+    format!(
+        concat!(
+            r#""ok":{},"error":{},"metrics":{{"instructions":{},"gas_used":{},"bytes_read":{},"bytes_written":{},"#,
+            r#""cells_used":{},"wall_time_ms":{:.3}}}"#,
+        ),
+        result.is_ok(), error, metrics.instructions, gas.map_or(String::from("null"), |gas| gas.used().to_string()),
+        metrics.bytes_read, metrics.bytes_written, metrics.cells_touched(), metrics.elapsed.as_secs_f64() * 1000.0,
+    )
+}
+
+/// `err` as a JSON object, as printed by `--error-format json`
+fn error_json(err: &(dyn Error + 'static)) -> String {
+    if let Some(err) = err.downcast_ref::<RuntimeError>() {
+        return err.to_json();
+    }
+    if let Some(err) = err.downcast_ref::<CompileError>() {
+        return err.to_json(None);
+    }
+    format!(
+        r#"{{"kind":"error","message":{},"file":null,"line":null,"col":null,"pc":null}}"#,
+        json_string(&err.to_string()),
+    )
+}
+
+/// Reason phrase of the HTTP `status`
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/* Response ***********************************************************************************************************/
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, format!(r#"{{"error":{}}}"#, json_string(message)))
+    }
+}
+
+/* Slot ***************************************************************************************************************/
+impl Slot {
+    /// Count a new connection in `connections`, unless there are `max` already
+    fn take(connections: &Arc<AtomicUsize>, max: usize) -> Option<Slot> {
+        connections.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| (count < max).then_some(count + 1))
+            .ok()
+            .map(|_| Slot(connections.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/* Request bodies *****************************************************************************************************/
+/// String fields of a JSON object whose values are strings, numbers, booleans or null
+fn json_fields(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut chars = text.chars().peekable();
+    let mut fields = HashMap::new();
+    expect(&mut chars, '{')?;
+    if skip_whitespace(&mut chars) == Some('}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let name = json_str(&mut chars)?;
+            expect(&mut chars, ':')?;
+            match skip_whitespace(&mut chars) {
+                Some('"') => {
+                    fields.insert(name, json_str(&mut chars)?);
+                }
+                Some(c) if c == '-' || c.is_ascii_alphanumeric() => {
+                    let scalar_char = |c: &char| "+-.".contains(*c) || c.is_ascii_alphanumeric();
+                    let scalar: String = std::iter::from_fn(|| chars.next_if(scalar_char)).collect();
+                    if !matches!(scalar.as_str(), "true" | "false" | "null") && scalar.parse::<f64>().is_err() {
+                        return Err(format!("Invalid JSON value for '{}'", name));
+                    }
+                }
+                _ => return Err(format!("Unsupported JSON value for '{}', only strings and scalars are", name)),
+            }
+            match skip_whitespace(&mut chars) {
+                Some(',') => chars.next(),
+                Some('}') => {
+                    chars.next();
+                    break;
+                }
+                _ => return Err(String::from("Invalid JSON object, expected ',' or '}'")),
+            };
+        }
+    }
+    match skip_whitespace(&mut chars) {
+        None => Ok(fields),
+        Some(_) => Err(String::from("Invalid JSON, unexpected text after the object")),
+    }
+}
+
+/// Skip whitespace, and peek at the next character
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.peek().copied()
+}
+
+/// Skip whitespace, then consume `expected`
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    match skip_whitespace(chars) {
+        Some(c) if c == expected => {
+            chars.next();
+            Ok(())
+        }
+        _ => Err(format!("Invalid JSON, expected '{}'", expected)),
+    }
+}
+
+/// JSON string starting at the next character, without quotes and escapes
+fn json_str(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut string = String::new();
+    loop {
+        match chars.next().ok_or("Invalid JSON, unterminated string")? {
+            '"' => return Ok(string),
+            '\\' => match chars.next().ok_or("Invalid JSON, unterminated string")? {
+                'n' => string.push('\n'),
+                'r' => string.push('\r'),
+                't' => string.push('\t'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let mut code = json_hex(chars)?;
+                    if (0xd800..=0xdbff).contains(&code) {
+                        // The low surrogate is only consumed if it is one, lone surrogates are replaced
+                        let mut ahead = chars.clone();
+                        if ahead.next() == Some('\\') && ahead.next() == Some('u') {
+                            if let Ok(low @ 0xdc00..=0xdfff) = json_hex(&mut ahead) {
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                *chars = ahead;
+                            }
+                        }
+                    }
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => string.push(c),
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+/// Four hexadecimal digits of a `\u` escape
+fn json_hex(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid JSON escape: '\\u{}'", digits))
+}
+
+/// Fields of a multipart/form-data `body`, split at the boundary given in `content_type`
+fn multipart_fields(content_type: &str, body: &[u8]) -> Result<HashMap<String, Vec<u8>>, String> {
+    let boundary = content_type.split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))
+        .ok_or("Missing the multipart boundary")?
+        .trim_matches('"');
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // The first delimiter may start the body, without the line break
+    let mut rest = [b"\r\n", body].concat();
+    let mut fields = HashMap::new();
+    let mut start = find(&rest, &delimiter).ok_or("Missing the first multipart boundary")? + delimiter.len();
+    while !rest[start..].starts_with(b"--") {
+        rest = rest.split_off(start);
+        let end = find(&rest, &delimiter).ok_or("Missing the closing multipart boundary")?;
+        let part = rest[..end].strip_prefix(b"\r\n").unwrap_or(&rest[..end]);
+        let head_end = find(part, b"\r\n\r\n").ok_or("Invalid multipart part, without a blank line")?;
+        let head = String::from_utf8_lossy(&part[..head_end]);
+        let name = head.lines()
+            .filter(|line| line.to_ascii_lowercase().starts_with("content-disposition:"))
+            .flat_map(|line| line.split(';'))
+            .find_map(|param| param.trim().strip_prefix("name="))
+            .ok_or("Multipart part without a name")?
+            .trim_matches('"');
+        fields.insert(name.to_string(), part[head_end + 4..].to_vec());
+        start = end + delimiter.len();
+    }
+    Ok(fields)
+}
+
+/// Position of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_json() {
+        let fields = json_fields(r#" { "program" : "+[.]\n", "steps": 10, "raw": true, "input": "A\"" } "#);
+        let expected = [("program", "+[.]\n"), ("input", "A\"")];
+        assert_eq!(fields, Ok(expected.map(|(name, value)| (name.to_string(), value.to_string())).into()));
+        assert!(json_fields(r#"{"program": ["+"]}"#).is_err());
+        assert!(json_fields(r#"{"program": "+"} ."#).is_err());
+        assert!(json_fields(r#"{"program": "+}"#).is_err());
+        // Surrogate pairs are joined, and lone surrogates replaced without losing the characters after them
+        let input = |text: &str| json_fields(text).map(|mut fields| fields.remove("input").unwrap());
+        assert_eq!(input(r#"{"input": "\ud83d\ude00"}"#), Ok(String::from("\u{1f600}")));
+        assert_eq!(input(r#"{"input": "ab\ud800cd"}"#), Ok(String::from("ab\u{fffd}cd")));
+        assert_eq!(input(r#"{"input": "\ud800\u0041"}"#), Ok(String::from("\u{fffd}A")));
+        assert_eq!(input(r#"{"input": "\ude00"}"#), Ok(String::from("\u{fffd}")));
+    }
+
+    #[test]
+    fn parse_multipart() {
+        let body = concat!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"program\"\r\n\r\n,[.,]\r\n",
+            "--XyZ\r\ncontent-disposition: form-data; name=input; filename=\"in.txt\"\r\n",
+            "Content-Type: application/octet-stream\r\n\r\nline\r\n\r\n--XyZ--\r\n",
+        );
+        let fields = multipart_fields("multipart/form-data; boundary=\"XyZ\"", body.as_bytes()).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["program"], b",[.,]");
+        assert_eq!(fields["input"], b"line\r\n");
+        assert!(multipart_fields("multipart/form-data", body.as_bytes()).is_err());
+        assert!(multipart_fields("multipart/form-data; boundary=XyZ", b"--XyZ\r\n\r\n+").is_err());
+    }
+
+    #[test]
+    fn parse_requests() {
+        let read = |request: &str| read_request(&mut request.as_bytes(), 8);
+        let request = read("POST /run HTTP/1.1\r\nHost: x\r\nContent-LENGTH: 5\r\n\r\n+[.]!extra").ok().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/run"));
+        assert_eq!(request.headers["content-length"], "5");
+        assert_eq!(request.body, b"+[.]!");
+        let status = |request: &str| read(request).err().map(|response| response.status);
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(status("GET / HTTP/1.1\r\n"), Some(400));
+        assert_eq!(status("\r\n"), Some(400));
+        assert_eq!(status("POST /run HTTP/1.1\r\nContent-Length: 9\r\n\r\n"), Some(413));
+        assert_eq!(status("POST /run HTTP/1.1\r\nContent-Length: 4\r\n\r\n+"), Some(400));
+        assert_eq!(status("POST /run HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"), Some(411));
+        assert_eq!(status(&format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_HEAD_BYTES))), Some(431));
+    }
+
+    #[test]
+    fn limit_connections() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let first = Slot::take(&connections, 2);
+        let second = Slot::take(&connections, 2);
+        assert!(first.is_some() && second.is_some());
+        assert!(Slot::take(&connections, 2).is_none());
+        drop(first);
+        assert!(Slot::take(&connections, 2).is_some());
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Interval between two checks for SIGUSR2 while paused
+const RESUME_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Flag that stops the program, set by every handled signal but SIGUSR2
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+/// Whether SIGINT was received
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Whether SIGUSR1 was received since the program last resumed
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Whether SIGUSR2 was received since the program paused
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGINT by setting the returned flag instead of killing the process, so that the program can be stopped at
+/// an instruction boundary with [`bfint::VirtualMachine::set_interrupt`]. Reads blocked waiting for input are
+/// interrupted as well, rather than restarted, so that the program stops at the input command
+pub fn on_interrupt() -> Result<Arc<AtomicBool>, Box<dyn Error>> {
+    let flag = stop_flag();
+    install(sys::SIGINT, handle_interrupt)?;
+    Ok(flag)
+}
+
+/// Handle SIGUSR1 by setting the returned flag, like SIGINT, and SIGUSR2 by ending [`wait_for_resume`]. Only supported
+/// where the numbers of both signals are known: on Linux, macOS and the BSDs
+pub fn on_pause() -> Result<Arc<AtomicBool>, Box<dyn Error>> {
+    let Some((usr1, usr2)) = sys::SIGUSR else {
+        return Err("SIGUSR1 and SIGUSR2 can only be handled on Linux, macOS and the BSDs".into());
+    };
+    let flag = stop_flag();
+    install(usr1, handle_pause)?;
+    install(usr2, handle_resume)?;
+    Ok(flag)
+}
+
+/// Whether SIGINT was received
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Whether the program was stopped by SIGUSR1 and should wait for SIGUSR2, rather than by SIGINT
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed) && !interrupted()
+}
+
+/// Block until SIGUSR2 or SIGINT is received. The stop flag is cleared when SIGUSR2 resumes the program
+pub fn wait_for_resume() {
+    while !RESUMED.swap(false, Ordering::Relaxed) {
+        if interrupted() {
+            return;
+        }
+        std::thread::sleep(RESUME_POLL_INTERVAL);
+    }
+    PAUSED.store(false, Ordering::Relaxed);
+    stop_flag().store(false, Ordering::Relaxed);
+}
+
+fn stop_flag() -> Arc<AtomicBool> {
+    STOP.get_or_init(Default::default).clone()
+}
+
+// Handlers only perform async-signal-safe operations: the stop flag is initialized before they are installed, so
+// getting it is a plain load
+
+extern "C" fn handle_interrupt(_: std::os::raw::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    stop();
+}
+
+extern "C" fn handle_pause(_: std::os::raw::c_int) {
+    PAUSED.store(true, Ordering::Relaxed);
+    stop();
+}
+
+extern "C" fn handle_resume(_: std::os::raw::c_int) {
+    RESUMED.store(true, Ordering::Relaxed);
+}
+
+fn stop() {
+    if let Some(flag) = STOP.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Make `handler` the handler of `signal`, interrupting system calls such as blocked reads, which then fail with
+/// [`std::io::ErrorKind::Interrupted`]
+#[cfg(unix)]
+fn install(signal: std::os::raw::c_int, handler: extern "C" fn(std::os::raw::c_int)) -> Result<(), Box<dyn Error>> {
+    let previous = unsafe { sys::signal(signal, handler as usize) };
+    if previous == sys::SIG_ERR || unsafe { sys::siginterrupt(signal, 1) } != 0 {
+        return Err(format!("Could not handle signal {}: {}", signal, std::io::Error::last_os_error()).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install(_: std::os::raw::c_int, _: extern "C" fn(std::os::raw::c_int)) -> Result<(), Box<dyn Error>> {
+    Err("Signals can only be handled on Unix systems".into())
+}
+
+/// Bindings to the C library, which the standard library already links
+mod sys {
+    use std::os::raw::c_int;
+
+    pub const SIGINT: c_int = 2;
+    /// SIGUSR1 and SIGUSR2, whose numbers depend on the system and, on Linux, on the architecture
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm",
+                                       target_arch = "aarch64", target_arch = "riscv64")))]
+    pub const SIGUSR: Option<(c_int, c_int)> = Some((10, 12));
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd",
+              target_os = "dragonfly"))]
+    pub const SIGUSR: Option<(c_int, c_int)> = Some((30, 31));
+    #[cfg(not(any(
+        all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm",
+                                     target_arch = "aarch64", target_arch = "riscv64")),
+        target_os = "macos", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd",
+        target_os = "dragonfly",
+    )))]
+    pub const SIGUSR: Option<(c_int, c_int)> = None;
+    #[cfg(unix)]
+    pub const SIG_ERR: usize = !0;
+
+    #[cfg(unix)]
+    extern "C" {
+        pub fn signal(signum: c_int, handler: usize) -> usize;
+        pub fn siginterrupt(signum: c_int, flag: c_int) -> c_int;
+    }
+}
@@ -0,0 +1,107 @@
+use crate::parse::program::Program;
+use super::random::Rng;
+
+/// Generator of random brainfuck programs that always compile and always terminate.
+///
+/// Brackets are balanced by construction, and every loop is bounded: its body returns the memory pointer to the loop
+/// cell, never changes that cell, and is followed by a `-`, so each iteration decrements the cell once and the loop
+/// runs at most as many times as the initial value of the cell, modulo the cell size. The memory pointer stays within
+/// the first [`cells`](Generator::cells) cells, so programs run on any tape at least as large
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Generator {
+    /// Number of commands generated at the top level. Loop bodies are up to a quarter as long as the block containing
+    /// them
+    pub length: usize,
+    /// Maximum loop nesting level
+    pub max_depth: usize,
+    /// Number of cells the memory pointer can reach, starting from the leftmost
+    pub cells: usize,
+    /// Whether to generate `,` and `.` commands
+    pub io: bool,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator { length: 64, max_depth: 2, cells: 16, io: true }
+    }
+}
+
+/* Generator **********************************************************************************************************/
+impl Generator {
+    /// Source code of a random program, made only of the eight brainfuck commands
+    pub fn source(&self, rng: &mut Rng) -> Vec<u8> {
+        let mut code = Vec::new();
+        let mut counters = Vec::new();
+        self.block(rng, self.length, 0, &mut counters, &mut code);
+        code
+    }
+
+    /// Random program, compiled from [`source`](Generator::source)
+    pub fn program(&self, rng: &mut Rng) -> Program {
+        Program::compile(&self.source(rng)[..]).expect("Generated programs are balanced")
+    }
+
+    /// Append `length` commands to `code`, with the memory pointer starting at the last of `counters` (or 0 at the top
+    /// level). The cells in `counters` belong to the enclosing loops, and are left untouched
+    fn block(&self, rng: &mut Rng, length: usize, depth: usize, counters: &mut Vec<usize>, code: &mut Vec<u8>) {
+        let start = counters.last().copied().unwrap_or(0);
+        let mut mp = start;
+        for _ in 0..length {
+            let free = !counters.contains(&mp);
+            match rng.below(8) {
+                0 if free => code.push(b'+'),
+                1 if free => code.push(b'-'),
+                2 if mp > 0 => {
+                    code.push(b'<');
+                    mp -= 1;
+                }
+                3 if mp + 1 < self.cells => {
+                    code.push(b'>');
+                    mp += 1;
+                }
+                4 if self.io => code.push(b'.'),
+                5 if self.io && free => code.push(b','),
+                6 | 7 if free && depth < self.max_depth => {
+                    let body = rng.below(length / 4 + 1) + 1;
+                    code.push(b'[');
+                    counters.push(mp);
+                    self.block(rng, body, depth + 1, counters, code);
+                    counters.pop();
+                    code.extend_from_slice(b"-]");
+                }
+                _ => {}
+            }
+        }
+        // Loop bodies end on their loop cell
+        if depth > 0 {
+            let (direction, distance) = if mp > start { (b'<', mp - start) } else { (b'>', start - mp) };
+            code.extend(std::iter::repeat_n(direction, distance));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::differential::execute;
+    use crate::interpreter::virtualmachine::Settings;
+
+    #[test]
+    fn generate_programs() {
+        let generator = Generator { length: 40, max_depth: 2, cells: 8, io: true };
+        assert_eq!(generator.source(&mut Rng::new(42)), generator.source(&mut Rng::new(42)));
+        for seed in 0..100 {
+            let source = generator.source(&mut Rng::new(seed));
+            let program = Program::compile(&source[..]).unwrap();
+            let settings = Settings {
+                memory_size: 8,
+                max_instructions: Some(10_000_000),
+                input: Box::new(std::io::empty()),
+                output: Box::new(std::io::sink()),
+                ..Settings::default()
+            };
+            let outcome = execute(&program, settings, b"\xff\x80\x01");
+            assert_eq!(outcome.error, None, "{}", String::from_utf8_lossy(&source));
+        }
+    }
+}
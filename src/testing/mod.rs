@@ -0,0 +1,44 @@
+//! Helpers for fuzzers and property tests: a seedable [`Rng`](random::Rng), a [`Generator`](generator::Generator) of
+//! random programs that always compile and terminate, and the [`Arbitrary`] trait tying them together.
+//!
+//! ```
+//! use bfint::optimizer::{OptimizationLevel, PassManager};
+//! use bfint::interpreter::differential::compare;
+//! use bfint::testing::{random::Rng, Arbitrary};
+//! use bfint::{Program, Settings};
+//!
+//! let settings = || Settings::builder().memory_size(16).input(std::io::empty()).output(std::io::sink()).build();
+//! let passes = PassManager::with_level(OptimizationLevel::O2, &settings());
+//! let mut rng = Rng::new(7);
+//! for _ in 0..10 {
+//!     let program = Program::arbitrary(&mut rng);
+//!     assert!(compare(&program, &passes, settings, b"input").is_ok());
+//! }
+//! ```
+use crate::parse::program::Program;
+use generator::Generator;
+use random::Rng;
+
+pub mod generator;
+pub mod random;
+
+/// Types that can build random values of themselves, for fuzzing and property testing
+pub trait Arbitrary: Sized {
+    /// Random value drawn from `rng`
+    fn arbitrary(rng: &mut Rng) -> Self;
+}
+
+/// Programs from a [`Generator`] with the default options
+impl Arbitrary for Program {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Generator::default().program(rng)
+    }
+}
+
+/// Input of a program, up to 16 bytes long
+impl Arbitrary for Vec<u8> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let len = rng.below(17);
+        (0..len).map(|_| rng.next_u64() as u8).collect()
+    }
+}
@@ -0,0 +1,51 @@
+/// Small seedable pseudo-random number generator (SplitMix64). It is not suitable for cryptography, but it is fast and
+/// the same seed always yields the same sequence, so failing cases can be reproduced from their seed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+/* Rng ****************************************************************************************************************/
+impl Rng {
+    /// Create a generator from a seed. Every seed, including 0, gives a good sequence
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Create a generator seeded by arbitrary data, such as the input of a fuzzer. Different data gives different
+    /// sequences with high probability
+    pub fn from_bytes(data: &[u8]) -> Rng {
+        // FNV-1a hash of the data
+        let seed = data.iter()
+            .fold(0xcbf29ce484222325, |hash: u64, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        Rng::new(seed)
+    }
+
+    /// Next number of the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `0..bound`. Panics if `bound` is 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        assert!(bound > 0, "Empty range");
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// True with probability `numerator / denominator`
+    pub fn chance(&mut self, numerator: usize, denominator: usize) -> bool {
+        self.below(denominator) < numerator
+    }
+
+    /// Random element of `items`, or None if it is empty
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        match items.len() {
+            0 => None,
+            len => items.get(self.below(len)),
+        }
+    }
+}
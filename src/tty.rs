@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::io::IsTerminal;
+
+/// Terminal on stdin switched to raw mode, where input is neither echoed nor buffered by line, so that every key is
+/// read as soon as it is pressed. Signals such as Ctrl+C are still generated, and interrupt the program even while it
+/// waits for a key (see [`crate::signal::on_interrupt`]), and output is still processed, so that newlines start a new
+/// line. The previous mode is restored when the guard is dropped, which also happens when the
+/// program panics
+pub struct RawTty {
+    saved: sys::Termios,
+}
+
+impl RawTty {
+    /// Switch the terminal on stdin to raw mode until the returned guard is dropped
+    pub fn enable() -> Result<RawTty, Box<dyn Error>> {
+        if !std::io::stdin().is_terminal() {
+            return Err("--raw-tty requires stdin to be a terminal".into());
+        }
+        let saved = sys::get()?;
+        let mut raw = saved;
+        raw.c_lflag &= !(sys::ICANON | sys::ECHO);
+        // Reads return as soon as a single byte is available
+        raw.c_cc[sys::VMIN] = 1;
+        raw.c_cc[sys::VTIME] = 0;
+        sys::set(&raw)?;
+        Ok(RawTty { saved })
+    }
+}
+
+impl Drop for RawTty {
+    fn drop(&mut self) {
+        // Nothing else can be done if the terminal cannot be restored
+        let _ = sys::set(&self.saved);
+    }
+}
+
+/// Bindings to the terminal interface of the C library, which the standard library already links
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::error::Error;
+    use std::os::raw::c_int;
+    #[cfg(target_os = "macos")]
+    use std::os::raw::c_ulong;
+
+    #[cfg(target_os = "linux")]
+    type Flags = u32;
+    #[cfg(target_os = "macos")]
+    type Flags = c_ulong;
+    #[cfg(target_os = "linux")]
+    type Speed = u32;
+    #[cfg(target_os = "macos")]
+    type Speed = c_ulong;
+
+    #[cfg(target_os = "linux")]
+    const NCCS: usize = 32;
+    #[cfg(target_os = "macos")]
+    const NCCS: usize = 20;
+    #[cfg(target_os = "linux")]
+    pub const ICANON: Flags = 0o2;
+    #[cfg(target_os = "macos")]
+    pub const ICANON: Flags = 0x100;
+    pub const ECHO: Flags = 0o10;
+    #[cfg(target_os = "linux")]
+    pub const VTIME: usize = 5;
+    #[cfg(target_os = "macos")]
+    pub const VTIME: usize = 17;
+    #[cfg(target_os = "linux")]
+    pub const VMIN: usize = 6;
+    #[cfg(target_os = "macos")]
+    pub const VMIN: usize = 16;
+    const TCSANOW: c_int = 0;
+    const STDIN: c_int = 0;
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    pub struct Termios {
+        c_iflag: Flags,
+        c_oflag: Flags,
+        c_cflag: Flags,
+        pub c_lflag: Flags,
+        #[cfg(target_os = "linux")]
+        c_line: u8,
+        pub c_cc: [u8; NCCS],
+        c_ispeed: Speed,
+        c_ospeed: Speed,
+    }
+
+    extern "C" {
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+    }
+
+    /// Attributes of the terminal on stdin
+    pub fn get() -> Result<Termios, Box<dyn Error>> {
+        let mut termios = std::mem::MaybeUninit::<Termios>::uninit();
+        if unsafe { tcgetattr(STDIN, termios.as_mut_ptr()) } != 0 {
+            return Err(format!("Could not get the terminal mode: {}", std::io::Error::last_os_error()).into());
+        }
+        Ok(unsafe { termios.assume_init() })
+    }
+
+    /// Change the attributes of the terminal on stdin
+    pub fn set(termios: &Termios) -> Result<(), Box<dyn Error>> {
+        if unsafe { tcsetattr(STDIN, TCSANOW, termios) } != 0 {
+            return Err(format!("Could not set the terminal mode: {}", std::io::Error::last_os_error()).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod sys {
+    use std::error::Error;
+
+    pub const ICANON: u32 = 0;
+    pub const ECHO: u32 = 0;
+    pub const VTIME: usize = 0;
+    pub const VMIN: usize = 1;
+
+    #[derive(Copy, Clone)]
+    pub struct Termios {
+        pub c_lflag: u32,
+        pub c_cc: [u8; 2],
+    }
+
+    pub fn get() -> Result<Termios, Box<dyn Error>> {
+        Err("Raw terminal mode is only supported on Linux and macOS".into())
+    }
+
+    pub fn set(_: &Termios) -> Result<(), Box<dyn Error>> {
+        Err("Raw terminal mode is only supported on Linux and macOS".into())
+    }
+}
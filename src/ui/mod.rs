@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{BufReader, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::interpreter::interpreter::Interpreter;
+use crate::interpreter::virtualmachine::{CellWidth, Status};
+
+/// Number of cells shown on each side of the memory pointer
+const TAPE_RADIUS: isize = 8;
+/// Number of output lines shown
+const OUTPUT_LINES: usize = 5;
+
+/// In-memory sink that can be shared between a VirtualMachine's output and its reader
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+/// In-memory input filled by the host while the program runs. Reading it when it is empty fails with
+/// [`WouldBlock`](std::io::ErrorKind::WouldBlock), so that the VirtualMachine waits for more input, until it is
+/// closed: then reads return EOF
+#[derive(Clone, Default)]
+pub struct InputQueue(Rc<RefCell<(VecDeque<u8>, bool)>>);
+
+/// Input typed by the user, which prints a prompt to `screen` whenever the program is about to wait for them, once it
+/// read everything typed so far, and can echo the bytes the program reads, for terminals that do not
+pub struct PromptedInput<R: Read, W: Write> {
+    input: BufReader<R>,
+    screen: W,
+    prompt: String,
+    echo: bool,
+    /// Whether the input ended, after which there is nothing to wait for
+    eof: bool,
+}
+
+/// Terminal visualizer: redraws the tape, the current instruction and the output after every instruction
+pub struct Visualizer<W: Write> {
+    screen: W,
+    source: Vec<String>,
+    output: SharedBuffer,
+    delay: Duration,
+}
+
+/// Format `cells`, the first of which is at address `start`, as a hexdump: each line shows the address of its first
+/// cell, the cells in hexadecimal with as many digits as `cell_width` needs, and the printable ASCII characters among
+/// them. Lines hold 16 bytes worth of cells
+pub fn hexdump(cells: &[u64], start: isize, cell_width: CellWidth) -> String {
+    let digits = cell_width.bits().div_ceil(8) as usize * 2;
+    let per_line = 32 / digits;
+    let mut dump = String::new();
+    for (line, chunk) in cells.chunks(per_line).enumerate() {
+        let address = start + (line * per_line) as isize;
+        let hex: Vec<String> = chunk.iter().map(|cell| format!("{:0width$x}", cell, width = digits)).collect();
+        let ascii: String = chunk.iter().map(|&cell| match cell {
+            0x20..=0x7e => cell as u8 as char,
+            _ => '.',
+        }).collect();
+        let width = per_line * (digits + 1) - 1;
+        let address = match address {
+            address if address < 0 => format!("-{:07x}", address.unsigned_abs()),
+            address => format!("{:08x}", address),
+        };
+        dump.push_str(&format!("{}  {:width$}  |{}|\n", address, hex.join(" "), ascii, width = width));
+    }
+    dump
+}
+
+/* SharedBuffer *******************************************************************************************************/
+impl SharedBuffer {
+    /// Create an empty buffer
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Copy of the bytes written so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// Bytes written so far, leaving the buffer empty
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/* InputQueue *********************************************************************************************************/
+impl InputQueue {
+    /// Create an empty, open queue
+    pub fn new() -> InputQueue {
+        InputQueue::default()
+    }
+
+    /// Append `bytes` to the input
+    pub fn push(&self, bytes: &[u8]) {
+        self.0.borrow_mut().0.extend(bytes);
+    }
+
+    /// Signal that no more input will be pushed
+    pub fn close(&self) {
+        self.0.borrow_mut().1 = true;
+    }
+}
+
+impl Read for InputQueue {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (queue, closed) = &mut *self.0.borrow_mut();
+        if queue.is_empty() && !*closed && !buf.is_empty() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let len = usize::min(buf.len(), queue.len());
+        for (byte, queued) in buf.iter_mut().zip(queue.drain(..len)) {
+            *byte = queued;
+        }
+        Ok(len)
+    }
+}
+
+/* PromptedInput ******************************************************************************************************/
+impl<R: Read, W: Write> PromptedInput<R, W> {
+    /// Read `input`, printing `prompt` to `screen` before waiting for the user, and what is read as well if `echo`
+    pub fn new(input: R, screen: W, prompt: &str, echo: bool) -> PromptedInput<R, W> {
+        PromptedInput { input: BufReader::new(input), screen, prompt: String::from(prompt), echo, eof: false }
+    }
+}
+
+impl<R: Read, W: Write> Read for PromptedInput<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.input.buffer().is_empty() && !self.eof && !self.prompt.is_empty() {
+            self.screen.write_all(self.prompt.as_bytes())?;
+            self.screen.flush()?;
+        }
+        let read = self.input.read(buf)?;
+        self.eof = read == 0 && !buf.is_empty();
+        if self.echo && read > 0 {
+            self.screen.write_all(&buf[..read])?;
+            self.screen.flush()?;
+        }
+        Ok(read)
+    }
+}
+
+/* Visualizer *********************************************************************************************************/
+impl<W: Write> Visualizer<W> {
+    /// Create a Visualizer drawing on `screen`. `source` is the source code of the program, used to show the context
+    /// of the current instruction, and `output` must be the output of the interpreter's VirtualMachine. `delay` is
+    /// the pause between two instructions
+    pub fn new(screen: W, source: &str, output: SharedBuffer, delay: Duration) -> Visualizer<W> {
+        Visualizer {
+            screen,
+            source: source.lines().map(String::from).collect(),
+            output,
+            delay,
+        }
+    }
+
+    /// Run the program loaded in `interpreter`, drawing a frame before every instruction and after the program stops
+    pub fn run(&mut self, interpreter: &mut Interpreter) -> Result<(), Box<dyn Error>> {
+        interpreter.startup()?;
+        self.animate(interpreter)
+    }
+
+    /// Continue the program paused in `interpreter`, drawing frames as [`Visualizer::run`] does
+    pub fn resume(&mut self, interpreter: &mut Interpreter) -> Result<(), Box<dyn Error>> {
+        interpreter.vm_mut().resume()?;
+        self.animate(interpreter)
+    }
+
+    /// Step through the program while it runs, drawing a frame before every instruction and when it stops
+    fn animate(&mut self, interpreter: &mut Interpreter) -> Result<(), Box<dyn Error>> {
+        while interpreter.status() == Status::Running {
+            self.draw(interpreter)?;
+            std::thread::sleep(self.delay);
+            interpreter.step()?;
+        }
+        self.draw(interpreter)?;
+        Ok(())
+    }
+
+    /// Draw a single frame showing the current state of `interpreter`
+    pub fn draw(&mut self, interpreter: &Interpreter) -> std::io::Result<()> {
+        let frame = self.render(interpreter);
+        // Move the cursor home and clear the screen before drawing
+        write!(self.screen, "\x1b[H\x1b[2J{}", frame)?;
+        self.screen.flush()
+    }
+
+    /// Render a frame as text
+    pub fn render(&self, interpreter: &Interpreter) -> String {
+        let vm = interpreter.vm();
+        let program = interpreter.program();
+        let mut frame = String::new();
+        // Instruction
+        match program.instructions().get(vm.pc()) {
+            Some(instruction) => frame.push_str(&format!("pc 0x{:08x}: {}\n", vm.pc(), instruction)),
+            None => frame.push_str("Program exited\n"),
+        }
+        // Source context
+        match program.location(vm.pc()) {
+            Some(loc) => {
+                let line = self.source.get(loc.row - 1).map_or("", String::as_str);
+                frame.push_str(&format!("{:>5} | {}\n        {}^\n", loc.row, line, " ".repeat(loc.col - 1)));
+            }
+            None => frame.push_str("\n\n"),
+        }
+        // Tape around the memory pointer
+        let address = vm.address();
+        let mut addresses = String::new();
+        let mut cells = String::new();
+        for addr in address - TAPE_RADIUS..=address + TAPE_RADIUS {
+            let cell = vm.peek(addr).map_or(String::from("-"), |val| val.to_string());
+            let (open, close) = if addr == address { ('[', ']') } else { (' ', ' ') };
+            let width = cell.len().max(addr.to_string().len());
+            addresses.push_str(&format!(" {:>width$} ", addr, width = width));
+            cells.push_str(&format!("{}{:>width$}{}", open, cell, close, width = width));
+        }
+        frame.push_str(&format!("\n{}\n{}\n\n", addresses, cells));
+        // Recent output
+        let output = String::from_utf8_lossy(&self.output.contents()).into_owned();
+        let lines: Vec<&str> = output.lines().collect();
+        frame.push_str("Output:\n");
+        for line in &lines[lines.len().saturating_sub(OUTPUT_LINES)..] {
+            frame.push_str(&format!("  {}\n", line));
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::virtualmachine::Settings;
+
+    #[test]
+    fn prompt_for_input() {
+        let screen = SharedBuffer::new();
+        let mut input = PromptedInput::new(&b"a\nb"[..], screen.clone(), "> ", true);
+        let mut byte = [0];
+        let read: Vec<usize> = (0..5).map(|_| input.read(&mut byte).expect("Could not read")).collect();
+        assert_eq!(read, [1, 1, 1, 0, 0]);
+        assert_eq!(screen.contents(), b"> a\nb> ");
+    }
+
+    #[test]
+    fn format_hexdump() {
+        let cells: Vec<u64> = b"Hello, World!\n\x00\xffbye".iter().map(|&byte| byte as u64).collect();
+        assert_eq!(
+            hexdump(&cells, -2, CellWidth::U8),
+            "-0000002  48 65 6c 6c 6f 2c 20 57 6f 72 6c 64 21 0a 00 ff  |Hello, World!...|\n\
+             0000000e  62 79 65                                         |bye|\n",
+        );
+        assert_eq!(hexdump(&[0x41, 0x1234], 0, CellWidth::U16), format!("00000000  {:39}  |A.|\n", "0041 1234"));
+    }
+
+    #[test]
+    fn render_frame() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_vm_settings(Settings {
+            memory_size: 16,
+            input: Box::new(std::io::empty()),
+            output: Box::new(output.clone()),
+            ..Settings::default()
+        });
+        let source = "++++++++[>++++++++<-]>+.\n>+++";
+        interpreter.load_snippet(source).expect("Could not load snippet");
+        let mut visualizer = Visualizer::new(Vec::new(), source, output, Duration::ZERO);
+        interpreter.add_breakpoint(24);
+        visualizer.run(&mut interpreter).expect("Error while running");
+        let frame = visualizer.render(&interpreter);
+        assert!(frame.starts_with("pc 0x00000018: incp\n    2 | >+++\n        ^\n"), "{}", frame);
+        assert!(frame.contains("[65]"));
+        assert!(frame.ends_with("Output:\n  A\n"));
+    }
+}
@@ -0,0 +1,6 @@
+//! Interpreter for web pages, built for `wasm32-unknown-unknown` with `--no-default-features`, which leaves out the
+//! command line interface. There is no stdin or stdout there: programs read the input the page pushes and write output
+//! the page takes, and should be limited by instructions rather than wall time, since there is no clock either.
+//! wasm-bindgen is not a dependency: a wrapper crate annotates a type holding a [`Playground`](playground::Playground)
+//! with `#[wasm_bindgen]` and forwards its methods
+pub mod playground;
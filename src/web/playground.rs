@@ -0,0 +1,127 @@
+use crate::interpreter::interpreter::{Interpreter, StepOutcome};
+use crate::interpreter::virtualmachine::{MemoryOverflowBehavior, Settings};
+use crate::ui::SharedBuffer;
+
+/// Interpreter driving a web playground: programs are compiled from source code, run a bounded number of
+/// instructions at a time so that the page stays responsive, and inspected in between. Its methods only take and
+/// return numbers, strings and byte vectors, so that they are exported to JavaScript as they are by wasm-bindgen
+pub struct Playground {
+    memory_size: usize,
+    interpreter: Interpreter,
+    output: SharedBuffer,
+    /// Number of bytes of output already taken by the page
+    taken: usize,
+}
+
+impl Playground {
+    /// Playground with a tape of `memory_size` 8-bit wrapping cells, where leaving the tape is an error
+    pub fn new(memory_size: usize) -> Playground {
+        let (interpreter, output) = machine(memory_size);
+        Playground { memory_size, interpreter, output, taken: 0 }
+    }
+
+    /// Compile `source` and load it on a new machine, without the tape, input and output of the previous program.
+    /// Returns the compile error, with its source location
+    pub fn compile(&mut self, source: &str) -> Result<(), String> {
+        (self.interpreter, self.output) = machine(self.memory_size);
+        self.taken = 0;
+        self.interpreter.load_str(source).map_err(|err| err.to_string())
+    }
+
+    /// Append `bytes` to the input of the program
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.interpreter.provide_input(bytes);
+    }
+
+    /// Signal that no more input will be pushed, so that the program reads EOF instead of waiting for more
+    pub fn close_input(&mut self) {
+        self.interpreter.close_input();
+    }
+
+    /// Execute at most `budget` instructions, and return the state of the program: `finished`, `runnable` if it can
+    /// continue, or `waiting` for input. Returns the error that stopped the program, with its source location
+    pub fn run(&mut self, budget: u32) -> Result<String, String> {
+        let state = match self.interpreter.run_for(budget as usize) {
+            StepOutcome::Finished => "finished",
+            StepOutcome::Runnable | StepOutcome::Paused => "runnable",
+            StepOutcome::WaitingForInput => "waiting",
+            StepOutcome::Error(err) => return Err(err.to_string()),
+        };
+        Ok(String::from(state))
+    }
+
+    /// Execute a single instruction, as [`Playground::run`] does
+    pub fn step(&mut self) -> Result<String, String> {
+        self.run(1)
+    }
+
+    /// Output written since it was last taken
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let output = self.output.contents().split_off(self.taken);
+        self.taken += output.len();
+        output
+    }
+
+    /// Address of the next instruction
+    pub fn pc(&self) -> u32 {
+        self.interpreter.vm().pc() as u32
+    }
+
+    /// Line and column of the source code of the next instruction, starting from 1, or 0 if it is unknown
+    pub fn location(&self) -> Vec<u32> {
+        match self.interpreter.program().location(self.interpreter.vm().pc()) {
+            Some(loc) => vec![loc.row as u32, loc.col as u32],
+            None => vec![0, 0],
+        }
+    }
+
+    /// Address of the cell under the memory pointer, relative to its initial position
+    pub fn address(&self) -> i32 {
+        self.interpreter.vm().address() as i32
+    }
+
+    /// Values of `len` cells starting from the address `start`, fewer past the end of the tape
+    pub fn memory(&self, start: i32, len: u32) -> Vec<u8> {
+        self.interpreter.vm().memory_slice(start as isize, len as usize).into_iter().map(|cell| cell as u8).collect()
+    }
+
+    /// Number of instructions executed since the program started
+    pub fn executed(&self) -> f64 {
+        self.interpreter.vm().executed() as f64
+    }
+}
+
+/// Interpreter with a tape of `memory_size` cells, reading the input pushed by the page, and the buffer it writes to
+fn machine(memory_size: usize) -> (Interpreter, SharedBuffer) {
+    let output = SharedBuffer::new();
+    let settings = Settings::builder()
+        .memory_size(memory_size)
+        .memory_overflow_behavior(MemoryOverflowBehavior::Error)
+        .output(output.clone())
+        .build();
+    (Interpreter::with_vm_settings(settings).with_input_queue(), output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_step_by_step() {
+        let mut playground = Playground::new(16);
+        assert_eq!(playground.compile("+[").unwrap_err(), "Unmatched '[' at line 1, column 2");
+        playground.compile(",[.,]").unwrap();
+        assert_eq!(playground.run(100), Ok(String::from("waiting")));
+        playground.push_input(b"ab");
+        assert_eq!(playground.step(), Ok(String::from("runnable")));
+        assert_eq!((playground.pc(), playground.location()), (1, vec![1, 2]));
+        assert_eq!(playground.memory(0, 2), b"a\0");
+        assert_eq!(playground.run(100), Ok(String::from("waiting")));
+        assert_eq!(playground.take_output(), b"ab");
+        playground.close_input();
+        assert_eq!(playground.run(100), Ok(String::from("finished")));
+        assert_eq!(playground.take_output(), b"");
+        playground.compile("<").unwrap();
+        assert!(playground.run(1).unwrap_err().starts_with("Memory pointer moved left of the tape"));
+    }
+}
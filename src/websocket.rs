@@ -0,0 +1,309 @@
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+/// Frame opcodes
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+/// Appended to the key of the client to prove that the server speaks WebSocket, see RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Reader of the messages sent by the client, as a stream of bytes. Pings are answered. An empty message or the
+/// closing of the connection ends the stream
+pub struct Receiver {
+    reader: BufReader<TcpStream>,
+    /// Stream the pongs are written to
+    stream: TcpStream,
+    /// Largest message accepted
+    max_len: usize,
+    message: Vec<u8>,
+    /// Position of the next byte to read in `message`
+    pos: usize,
+    ended: bool,
+}
+
+/// Writer sending what is written as a binary message whenever it is flushed
+pub struct Sender {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+/// Complete the opening handshake of a WebSocket connection requested with `key`, the Sec-WebSocket-Key header
+pub fn accept(stream: &mut TcpStream, key: &str) -> std::io::Result<()> {
+    let accept = base64(&sha1(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept,
+    )?;
+    stream.flush()
+}
+
+/// Send `text` as a text message
+pub fn send_text(stream: &mut TcpStream, text: &str) -> std::io::Result<()> {
+    write_frame(stream, TEXT, text.as_bytes())
+}
+
+/// Start the closing handshake
+pub fn close(stream: &mut TcpStream) -> std::io::Result<()> {
+    write_frame(stream, CLOSE, &[])
+}
+
+/* Receiver ***********************************************************************************************************/
+impl Receiver {
+    /// Receiver of the messages read by `reader`, which may already hold some of them, each at most `max_len` bytes
+    /// long
+    pub fn new(reader: BufReader<TcpStream>, max_len: usize) -> std::io::Result<Receiver> {
+        let stream = reader.get_ref().try_clone()?;
+        Ok(Receiver { reader, stream, max_len, message: Vec::new(), pos: 0, ended: false })
+    }
+
+    /// Next message with data, put back together from its fragments, or None once the client ended the stream.
+    /// Fails with `TimedOut` if the read timeout of the stream expires while waiting for it
+    pub fn next_message(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut message: Option<Vec<u8>> = None;
+        while !self.ended {
+            let (fin, opcode, payload) = read_frame(&mut self.reader, self.max_len).map_err(|err| match err.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    std::io::Error::new(ErrorKind::TimedOut, "The session timed out")
+                }
+                _ => err,
+            })?;
+            match (opcode, &mut message) {
+                // Control frames may come between the fragments of a message
+                (PING, _) => write_frame(&mut self.stream, PONG, &payload)?,
+                (PONG, _) => {}
+                (CLOSE, _) => self.ended = true,
+                (TEXT | BINARY, None) | (CONTINUATION, Some(_)) => {
+                    let message = message.get_or_insert_with(Vec::new);
+                    if message.len() + payload.len() > self.max_len {
+                        return Err(invalid(&format!("WebSocket messages are limited to {} bytes", self.max_len)));
+                    }
+                    message.extend_from_slice(&payload);
+                    match fin {
+                        false => {}
+                        true if message.is_empty() => self.ended = true,
+                        true => return Ok(Some(std::mem::take(message))),
+                    }
+                }
+                (TEXT | BINARY | CONTINUATION, _) => return Err(invalid("Invalid WebSocket message fragment")),
+                (opcode, _) => return Err(invalid(&format!("Invalid WebSocket opcode: {:#x}", opcode))),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Read for Receiver {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.message.len() {
+            match self.next_message()? {
+                Some(message) => (self.message, self.pos) = (message, 0),
+                None => return Ok(0),
+            }
+        }
+        let read = (&self.message[self.pos..]).read(buf)?;
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/* Sender *************************************************************************************************************/
+impl Sender {
+    pub fn new(stream: TcpStream) -> Sender {
+        Sender { stream, buffer: Vec::new() }
+    }
+}
+
+impl Write for Sender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_frame(&mut self.stream, BINARY, &self.buffer)?;
+            self.buffer.clear();
+        }
+        self.stream.flush()
+    }
+}
+
+/* Frames *************************************************************************************************************/
+/// FIN bit, opcode and unmasked payload of the next frame read from `stream`, which must not be longer than `max_len`
+/// bytes
+fn read_frame<R: Read>(stream: &mut R, max_len: usize) -> std::io::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > max_len as u64 {
+        return Err(invalid(&format!("WebSocket messages are limited to {} bytes", max_len)));
+    }
+    // Frames sent by clients are always masked
+    let mut mask = [0; 4];
+    if head[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (byte, mask) in payload.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= mask;
+    }
+    Ok((head[0] & 0x80 != 0, head[0] & 0x0f, payload))
+}
+
+/// Write `payload` to `stream` as a single unmasked frame with `opcode`
+fn write_frame<W: Write>(stream: &mut W, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/* Handshake **********************************************************************************************************/
+/// SHA-1 digest of `data`, which the handshake requires despite its weakness
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, state) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64 encoding of `bytes`, with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+
+    /// Frame sent by a client, masked with an arbitrary key
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(byte, mask)| byte ^ mask));
+        frame
+    }
+
+    #[test]
+    fn handshake() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&sha1(b"abc"))[..], *"qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+        // Example of RFC 6455, section 1.3
+        let key = format!("{}{}", "dGhlIHNhbXBsZSBub25jZQ==", ACCEPT_GUID);
+        assert_eq!(base64(&sha1(key.as_bytes())), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn parse_frames() {
+        let mut frames = Cursor::new([client_frame(false, TEXT, b"Hello"), vec![0x8a, 0x02, b'o', b'k']].concat());
+        assert_eq!(read_frame(&mut frames, 16).unwrap(), (false, TEXT, b"Hello".to_vec()));
+        assert_eq!(read_frame(&mut frames, 16).unwrap(), (true, PONG, b"ok".to_vec()));
+        let mut long = Vec::new();
+        write_frame(&mut long, BINARY, &[7; 300]).unwrap();
+        assert_eq!(long[..4], [0x82, 126, 0x01, 0x2c]);
+        assert_eq!(read_frame(&mut Cursor::new(&long), 300).unwrap(), (true, BINARY, vec![7; 300]));
+        assert!(read_frame(&mut Cursor::new(&long), 299).is_err());
+    }
+
+    #[test]
+    fn join_fragments() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut receiver = Receiver::new(BufReader::new(listener.accept().unwrap().0), 16).unwrap();
+        let frames = [
+            client_frame(false, TEXT, b"+["),
+            client_frame(true, PING, b"?"),
+            client_frame(true, CONTINUATION, b"-]"),
+            client_frame(true, BINARY, b"in"),
+            client_frame(true, BINARY, b""),
+        ];
+        client.write_all(&frames.concat()).unwrap();
+        assert_eq!(receiver.next_message().unwrap(), Some(b"+[-]".to_vec()));
+        let mut input = Vec::new();
+        receiver.read_to_end(&mut input).unwrap();
+        assert_eq!(input, b"in");
+        let mut pong = [0; 3];
+        client.read_exact(&mut pong).unwrap();
+        assert_eq!(pong, [0x8a, 1, b'?']);
+    }
+}